@@ -0,0 +1,70 @@
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A change made to the running controller from outside it, such as a fan
+/// set by hand, a profile switch or a reload
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    /// Where the change came from, `SIGHUP` or the control socket and the
+    /// client's user, e.g. `control socket uid 1000`
+    pub source: String,
+    /// Name the client gave itself, as sent and not checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// What was changed, e.g. `set cpu`, `profile` or `reload`
+    pub action: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Change {
+    pub fn new(source: &str, action: String, old: Option<String>, new: Option<String>) -> Self {
+        Self {
+            source: source.to_string(),
+            label: None,
+            action,
+            old,
+            new,
+        }
+    }
+
+    pub fn labelled(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    change: &'a Change,
+}
+
+/// Append-only record of every change made from outside the controller,
+/// a JSON object per line with the Unix `timestamp`, `source`, `action`,
+/// and the `old` and `new` values. Unlike the sample log it is never
+/// rotated, so nothing recorded is lost.
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, change: &Change) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let line = serde_json::to_string(&Line { timestamp, change })?;
+        writeln!(self.file, "{}", line)
+    }
+}
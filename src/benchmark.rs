@@ -0,0 +1,93 @@
+use crate::controller::{Controller, TickTimings};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Run `controller` for `ticks` ticks, a sample interval apart as in normal
+/// running, then write the average time each tick spent refreshing sensors,
+/// deciding, and writing outputs, along with the CPU time used. `ticks`
+/// must be at least 1.
+pub fn run<W: Write>(controller: &mut Controller, ticks: u64, out: &mut W) -> io::Result<()> {
+    let mut total = TickTimings::default();
+    let cpu_before = cpu_time();
+    for tick in 0..ticks {
+        if tick > 0 {
+            controller.wait();
+        }
+        controller.tick();
+        let timings = controller.timings();
+        total.sensors += timings.sensors;
+        total.decisions += timings.decisions;
+        total.writes += timings.writes;
+    }
+    // Includes printing the status lines, as in normal running
+    let cpu = cpu_time().saturating_sub(cpu_before);
+
+    let per_tick = |time: Duration| time.div_f64(ticks as f64);
+    let wall = per_tick(total.sensors + total.decisions + total.writes);
+    let interval = controller.sample_interval();
+    writeln!(
+        out,
+        "{} ticks of {} fans, averaged per tick:",
+        ticks,
+        controller.fans().len()
+    )?;
+    writeln!(out, "sensor refresh  {:?}", per_tick(total.sensors))?;
+    writeln!(out, "decisions       {:?}", per_tick(total.decisions))?;
+    writeln!(out, "output writes   {:?}", per_tick(total.writes))?;
+    writeln!(
+        out,
+        "total           {:?}, {:.3}% of the {}s sample interval",
+        wall,
+        wall.as_secs_f64() / interval.as_secs_f64().max(f64::EPSILON) * 100.0,
+        interval.as_secs()
+    )?;
+    writeln!(out, "cpu time        {:?}", per_tick(cpu))?;
+
+    Ok(())
+}
+
+/// User and system CPU time used by this process so far
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::ZERO;
+    }
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::testing::TestController;
+
+    #[test]
+    fn reports_timings_after_every_tick() {
+        let mut test = TestController::new("benchmark", "");
+        let mut out = Vec::new();
+        run(&mut test.controller, 5, &mut out).unwrap();
+
+        // A sample interval waited between each of the ticks
+        assert_eq!(test.clock.since_boot(), Duration::from_secs(4));
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "5 ticks of 1 fans, averaged per tick:");
+        let stages: Vec<&str> = lines[1..]
+            .iter()
+            .filter_map(|l| l.split("  ").next())
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                "sensor refresh",
+                "decisions",
+                "output writes",
+                "total",
+                "cpu time"
+            ]
+        );
+        assert!(lines[4].ends_with("of the 1s sample interval"));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::FanConfig;
+use crate::daily::DailyExtremes;
+use crate::output;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Duty added each step of the sweep, in percent
+const SWEEP_STEP: f32 = 5f32;
+/// Time for the fan to settle at each duty before its tach is read
+const SETTLE: Duration = Duration::from_secs(3);
+/// Tach reads taken at each duty, all must show the fan spinning
+const READS: u32 = 3;
+const READ_INTERVAL: Duration = Duration::from_secs(1);
+/// Slowest reading counted as spinning rather than the tach's noise floor
+const SPINNING_RPM: u32 = 100;
+
+/// Values worked out at runtime and kept between runs, such as each fan's
+/// calibrated minimum duty and daily temperature extremes.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    /// Lowest duty each fan reliably spins at, by fan name
+    pub min_duty: BTreeMap<String, f32>,
+    /// Temperature extremes of today and yesterday, by fan name
+    pub daily: BTreeMap<String, DailyExtremes>,
+}
+
+impl State {
+    /// Read the state at `path`, empty if it doesn't exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(self).map_err(|e| Error::Config(e.to_string()))?;
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+}
+
+/// Read the RPM from a tach input such as `hwmonX/fanY_input`
+pub fn read_rpm<P: AsRef<Path>>(path: P) -> Result<u32, Error> {
+    let path = path.as_ref();
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| Error::Sensor(format!("invalid RPM in {}: {}", path.display(), e)))
+}
+
+/// Sweep the duty up from stopped in `SWEEP_STEP`s, returning the first duty
+/// `spins` reports the fan reliably spinning at, `None` if it never does
+pub fn stall_duty<F>(mut spins: F) -> Result<Option<f32>, Error>
+where
+    F: FnMut(f32) -> Result<bool, Error>,
+{
+    let steps = (100f32 / SWEEP_STEP).ceil() as u32;
+    for step in 1..=steps {
+        let duty = (step as f32 * SWEEP_STEP).min(100f32);
+        if spins(duty)? {
+            return Ok(Some(duty));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the lowest duty `fan` reliably spins at by driving its output while
+/// watching its tach. The fan is stopped first, so it has to start rather
+/// than keep turning at each duty.
+pub fn calibrate(fan: &FanConfig) -> Result<Option<f32>, Error> {
+    let tach = fan
+        .tach
+        .as_ref()
+        .ok_or_else(|| Error::Config("fan has no tach to calibrate from".to_string()))?;
+    if !fan.output.variable_speed() {
+        return Err(Error::Config(
+            "only variable speed outputs can be calibrated".to_string(),
+        ));
+    }
+    let mut output = output::from_config(&fan.output)?;
+
+    stall_duty(|duty| {
+        // Spinning down between steps, so each duty has to start the fan
+        output.set_duty(0f32)?;
+        thread::sleep(SETTLE);
+        output.set_duty(duty)?;
+        thread::sleep(SETTLE);
+        spinning(tach)
+    })
+}
+
+/// Whether every one of `READS` tach reads shows the fan spinning
+fn spinning(tach: &Path) -> Result<bool, Error> {
+    for read in 0..READS {
+        if read > 0 {
+            thread::sleep(READ_INTERVAL);
+        }
+        if read_rpm(tach)? < SPINNING_RPM {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RPM of a fan that stalls below 22% then spins up linearly
+    fn rpm(duty: f32) -> u32 {
+        if duty < 22f32 {
+            40
+        } else {
+            (duty * 24f32) as u32
+        }
+    }
+
+    #[test]
+    fn stall_point_is_the_first_spinning_step() {
+        let mut swept = Vec::new();
+        let stall = stall_duty(|duty| {
+            swept.push(duty);
+            Ok(rpm(duty) >= SPINNING_RPM)
+        });
+        assert_eq!(stall.unwrap(), Some(25.0));
+        assert_eq!(swept, [5.0, 10.0, 15.0, 20.0, 25.0]);
+
+        assert_eq!(stall_duty(|_| Ok(false)).unwrap(), None);
+    }
+}
@@ -0,0 +1,54 @@
+use gpio_cdev::Chip;
+use std::path::PathBuf;
+
+/// Open a GPIO chip given either its label (e.g. `pinctrl-bcm2835`) or its
+/// device path. Labels are stable across reboots where `/dev/gpiochipN`
+/// numbering may not be, so they are tried first.
+pub fn open(chip: &str) -> Result<Chip, gpio_cdev::Error> {
+    let chips = gpio_cdev::chips()?
+        .filter_map(Result::ok)
+        .map(|c| (c.path().to_path_buf(), c.label().to_string()));
+
+    Chip::new(resolve(chip, chips))
+}
+
+/// Pick the path of the first chip labelled `chip`, falling back to treating
+/// `chip` as a path when no label matches.
+pub fn resolve<I>(chip: &str, chips: I) -> PathBuf
+where
+    I: IntoIterator<Item = (PathBuf, String)>,
+{
+    chips
+        .into_iter()
+        .find(|(_, label)| label == chip)
+        .map(|(path, _)| path)
+        .unwrap_or_else(|| PathBuf::from(chip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chips() -> Vec<(PathBuf, String)> {
+        vec![
+            ("/dev/gpiochip0".into(), "pinctrl-bcm2835".to_string()),
+            ("/dev/gpiochip1".into(), "raspberrypi-exp-gpio".to_string()),
+        ]
+    }
+
+    #[test]
+    fn resolve_finds_chip_by_label() {
+        assert_eq!(
+            resolve("raspberrypi-exp-gpio", chips()),
+            PathBuf::from("/dev/gpiochip1")
+        );
+    }
+
+    #[test]
+    fn resolve_falls_back_to_path() {
+        assert_eq!(
+            resolve("/dev/gpiochip4", chips()),
+            PathBuf::from("/dev/gpiochip4")
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Source of the current time, so time based behaviour can be driven
+/// without waiting on the real clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Time since boot, including any time spent suspended, which `now`
+    /// doesn't count
+    fn since_boot(&self) -> Duration;
+
+    /// Minutes since local midnight
+    fn local_minutes(&self) -> u32;
+
+    /// Days since the Unix epoch in local time, moving on at local midnight
+    fn local_day(&self) -> i64;
+
+    /// Block for `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn since_boot(&self) -> Duration {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // Only fails for an unknown clock, which CLOCK_BOOTTIME isn't
+        unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    }
+
+    fn local_minutes(&self) -> u32 {
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        // Falls back to midnight UTC if the time can't be converted
+        if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+            return 0;
+        }
+        tm.tm_hour as u32 * 60 + tm.tm_min as u32
+    }
+
+    fn local_day(&self) -> i64 {
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        // Falls back to the UTC day if the time can't be converted
+        if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+            return now.div_euclid(24 * 60 * 60);
+        }
+        (now + tm.tm_gmtoff).div_euclid(24 * 60 * 60)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when advanced
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Cell::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.set(self.elapsed.get() + by);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn since_boot(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    /// Starts at midnight
+    fn local_minutes(&self) -> u32 {
+        (self.elapsed.get().as_secs() / 60 % (24 * 60)) as u32
+    }
+
+    /// Starts on day 0
+    fn local_day(&self) -> i64 {
+        (self.elapsed.get().as_secs() / (24 * 60 * 60)) as i64
+    }
+
+    /// Moves the clock on straight away
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
@@ -0,0 +1,2569 @@
+use crate::curve::{self, CurvePoint};
+use crate::measurement::{self, JumpAction, WindowMode};
+use crate::units::Units;
+use crate::usage::{Aggregation, SensorFilter, WindowRole};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Sensors followed by a fan that isn't assigned any zones
+pub const DEFAULT_SENSOR: &str = "coretemp";
+
+/// Prefix of environment variables overriding config fields, e.g.
+/// `GPIO_FAN_MAX_FAN_ON_TEMP`
+pub const ENV_PREFIX: &str = "GPIO_FAN_";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    /// Wait before the first sensor read and GPIO request, giving drivers
+    /// time to probe on boot
+    pub startup_delay_secs: u64,
+    /// Units every temperature in the config is given in
+    pub logic_units: Units,
+    /// Units temperatures are logged in
+    pub display_units: Units,
+    /// Decimal places temperatures are shown with in logs and the status
+    /// line. Only the display is rounded.
+    pub display_decimals: u8,
+    /// Seconds between sensor samples
+    pub sample_interval_secs: u64,
+    /// Whether the sensors are refreshed once per tick for every fan, or by
+    /// each fan on its own
+    pub sensor_refresh: SensorRefresh,
+    /// Longest a sensor refresh may take, in milliseconds, before the tick
+    /// goes on with the last readings
+    pub sensor_timeout_ms: Option<u64>,
+    /// Sensors read less often than every sample, e.g. slow disk
+    /// temperatures, their last reading used in between
+    pub sensor_intervals: Vec<SensorInterval>,
+    /// Seconds between fan decisions, a multiple of `sample_interval_secs`.
+    /// Every sample taken in between still feeds the windows.
+    pub decision_interval_secs: u64,
+    /// Minimum seconds between status lines, transitions are printed as
+    /// they happen regardless
+    pub verbose_interval_secs: u64,
+    /// Follow each fan's status line with the condition behind its state,
+    /// as `--explain` does
+    pub explain: bool,
+    pub cpu_intervals: Vec<usize>,
+    pub temp_intervals: Vec<usize>,
+    /// Role of each window in `cpu_intervals` and `temp_intervals`, in the
+    /// same order. Windows without one take part in every decision.
+    pub cpu_window_roles: Vec<WindowRole>,
+    pub temp_window_roles: Vec<WindowRole>,
+    /// How the samples of each CPU usage and temperature window are averaged
+    pub cpu_window_mode: WindowMode,
+    pub temp_window_mode: WindowMode,
+    /// Time constant in seconds `"ewma"` windows smooth with, instead of
+    /// one derived from each window's length
+    pub ewma_time_constant_secs: Option<f32>,
+    /// Most samples any window keeps in memory, however long it is
+    /// configured, so a huge window can't exhaust memory
+    pub max_window_samples: usize,
+    /// Fill each window with the first sample read rather than starting
+    /// empty, so averages are meaningful straight away
+    pub seed_windows: bool,
+    /// Average each temperature and CPU usage sample with those just before
+    /// it, this many in all, before it enters the windows, smoothing out
+    /// sensor quantization noise
+    pub prefilter_samples: Option<usize>,
+    /// Restart the longer temperature windows from the shortest once CPU
+    /// usage steps to a new level, so they don't lag behind it
+    pub load_step: Option<LoadStepConfig>,
+    /// How the temperatures of the components a fan follows are combined.
+    /// Components reporting the same label are only counted once.
+    pub temp_aggregation: Aggregation,
+    /// How the usage of each CPU is combined into one sample
+    pub cpu_aggregation: Aggregation,
+    /// Largest change in °C allowed between consecutive temperature samples,
+    /// larger jumps are treated as sensor glitches
+    pub sample_jump_limit: Option<f32>,
+    pub sample_jump_action: JumpAction,
+    /// Window the CPU frequency trigger averages over
+    pub cpu_freq_secs: usize,
+    /// Directory holding the `cpuN/cpufreq` entries
+    pub cpu_freq_root: PathBuf,
+    /// What to do while no temperature has been read since starting
+    pub startup_read_failure: StartupReadFailure,
+    /// Attempts made by `startup_read_failure = "retry"`
+    pub startup_read_retries: u32,
+    /// Force the fans on once no temperature has been read for this long
+    pub stale_after_secs: Option<u64>,
+    /// Time allowed for the first temperature to be read, since starting or
+    /// resuming, before `stale_after_secs` applies, `stale_after_secs`
+    /// itself when not given
+    pub stale_grace_secs: Option<u64>,
+    /// Reading some sensors clamp to when they aren't reporting, e.g. 0°C.
+    /// Every followed sensor at exactly this value for `sensor_floor_secs`
+    /// is handled by `sensor_floor_action` rather than taken as cold.
+    pub sensor_floor: Option<f32>,
+    pub sensor_floor_secs: u64,
+    pub sensor_floor_action: SensorFloorAction,
+    /// CPU usage reading exactly 0% for this long is taken as the usage
+    /// reading not working in this environment, handled by
+    /// `zero_usage_action`. 0 to never.
+    pub zero_usage_secs: u64,
+    pub zero_usage_action: ZeroUsageAction,
+    /// Seconds a sensor appearing after the first reading, e.g. once its
+    /// driver loads, is read without taking part in decisions
+    pub new_sensor_grace_secs: u64,
+    /// Whether the maximum temperature sensors report lowers the threshold
+    pub sensor_max: SensorMax,
+    /// Gap between ticks, in seconds, taken as the system having been
+    /// suspended, restarting the windows and re-opening the outputs. 0 to
+    /// never.
+    pub resume_gap_secs: u64,
+    /// The fan used when no `fans` are listed, configured at the top level
+    #[serde(flatten)]
+    pub fan: FanConfig,
+    pub fans: Vec<FanConfig>,
+    pub zones: Vec<ZoneConfig>,
+    /// Exact component labels allowed to feed any fan, when not empty
+    pub sensor_include: Vec<String>,
+    /// Exact component labels never used, even if they match a zone
+    pub sensor_exclude: Vec<String>,
+    /// Label prefixes in order of preference. Only the components matching
+    /// the first with any match are used, rather than all of them.
+    pub sensor_preference: Vec<String>,
+    /// Per component offset and scale correcting its readings
+    pub calibration: Vec<SensorCalibration>,
+    /// Sensors that should agree, taken as faulty when they don't
+    pub divergence: Vec<DivergenceCheck>,
+    /// Backend the readings come from, by its type in the sensor source
+    /// registry. Picked from which of the source tables below is set when
+    /// not given.
+    pub sensor_source: Option<String>,
+    /// Read temperatures from thermal zones instead of `sysinfo` components
+    pub thermal_zones: Option<ThermalZonesConfig>,
+    /// Read the temperature from `vcgencmd measure_temp` on a Raspberry Pi
+    pub vcgencmd: Option<VcgencmdConfig>,
+    /// Read temperatures reported by the BMC through `ipmitool sdr`
+    pub ipmi: Option<IpmiConfig>,
+    /// Read temperatures remote sensors publish to an MQTT broker
+    pub mqtt: Option<MqttConfig>,
+    /// Seconds a reading injected through the control socket is used for,
+    /// with `sensor_source = "injected"`
+    pub injected_max_age_secs: u64,
+    /// Send fans turning on and off to the systemd journal as structured
+    /// entries, when running under systemd
+    pub journal: bool,
+    /// Show fans turning on and off, critical temperatures and failing fans
+    /// as desktop notifications
+    pub notifications: Option<NotificationsConfig>,
+    pub sample_log: Option<SampleLogConfig>,
+    /// File every manual override, profile switch and reload is appended to
+    pub audit_log: Option<PathBuf>,
+    /// Push metrics to a StatsD or InfluxDB UDP endpoint
+    pub metrics: Option<MetricsConfig>,
+    /// Named pipe the status is written to each tick
+    pub status_fifo: Option<StatusFifoConfig>,
+    /// Unix socket answering status queries
+    pub control_socket: Option<PathBuf>,
+    /// Force every fan on once no `heartbeat` has been sent to the control
+    /// socket for this many seconds, until the next one
+    pub heartbeat_secs: Option<u64>,
+    /// Force every fan on while any one's readings are critical, rather than
+    /// only the fans following them
+    pub critical_all_fans: bool,
+    /// Run every fan at full speed while this file exists, e.g. created by
+    /// a backup script for its duration
+    pub maintenance_file: Option<PathBuf>,
+    /// Force every fan on while a config reload has failed, until a valid
+    /// config is loaded
+    pub fail_hot: bool,
+    /// Sweep each fan's curves and thresholds for sane duties before
+    /// applying a reload, rejecting it if they aren't
+    pub reload_check: bool,
+    /// Where values worked out at runtime, such as calibrated minimum
+    /// duties, are kept between runs
+    pub state_file: PathBuf,
+    /// Track each fan's daily temperature extremes and peak duty, kept in
+    /// `state_file`
+    pub daily_stats: bool,
+    /// Named sets of fan settings that can be switched between at runtime
+    pub profiles: Vec<ProfileConfig>,
+    /// Profile applied on start, the fans' own settings when not given
+    pub profile: Option<String>,
+}
+
+/// Name of the control socket's `profile` argument switching back to the
+/// fans' own settings
+pub const NO_PROFILE: &str = "none";
+
+/// Fan settings, such as `max_fan_on_temp` or `curve`, replacing those of
+/// every fan while the profile is active. Settings not given are left as
+/// each fan has them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: toml::Table,
+}
+
+impl ProfileConfig {
+    /// `fan` with this profile's settings in place of its own
+    pub fn apply(&self, fan: &FanConfig) -> Result<FanConfig, String> {
+        let mut table = toml::Table::try_from(fan).map_err(|e| e.to_string())?;
+        for (key, value) in &self.settings {
+            table.insert(key.clone(), value.clone());
+        }
+
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| format!("profile {}: {}", self.name, e))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FanConfig {
+    pub name: Option<String>,
+    /// Seconds between this fan's decisions, overriding the top level
+    /// `decision_interval_secs`. Only used by fans listed under `fans`.
+    pub decision_interval_secs: Option<u64>,
+    pub max_fan_on_temp: f32,
+    pub max_fan_on_cpu: f32,
+    /// Whether a value exactly at `max_fan_on_temp`, `max_fan_on_cpu` or
+    /// `cpu_freq_trigger` turns the fan on
+    pub threshold_boundary: Boundary,
+    /// Zone deciding temperature windows within a band of `max_fan_on_temp`,
+    /// where the fan's own sensors are too close to call
+    pub tie_breaker: Option<TieBreakerConfig>,
+    /// Only let CPU usage turn the fan on while the shortest temperature
+    /// window is above this, so brief work on a cold box doesn't run it
+    pub usage_trigger_min_temp: Option<f32>,
+    /// Turn the fan on while the fastest CPU's frequency, averaged over
+    /// `cpu_freq_secs`, is above this many MHz
+    pub cpu_freq_trigger: Option<f32>,
+    /// Once on, keep the fan running until the shortest temperature window
+    /// falls to this temperature, rather than stopping once it stops rising
+    pub cooldown_target: Option<f32>,
+    /// Temperature threshold as a percentage of the lowest critical
+    /// temperature the fan's sensors report, in °C, instead of
+    /// `max_fan_on_temp`, which is still used while none report one
+    pub max_fan_on_critical_percent: Option<f32>,
+    /// Cooldown target as a percentage below `max_fan_on_temp`, instead of
+    /// an absolute `cooldown_target`
+    pub hysteresis_percent: Option<f32>,
+    /// Turn the fan on early when the shortest temperature window is rising
+    /// faster than this many °C/sec
+    pub rise_rate_trigger: Option<f32>,
+    /// Force the fan to full as soon as the temperature rises faster than
+    /// this many °C/sec between two consecutive samples, whatever it is at
+    pub runaway_rate: Option<f32>,
+    pub cold_start: ColdStart,
+    /// Decisions to hold the fan in `warmup_state` for on start, without
+    /// running any triggers, while the windows fill
+    pub warmup_ticks: u32,
+    pub warmup_state: WarmupState,
+    /// Run the fan at a fixed duty for a while every time control starts,
+    /// before any automatic control
+    pub startup_purge: Option<PurgeConfig>,
+    /// Warn while a temperature window is above this, below
+    /// `max_fan_on_temp`, without turning the fan on
+    pub alert_temp: Option<f32>,
+    /// Warn when the temperature rises by more than this many °C within
+    /// `temp_delta_secs`, even below the thresholds
+    pub temp_delta_alert: Option<f32>,
+    /// Period the rise is measured over, taken from the longest temperature
+    /// window. The whole window when not given.
+    pub temp_delta_secs: Option<u64>,
+    /// Force the fan on whenever a followed component reads above the
+    /// critical temperature it reports itself
+    pub sensor_critical_force_on: bool,
+    /// Turn the fan on immediately once a raw reading reaches this
+    /// temperature, in any mode and without waiting for `on_ticks`
+    pub critical_temp: Option<f32>,
+    /// Run `panic_command` as soon as a reading reaches this temperature, as
+    /// a last resort to protect the hardware
+    pub panic_temp: Option<f32>,
+    pub panic_command: String,
+    /// Consecutive decisions the thresholds must be exceeded for before the
+    /// fan is turned on
+    pub on_ticks: u32,
+    /// Order the automatic triggers are checked in, the first to call for
+    /// the fan being the one reported. Triggers left out follow in their
+    /// default order.
+    pub trigger_order: Vec<AutoTrigger>,
+    /// Limit how often the fan is switched, protecting a relay from wear
+    pub switch_limit: Option<SwitchLimitConfig>,
+    /// Seconds the thresholds must keep calling for the fan to be off
+    /// before it is turned off, re-checked at each decision meanwhile
+    pub off_confirm_secs: u64,
+    /// Consecutive decisions the fan must be decided on, or off, for before
+    /// it is switched, after `on_ticks` and `off_confirm_secs` have applied
+    pub debounce_decisions: u32,
+    /// Raise the temperature threshold for a while after the fan turns off,
+    /// so residual heat rebounding doesn't turn it straight back on
+    pub heat_soak: Option<HeatSoakConfig>,
+    /// Points of a fan curve. When given the fan's duty follows the curve
+    /// from the shortest temperature window instead of switching on and off.
+    pub curve: Vec<CurvePoint>,
+    /// In curve mode, run the fan at full speed from this temperature until
+    /// it falls `full_speed_hysteresis` °C below it
+    pub full_speed_temp: Option<f32>,
+    pub full_speed_hysteresis: f32,
+    /// In curve mode, only change the duty once the temperature has moved
+    /// this many °C from where it was last changed
+    pub curve_min_delta: Option<f32>,
+    /// In curve mode, most the duty may change by in one decision, in
+    /// percentage points
+    pub curve_max_step: Option<f32>,
+    /// In curve mode, add up the weighted duties of the fan's own curve and
+    /// its zones' curves rather than taking the highest
+    pub curve_sum: Option<CurveSumConfig>,
+    /// Stop the fan fully once the system has been idle for a while
+    pub idle: Option<IdleConfig>,
+    /// Let an on/off fan cooling down turn off early once the CPU usage has
+    /// stayed low for a while, the heat source being gone
+    pub low_usage_off: Option<LowUsageOffConfig>,
+    /// Keep the fan off while the latest temperature is below this,
+    /// whatever the CPU usage or other triggers call for
+    pub off_below_temp: Option<f32>,
+    /// Fixed duties for times of day, overriding automatic control
+    pub schedule: Vec<ScheduleConfig>,
+    /// Periodically nudge a fan running close to its stall point
+    pub anti_stall: Option<AntiStallConfig>,
+    /// Lowest duty to run the fan at while it is on, the calibrated value
+    /// from `state_file` when not given
+    pub min_duty: Option<f32>,
+    /// Tach RPM input, e.g. `hwmonX/fanY_input`, used by `--calibrate-fan`,
+    /// `health_check` and `spin_down`
+    pub tach: Option<PathBuf>,
+    /// Periodically run the fan and check its tach, flagging it as degraded
+    /// when the RPM is out of bounds
+    pub health_check: Option<HealthCheckConfig>,
+    /// Alert when the fan slows down while it should be running
+    pub spin_down: Option<SpinDownConfig>,
+    /// Honor the state the kernel's thermal governor requests of a cooling
+    /// device, rather than fighting it
+    pub kernel_cooling: Option<KernelCoolingConfig>,
+    /// Mirror another fan rather than deciding from sensors
+    pub follow: Option<FollowConfig>,
+    /// Drive the fan from a weighted blend of temperature and CPU usage
+    /// instead of the separate triggers
+    pub blend: Option<BlendConfig>,
+    /// Shell commands run when the fan turns on and off
+    pub on_command: Option<String>,
+    pub off_command: Option<String>,
+    /// How long a hook command may run before it is killed and counted as
+    /// failed
+    pub hook_timeout_secs: u64,
+    pub hook_failure: HookFailure,
+    /// Names of the zones whose sensors this fan follows. When empty the
+    /// CPU's `coretemp` sensors are used, or every thermal zone read.
+    // Skipped when empty so it doesn't clash with `Config::zones` when
+    // flattened into the top level
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+    /// How this fan combines the temperatures it follows, in place of
+    /// `Config::temp_aggregation`
+    // Skipped when unset so it doesn't clash with `Config::temp_aggregation`
+    // when flattened into the top level
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temp_aggregation: Option<Aggregation>,
+    /// Follow only the first of `zones` with a reading, in the order they
+    /// are listed, rather than combining them all
+    pub zone_priority: bool,
+    pub output: OutputConfig,
+    /// Second output, e.g. an alarm LED, buzzer or backup fan, switched on
+    /// while the critical temperature is reached
+    pub alarm_output: Option<OutputConfig>,
+    /// Steps written to a switched output each time it turns off, before
+    /// it is left off, e.g. to brake a fan through a relay
+    pub brake_pulse: Vec<PulseStep>,
+    /// Milliseconds to wait after any other fan started before starting
+    /// this one, so they don't all draw their inrush current at once
+    pub start_stagger_ms: u64,
+    /// The fan's addressable LEDs, colored by temperature, when built with
+    /// the `rgb` feature
+    pub rgb: Option<RgbConfig>,
+}
+
+/// WS2812 LEDs on the `spidev` SPI device, all `leds` of them shown on a
+/// gradient from blue at `cold_temp` to red at `hot_temp`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RgbConfig {
+    pub spidev: PathBuf,
+    #[serde(default = "RgbConfig::default_leds")]
+    pub leds: u32,
+    #[serde(default = "RgbConfig::default_cold_temp")]
+    pub cold_temp: f32,
+    #[serde(default = "RgbConfig::default_hot_temp")]
+    pub hot_temp: f32,
+    /// Scales every channel, 0-1
+    #[serde(default = "RgbConfig::default_brightness")]
+    pub brightness: f32,
+}
+
+impl RgbConfig {
+    fn default_leds() -> u32 {
+        1
+    }
+
+    fn default_cold_temp() -> f32 {
+        40f32
+    }
+
+    fn default_hot_temp() -> f32 {
+        80f32
+    }
+
+    fn default_brightness() -> f32 {
+        1f32
+    }
+}
+
+/// One step of a pulse sequence, holding the output on or off for `ms`
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PulseStep {
+    pub on: bool,
+    pub ms: u64,
+}
+
+/// What to do with the fan on the very first update, before there is any
+/// history to decide from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColdStart {
+    Off,
+    On,
+    /// On if the first temperature read is already above `max_fan_on_temp`
+    #[default]
+    Temperature,
+}
+
+/// Runs the fan at `duty` percent from `from` until `to` each day, local
+/// time. A range ending before it starts runs over midnight. With
+/// `precool_mins` the fan is run at `precool_duty` for that long before
+/// `from`, so the load starts on an already cool box.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScheduleConfig {
+    pub from: TimeOfDay,
+    pub to: TimeOfDay,
+    pub duty: f32,
+    #[serde(default)]
+    pub precool_mins: u32,
+    #[serde(default = "ScheduleConfig::default_precool_duty")]
+    pub precool_duty: f32,
+}
+
+impl ScheduleConfig {
+    fn default_precool_duty() -> f32 {
+        100f32
+    }
+
+    /// Whether `minute`, counted from local midnight, is within
+    /// `precool_mins` before the range starts
+    pub fn precooling(&self, minute: u32) -> bool {
+        let lead = (self.from.0 + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY;
+        lead > 0 && lead <= self.precool_mins
+    }
+
+    /// Whether `minute`, counted from local midnight, is within the range
+    pub fn contains(&self, minute: u32) -> bool {
+        let (from, to) = (self.from.0, self.to.0);
+        if from <= to {
+            (from..to).contains(&minute)
+        } else {
+            minute >= from || minute < to
+        }
+    }
+}
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+/// Longest `brake_pulse` allowed, as it holds up the tick while it runs
+const MAX_BRAKE_PULSE_MS: u64 = 1000;
+
+/// Minutes after local midnight, written as `"HH:MM"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(pub u32);
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .split_once(':')
+            .and_then(|(hours, minutes)| {
+                Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?))
+            })
+            .filter(|&(hours, minutes)| hours < 24 && minutes < 60)
+            .map(|(hours, minutes)| TimeOfDay(hours * 60 + minutes))
+            .ok_or_else(|| format!("invalid time \"{}\", expected \"HH:MM\"", value))
+    }
+}
+
+/// Whether reaching a threshold exactly counts as exceeding it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// Only values above the threshold trigger
+    #[default]
+    Exclusive,
+    /// Values at or above the threshold trigger
+    Inclusive,
+}
+
+impl Boundary {
+    /// Whether `value` is past `threshold`
+    pub fn exceeds(self, value: f32, threshold: f32) -> bool {
+        match self {
+            Boundary::Exclusive => value > threshold,
+            Boundary::Inclusive => value >= threshold,
+        }
+    }
+}
+
+/// State the fan is held in while warming up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WarmupState {
+    #[default]
+    Off,
+    On,
+}
+
+/// The system counts as idle while the latest temperature is below
+/// `max_temp` and CPU usage below `max_cpu`. Once idle for `secs` the fan is
+/// stopped, whatever its curve or triggers call for, until either rises.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IdleConfig {
+    pub max_temp: f32,
+    pub max_cpu: f32,
+    pub secs: u64,
+}
+
+/// Once the latest CPU usage has stayed below `max_cpu` for `secs`, an on/off
+/// fan kept running by its cooldown or trend may turn off, as long as the
+/// latest temperature is below `max_temp`, `max_fan_on_temp` when not set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LowUsageOffConfig {
+    pub max_cpu: f32,
+    pub secs: u64,
+    pub max_temp: Option<f32>,
+}
+
+/// Duties of the fan's own curve and its zones' curves added up, each
+/// multiplied by its weight, and capped at `cap` percent, so several warm
+/// sources together can call for more airflow than any one of them. The
+/// fan's own curve is weighted by `weight`, zones by `zone_weights` by name;
+/// either defaults to 1.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CurveSumConfig {
+    #[serde(default = "CurveSumConfig::default_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub zone_weights: BTreeMap<String, f32>,
+    #[serde(default = "CurveSumConfig::default_cap")]
+    pub cap: f32,
+}
+
+impl CurveSumConfig {
+    fn default_weight() -> f32 {
+        1f32
+    }
+
+    fn default_cap() -> f32 {
+        100f32
+    }
+
+    /// Weight of the curve of the zone named `zone`
+    pub fn zone_weight(&self, zone: &str) -> f32 {
+        self.zone_weights
+            .get(zone)
+            .copied()
+            .unwrap_or(Self::default_weight())
+    }
+}
+
+/// For `secs` after the fan turns off, the temperature threshold is raised
+/// by `margin` °C
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeatSoakConfig {
+    pub secs: u64,
+    #[serde(default = "HeatSoakConfig::default_margin")]
+    pub margin: f32,
+}
+
+impl HeatSoakConfig {
+    fn default_margin() -> f32 {
+        2f32
+    }
+}
+
+/// While a duty-driven fan runs above 0 but below `below` percent, it is
+/// pulsed to `duty` percent for `for_secs` every `every_secs` so it doesn't
+/// stall.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AntiStallConfig {
+    pub below: f32,
+    pub duty: f32,
+    pub every_secs: u64,
+    #[serde(default = "AntiStallConfig::default_for_secs")]
+    pub for_secs: u64,
+}
+
+impl AntiStallConfig {
+    fn default_for_secs() -> u64 {
+        2
+    }
+}
+
+/// What to do when an on or off hook command fails or times out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailure {
+    #[default]
+    Ignore,
+    Log,
+    /// Log the failure and turn the fan on
+    ForceOn,
+}
+
+/// A temperature window within `band` °C of the fan's threshold counts as
+/// over it when the zone named `zone` is above `threshold`, and as under it
+/// otherwise. The zone is read from every sensor, not just the fan's own.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TieBreakerConfig {
+    pub zone: String,
+    pub threshold: f32,
+    #[serde(default = "TieBreakerConfig::default_band")]
+    pub band: f32,
+}
+
+impl TieBreakerConfig {
+    fn default_band() -> f32 {
+        1f32
+    }
+}
+
+/// Runs the fan at `duty` for `secs` on start, e.g. to clear dust and show
+/// it works. On/off outputs are switched on for any duty above 0.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PurgeConfig {
+    pub secs: u64,
+    #[serde(default = "PurgeConfig::default_duty")]
+    pub duty: f32,
+}
+
+impl PurgeConfig {
+    fn default_duty() -> f32 {
+        100f32
+    }
+}
+
+/// Every `every_secs` the fan is run at `duty` for `run_secs`, after which
+/// its tach must read between `min_rpm` and `max_rpm`. With
+/// `skip_if_running`, a check falling due while the fan is already on is
+/// skipped until the next one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthCheckConfig {
+    pub every_secs: u64,
+    #[serde(default = "HealthCheckConfig::default_run_secs")]
+    pub run_secs: u64,
+    #[serde(default = "HealthCheckConfig::default_duty")]
+    pub duty: f32,
+    pub min_rpm: u32,
+    pub max_rpm: Option<u32>,
+    #[serde(default = "HealthCheckConfig::default_skip_if_running")]
+    pub skip_if_running: bool,
+}
+
+impl HealthCheckConfig {
+    fn default_run_secs() -> u64 {
+        10
+    }
+
+    fn default_duty() -> f32 {
+        100f32
+    }
+
+    fn default_skip_if_running() -> bool {
+        true
+    }
+}
+
+/// A fan whose tach reads below `min_rpm` for `secs` while it is on is
+/// flagged as spinning down, likely failing
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpinDownConfig {
+    pub min_rpm: u32,
+    #[serde(default = "SpinDownConfig::default_secs")]
+    pub secs: u64,
+}
+
+impl SpinDownConfig {
+    fn default_secs() -> u64 {
+        10
+    }
+}
+
+/// A kernel cooling device, e.g. `/sys/class/thermal/cooling_device0`,
+/// whose `cur_state` out of `max_state` is taken as the duty the kernel's
+/// thermal governor wants the fan at
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KernelCoolingConfig {
+    pub device: PathBuf,
+    #[serde(default)]
+    pub mode: KernelCoolingMode,
+}
+
+/// How the kernel's requested state is honored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KernelCoolingMode {
+    /// Run the fan at the requested state instead of deciding automatically
+    #[default]
+    Follow,
+    /// Decide automatically, but never run the fan below the requested state
+    Max,
+}
+
+/// Follows the fan named `fan`, e.g. an exhaust fan mirroring its intake.
+/// A leader driven by duty is followed at that duty plus `duty_offset`, and
+/// the follower keeps running for `off_delay_secs` after the leader stops.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FollowConfig {
+    pub fan: String,
+    #[serde(default)]
+    pub duty_offset: f32,
+    #[serde(default)]
+    pub off_delay_secs: u64,
+}
+
+/// Combines the shortest temperature and CPU usage windows into a demand
+/// between 0 and 1. Temperature is scaled from 0 at `idle_temp` to 1 at the
+/// fan's `max_fan_on_temp`, usage from 0 to 1 over 0-100%, and the two are
+/// averaged by weight.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlendConfig {
+    pub temp_weight: f32,
+    pub cpu_weight: f32,
+    pub idle_temp: f32,
+    /// Demand at which an on/off output is switched on
+    pub threshold: f32,
+}
+
+impl Default for BlendConfig {
+    fn default() -> Self {
+        Self {
+            temp_weight: 0.7,
+            cpu_weight: 0.3,
+            idle_temp: 25f32,
+            threshold: 0.5,
+        }
+    }
+}
+
+impl BlendConfig {
+    pub fn demand(&self, temp: f32, cpu: f32, max_temp: f32) -> f32 {
+        let temp = ((temp - self.idle_temp) / (max_temp - self.idle_temp)).clamp(0f32, 1f32);
+        let cpu = (cpu / 100f32).clamp(0f32, 1f32);
+
+        (temp * self.temp_weight + cpu * self.cpu_weight) / (self.temp_weight + self.cpu_weight)
+    }
+}
+
+/// A named group of temperature sensors, typically one physical airflow
+/// region of the board.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ZoneConfig {
+    pub name: String,
+    /// Component label prefixes of the sensors in this zone
+    pub sensors: Vec<String>,
+    /// Curve this zone's temperature is followed by on fans in curve mode,
+    /// which run at the highest duty of their zones' curves
+    #[serde(default)]
+    pub curve: Vec<CurvePoint>,
+    /// Temperature above which this zone turns on every fan following it
+    /// that is switched on and off, whatever the fan's own thresholds
+    pub threshold: Option<f32>,
+}
+
+/// Sensors whose labels start with one of `sensors`, read every `secs`
+/// rather than every sample
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SensorInterval {
+    pub sensors: Vec<String>,
+    pub secs: u64,
+}
+
+/// Correction applied to every reading of the component labelled `label`,
+/// before it is aggregated: `reading * scale + offset`, then clamped to
+/// `min` and `max` so a glitched reading can't saturate the windows.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SensorCalibration {
+    pub label: String,
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default = "SensorCalibration::default_scale")]
+    pub scale: f32,
+    /// Lowest plausible corrected reading
+    pub min: Option<f32>,
+    /// Highest plausible corrected reading
+    pub max: Option<f32>,
+}
+
+/// Sensors, by label prefix, that should read within `max_delta` of each
+/// other, e.g. two CPU packages. Spread further apart one is likely faulty,
+/// so the highest of them is trusted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DivergenceCheck {
+    pub sensors: Vec<String>,
+    pub max_delta: f32,
+}
+
+impl SensorCalibration {
+    fn default_scale() -> f32 {
+        1f32
+    }
+
+    pub fn apply(&self, reading: f32) -> f32 {
+        reading * self.scale + self.offset
+    }
+
+    /// `reading` held within `min` and `max`
+    pub fn clamp(&self, reading: f32) -> f32 {
+        let reading = self.min.map_or(reading, |min| reading.max(min));
+        self.max.map_or(reading, |max| reading.min(max))
+    }
+}
+
+/// How the fan is physically driven.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum OutputConfig {
+    /// A single GPIO line, driven high for on and low for off.
+    /// `chip` is either the chip label or its device path.
+    Line {
+        chip: String,
+        line: u32,
+        #[serde(default)]
+        bias: Bias,
+        /// Name the line must have, so a wrong `line` offset is caught
+        /// rather than driving some other line
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        line_name: Option<String>,
+        /// Extra attempts at a write failing with `EAGAIN` or `EINTR`
+        #[serde(default = "OutputConfig::default_write_retries")]
+        write_retries: u32,
+        #[serde(default)]
+        empty_chip: EmptyChip,
+    },
+    /// Several lines that must change together, e.g. a relay bank.
+    /// Lines within a group are set atomically in a single request.
+    MultiLine {
+        groups: Vec<LineGroupConfig>,
+        #[serde(default = "OutputConfig::default_write_retries")]
+        write_retries: u32,
+        #[serde(default)]
+        empty_chip: EmptyChip,
+    },
+    /// A fan exposed through `<hwmon>/pwm<pwm>`, bypassing GPIO entirely.
+    HwmonPwm {
+        hwmon: PathBuf,
+        pwm: u32,
+        #[serde(default = "OutputConfig::default_pwm_on")]
+        on_value: u8,
+        #[serde(default)]
+        off_value: u8,
+    },
+    /// An ACPI fan's thermal cooling device, as found on some x86 systems.
+    /// `on_level` defaults to the device's `max_state`.
+    AcpiFan {
+        path: PathBuf,
+        on_level: Option<u32>,
+    },
+    /// A hardware PWM channel, `chip` being a `/sys/class/pwm/pwmchipN`
+    /// directory. Duty cycles are percentages.
+    Pwm {
+        chip: PathBuf,
+        channel: u32,
+        #[serde(default = "OutputConfig::default_pwm_frequency")]
+        frequency_hz: u32,
+        #[serde(default = "OutputConfig::default_pwm_on_duty")]
+        on_duty: f32,
+        #[serde(default)]
+        off_duty: f32,
+    },
+    /// A pin (0-15, 8-15 being port B) of an MCP23017 I2C GPIO expander
+    Mcp23017 {
+        bus: PathBuf,
+        #[serde(default = "OutputConfig::default_mcp23017_address")]
+        address: u16,
+        pin: u8,
+    },
+    /// An MCP4725 I2C DAC setting an analog fan's control voltage from the
+    /// duty, 100% writing `max_code` (of 0-4095)
+    Mcp4725 {
+        bus: PathBuf,
+        #[serde(default = "OutputConfig::default_mcp4725_address")]
+        address: u16,
+        #[serde(default = "OutputConfig::default_mcp4725_max_code")]
+        max_code: u16,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LineGroupConfig {
+    /// Chip label or device path
+    pub chip: String,
+    /// Ordered line offsets on `chip`
+    pub lines: Vec<u32>,
+    /// Values for each of `lines` when the fan is on
+    pub on: Vec<u8>,
+    /// Values for each of `lines` when the fan is off
+    pub off: Vec<u8>,
+    /// Bias applied to every line of the group
+    #[serde(default)]
+    pub bias: Bias,
+}
+
+/// Pull resistor requested on output lines. Needs Linux 5.5 or later for
+/// anything but `as-is`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bias {
+    /// Leave the line's bias as the board or device tree set it
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    Disable,
+}
+
+/// What a line output does when its chip has no lines at all, as some
+/// virtual or minimal chips don't
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyChip {
+    /// Fail to start, naming the chip
+    #[default]
+    Error,
+    /// Run without driving anything, logging a warning
+    DryRun,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VcgencmdConfig {
+    /// Path or name of the `vcgencmd` binary
+    pub command: PathBuf,
+}
+
+impl Default for VcgencmdConfig {
+    fn default() -> Self {
+        Self {
+            command: PathBuf::from("vcgencmd"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IpmiConfig {
+    /// Path or name of the `ipmitool` binary
+    pub command: PathBuf,
+    /// Arguments passed before `sdr`, e.g. `-H` and `-U` for a remote BMC
+    pub args: Vec<String>,
+    /// Exact names of the sensors to read, every temperature sensor when
+    /// empty
+    pub sensors: Vec<String>,
+}
+
+impl Default for IpmiConfig {
+    fn default() -> Self {
+        Self {
+            command: PathBuf::from("ipmitool"),
+            args: Vec::new(),
+            sensors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    /// `host:port` of the broker
+    pub address: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive_secs: u64,
+    /// Seconds a published value is used for before it counts as stale
+    pub max_age_secs: u64,
+    pub topics: Vec<MqttTopic>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            address: "localhost:1883".to_string(),
+            client_id: "gpio-fan".to_string(),
+            username: None,
+            password: None,
+            keep_alive_secs: 30,
+            max_age_secs: 60,
+            topics: Vec::new(),
+        }
+    }
+}
+
+/// A topic a remote sensor publishes its temperature to, in °C, either as
+/// the bare number or in a JSON object under `json_key`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MqttTopic {
+    pub topic: String,
+    /// Label the reading is given, the topic when not set
+    pub label: Option<String>,
+    pub json_key: Option<String>,
+}
+
+impl MqttTopic {
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.topic)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ThermalZonesConfig {
+    pub root: PathBuf,
+    /// Zone types to read, all zones when empty
+    pub types: Vec<String>,
+    /// Threads the zones are read with each sample, 1 to read them in turn
+    pub concurrency: usize,
+}
+
+impl Default for ThermalZonesConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("/sys/class/thermal"),
+            concurrency: 1,
+            types: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SampleLogConfig {
+    pub path: PathBuf,
+    /// Size the log may reach before it is rotated
+    #[serde(default = "SampleLogConfig::default_max_bytes")]
+    pub max_bytes: u64,
+}
+
+impl SampleLogConfig {
+    fn default_max_bytes() -> u64 {
+        1024 * 1024
+    }
+}
+
+/// How the sensors read by the fans are refreshed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SensorRefresh {
+    /// One source, refreshed once per tick and read by every fan
+    #[default]
+    Shared,
+    /// A source per fan, each refreshed as its fan samples
+    PerFan,
+}
+
+/// Behavior when the first temperature reads fail, e.g. because sensor
+/// drivers are still loading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StartupReadFailure {
+    /// Start controlling straight away, as if nothing was hot
+    #[default]
+    Proceed,
+    /// Wait for a reading, retrying with an increasing delay, before
+    /// controlling
+    Retry,
+    /// Run the fans until their first successful read
+    FanOn,
+}
+
+/// Once the fan has been switched on or off `max` times within the last
+/// `window_secs`, it is held by `action` until older switches drop out of
+/// the window. Critical temperatures still turn it on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SwitchLimitConfig {
+    pub max: u32,
+    #[serde(default = "SwitchLimitConfig::default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default)]
+    pub action: SwitchLimitAction,
+}
+
+impl SwitchLimitConfig {
+    fn default_window_secs() -> u64 {
+        60
+    }
+}
+
+/// How a fan that has hit its switch limit is held
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwitchLimitAction {
+    /// Keep the fan in the state it was last switched to
+    #[default]
+    Hold,
+    /// Run the fan, the safe state for a board flapping near its threshold
+    FanOn,
+}
+
+/// A trigger of automatic on/off control, see `trigger_order`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutoTrigger {
+    /// A temperature window above `max_fan_on_temp`
+    Temperature,
+    /// A zone above its own threshold
+    Zone,
+    /// A CPU usage window above `max_fan_on_cpu`
+    Cpu,
+    /// The CPU frequency above `cpu_freq_trigger`
+    CpuFrequency,
+    /// The temperature rising faster than `rise_rate_trigger`
+    RiseRate,
+}
+
+impl AutoTrigger {
+    pub const DEFAULT_ORDER: [AutoTrigger; 5] = [
+        AutoTrigger::Temperature,
+        AutoTrigger::Zone,
+        AutoTrigger::Cpu,
+        AutoTrigger::CpuFrequency,
+        AutoTrigger::RiseRate,
+    ];
+
+    /// Name the trigger is configured by
+    pub fn name(&self) -> &'static str {
+        match self {
+            AutoTrigger::Temperature => "temperature",
+            AutoTrigger::Zone => "zone",
+            AutoTrigger::Cpu => "cpu",
+            AutoTrigger::CpuFrequency => "cpu-frequency",
+            AutoTrigger::RiseRate => "rise-rate",
+        }
+    }
+
+    /// `order` followed by the triggers it leaves out, in their default
+    /// order
+    pub fn resolve(order: &[AutoTrigger]) -> Vec<AutoTrigger> {
+        let rest = Self::DEFAULT_ORDER.iter().filter(|t| !order.contains(t));
+        order.iter().chain(rest).copied().collect()
+    }
+}
+
+/// What to do while the sensors are stuck at `sensor_floor`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SensorFloorAction {
+    /// Run the fans until a real reading comes back
+    #[default]
+    FanOn,
+    /// Keep each fan as it was
+    Hold,
+}
+
+/// How the maximum temperature each sensor reports bounds the threshold.
+/// Maximums of 0°C or below, or infinite, are taken as the driver not knowing
+/// one and left out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SensorMax {
+    /// Use the configured threshold alone
+    #[default]
+    Ignore,
+    /// Lower the threshold to the lowest maximum reported since starting
+    Lowest,
+    /// Lower the threshold to the lowest maximum of the latest reading
+    Latest,
+}
+
+/// What to do once CPU usage has read 0% for `zero_usage_secs`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZeroUsageAction {
+    /// Log a warning, still counting the usage in decisions
+    #[default]
+    Warn,
+    /// Log a warning and leave the usage out of decisions until it reads
+    /// above 0% again
+    Disable,
+}
+
+/// A step in load: the shortest CPU usage window at least `delta` percentage
+/// points from the longest for `secs`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoadStepConfig {
+    pub delta: f32,
+    #[serde(default = "LoadStepConfig::default_secs")]
+    pub secs: u64,
+}
+
+impl LoadStepConfig {
+    fn default_secs() -> u64 {
+        5
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotificationsConfig {
+    /// Least time between notifications of fans turning on or off
+    #[serde(default = "NotificationsConfig::default_min_interval_secs")]
+    pub min_interval_secs: u64,
+    /// Notify fans turning on and off, not only critical temperatures and
+    /// failing fans
+    #[serde(default = "NotificationsConfig::default_fan_state")]
+    pub fan_state: bool,
+    /// `notify-send` compatible command showing each notification
+    #[serde(default = "NotificationsConfig::default_command")]
+    pub command: String,
+}
+
+impl NotificationsConfig {
+    fn default_min_interval_secs() -> u64 {
+        300
+    }
+
+    fn default_fan_state() -> bool {
+        true
+    }
+
+    fn default_command() -> String {
+        "notify-send".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    /// `host:port` the metrics are sent to over UDP
+    pub address: String,
+    #[serde(default)]
+    pub protocol: MetricsProtocol,
+    #[serde(default = "MetricsConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Measurement name for InfluxDB, or the metric prefix for StatsD
+    #[serde(default = "MetricsConfig::default_prefix")]
+    pub prefix: String,
+}
+
+impl MetricsConfig {
+    fn default_interval_secs() -> u64 {
+        10
+    }
+
+    fn default_prefix() -> String {
+        "gpio_fan".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsProtocol {
+    /// InfluxDB line protocol
+    #[default]
+    Influx,
+    StatsD,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusFifoConfig {
+    /// Created as a FIFO if it doesn't exist
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: StatusFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatusFormat {
+    /// The control socket's `status` reply
+    #[default]
+    Json,
+    /// A line of `key=value` pairs per fan
+    KeyValue,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            startup_delay_secs: 0,
+            logic_units: Units::default(),
+            display_units: Units::default(),
+            display_decimals: 1,
+            sample_interval_secs: 1,
+            decision_interval_secs: 1,
+            verbose_interval_secs: 0,
+            explain: false,
+            cpu_intervals: vec![3, 10, 60],
+            temp_intervals: vec![5, 30, 60],
+            cpu_window_roles: Vec::new(),
+            temp_window_roles: Vec::new(),
+            cpu_window_mode: WindowMode::default(),
+            temp_window_mode: WindowMode::default(),
+            ewma_time_constant_secs: None,
+            max_window_samples: measurement::DEFAULT_MAX_SAMPLES,
+            seed_windows: false,
+            prefilter_samples: None,
+            load_step: None,
+            temp_aggregation: Aggregation::default(),
+            cpu_aggregation: Aggregation::default(),
+            sample_jump_limit: None,
+            sample_jump_action: JumpAction::default(),
+            cpu_freq_secs: 10,
+            cpu_freq_root: PathBuf::from("/sys/devices/system/cpu"),
+            startup_read_failure: StartupReadFailure::default(),
+            sensor_refresh: SensorRefresh::default(),
+            sensor_timeout_ms: None,
+            sensor_intervals: Vec::new(),
+            startup_read_retries: 5,
+            stale_after_secs: None,
+            stale_grace_secs: None,
+            sensor_floor: None,
+            sensor_floor_secs: 30,
+            new_sensor_grace_secs: 0,
+            sensor_max: SensorMax::default(),
+            resume_gap_secs: 30,
+            sensor_floor_action: SensorFloorAction::default(),
+            zero_usage_secs: 600,
+            zero_usage_action: ZeroUsageAction::default(),
+            fan: FanConfig::default(),
+            fans: Vec::new(),
+            zones: Vec::new(),
+            sensor_include: Vec::new(),
+            sensor_exclude: Vec::new(),
+            sensor_preference: Vec::new(),
+            calibration: Vec::new(),
+            divergence: Vec::new(),
+            sensor_source: None,
+            thermal_zones: None,
+            vcgencmd: None,
+            ipmi: None,
+            mqtt: None,
+            injected_max_age_secs: 60,
+            journal: true,
+            sample_log: None,
+            audit_log: None,
+            notifications: None,
+            metrics: None,
+            status_fifo: None,
+            control_socket: None,
+            heartbeat_secs: None,
+            maintenance_file: None,
+            critical_all_fans: false,
+            fail_hot: false,
+            reload_check: false,
+            state_file: PathBuf::from("/var/lib/gpio-fan/state.toml"),
+            daily_stats: false,
+            profiles: Vec::new(),
+            profile: None,
+        }
+    }
+}
+
+impl Default for FanConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            decision_interval_secs: None,
+            max_fan_on_temp: 31f32,
+            max_fan_on_cpu: 10f32,
+            threshold_boundary: Boundary::default(),
+            tie_breaker: None,
+            usage_trigger_min_temp: None,
+            cpu_freq_trigger: None,
+            cooldown_target: None,
+            hysteresis_percent: None,
+            max_fan_on_critical_percent: None,
+            rise_rate_trigger: None,
+            runaway_rate: None,
+            cold_start: ColdStart::default(),
+            warmup_ticks: 0,
+            warmup_state: WarmupState::default(),
+            startup_purge: None,
+            alert_temp: None,
+            temp_delta_alert: None,
+            temp_delta_secs: None,
+            sensor_critical_force_on: true,
+            critical_temp: None,
+            panic_temp: None,
+            panic_command: "poweroff".to_string(),
+            on_ticks: 1,
+            off_confirm_secs: 0,
+            debounce_decisions: 0,
+            heat_soak: None,
+            trigger_order: Vec::new(),
+            switch_limit: None,
+            curve: Vec::new(),
+            full_speed_temp: None,
+            full_speed_hysteresis: 5f32,
+            curve_min_delta: None,
+            curve_max_step: None,
+            curve_sum: None,
+            blend: None,
+            idle: None,
+            low_usage_off: None,
+            off_below_temp: None,
+            schedule: Vec::new(),
+            anti_stall: None,
+            min_duty: None,
+            tach: None,
+            health_check: None,
+            spin_down: None,
+            kernel_cooling: None,
+            follow: None,
+            on_command: None,
+            off_command: None,
+            hook_timeout_secs: 10,
+            hook_failure: HookFailure::default(),
+            zones: Vec::new(),
+            temp_aggregation: None,
+            zone_priority: false,
+            output: OutputConfig::default(),
+            alarm_output: None,
+            brake_pulse: Vec::new(),
+            start_stagger_ms: 0,
+            rgb: None,
+        }
+    }
+}
+
+impl OutputConfig {
+    /// Whether the output can run the fan at a duty rather than only on/off
+    pub fn variable_speed(&self) -> bool {
+        matches!(
+            self,
+            OutputConfig::HwmonPwm { .. }
+                | OutputConfig::Pwm { .. }
+                | OutputConfig::AcpiFan { .. }
+                | OutputConfig::Mcp4725 { .. }
+        )
+    }
+
+    fn default_write_retries() -> u32 {
+        3
+    }
+
+    fn default_pwm_on() -> u8 {
+        u8::MAX
+    }
+
+    fn default_mcp23017_address() -> u16 {
+        0x20
+    }
+
+    fn default_mcp4725_address() -> u16 {
+        0x60
+    }
+
+    fn default_mcp4725_max_code() -> u16 {
+        crate::mcp4725::MAX_CODE
+    }
+
+    fn default_pwm_frequency() -> u32 {
+        25_000
+    }
+
+    fn default_pwm_on_duty() -> f32 {
+        100f32
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self::Line {
+            chip: "/dev/gpiochip0".to_string(),
+            line: 1,
+            bias: Bias::default(),
+            line_name: None,
+            write_retries: Self::default_write_retries(),
+            empty_chip: EmptyChip::default(),
+        }
+    }
+}
+
+/// Parse a config file's contents as JSON when it is named `.json` or
+/// reads as a JSON object, as TOML otherwise
+fn parse_table(path: &Path, contents: &str) -> Result<toml::Table, Error> {
+    let json =
+        path.extension().is_some_and(|e| e == "json") || contents.trim_start().starts_with('{');
+    if json {
+        serde_json::from_str(contents).map_err(|e| Error::Config(format!("invalid JSON: {}", e)))
+    } else {
+        toml::from_str(contents).map_err(|e| Error::Config(e.to_string()))
+    }
+}
+
+/// Read the TOML or JSON table at `path`, merging in the files listed under its
+/// `include` key, relative to it. `stack` holds the files being included so
+/// cycles can be reported.
+fn load_table(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Table, Error> {
+    let invalid = Error::Config;
+
+    let canonical = fs::canonicalize(path)?;
+    if stack.contains(&canonical) {
+        return Err(invalid("already being included, include cycle".to_string()));
+    }
+    let contents = fs::read_to_string(&canonical)?;
+    let mut table = parse_table(&canonical, &contents)?;
+
+    let includes = match table.remove("include") {
+        None => Vec::new(),
+        Some(toml::Value::Array(includes)) => includes
+            .into_iter()
+            .map(|i| match i {
+                toml::Value::String(include) => Ok(PathBuf::from(include)),
+                _ => Err(invalid("include must be a list of paths".to_string())),
+            })
+            .collect::<Result<_, Error>>()?,
+        Some(_) => return Err(invalid("include must be a list of paths".to_string())),
+    };
+
+    let dir = canonical
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    stack.push(canonical);
+    for include in includes {
+        let include = dir.join(include);
+        let included = load_table(&include, stack).map_err(|e| Error::Include {
+            path: include.clone(),
+            source: Box::new(e),
+        })?;
+        merge(&mut table, included);
+    }
+    stack.pop();
+
+    Ok(table)
+}
+
+/// Merge an included table into `table`: lists such as `fans` and `zones`
+/// are appended to, anything else already set by the including file is kept
+fn merge(table: &mut toml::Table, included: toml::Table) {
+    for (key, value) in included {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Array(existing)), toml::Value::Array(more)) => existing.extend(more),
+            (Some(toml::Value::Table(existing)), toml::Value::Table(more)) => merge(existing, more),
+            (Some(_), _) => {}
+            (None, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Set the field at `key`, its dotted path from the top level, to `value`,
+/// creating the tables along the way
+fn set_field(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), Error> {
+    let (parents, field) = match key.rsplit_once('.') {
+        Some((parents, field)) => (parents.split('.').collect(), field),
+        None => (Vec::new(), key),
+    };
+    let mut table = table;
+    for parent in parents {
+        let entry = table
+            .entry(parent)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        table = entry.as_table_mut().ok_or_else(|| {
+            Error::Config(format!("{} is not a table, can't set {}", parent, key))
+        })?;
+    }
+    table.insert(field.to_string(), value);
+
+    Ok(())
+}
+
+/// A TOML value as written after `key = `, taken as a plain string when it
+/// isn't one, so strings don't need quoting
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+impl Config {
+    /// Load and validate the config at `path`, along with any files it
+    /// `include`s
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::load_with(Some(path.as_ref()), &[])
+    }
+
+    /// Load and validate the config at `path`, or the defaults without one,
+    /// with fields overridden first by `GPIO_FAN_*` environment variables
+    /// and then by each `key=value` of `overrides`. Nested fields are
+    /// reached with `__` in variable names and `.` in keys, e.g.
+    /// `GPIO_FAN_THERMAL_ZONES__ROOT` and `thermal_zones.root`.
+    pub fn load_with(path: Option<&Path>, overrides: &[String]) -> Result<Self, Error> {
+        Self::load_from(path, std::env::vars(), overrides)
+    }
+
+    /// As `load_with`, with the environment given as `vars`
+    fn load_from<I>(path: Option<&Path>, vars: I, overrides: &[String]) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut table = match path {
+            Some(path) => load_table(path, &mut Vec::new())?,
+            None => toml::Table::new(),
+        };
+        let mut vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(ENV_PREFIX)?;
+                Some((key.to_lowercase().replace("__", "."), value))
+            })
+            .collect();
+        vars.sort();
+        for (key, value) in vars {
+            set_field(&mut table, &key, parse_value(&value))?;
+        }
+        for field in overrides {
+            let (key, value) = field.split_once('=').ok_or_else(|| {
+                Error::Config(format!("invalid override {}, expected key=value", field))
+            })?;
+            set_field(&mut table, key.trim(), parse_value(value.trim()))?;
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| Error::Config(e.to_string()))?;
+        config.validate().map_err(Error::Config)?;
+
+        Ok(config)
+    }
+
+    pub fn startup_delay(&self) -> Duration {
+        Duration::from_secs(self.startup_delay_secs)
+    }
+
+    pub fn sample_interval(&self) -> Duration {
+        Duration::from_secs(self.sample_interval_secs)
+    }
+
+    pub fn verbose_interval(&self) -> Duration {
+        Duration::from_secs(self.verbose_interval_secs)
+    }
+
+    /// Number of samples taken for each decision of `fan`
+    pub fn samples_per_decision(&self, fan: &FanConfig) -> u64 {
+        let interval = fan
+            .decision_interval_secs
+            .unwrap_or(self.decision_interval_secs);
+        (interval / self.sample_interval_secs.max(1)).max(1)
+    }
+
+    /// Fans to control, either those listed under `fans` or the single
+    /// top level fan
+    pub fn fans(&self) -> &[FanConfig] {
+        if self.fans.is_empty() {
+            std::slice::from_ref(&self.fan)
+        } else {
+            &self.fans
+        }
+    }
+
+    /// Type of the sensor source readings come from, `sensor_source` or
+    /// the one whose table is set
+    pub fn source_kind(&self) -> &str {
+        if let Some(kind) = &self.sensor_source {
+            return kind;
+        }
+        match (&self.thermal_zones, &self.vcgencmd, &self.ipmi, &self.mqtt) {
+            (Some(_), _, _, _) => "thermal-zones",
+            (None, Some(_), _, _) => "vcgencmd",
+            (None, None, Some(_), _) => "ipmi",
+            (None, None, None, Some(_)) => "mqtt",
+            (None, None, None, None) => "sysinfo",
+        }
+    }
+
+    /// Sensor label prefixes followed by `fan`, gathered from its zones
+    pub fn fan_sensors(&self, fan: &FanConfig) -> Vec<String> {
+        if fan.zones.is_empty() {
+            // Thermal zones are already filtered by type and vcgencmd reports
+            // a single temperature, follow all of them
+            let default = if self.source_kind() == "sysinfo" {
+                DEFAULT_SENSOR
+            } else {
+                ""
+            };
+            return vec![default.to_string()];
+        }
+
+        self.zones
+            .iter()
+            .filter(|z| fan.zones.contains(&z.name))
+            .flat_map(|z| z.sensors.iter().cloned())
+            .collect()
+    }
+
+    /// Filter selecting the components followed by `fan`
+    pub fn sensor_filter(&self, fan: &FanConfig) -> SensorFilter {
+        SensorFilter {
+            prefixes: self.fan_sensors(fan),
+            include: self.sensor_include.clone(),
+            exclude: self.sensor_exclude.clone(),
+            preference: self.sensor_preference.clone(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sample_interval_secs == 0 {
+            return Err("sample_interval_secs must be greater than 0".to_string());
+        }
+        if !self
+            .decision_interval_secs
+            .is_multiple_of(self.sample_interval_secs)
+        {
+            return Err(format!(
+                "decision_interval_secs ({}) must be a multiple of sample_interval_secs ({})",
+                self.decision_interval_secs, self.sample_interval_secs
+            ));
+        }
+        if self.cpu_intervals.contains(&0) || self.temp_intervals.contains(&0) {
+            return Err("window intervals must be greater than 0".to_string());
+        }
+        if self.cpu_window_roles.len() > self.cpu_intervals.len()
+            || self.temp_window_roles.len() > self.temp_intervals.len()
+        {
+            return Err("more window roles than windows".to_string());
+        }
+        if self.prefilter_samples.is_some_and(|samples| samples < 2) {
+            return Err("prefilter_samples must be at least 2".to_string());
+        }
+        if let Some(load_step) = &self.load_step {
+            if !(load_step.delta > 0f32 && load_step.delta <= 100f32) {
+                return Err("load_step delta must be between 0 and 100".to_string());
+            }
+            if load_step.secs == 0 {
+                return Err("load_step secs must be greater than 0".to_string());
+            }
+            if self.cpu_intervals.len() < 2 || self.temp_intervals.len() < 2 {
+                return Err(
+                    "load_step requires at least two CPU and two temperature windows".to_string(),
+                );
+            }
+        }
+        if self.max_window_samples == 0 {
+            return Err("max_window_samples must be greater than 0".to_string());
+        }
+        if self.ewma_time_constant_secs.is_some_and(|t| t <= 0f32) {
+            return Err("ewma_time_constant_secs must be greater than 0".to_string());
+        }
+        if self.sample_jump_limit.is_some_and(|l| l <= 0f32) {
+            return Err("sample_jump_limit must be greater than 0".to_string());
+        }
+        if self.cpu_freq_secs == 0 {
+            return Err("cpu_freq_secs must be greater than 0".to_string());
+        }
+        if self.startup_read_failure == StartupReadFailure::Retry && self.startup_read_retries == 0
+        {
+            return Err("startup_read_retries must be greater than 0".to_string());
+        }
+        if self
+            .thermal_zones
+            .as_ref()
+            .is_some_and(|z| z.concurrency == 0)
+        {
+            return Err("thermal_zones.concurrency must be greater than 0".to_string());
+        }
+        let sources = [
+            self.thermal_zones.is_some(),
+            self.vcgencmd.is_some(),
+            self.ipmi.is_some(),
+            self.mqtt.is_some(),
+        ];
+        if sources.iter().filter(|&&set| set).count() > 1 {
+            return Err(
+                "only one of thermal_zones, vcgencmd, ipmi and mqtt can be set".to_string(),
+            );
+        }
+        match self.sensor_source.as_deref() {
+            Some("mqtt") if self.mqtt.is_none() => {
+                return Err("sensor_source \"mqtt\" requires [mqtt]".to_string());
+            }
+            Some("injected") if self.control_socket.is_none() => {
+                return Err("sensor_source \"injected\" requires a control_socket".to_string());
+            }
+            Some("injected") if self.injected_max_age_secs == 0 => {
+                return Err("injected_max_age_secs must be greater than 0".to_string());
+            }
+            Some("") => return Err("sensor_source must not be empty".to_string()),
+            _ => {}
+        }
+        if let Some(mqtt) = &self.mqtt {
+            if mqtt.topics.is_empty() {
+                return Err("mqtt requires at least one topic".to_string());
+            }
+            if mqtt.keep_alive_secs < 2 || mqtt.keep_alive_secs > u16::MAX as u64 {
+                return Err("mqtt.keep_alive_secs must be between 2 and 65535".to_string());
+            }
+        }
+        if let Some(heartbeat) = self.heartbeat_secs {
+            if self.control_socket.is_none() {
+                return Err("heartbeat_secs requires a control_socket".to_string());
+            }
+            if heartbeat < self.sample_interval_secs {
+                return Err(format!(
+                    "heartbeat_secs ({}) must be at least sample_interval_secs ({})",
+                    heartbeat, self.sample_interval_secs
+                ));
+            }
+        }
+        if self.display_decimals > 6 {
+            return Err("display_decimals must be at most 6".to_string());
+        }
+        if let Some(timeout) = self.sensor_timeout_ms {
+            if timeout == 0 || timeout >= self.sample_interval_secs.saturating_mul(1000) {
+                return Err(format!(
+                    "sensor_timeout_ms ({}) must be greater than 0 and below sample_interval_secs ({})",
+                    timeout, self.sample_interval_secs
+                ));
+            }
+        }
+        for interval in &self.sensor_intervals {
+            if interval.sensors.is_empty() {
+                return Err("sensor_intervals entries need at least one sensor".to_string());
+            }
+            if interval.secs < self.sample_interval_secs {
+                return Err(format!(
+                    "sensor interval for {:?} ({}s) must be at least sample_interval_secs ({})",
+                    interval.sensors, interval.secs, self.sample_interval_secs
+                ));
+            }
+        }
+        if self.zero_usage_secs != 0 && self.zero_usage_secs < self.sample_interval_secs {
+            return Err(format!(
+                "zero_usage_secs ({}) must be at least sample_interval_secs ({})",
+                self.zero_usage_secs, self.sample_interval_secs
+            ));
+        }
+        if self.resume_gap_secs != 0 && self.resume_gap_secs <= self.sample_interval_secs {
+            return Err(format!(
+                "resume_gap_secs ({}) must be above sample_interval_secs ({})",
+                self.resume_gap_secs, self.sample_interval_secs
+            ));
+        }
+        if self.sensor_floor.is_some() && self.sensor_floor_secs < self.sample_interval_secs {
+            return Err(format!(
+                "sensor_floor_secs ({}) must be at least sample_interval_secs ({})",
+                self.sensor_floor_secs, self.sample_interval_secs
+            ));
+        }
+        if let Some(stale) = self.stale_after_secs {
+            if stale < self.sample_interval_secs {
+                return Err(format!(
+                    "stale_after_secs ({}) must be at least sample_interval_secs ({})",
+                    stale, self.sample_interval_secs
+                ));
+            }
+        }
+        match (self.stale_after_secs, self.stale_grace_secs) {
+            (None, Some(_)) => {
+                return Err("stale_grace_secs needs stale_after_secs".to_string());
+            }
+            (Some(stale), Some(grace)) if grace < stale => {
+                return Err(format!(
+                    "stale_grace_secs ({}) must be at least stale_after_secs ({})",
+                    grace, stale
+                ));
+            }
+            _ => {}
+        }
+        if self
+            .notifications
+            .as_ref()
+            .is_some_and(|n| n.command.trim().is_empty())
+        {
+            return Err("notifications.command must not be empty".to_string());
+        }
+        if let Some(metrics) = &self.metrics {
+            if metrics.interval_secs == 0 {
+                return Err("metrics.interval_secs must be greater than 0".to_string());
+            }
+        }
+        if let Some(sample_log) = &self.sample_log {
+            if sample_log.max_bytes == 0 {
+                return Err("sample_log.max_bytes must be greater than 0".to_string());
+            }
+        }
+        for check in &self.divergence {
+            if check.sensors.len() < 2 {
+                return Err("divergence requires at least two sensors".to_string());
+            }
+            if check.max_delta <= 0f32 {
+                return Err("divergence max_delta must be greater than 0".to_string());
+            }
+        }
+        if let Some(c) = self.calibration.iter().find(|c| c.scale <= 0f32) {
+            return Err(format!(
+                "calibration scale for {} must be greater than 0",
+                c.label
+            ));
+        }
+        if let Some(c) = self
+            .calibration
+            .iter()
+            .find(|c| matches!((c.min, c.max), (Some(min), Some(max)) if min >= max))
+        {
+            return Err(format!(
+                "calibration min for {} must be below its max",
+                c.label
+            ));
+        }
+        for zone in &self.zones {
+            if zone.sensors.is_empty() {
+                return Err(format!("zone {} has no sensors", zone.name));
+            }
+        }
+        let fans = self.fans();
+        for (i, fan) in fans.iter().enumerate() {
+            let Some(follow) = &fan.follow else {
+                continue;
+            };
+            let leader = fans
+                .iter()
+                .enumerate()
+                .find(|(j, f)| f.name(*j) == follow.fan);
+            match leader {
+                None => {
+                    return Err(format!(
+                        "{} follows unknown fan {}",
+                        fan.name(i),
+                        follow.fan
+                    ))
+                }
+                Some((j, _)) if j == i => {
+                    return Err(format!("{} can't follow itself", fan.name(i)))
+                }
+                Some((_, leader)) if leader.follow.is_some() => {
+                    return Err(format!(
+                        "{} follows {}, which is itself a follower",
+                        fan.name(i),
+                        follow.fan
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        for zone in &self.zones {
+            if !zone.curve.is_empty() {
+                curve::validate(&zone.curve).map_err(|e| format!("zone {}: {}", zone.name, e))?;
+            }
+        }
+        for fan in self.fans() {
+            for zone in &fan.zones {
+                if !self.zones.iter().any(|z| &z.name == zone) {
+                    return Err(format!("fan follows unknown zone {}", zone));
+                }
+            }
+            if let Some(tie_breaker) = &fan.tie_breaker {
+                if !self.zones.iter().any(|z| z.name == tie_breaker.zone) {
+                    return Err(format!("tie_breaker zone {} is unknown", tie_breaker.zone));
+                }
+                if tie_breaker.band <= 0f32 {
+                    return Err("tie_breaker band must be greater than 0".to_string());
+                }
+            }
+            let zone_curves = self
+                .zones
+                .iter()
+                .any(|z| fan.zones.contains(&z.name) && !z.curve.is_empty());
+            if fan.full_speed_temp.is_some() && fan.curve.is_empty() && !zone_curves {
+                return Err("full_speed_temp requires a curve".to_string());
+            }
+            if let Some(sum) = &fan.curve_sum {
+                if fan.curve.is_empty() && !zone_curves {
+                    return Err("curve_sum requires a curve".to_string());
+                }
+                for zone in sum.zone_weights.keys() {
+                    if !fan.zones.contains(zone) {
+                        return Err(format!(
+                            "curve_sum weights zone {} the fan doesn't follow",
+                            zone
+                        ));
+                    }
+                }
+            }
+            if fan.blend.is_some() && zone_curves {
+                return Err("blend can't be used with zone curves".to_string());
+            }
+            if let Some(interval) = fan.decision_interval_secs {
+                if interval == 0 || !interval.is_multiple_of(self.sample_interval_secs) {
+                    return Err(format!(
+                        "fan decision_interval_secs ({}) must be a multiple of sample_interval_secs ({})",
+                        interval, self.sample_interval_secs
+                    ));
+                }
+            }
+            fan.validate()?;
+        }
+        if let Some(profile) = &self.profile {
+            if !self.profiles.iter().any(|p| &p.name == profile) {
+                return Err(format!("profile {} is unknown", profile));
+            }
+        }
+        for profile in &self.profiles {
+            if profile.name == NO_PROFILE {
+                return Err(format!("{} can't be used as a profile name", NO_PROFILE));
+            }
+            let mut profiled = self.with_profile(Some(&profile.name))?;
+            profiled.profiles.clear();
+            profiled.profile = None;
+            profiled
+                .validate()
+                .map_err(|e| format!("profile {}: {}", profile.name, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// This config with the named profile's settings applied to every fan,
+    /// or unchanged for `None`
+    pub fn with_profile(&self, name: Option<&str>) -> Result<Config, String> {
+        let mut config = self.clone();
+        config.profile = name.map(str::to_string);
+        let Some(name) = name else {
+            return Ok(config);
+        };
+        let profile = self
+            .profiles
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("profile {} is unknown", name))?;
+        config.fan = profile.apply(&self.fan)?;
+        config.fans = self
+            .fans
+            .iter()
+            .map(|fan| profile.apply(fan))
+            .collect::<Result<_, _>>()?;
+
+        Ok(config)
+    }
+}
+
+impl FanConfig {
+    /// Configured name, or `fan<index>` for an unnamed fan
+    pub fn name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("fan{}", index))
+    }
+
+    /// Temperature the fan runs down to once on, from either
+    /// `cooldown_target` or `hysteresis_percent`
+    pub fn off_threshold(&self) -> Option<f32> {
+        self.cooldown_target.or_else(|| {
+            self.hysteresis_percent
+                .map(|pct| self.max_fan_on_temp * (1f32 - pct / 100f32))
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.zone_priority && self.zones.len() < 2 {
+            return Err("zone_priority requires at least two zones".to_string());
+        }
+        if self.cooldown_target.is_some() && self.hysteresis_percent.is_some() {
+            return Err("cooldown_target and hysteresis_percent can't both be set".to_string());
+        }
+        if let Some(target) = self.off_threshold() {
+            if target <= 0f32 || target >= self.max_fan_on_temp {
+                return Err(format!(
+                    "cooldown target ({}) must be above 0 and below max_fan_on_temp ({})",
+                    target, self.max_fan_on_temp
+                ));
+            }
+        }
+        if self
+            .max_fan_on_critical_percent
+            .is_some_and(|pct| !(pct > 0f32 && pct <= 100f32))
+        {
+            return Err("max_fan_on_critical_percent must be above 0 and at most 100".to_string());
+        }
+        if self.rise_rate_trigger.is_some_and(|r| r <= 0f32) {
+            return Err("rise_rate_trigger must be greater than 0".to_string());
+        }
+        if self.runaway_rate.is_some_and(|r| r <= 0f32) {
+            return Err("runaway_rate must be greater than 0".to_string());
+        }
+        if let Some(floor) = self.usage_trigger_min_temp {
+            if floor >= self.max_fan_on_temp {
+                return Err(format!(
+                    "usage_trigger_min_temp ({}) must be below max_fan_on_temp ({})",
+                    floor, self.max_fan_on_temp
+                ));
+            }
+        }
+        if self.cpu_freq_trigger.is_some_and(|f| f <= 0f32) {
+            return Err("cpu_freq_trigger must be greater than 0".to_string());
+        }
+        if self.temp_delta_alert.is_some_and(|d| d <= 0f32) {
+            return Err("temp_delta_alert must be greater than 0".to_string());
+        }
+        if self.temp_delta_secs == Some(0) {
+            return Err("temp_delta_secs must be greater than 0".to_string());
+        }
+        if !self.brake_pulse.is_empty() {
+            if self.output.variable_speed() {
+                return Err("brake_pulse needs an output that switches the fan".to_string());
+            }
+            if self.brake_pulse.iter().any(|step| step.ms == 0) {
+                return Err("brake_pulse steps must last more than 0ms".to_string());
+            }
+            let total: u64 = self.brake_pulse.iter().map(|step| step.ms).sum();
+            if total > MAX_BRAKE_PULSE_MS {
+                return Err(format!(
+                    "brake_pulse lasts {}ms, at most {}ms is allowed",
+                    total, MAX_BRAKE_PULSE_MS
+                ));
+            }
+        }
+        if let Some(rgb) = &self.rgb {
+            if !cfg!(feature = "rgb") {
+                return Err("rgb needs gpio-fan built with the rgb feature".to_string());
+            }
+            if rgb.leds == 0 {
+                return Err("rgb leds must be greater than 0".to_string());
+            }
+            if rgb.cold_temp >= rgb.hot_temp {
+                return Err(format!(
+                    "rgb cold_temp ({}) must be below hot_temp ({})",
+                    rgb.cold_temp, rgb.hot_temp
+                ));
+            }
+            if !(0f32..=1f32).contains(&rgb.brightness) {
+                return Err("rgb brightness must be between 0 and 1".to_string());
+            }
+        }
+        if let Some(heat_soak) = &self.heat_soak {
+            if heat_soak.secs == 0 {
+                return Err("heat_soak secs must be greater than 0".to_string());
+            }
+            if heat_soak.margin <= 0f32 {
+                return Err("heat_soak margin must be greater than 0".to_string());
+            }
+        }
+        if let Some(idle) = &self.idle {
+            if idle.max_temp >= self.max_fan_on_temp {
+                return Err(format!(
+                    "idle max_temp ({}) must be below max_fan_on_temp ({})",
+                    idle.max_temp, self.max_fan_on_temp
+                ));
+            }
+        }
+        if let Some(low_usage) = &self.low_usage_off {
+            if !(0f32..=100f32).contains(&low_usage.max_cpu) || low_usage.max_cpu == 0f32 {
+                return Err("low_usage_off max_cpu must be above 0 and at most 100".to_string());
+            }
+            if low_usage.secs == 0 {
+                return Err("low_usage_off secs must be greater than 0".to_string());
+            }
+            if low_usage.max_temp.is_some_and(|t| t > self.max_fan_on_temp) {
+                return Err(format!(
+                    "low_usage_off max_temp must be at most max_fan_on_temp ({})",
+                    self.max_fan_on_temp
+                ));
+            }
+        }
+        for entry in &self.schedule {
+            if entry.from == entry.to {
+                return Err(format!(
+                    "schedule from {} to {} is empty",
+                    String::from(entry.from),
+                    String::from(entry.to)
+                ));
+            }
+            if !(0f32..=100f32).contains(&entry.duty) {
+                return Err(format!(
+                    "schedule duty {} must be between 0 and 100",
+                    entry.duty
+                ));
+            }
+            if !(0f32..=100f32).contains(&entry.precool_duty) {
+                return Err(format!(
+                    "schedule precool_duty {} must be between 0 and 100",
+                    entry.precool_duty
+                ));
+            }
+            if entry.precool_mins >= MINUTES_PER_DAY {
+                return Err("schedule precool_mins must be under a day".to_string());
+            }
+        }
+        if let Some(anti_stall) = &self.anti_stall {
+            if !(0f32..=100f32).contains(&anti_stall.below)
+                || !(0f32..=100f32).contains(&anti_stall.duty)
+            {
+                return Err("anti_stall below and duty must be between 0 and 100".to_string());
+            }
+            if anti_stall.for_secs == 0 || anti_stall.for_secs >= anti_stall.every_secs {
+                return Err(format!(
+                    "anti_stall for_secs ({}) must be greater than 0 and below every_secs ({})",
+                    anti_stall.for_secs, anti_stall.every_secs
+                ));
+            }
+        }
+        if self.min_duty.is_some_and(|d| !(0f32..=100f32).contains(&d)) {
+            return Err("min_duty must be between 0 and 100".to_string());
+        }
+        if let Some(pair) = self
+            .trigger_order
+            .iter()
+            .enumerate()
+            .find(|(i, t)| self.trigger_order[..*i].contains(t))
+        {
+            return Err(format!(
+                "trigger_order lists {} more than once",
+                pair.1.name()
+            ));
+        }
+        if let Some(limit) = &self.switch_limit {
+            if limit.max == 0 || limit.window_secs == 0 {
+                return Err("switch_limit max and window_secs must be greater than 0".to_string());
+            }
+        }
+        if let Some(purge) = &self.startup_purge {
+            if purge.secs == 0 {
+                return Err("startup_purge secs must be greater than 0".to_string());
+            }
+            if !(0f32..=100f32).contains(&purge.duty) {
+                return Err("startup_purge duty must be between 0 and 100".to_string());
+            }
+        }
+        if let Some(check) = &self.health_check {
+            if self.tach.is_none() {
+                return Err("health_check requires a tach".to_string());
+            }
+            if check.run_secs == 0 || check.run_secs >= check.every_secs {
+                return Err(format!(
+                    "health_check run_secs ({}) must be greater than 0 and below every_secs ({})",
+                    check.run_secs, check.every_secs
+                ));
+            }
+            if !(0f32..=100f32).contains(&check.duty) {
+                return Err("health_check duty must be between 0 and 100".to_string());
+            }
+            if check.max_rpm.is_some_and(|max| max < check.min_rpm) {
+                return Err("health_check max_rpm must be at least min_rpm".to_string());
+            }
+        }
+        if let Some(spin_down) = &self.spin_down {
+            if self.tach.is_none() {
+                return Err("spin_down requires a tach".to_string());
+            }
+            if spin_down.secs == 0 {
+                return Err("spin_down secs must be greater than 0".to_string());
+            }
+        }
+        if let Some(alert) = self.alert_temp {
+            if alert >= self.max_fan_on_temp {
+                return Err(format!(
+                    "alert_temp ({}) must be below max_fan_on_temp ({})",
+                    alert, self.max_fan_on_temp
+                ));
+            }
+        }
+        if let Some(floor) = self.off_below_temp {
+            if floor >= self.max_fan_on_temp {
+                return Err(format!(
+                    "off_below_temp ({}) must be below max_fan_on_temp ({})",
+                    floor, self.max_fan_on_temp
+                ));
+            }
+        }
+        if let Some(panic) = self.panic_temp {
+            if panic <= self.max_fan_on_temp {
+                return Err(format!(
+                    "panic_temp ({}) must be above max_fan_on_temp ({})",
+                    panic, self.max_fan_on_temp
+                ));
+            }
+            if self.panic_command.trim().is_empty() {
+                return Err("panic_command must not be empty".to_string());
+            }
+        }
+        if self.hook_timeout_secs == 0 {
+            return Err("hook_timeout_secs must be greater than 0".to_string());
+        }
+        if self.on_ticks == 0 {
+            return Err("on_ticks must be greater than 0".to_string());
+        }
+        if !self.curve.is_empty() {
+            curve::validate(&self.curve)?;
+        }
+        if self.full_speed_hysteresis < 0f32 {
+            return Err("full_speed_hysteresis must not be negative".to_string());
+        }
+        if self.curve_min_delta.is_some_and(|d| d < 0f32) {
+            return Err("curve_min_delta must not be negative".to_string());
+        }
+        if self
+            .curve_max_step
+            .is_some_and(|s| !s.is_finite() || s <= 0f32)
+        {
+            return Err("curve_max_step must be greater than 0".to_string());
+        }
+        if let Some(sum) = &self.curve_sum {
+            let weights = std::iter::once(&sum.weight).chain(sum.zone_weights.values());
+            if weights.into_iter().any(|w| !w.is_finite() || *w < 0f32) {
+                return Err("curve_sum weights must not be negative".to_string());
+            }
+            if sum.cap <= 0f32 || sum.cap > 100f32 {
+                return Err("curve_sum cap must be above 0 and at most 100".to_string());
+            }
+        }
+        if let Some(blend) = &self.blend {
+            if !self.curve.is_empty() {
+                return Err("blend and curve can't be used together".to_string());
+            }
+            if blend.temp_weight < 0f32
+                || blend.cpu_weight < 0f32
+                || blend.temp_weight + blend.cpu_weight <= 0f32
+            {
+                return Err("blend weights must not be negative or both 0".to_string());
+            }
+            if blend.idle_temp >= self.max_fan_on_temp {
+                return Err(format!(
+                    "blend idle_temp ({}) must be below max_fan_on_temp ({})",
+                    blend.idle_temp, self.max_fan_on_temp
+                ));
+            }
+            if !(0f32..=1f32).contains(&blend.threshold) {
+                return Err("blend threshold must be between 0 and 1".to_string());
+            }
+        }
+        if let OutputConfig::Pwm {
+            frequency_hz,
+            on_duty,
+            off_duty,
+            ..
+        } = &self.output
+        {
+            if *frequency_hz == 0 {
+                return Err("pwm frequency_hz must be greater than 0".to_string());
+            }
+            if !(0f32..=100f32).contains(on_duty) || !(0f32..=100f32).contains(off_duty) {
+                return Err("pwm duty cycles must be between 0 and 100".to_string());
+            }
+        }
+        if let OutputConfig::Mcp23017 { pin, .. } = &self.output {
+            if *pin > 15 {
+                return Err(format!("mcp23017 pin {} must be between 0 and 15", pin));
+            }
+        }
+        if let OutputConfig::Mcp4725 { max_code, .. } = &self.output {
+            if *max_code > crate::mcp4725::MAX_CODE {
+                return Err(format!(
+                    "mcp4725 max_code {} must be at most {}",
+                    max_code,
+                    crate::mcp4725::MAX_CODE
+                ));
+            }
+        }
+        if let OutputConfig::MultiLine { groups, .. } = &self.output {
+            if groups.is_empty() {
+                return Err("multi-line output requires at least one group".to_string());
+            }
+            for group in groups {
+                if group.lines.is_empty() {
+                    return Err(format!("multi-line group on {} has no lines", group.chip));
+                }
+                if group.on.len() != group.lines.len() || group.off.len() != group.lines.len() {
+                    return Err(format!(
+                        "multi-line group on {} has {} lines but {} on and {} off values",
+                        group.chip,
+                        group.lines.len(),
+                        group.on.len(),
+                        group.off.len()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn zones_give_each_fan_its_own_sensors() {
+        let config: Config = toml::from_str(
+            r#"
+            [[zones]]
+            name = "cpu"
+            sensors = ["coretemp"]
+
+            [[zones]]
+            name = "board"
+            sensors = ["acpitz", "nvme"]
+
+            [[fans]]
+            name = "cpu"
+            zones = ["cpu"]
+            max_fan_on_temp = 60.0
+
+            [[fans]]
+            name = "chassis"
+            zones = ["board"]
+            max_fan_on_temp = 45.0
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let fans = config.fans();
+        assert_eq!(fans.len(), 2);
+        assert_eq!(config.fan_sensors(&fans[0]), ["coretemp"]);
+        assert_eq!(config.fan_sensors(&fans[1]), ["acpitz", "nvme"]);
+        assert_eq!(fans[1].max_fan_on_temp, 45.0);
+
+        // The top level fan, without zones, follows the CPU
+        let single: Config = toml::from_str("max_fan_on_temp = 50.0").unwrap();
+        assert_eq!(single.fans().len(), 1);
+        assert_eq!(single.fan_sensors(&single.fans()[0]), [DEFAULT_SENSOR]);
+    }
+
+    #[test]
+    fn fan_following_an_unknown_zone_is_rejected() {
+        let config: Config =
+            toml::from_str("[[fans]]\nzones = [\"gpu\"]\nmax_fan_on_temp = 50.0").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn included_fans_are_merged() {
+        let dir = testing::temp_dir("include");
+        fs::write(
+            dir.join("gpio-fan.toml"),
+            "include = [\"case.toml\"]\nmax_fan_on_temp = 55.0\n\n[[fans]]\nname = \"cpu\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("case.toml"),
+            "max_fan_on_temp = 70.0\n\n[[fans]]\nname = \"case\"\nmax_fan_on_temp = 45.0\n",
+        )
+        .unwrap();
+
+        let config = Config::load(dir.join("gpio-fan.toml")).unwrap();
+        let fans = config.fans();
+        let names: Vec<String> = fans.iter().enumerate().map(|(i, f)| f.name(i)).collect();
+        assert_eq!(names, ["cpu", "case"]);
+        assert_eq!(fans[1].max_fan_on_temp, 45.0);
+        // The including file's own settings win
+        assert_eq!(config.fan.max_fan_on_temp, 55.0);
+    }
+
+    #[test]
+    fn json_and_toml_configs_load_the_same() {
+        let dir = testing::temp_dir("json");
+        fs::write(
+            dir.join("gpio-fan.toml"),
+            r#"
+            max_fan_on_temp = 55.0
+            temp_intervals = [2, 10]
+
+            [thermal_zones]
+            root = "/srv/thermal"
+
+            [[fans]]
+            name = "cpu"
+            curve = [{ temp = 40.0, duty = 20.0 }, { temp = 70.0, duty = 100.0 }]
+            output = { type = "hwmon-pwm", hwmon = "/srv/hwmon0", pwm = 2 }
+            "#,
+        )
+        .unwrap();
+        let json = r#"{
+            "max_fan_on_temp": 55.0,
+            "temp_intervals": [2, 10],
+            "thermal_zones": { "root": "/srv/thermal" },
+            "fans": [{
+                "name": "cpu",
+                "curve": [{ "temp": 40.0, "duty": 20.0 }, { "temp": 70.0, "duty": 100.0 }],
+                "output": { "type": "hwmon-pwm", "hwmon": "/srv/hwmon0", "pwm": 2 }
+            }]
+        }"#;
+        fs::write(dir.join("gpio-fan.json"), json).unwrap();
+        // Told apart by its contents without the extension
+        fs::write(dir.join("gpio-fan.conf"), json).unwrap();
+        let load = |name: &str| {
+            let config = Config::load_from(Some(&dir.join(name)), [], &[]).unwrap();
+            format!("{:?}", config)
+        };
+
+        let toml = load("gpio-fan.toml");
+        assert_eq!(load("gpio-fan.json"), toml);
+        assert_eq!(load("gpio-fan.conf"), toml);
+        assert_ne!(format!("{:?}", Config::default()), toml);
+    }
+
+    #[test]
+    fn environment_overrides_the_file_and_set_overrides_both() {
+        let path = testing::temp_dir("env-overrides").join("gpio-fan.toml");
+        fs::write(&path, "max_fan_on_temp = 50.0\nmax_fan_on_cpu = 20.0\n").unwrap();
+        let vars = || {
+            [
+                ("GPIO_FAN_MAX_FAN_ON_TEMP", "55"),
+                ("GPIO_FAN_THERMAL_ZONES__ROOT", "/srv/thermal"),
+                ("MAX_FAN_ON_CPU", "90"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        };
+
+        let config = Config::load_from(Some(&path), vars(), &[]).unwrap();
+        assert_eq!(config.fan.max_fan_on_temp, 55.0);
+        // Only prefixed variables count
+        assert_eq!(config.fan.max_fan_on_cpu, 20.0);
+        let zones = config.thermal_zones.as_ref().unwrap();
+        assert_eq!(zones.root, Path::new("/srv/thermal"));
+
+        let set = ["max_fan_on_temp=60".to_string()];
+        let config = Config::load_from(Some(&path), vars(), &set).unwrap();
+        assert_eq!(config.fan.max_fan_on_temp, 60.0);
+    }
+
+    #[test]
+    fn off_threshold_from_either_style() {
+        let target = testing::config("max_fan_on_temp = 60.0\ncooldown_target = 54.0");
+        let percent = testing::config("max_fan_on_temp = 60.0\nhysteresis_percent = 10.0");
+        assert_eq!(target.fan.off_threshold(), Some(54.0));
+        assert_eq!(percent.fan.off_threshold(), Some(54.0));
+        assert_eq!(testing::config("").fan.off_threshold(), None);
+
+        let both: Config =
+            toml::from_str("cooldown_target = 54.0\nhysteresis_percent = 10.0").unwrap();
+        assert!(both.validate().is_err());
+    }
+
+    #[test]
+    fn curve_max_step_must_be_a_positive_number() {
+        for step in ["nan", "inf", "0.0", "-5.0"] {
+            let config: Config = toml::from_str(&format!("curve_max_step = {}", step)).unwrap();
+            assert!(config.validate().is_err(), "{}", step);
+        }
+        let config: Config = toml::from_str("curve_max_step = 5.0").unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
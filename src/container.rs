@@ -0,0 +1,52 @@
+use crate::config::Config;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Markers container runtimes leave in `/proc/1/cgroup`
+const CGROUP_MARKERS: [(&str, &str); 4] = [
+    ("docker", "docker"),
+    ("kubepods", "kubernetes"),
+    ("lxc", "lxc"),
+    ("containerd", "containerd"),
+];
+
+/// The container runtime gpio-fan appears to be running under, if any
+pub fn detect() -> Option<String> {
+    // Set by podman, systemd-nspawn and others
+    if let Some(container) = env::var_os("container").filter(|c| !c.is_empty()) {
+        return Some(container.to_string_lossy().into_owned());
+    }
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker".to_string());
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman".to_string());
+    }
+    let cgroup = fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    CGROUP_MARKERS
+        .iter()
+        .find(|(marker, _)| cgroup.contains(marker))
+        .map(|(_, runtime)| runtime.to_string())
+}
+
+/// What to check when running in `runtime` with `config`, one line each
+pub fn guidance(runtime: &str, config: &Config) -> Vec<String> {
+    let mut lines = vec![format!("Running in a {} container", runtime)];
+    match config.source_kind() {
+        "injected" | "mqtt" => {}
+        "sysinfo" | "thermal-zones" => lines.push(
+            "Sensors read here may be the host's or missing; mount /sys read-only, or set \
+             sensor_source = \"injected\" or configure [mqtt] to take readings from outside"
+                .to_string(),
+        ),
+        kind => lines.push(format!(
+            "sensor_source {} must be reachable from inside the container",
+            kind
+        )),
+    }
+    lines.push(
+        "GPIO outputs need their /dev/gpiochipN passed through, e.g. with --device".to_string(),
+    );
+    lines
+}
@@ -0,0 +1,446 @@
+use crate::audit::Change;
+use crate::config::NO_PROFILE;
+use crate::export;
+use crate::fan_control::{FanControl, ManualSource};
+use crate::injected;
+use crate::status::FanStatus;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a connected client has to send its command
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Interface changes made through the socket are recorded as coming from,
+/// along with the client's user
+const INTERFACE: &str = "control socket";
+
+/// Unix socket answering one line commands from other processes, polled
+/// between updates so it never blocks the control loop.
+///
+/// Commands:
+/// - `status`: JSON array with each fan's state and window averages
+/// - `history`: as `status`, also including every window's raw samples
+/// - `disable <fan>`: drive the named fan off and stop controlling it
+/// - `enable <fan>`: resume controlling a disabled fan
+/// - `heartbeat`: a supervisor checking in, see `heartbeat_secs`
+/// - `export <path>`: write every window's samples to `path` as CSV. The
+///   file must not already exist, so a client can't have the daemon
+///   overwrite or truncate anything it can write to, and only root or the
+///   daemon's own user may ask
+/// - `set <fan> <on|off|auto|duty> [<label>]`: run the named fan on, off
+///   or at a duty in percent whatever automatic control would do, `auto` to
+///   hand it back. It is recorded as set on the socket by the client's
+///   user, along with `label` if the client names itself, and the latest
+///   setting wins whoever sent it
+/// - `profile <name>`: switch every fan to the named profile, `none` for
+///   the fans' own settings
+/// - `maintenance <on|off>`: run every fan at full speed until turned off
+/// - `reload <thresholds|curve|sensors>`: apply just that part of the
+///   config file, keeping the windows and everything else as they are
+/// - `inject <label> <temperature>`: record a reading of the sensor
+///   `label`, for `sensor_source = "injected"`
+/// - `inject-cpu <usage>`: record the CPU usage in percent, likewise
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl ControlSocket {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        // Clear out a socket left behind by a previous run
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self { path, listener })
+    }
+
+    /// Answer every client currently waiting to be accepted, returning what
+    /// they asked of the controller. `profiles` are the names that can be
+    /// switched to.
+    pub fn poll(&self, fans: &mut [FanControl], profiles: &[String]) -> Requests {
+        let mut requests = Requests::default();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = handle(stream, fans, profiles, &mut requests) {
+                        eprintln!("Control socket client error: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("Control socket error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        requests
+    }
+}
+
+/// What clients asked of the controller itself in one poll
+#[derive(Debug, Default)]
+pub struct Requests {
+    pub heartbeat: bool,
+    /// Profile to switch to, the last one asked for if several were
+    pub profile: Option<String>,
+    /// Whether maintenance should be under way, the last asked for if
+    /// several were
+    pub maintenance: Option<bool>,
+    /// Parts of the config to reload, in the order asked for
+    pub reload: Vec<ReloadSection>,
+    /// Fans set or enabled by hand, for the audit log
+    pub audit: Vec<Change>,
+}
+
+/// Part of the config that can be reloaded on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadSection {
+    /// The temperature, CPU usage and other thresholds the fan is decided by
+    Thresholds,
+    /// The fan curve and the settings shaping how it is followed
+    Curve,
+    /// Which sensors each fan follows, how they are combined and calibrated
+    Sensors,
+}
+
+impl ReloadSection {
+    fn parse(section: &str) -> Option<Self> {
+        match section {
+            "thresholds" => Some(Self::Thresholds),
+            "curve" => Some(Self::Curve),
+            "sensors" => Some(Self::Sensors),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ReloadSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Thresholds => write!(f, "thresholds"),
+            Self::Curve => write!(f, "curve"),
+            Self::Sensors => write!(f, "sensors"),
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Answer one client, adding anything it asked of the controller to
+/// `requests`
+fn handle(
+    stream: UnixStream,
+    fans: &mut [FanControl],
+    profiles: &[String],
+    requests: &mut Requests,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+
+    // Known from the socket itself, unlike anything the client sends
+    let interface = match peer_uid(&stream) {
+        Ok(uid) => format!("{} uid {}", INTERFACE, uid),
+        Err(_) => INTERFACE.to_string(),
+    };
+    let mut words = command.split_whitespace();
+    let response = match (words.next().unwrap_or_default(), words.next()) {
+        ("status", None) => status(fans, false),
+        ("history", None) => status(fans, true),
+        ("export", Some(path)) => match peer_uid(&stream) {
+            Ok(uid) if may_export(uid) => export(fans, Path::new(path)),
+            Ok(uid) => format!("export not permitted for uid {}", uid),
+            Err(e) => format!("unable to identify client: {}", e),
+        },
+        ("enable", Some(name)) => set_enabled(fans, name, true, &interface, requests),
+        ("disable", Some(name)) => set_enabled(fans, name, false, &interface, requests),
+        ("set", Some(name)) => {
+            let setting = words.next().unwrap_or_default();
+            let label = words.next().map(str::to_string);
+            let source = ManualSource::new(interface, label);
+            set_manual(fans, name, setting, source, requests)
+        }
+        ("heartbeat", None) => {
+            requests.heartbeat = true;
+            "ok".to_string()
+        }
+        ("profile", Some(name)) if name == NO_PROFILE || profiles.iter().any(|p| p == name) => {
+            requests.profile = Some(name.to_string());
+            "ok".to_string()
+        }
+        ("profile", Some(name)) => format!("unknown profile: {}", name),
+        ("maintenance", Some(setting @ ("on" | "off"))) => {
+            requests.maintenance = Some(setting == "on");
+            "ok".to_string()
+        }
+        ("maintenance", Some(setting)) => format!("invalid setting: {}", setting),
+        ("reload", Some(section)) => match ReloadSection::parse(section) {
+            Some(section) => {
+                requests.reload.push(section);
+                "ok".to_string()
+            }
+            None => format!("unknown section: {}", section),
+        },
+        ("inject", Some(label)) => match words.next().and_then(|t| t.parse::<f32>().ok()) {
+            Some(temperature) if temperature.is_finite() => {
+                injected::temperature(label, temperature);
+                "ok".to_string()
+            }
+            _ => "invalid temperature".to_string(),
+        },
+        ("inject-cpu", Some(usage)) => match usage.parse::<f32>() {
+            Ok(usage) if (0f32..=100f32).contains(&usage) => {
+                injected::cpu_usage(usage);
+                "ok".to_string()
+            }
+            _ => format!("invalid usage: {}", usage),
+        },
+        _ => format!("unknown command: {}", command.trim()),
+    };
+
+    let mut stream = stream;
+    writeln!(stream, "{}", response)?;
+
+    Ok(())
+}
+
+fn status(fans: &[FanControl], history: bool) -> String {
+    let status: Vec<FanStatus> = fans.iter().map(|f| FanStatus::new(f, history)).collect();
+    serde_json::to_string(&status).unwrap_or_else(|e| format!("unable to encode status: {}", e))
+}
+
+/// User id of the process at the other end of `stream`
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred.uid)
+}
+
+/// Whether a client running as `uid` may have files written for it. Only
+/// root and the daemon's own user can, as the file is created with the
+/// daemon's permissions.
+fn may_export(uid: libc::uid_t) -> bool {
+    uid == 0 || uid == unsafe { libc::geteuid() }
+}
+
+fn export(fans: &[FanControl], path: &Path) -> String {
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            export::write_csv(fans, SystemTime::now(), &mut out)?;
+            out.flush()
+        });
+    match written {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("unable to write {}: {}", path.display(), e),
+    }
+}
+
+fn set_manual(
+    fans: &mut [FanControl],
+    name: &str,
+    value: &str,
+    source: ManualSource,
+    requests: &mut Requests,
+) -> String {
+    let duty = match value {
+        "on" => Some(100f32),
+        "off" => Some(0f32),
+        "auto" => None,
+        _ => match value.parse::<f32>() {
+            Ok(duty) if (0f32..=100f32).contains(&duty) => Some(duty),
+            _ => return format!("invalid setting: {}", value),
+        },
+    };
+    match fans.iter_mut().find(|f| f.name() == name) {
+        Some(fan) => {
+            let setting = |duty: Option<f32>| match duty {
+                Some(duty) => duty.to_string(),
+                None => "auto".to_string(),
+            };
+            requests.audit.push(
+                Change::new(
+                    &source.interface,
+                    format!("set {}", name),
+                    Some(setting(fan.manual())),
+                    Some(setting(duty)),
+                )
+                .labelled(source.label.clone()),
+            );
+            fan.set_manual(duty, source);
+            "ok".to_string()
+        }
+        None => format!("unknown fan: {}", name),
+    }
+}
+
+fn set_enabled(
+    fans: &mut [FanControl],
+    name: &str,
+    enabled: bool,
+    interface: &str,
+    requests: &mut Requests,
+) -> String {
+    match fans.iter_mut().find(|f| f.name() == name) {
+        Some(fan) => {
+            let setting = |enabled: bool| if enabled { "enabled" } else { "disabled" }.to_string();
+            requests.audit.push(Change::new(
+                interface,
+                format!("{} {}", if enabled { "enable" } else { "disable" }, name),
+                Some(setting(fan.enabled())),
+                Some(setting(enabled)),
+            ));
+            fan.set_enabled(enabled);
+            "ok".to_string()
+        }
+        None => format!("unknown fan: {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::NullOutput;
+    use crate::testing::{self, TestFan};
+
+    #[test]
+    fn export_refuses_to_overwrite() {
+        let fan = TestFan::new(&testing::config(""));
+        let fans = [fan.control];
+        let path = testing::temp_dir("export").join("samples.csv");
+
+        assert_eq!(export(&fans, &path), "ok");
+        fs::write(&path, "keep").unwrap();
+        assert!(export(&fans, &path).starts_with("unable to write"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep");
+    }
+
+    #[test]
+    fn latest_override_wins_and_names_its_source() {
+        let config = testing::config("temp_intervals = [1]\ncpu_intervals = [1]");
+        let fan = TestFan::new(&config);
+        let mut fans = [fan.control];
+        let mut requests = Requests::default();
+        let mut send = |fans: &mut [FanControl], command: &str| {
+            let (mut client, daemon) = UnixStream::pair().unwrap();
+            writeln!(client, "{}", command).unwrap();
+            handle(daemon, fans, &[], &mut requests).unwrap();
+            let mut response = String::new();
+            BufReader::new(client).read_line(&mut response).unwrap();
+            response
+        };
+        let interface = format!("control socket uid {}", unsafe { libc::geteuid() });
+
+        assert_eq!(send(&mut fans, "set fan0 off home-assistant"), "ok\n");
+        let labelled = ManualSource::new(interface.clone(), Some("home-assistant".to_string()));
+        assert_eq!(fans[0].manual_source(), Some(&labelled));
+        assert_eq!(send(&mut fans, "set fan0 70"), "ok\n");
+        let reading = testing::reading("coretemp Package id 0", 20.0);
+        fan.source.borrow_mut().push(vec![0.0], vec![reading]);
+        assert_eq!(fans[0].update(), Some(true));
+        assert_eq!(fans[0].manual(), Some(70.0));
+
+        let status: serde_json::Value = serde_json::from_str(&status(&fans, false)).unwrap();
+        assert_eq!(status[0]["manual"], 70.0);
+        assert_eq!(status[0]["manual_source"], interface.as_str());
+        assert!(status[0].get("manual_label").is_none());
+
+        // Handing back clears the source along with the duty
+        assert_eq!(send(&mut fans, "set fan0 auto"), "ok\n");
+        assert_eq!(fans[0].manual_source(), None);
+        let labels: Vec<_> = requests.audit.iter().map(|c| c.label.as_deref()).collect();
+        assert_eq!(labels, [Some("home-assistant"), None, None]);
+        assert!(requests.audit.iter().all(|c| c.source == interface));
+    }
+
+    #[test]
+    fn export_is_only_for_root_or_the_daemon_user() {
+        let (client, _daemon) = UnixStream::pair().unwrap();
+        let own = unsafe { libc::geteuid() };
+        assert_eq!(peer_uid(&client).unwrap(), own);
+
+        assert!(may_export(own));
+        assert!(may_export(0));
+        assert!(!may_export(own + 1000));
+    }
+
+    #[test]
+    fn disabled_fan_is_left_alone() {
+        let config = testing::config(
+            r#"
+            temp_intervals = [1]
+            cpu_intervals = [1]
+
+            [[fans]]
+            name = "cpu"
+            max_fan_on_temp = 50.0
+
+            [[fans]]
+            name = "case"
+            max_fan_on_temp = 50.0
+            "#,
+        );
+        let (mut fans, sources): (Vec<_>, Vec<_>) = (0..2)
+            .map(|i| {
+                let fan = TestFan::with_output(&config, i, Box::new(NullOutput));
+                (fan.control, fan.source)
+            })
+            .unzip();
+        let mut requests = Requests::default();
+
+        assert_eq!(
+            set_enabled(&mut fans, "case", false, INTERFACE, &mut requests),
+            "ok"
+        );
+        assert_eq!(requests.audit[0].action, "disable case");
+        assert_eq!(
+            set_enabled(&mut fans, "attic", false, INTERFACE, &mut requests),
+            "unknown fan: attic"
+        );
+
+        for (fan, source) in fans.iter_mut().zip(&sources) {
+            let reading = testing::reading("coretemp Package id 0", 70.0);
+            source.borrow_mut().push(vec![0.0], vec![reading]);
+            fan.update();
+        }
+        assert_eq!(fans[0].fan_on(), Some(true));
+        assert_eq!(fans[1].fan_on(), Some(false));
+        assert!(!fans[1].enabled());
+    }
+}
@@ -0,0 +1,1572 @@
+use crate::audit::{AuditLog, Change};
+use crate::calibrate::State;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, SensorRefresh, StartupReadFailure, NO_PROFILE};
+use crate::control::{ControlSocket, ReloadSection, Requests};
+use crate::events::{Event, EventBus};
+use crate::fan_control::{FanControl, TriggerReason};
+use crate::journal::Journal;
+use crate::metrics::MetricsPush;
+use crate::notify::Notifications;
+use crate::output::{self, FanOutput, LastStart};
+use crate::sample_log::SampleLog;
+use crate::sensors::{SharedSource, SourceRegistry};
+use crate::status_fifo::StatusFifo;
+use crate::usage::Usage;
+use crate::validate;
+use crate::Error;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// Least time between saves of the daily extremes, apart from at midnight,
+/// to spare flash storage
+const DAILY_SAVE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Everything built from a config: the fans and the optional sample log,
+/// audit log, metrics push, journal, notifications, status FIFO and control socket. Rebuilt as a
+/// whole on reload.
+struct Running {
+    fans: Vec<FanControl>,
+    /// Sensors every fan reads, when refreshed once per tick
+    source: Option<SharedSource>,
+    sample_log: Option<SampleLog>,
+    audit_log: Option<AuditLog>,
+    metrics: Option<MetricsPush>,
+    journal: Option<Journal>,
+    notifications: Option<Notifications>,
+    status_fifo: Option<StatusFifo>,
+    control: Option<ControlSocket>,
+    /// Outputs left on when no config would start, only kept so they stay on
+    /// until one does
+    _held: Vec<Box<dyn FanOutput>>,
+}
+
+impl Running {
+    /// Nothing running, every output released
+    fn stopped() -> Self {
+        Self {
+            fans: Vec::new(),
+            source: None,
+            sample_log: None,
+            audit_log: None,
+            metrics: None,
+            journal: None,
+            notifications: None,
+            status_fifo: None,
+            control: None,
+            _held: Vec::new(),
+        }
+    }
+
+    /// Nothing running but `config`'s outputs, each driven on, for when
+    /// `config` itself couldn't be started
+    fn fans_on(config: &Config) -> Self {
+        let mut held = Vec::new();
+        for fan in config.fans() {
+            match output::from_config(&fan.output).and_then(|mut output| {
+                output.set(true)?;
+                Ok(output)
+            }) {
+                Ok(output) => held.push(output),
+                Err(e) => eprintln!("Unable to drive output on: {}", e),
+            }
+        }
+
+        Self {
+            _held: held,
+            ..Self::stopped()
+        }
+    }
+
+    fn start(config: &Config, registry: &SourceRegistry) -> Result<Self, Error> {
+        let state = State::load(&config.state_file).unwrap_or_else(|e| {
+            eprintln!("Unable to load state, ignoring it: {}", e);
+            State::default()
+        });
+        let source = match config.sensor_refresh {
+            SensorRefresh::Shared => Some(SharedSource::new(registry.open(config)?)),
+            SensorRefresh::PerFan => None,
+        };
+        let journal = if config.journal {
+            Journal::detect()
+        } else {
+            None
+        };
+        let last_start = LastStart::default();
+        let mut fans = Vec::new();
+        for (i, fan) in config.fans().iter().enumerate() {
+            let name = fan.name(i);
+            let mut fan = fan.clone();
+            fan.min_duty = fan.min_duty.or(state.min_duty.get(&name).copied());
+            let usage = match &source {
+                Some(source) => Usage::with_source(config, &fan, source.view()),
+                None => Usage::with_source(config, &fan, registry.open(config)?),
+            };
+            let mut fan_control = FanControl::new(name.clone(), &fan, usage)?
+                .with_profile(config.profile.clone())
+                .with_start_stagger(&last_start);
+            if config.daily_stats {
+                let daily = state.daily.get(&name).cloned().unwrap_or_default();
+                fan_control = fan_control.with_daily(daily);
+            }
+            if journal.is_some() {
+                fan_control = fan_control.without_transition_log();
+            }
+            fans.push(fan_control);
+        }
+        let sample_log = match &config.sample_log {
+            Some(c) => Some(SampleLog::open(&c.path, c.max_bytes)?),
+            None => None,
+        };
+        let audit_log = match &config.audit_log {
+            Some(path) => Some(AuditLog::open(path)?),
+            None => None,
+        };
+        let metrics = match &config.metrics {
+            Some(c) => Some(MetricsPush::new(c)?),
+            None => None,
+        };
+        let notifications = config.notifications.as_ref().map(Notifications::new);
+        let status_fifo = match &config.status_fifo {
+            Some(c) => Some(StatusFifo::create(c)?),
+            None => None,
+        };
+        let control = match &config.control_socket {
+            Some(path) => Some(ControlSocket::bind(path)?),
+            None => None,
+        };
+
+        Ok(Self {
+            fans,
+            source,
+            sample_log,
+            audit_log,
+            metrics,
+            journal,
+            notifications,
+            status_fifo,
+            control,
+            _held: Vec::new(),
+        })
+    }
+}
+
+/// Runs the fans for a config, reloading it from `path` on request.
+pub struct Controller {
+    path: Option<PathBuf>,
+    /// Sensor sources `sensor_source` picks from
+    registry: SourceRegistry,
+    /// `key=value` fields set on the command line, kept over reloads
+    overrides: Vec<String>,
+    /// Config as loaded, before any profile is applied
+    base: Config,
+    /// Config being run, with the active profile applied
+    config: Config,
+    running: Running,
+    reload_failed: bool,
+    ticks: u64,
+    clock: Rc<dyn Clock>,
+    last_verbose: Option<Instant>,
+    /// Set by `--explain`, kept across reloads unlike `explain` in the config
+    explain: bool,
+    /// Time since boot at the last tick, to notice suspends
+    last_tick: Option<Duration>,
+    /// When the supervisor last sent a heartbeat, or the controller started
+    last_heartbeat: Instant,
+    heartbeat_lapsed: bool,
+    /// Whether every fan was last forced on for another's critical reading
+    shared_critical: bool,
+    /// Whether maintenance was started through the control socket
+    maintenance_requested: bool,
+    /// Whether the fans were last forced on for maintenance
+    maintenance: bool,
+    events: EventBus,
+    timings: TickTimings,
+    /// Local day the daily extremes were last recorded for
+    daily_day: Option<i64>,
+    /// When the daily extremes were last saved to the state file
+    daily_saved: Option<Instant>,
+}
+
+/// Time the last tick spent on each of its stages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TickTimings {
+    /// Refreshing the shared sensors. With `sensor_refresh = "per-fan"`
+    /// each fan refreshes its own, counted under `decisions`.
+    pub sensors: Duration,
+    /// Sampling and deciding every fan, other than writing the outputs
+    pub decisions: Duration,
+    pub writes: Duration,
+}
+
+impl Controller {
+    pub fn new(config: Config, path: Option<PathBuf>) -> Result<Self, Error> {
+        Self::with_clock(config, path, Rc::new(SystemClock))
+    }
+
+    /// Run `config` by `clock`, which the caller can keep a handle on, e.g.
+    /// to advance a `ManualClock`
+    pub fn with_clock(
+        config: Config,
+        path: Option<PathBuf>,
+        clock: Rc<dyn Clock>,
+    ) -> Result<Self, Error> {
+        Self::with_sources(config, path, clock, SourceRegistry::builtin())
+    }
+
+    /// Run `config` reading sensors from the sources in `registry`, which
+    /// `sensor_source` picks from
+    pub fn with_sources(
+        config: Config,
+        path: Option<PathBuf>,
+        clock: Rc<dyn Clock>,
+        registry: SourceRegistry,
+    ) -> Result<Self, Error> {
+        let base = config;
+        let config = base
+            .with_profile(base.profile.as_deref())
+            .map_err(Error::Config)?;
+        // Drivers may still be probing on boot
+        clock.sleep(config.startup_delay());
+        let running = Running::start(&config, &registry)?;
+        let last_heartbeat = clock.now();
+
+        Ok(Self {
+            path,
+            registry,
+            overrides: Vec::new(),
+            base,
+            config,
+            running,
+            reload_failed: false,
+            ticks: 0,
+            clock,
+            last_verbose: None,
+            explain: false,
+            last_tick: None,
+            last_heartbeat,
+            heartbeat_lapsed: false,
+            shared_critical: false,
+            maintenance_requested: false,
+            maintenance: false,
+            events: EventBus::default(),
+            timings: TickTimings::default(),
+            daily_day: None,
+            daily_saved: None,
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn fans(&self) -> &[FanControl] {
+        &self.running.fans
+    }
+
+    /// Time spent on each stage of the last tick
+    pub fn timings(&self) -> TickTimings {
+        self.timings
+    }
+
+    /// Receive the events of every following tick
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Apply `overrides`, as given to `Config::load_with`, to every reload
+    pub fn with_overrides(mut self, overrides: Vec<String>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Follow each status line with why the fan is in its state, whatever
+    /// the config sets
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+    }
+
+    /// True while the last reload attempt failed and the previous config is
+    /// still in use
+    pub fn reload_failed(&self) -> bool {
+        self.reload_failed
+    }
+
+    /// Reload the config file. If the new config can't be loaded the
+    /// previous one keeps running, with the fans forced on until a valid
+    /// config is loaded when `fail_hot` is set.
+    pub fn reload(&mut self) {
+        let Some((base, config)) = self.load() else {
+            return;
+        };
+
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        match self.restart(config) {
+            Ok(()) => {
+                println!("Reloaded {}", path.display());
+                self.base = base;
+                self.reload_failed = false;
+                self.audit(Change::new(
+                    "SIGHUP",
+                    "reload".to_string(),
+                    None,
+                    Some("full".to_string()),
+                ));
+            }
+            Err(e) => {
+                eprintln!(
+                    "Unable to apply {}, restoring previous config: {}",
+                    path.display(),
+                    e
+                );
+                self.reload_failed = true;
+            }
+        }
+    }
+
+    /// Apply only `section` of the config file to the running fans, leaving
+    /// their windows, state and the rest of their settings as they are. The
+    /// config last loaded in full is kept for reloads and profile switches.
+    pub fn reload_section(&mut self, section: ReloadSection) {
+        let Some((_, config)) = self.load() else {
+            return;
+        };
+        let fans = config.fans();
+        let names: Vec<String> = fans.iter().enumerate().map(|(i, f)| f.name(i)).collect();
+        let running: Vec<&str> = self.running.fans.iter().map(|f| f.name()).collect();
+        if names != running {
+            // Nothing wrong with the file, so the running config carries on
+            eprintln!(
+                "Unable to reload {}: the fans changed, keeping the running config until a full reload",
+                section
+            );
+            return;
+        }
+
+        for (fan_control, fan) in self.running.fans.iter_mut().zip(fans) {
+            match section {
+                ReloadSection::Thresholds => fan_control.reload_thresholds(fan),
+                ReloadSection::Curve => {
+                    // Validated on load, so the curve always builds
+                    if let Err(e) = fan_control.reload_curve(fan) {
+                        eprintln!("Unable to reload {} curve: {}", fan_control.name(), e);
+                    }
+                }
+                ReloadSection::Sensors => fan_control.reload_sensors(&config, fan),
+            }
+        }
+        println!("Reloaded {}", section);
+        self.reload_failed = false;
+        self.audit(Change::new(
+            "control socket",
+            "reload".to_string(),
+            None,
+            Some(section.to_string()),
+        ));
+    }
+
+    /// Load the config file, returning it as loaded and with the active
+    /// profile applied, or `None` after logging why it can't be
+    fn load(&mut self) -> Option<(Config, Config)> {
+        let Some(path) = self.path.as_ref() else {
+            eprintln!("No config file to reload");
+            return None;
+        };
+        let base = match Config::load_with(Some(path), &self.overrides) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!(
+                    "Unable to reload {}, keeping previous config: {}",
+                    path.display(),
+                    e
+                );
+                self.reload_failed = true;
+                return None;
+            }
+        };
+
+        // The active profile is kept, unless the new config dropped it
+        let config = base
+            .with_profile(self.config.profile.as_deref())
+            .or_else(|e| {
+                eprintln!("{}, using the profile set in {}", e, path.display());
+                base.with_profile(base.profile.as_deref())
+            });
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Unable to reload {}: {}", path.display(), e);
+                self.reload_failed = true;
+                return None;
+            }
+        };
+        if config.reload_check {
+            let problems = validate::sweep(&config);
+            if !problems.is_empty() {
+                eprintln!(
+                    "Unable to reload {}, keeping previous config: {}",
+                    path.display(),
+                    problems.join("; ")
+                );
+                self.reload_failed = true;
+                return None;
+            }
+        }
+
+        Some((base, config))
+    }
+
+    /// Switch every fan to the named profile, or back to its own settings
+    /// for `None`. Control restarts as it does on a reload.
+    pub fn set_profile(&mut self, profile: Option<&str>) -> Result<(), Error> {
+        let config = self.base.with_profile(profile).map_err(Error::Config)?;
+        self.restart(config)?;
+        println!("Switched to profile {}", profile.unwrap_or(NO_PROFILE));
+
+        Ok(())
+    }
+
+    /// Append `change` to the audit log, when there is one
+    fn audit(&mut self, change: Change) {
+        if let Some(audit_log) = self.running.audit_log.as_mut() {
+            if let Err(e) = audit_log.record(&change) {
+                eprintln!("Unable to write to the audit log: {}", e);
+            }
+        }
+    }
+
+    /// Start running `config` in place of the current one, which is put back
+    /// if `config` fails to start
+    fn restart(&mut self, config: Config) -> Result<(), Error> {
+        // Outputs hold their lines, release them before requesting again
+        self.running = Running::stopped();
+        match Running::start(&config, &self.registry) {
+            Ok(running) => {
+                self.running = running;
+                self.config = config;
+                Ok(())
+            }
+            Err(e) => {
+                match Running::start(&self.config, &self.registry) {
+                    Ok(running) => self.running = running,
+                    Err(e) => {
+                        eprintln!("Unable to restore previous config, fans left on: {}", e);
+                        self.running = Running::fans_on(&self.config);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Drive every follower from the state its leader was just left in
+    fn follow_leaders(&mut self) {
+        let leaders: Vec<(String, bool, Option<f32>)> = self
+            .running
+            .fans
+            .iter()
+            .map(|f| (f.name().to_string(), f.fan_on() == Some(true), f.duty()))
+            .collect();
+        for fan_control in self.running.fans.iter_mut() {
+            let Some(leader) = fan_control.leader() else {
+                continue;
+            };
+            if let Some((_, on, duty)) = leaders.iter().find(|(name, ..)| name == leader) {
+                fan_control.follow(*on, *duty);
+            }
+        }
+    }
+
+    /// With `startup_read_failure = "retry"`, sample until every fan has
+    /// read a temperature, waiting twice as long after each failed attempt,
+    /// giving up after `startup_read_retries` attempts
+    pub fn wait_for_sensors(&mut self) {
+        if self.config.startup_read_failure != StartupReadFailure::Retry {
+            return;
+        }
+
+        let mut delay = self.config.sample_interval();
+        for attempt in 1..=self.config.startup_read_retries {
+            self.refresh_sensors();
+            for fan_control in self.running.fans.iter_mut() {
+                if !fan_control.usage().read_temperature() {
+                    fan_control.sample();
+                }
+            }
+            if self
+                .running
+                .fans
+                .iter()
+                .all(|f| f.usage().read_temperature())
+            {
+                return;
+            }
+            if attempt < self.config.startup_read_retries {
+                eprintln!("No temperature read yet, retrying in {}s", delay.as_secs());
+                self.clock.sleep(delay);
+                delay *= 2;
+            }
+        }
+        eprintln!(
+            "No temperature read after {} attempts, starting anyway",
+            self.config.startup_read_retries
+        );
+    }
+
+    /// Take a fresh set of readings for every fan sharing the sensors
+    fn refresh_sensors(&self) {
+        if let Some(source) = &self.running.source {
+            source.refresh();
+        }
+    }
+
+    /// How long to wait between ticks
+    pub fn sample_interval(&self) -> Duration {
+        self.config.sample_interval()
+    }
+
+    /// Wait on the controller's clock until the next tick is due
+    pub fn wait(&self) {
+        self.clock.sleep(self.sample_interval());
+    }
+
+    /// Restart every fan if the time since the last tick shows the system was
+    /// suspended, as samples from before then no longer describe it
+    fn check_resumed(&mut self) {
+        let since_boot = self.clock.since_boot();
+        let gap = self.last_tick.map(|last| since_boot.saturating_sub(last));
+        self.last_tick = Some(since_boot);
+        let resume_gap = Duration::from_secs(self.config.resume_gap_secs);
+        let Some(gap) = gap.filter(|&gap| !resume_gap.is_zero() && gap >= resume_gap) else {
+            return;
+        };
+
+        println!("Resumed after {}s, restarting windows", gap.as_secs());
+        for fan_control in self.running.fans.iter_mut() {
+            fan_control.resumed();
+        }
+    }
+
+    /// Count the tick towards each fan's daily extremes, saving them to the
+    /// state file when they changed, at most every `DAILY_SAVE_INTERVAL`
+    /// unless the day rolled over
+    fn record_daily(&mut self, now: Instant) {
+        if !self.config.daily_stats {
+            return;
+        }
+        let day = self.clock.local_day();
+        let mut changed = false;
+        for fan_control in self.running.fans.iter_mut() {
+            changed |= fan_control.record_day(day);
+        }
+        let rolled = self.daily_day.is_some_and(|last| last != day);
+        self.daily_day = Some(day);
+        let due = self
+            .daily_saved
+            .is_none_or(|saved| now.duration_since(saved) >= DAILY_SAVE_INTERVAL);
+        if !changed || !(due || rolled) {
+            return;
+        }
+
+        self.daily_saved = Some(now);
+        let path = &self.config.state_file;
+        let result = State::load(path).and_then(|mut state| {
+            for fan_control in self.running.fans.iter() {
+                if let Some(daily) = fan_control.daily() {
+                    state
+                        .daily
+                        .insert(fan_control.name().to_string(), daily.clone());
+                }
+            }
+            state.save(path)
+        });
+        if let Err(e) = result {
+            eprintln!("Unable to save daily extremes to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Whether a heartbeat is expected and hasn't been sent in time, logging
+    /// when that changes
+    fn check_heartbeat(&mut self, now: Instant) -> bool {
+        let lapsed = self.config.heartbeat_secs.is_some_and(|secs| {
+            now.duration_since(self.last_heartbeat) > Duration::from_secs(secs)
+        });
+        if lapsed != self.heartbeat_lapsed {
+            if lapsed {
+                eprintln!(
+                    "No heartbeat for {}s, forcing the fans on",
+                    now.duration_since(self.last_heartbeat).as_secs()
+                );
+            } else {
+                println!("Heartbeat resumed");
+            }
+            self.heartbeat_lapsed = lapsed;
+        }
+
+        lapsed
+    }
+
+    /// Whether `critical_all_fans` is set and any fan's latest readings are
+    /// critical, logging when that changes
+    fn check_shared_critical(&mut self) -> bool {
+        let critical =
+            self.config.critical_all_fans && self.running.fans.iter().any(FanControl::critical);
+        if critical != self.shared_critical {
+            if critical {
+                let names: Vec<&str> = self
+                    .running
+                    .fans
+                    .iter()
+                    .filter(|f| f.critical())
+                    .map(FanControl::name)
+                    .collect();
+                eprintln!("{} critical, forcing every fan on", names.join(", "));
+            } else {
+                println!("Critical cleared, releasing the fans");
+            }
+            self.shared_critical = critical;
+        }
+
+        critical
+    }
+
+    /// Whether maintenance is under way, asked for through the control
+    /// socket or by `maintenance_file` existing, logging when that changes
+    fn check_maintenance(&mut self) -> bool {
+        let maintenance = self.maintenance_requested
+            || self
+                .config
+                .maintenance_file
+                .as_ref()
+                .is_some_and(|path| path.exists());
+        if maintenance != self.maintenance {
+            if maintenance {
+                println!("Maintenance started, running the fans at full speed");
+            } else {
+                println!("Maintenance done, resuming automatic control");
+            }
+            self.maintenance = maintenance;
+        }
+
+        maintenance
+    }
+
+    /// Sample every fan's sensors once, re-evaluating each fan every
+    /// `decision_interval_secs` (its own or the top level one) worth of
+    /// ticks, or straight away on a critical reading
+    pub fn tick(&mut self) {
+        self.check_resumed();
+        let minute = self.clock.local_minutes();
+        for fan_control in self.running.fans.iter_mut() {
+            fan_control.set_time_of_day(minute);
+        }
+        let ticks = self.ticks;
+        self.ticks += 1;
+
+        // Transitions are always printed as they happen, the status line
+        // only every verbose interval
+        let now = self.clock.now();
+        let forced = if self.reload_failed && self.config.fail_hot {
+            Some(TriggerReason::FailHot)
+        } else if self.check_heartbeat(now) {
+            Some(TriggerReason::Heartbeat)
+        } else if self.check_maintenance() {
+            Some(TriggerReason::Maintenance)
+        } else {
+            None
+        };
+        let show_verbose = self.verbose_due(now);
+
+        let before: Vec<FanState> = self.running.fans.iter().map(FanState::of).collect();
+        let started = Instant::now();
+        self.refresh_sensors();
+        let sensors = started.elapsed();
+        let started = Instant::now();
+        // Every fan samples before any decides, so a critical reading for one
+        // can force the rest on in the same tick
+        for fan_control in self.running.fans.iter_mut() {
+            fan_control.sample();
+        }
+        let shared_critical = self.check_shared_critical();
+        let fans = self.running.fans.iter_mut().zip(self.config.fans());
+        for (fan_control, fan) in fans {
+            let decide = ticks.is_multiple_of(self.config.samples_per_decision(fan));
+            let unread = self.config.startup_read_failure == StartupReadFailure::FanOn
+                && !fan_control.usage().read_temperature();
+            if let Some(reason) = forced {
+                fan_control.force(reason);
+            } else if unread {
+                fan_control.force(TriggerReason::NoReading);
+            } else if shared_critical && !fan_control.critical() {
+                fan_control.force(TriggerReason::SharedCritical);
+            } else if fan_control.leader().is_none() && (decide || fan_control.urgent()) {
+                // A critical reading or runaway rise can't wait for the next
+                // decision
+                fan_control.decide();
+            }
+        }
+        if forced.is_none() && !shared_critical {
+            self.follow_leaders();
+        }
+        let fans = started.elapsed();
+        let writes = self
+            .running
+            .fans
+            .iter_mut()
+            .map(FanControl::take_write_time)
+            .sum();
+        self.timings = TickTimings {
+            sensors,
+            decisions: fans.saturating_sub(writes),
+            writes,
+        };
+        if self.events.has_subscribers() {
+            self.emit_events(&before);
+        }
+
+        let show_names = self.running.fans.len() > 1;
+        for fan_control in self.running.fans.iter() {
+            if show_verbose {
+                verbose(fan_control, show_names);
+                if self.explain || self.config.explain {
+                    println!("  {}", fan_control.explain());
+                }
+            }
+            if let Some(sample_log) = self.running.sample_log.as_mut() {
+                if let Err(e) = sample_log.record(fan_control) {
+                    eprintln!("Unable to write sample log: {}", e);
+                }
+            }
+        }
+        if let Some(metrics) = self.running.metrics.as_mut() {
+            metrics.push(now, &self.running.fans);
+        }
+        self.record_daily(now);
+        if let Some(journal) = self.running.journal.as_mut() {
+            journal.record(&self.running.fans);
+        }
+        if let Some(notifications) = self.running.notifications.as_mut() {
+            notifications.record(now, &self.running.fans);
+        }
+        if let Some(status_fifo) = self.running.status_fifo.as_ref() {
+            status_fifo.write(&self.running.fans);
+        }
+        let profiles: Vec<String> = self.base.profiles.iter().map(|p| p.name.clone()).collect();
+        let requests = match self.running.control.as_ref() {
+            Some(control) => control.poll(&mut self.running.fans, &profiles),
+            None => Requests::default(),
+        };
+        if requests.heartbeat {
+            self.last_heartbeat = now;
+        }
+        for change in requests.audit {
+            self.audit(change);
+        }
+        if let Some(maintenance) = requests.maintenance {
+            let setting = |on: bool| if on { "on" } else { "off" }.to_string();
+            self.audit(Change::new(
+                "control socket",
+                "maintenance".to_string(),
+                Some(setting(self.maintenance_requested)),
+                Some(setting(maintenance)),
+            ));
+            self.maintenance_requested = maintenance;
+        }
+        for section in requests.reload {
+            self.reload_section(section);
+        }
+        if let Some(profile) = requests.profile {
+            let name = (profile != NO_PROFILE).then_some(profile.as_str());
+            let old = self
+                .config
+                .profile
+                .clone()
+                .unwrap_or(NO_PROFILE.to_string());
+            match self.set_profile(name) {
+                Ok(()) => self.audit(Change::new(
+                    "control socket",
+                    "profile".to_string(),
+                    Some(old),
+                    Some(profile),
+                )),
+                Err(e) => eprintln!("Unable to switch to profile {}: {}", profile, e),
+            }
+        }
+    }
+
+    /// Whether the status line is due, at most once every verbose interval
+    fn verbose_due(&mut self, now: Instant) -> bool {
+        let due = self
+            .last_verbose
+            .is_none_or(|last| now.duration_since(last) >= self.config.verbose_interval());
+        if due {
+            self.last_verbose = Some(now);
+        }
+
+        due
+    }
+
+    /// Emit the events of the tick just taken, `before` holding each fan's
+    /// state from before it
+    fn emit_events(&mut self, before: &[FanState]) {
+        for (fan_control, before) in self.running.fans.iter().zip(before) {
+            if !fan_control.enabled() {
+                continue;
+            }
+            let fan = fan_control.name().to_string();
+            let usage = fan_control.usage();
+            self.events.emit(Event::SampleTaken {
+                fan: fan.clone(),
+                temperature: usage.last_temperature(),
+                cpu: usage.last_cpu(),
+            });
+            if usage.last_temperature().is_none() {
+                self.events.emit(Event::SensorError { fan: fan.clone() });
+            }
+
+            let after = FanState::of(fan_control);
+            if after == *before {
+                continue;
+            }
+            let critical = [TriggerReason::Critical, TriggerReason::SensorCritical];
+            if after.trigger.is_some_and(|t| critical.contains(&t))
+                && after.trigger != before.trigger
+            {
+                self.events.emit(Event::CriticalTriggered {
+                    fan: fan.clone(),
+                    temperature: usage.last_temperature(),
+                });
+            }
+            self.events.emit(Event::FanStateChanged {
+                fan,
+                on: after.on == Some(true),
+                duty: after.duty,
+                trigger: after.trigger,
+            });
+        }
+    }
+}
+
+/// What a fan was doing, to tell when a tick changed it
+#[derive(PartialEq)]
+struct FanState {
+    on: Option<bool>,
+    duty: Option<f32>,
+    trigger: Option<TriggerReason>,
+}
+
+impl FanState {
+    fn of(fan_control: &FanControl) -> Self {
+        Self {
+            on: fan_control.fan_on(),
+            duty: fan_control.duty(),
+            trigger: fan_control.trigger(),
+        }
+    }
+}
+
+fn verbose(fan_control: &FanControl, show_name: bool) {
+    let usage = fan_control.usage();
+    let cpu_measurements: Vec<f32> = usage.cpu.iter().map(|c| c.measurement()).collect();
+    let temp_measurements: Vec<f32> = usage
+        .temperature
+        .iter()
+        .map(|t| usage.display_value(t.measurement()))
+        .collect();
+    let fan_verbose = match fan_control.fan_on() {
+        Some(true) => "ON",
+        Some(false) => "OFF",
+        _ => "--",
+    };
+    let duty = fan_control
+        .duty()
+        .map(|d| format!(" {:.0}%", d))
+        .unwrap_or_default();
+    let trigger = match (fan_control.trigger(), fan_control.manual_source()) {
+        (Some(TriggerReason::Manual), Some(source)) => format!(" (manual, {})", source),
+        (Some(trigger), _) => format!(" ({})", trigger),
+        (None, _) => String::new(),
+    };
+    let name = if show_name {
+        format!("{} ", fan_control.name())
+    } else {
+        String::new()
+    };
+    println!(
+        "{}[{}{}]{} {:?}, {:?}",
+        name, fan_verbose, duty, trigger, cpu_measurements, temp_measurements
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestController};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn startup_delay_passes_before_the_first_read() {
+        let mut test = TestController::new("startup-delay", "startup_delay_secs = 5");
+        assert_eq!(test.clock.since_boot(), Duration::from_secs(5));
+        assert_eq!(test.fan().usage().last_temperature(), None);
+
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().usage().last_temperature(), Some(40.0));
+    }
+
+    #[test]
+    fn startup_read_failure_retries_or_runs_the_fans() {
+        let retry = "startup_read_failure = \"retry\"\nstartup_read_retries = 3";
+        let mut test = TestController::new("startup-retry", retry);
+        testing::fail_reads(2);
+        test.controller.wait_for_sensors();
+        assert!(test.fan().usage().read_temperature());
+        // Waited 1s then 2s
+        assert_eq!(test.clock.since_boot(), Duration::from_secs(3));
+
+        let mut test = TestController::new("startup-give-up", retry);
+        testing::fail_reads(5);
+        test.controller.wait_for_sensors();
+        assert!(!test.fan().usage().read_temperature());
+        assert_eq!(test.clock.since_boot(), Duration::from_secs(3));
+        testing::fail_reads(0);
+
+        let mut test = TestController::new("startup-fan-on", "startup_read_failure = \"fan-on\"");
+        testing::fail_reads(2);
+        for _ in 0..2 {
+            test.tick(40.0, 0.0);
+            assert_eq!(test.fan().fan_on(), Some(true));
+            assert_eq!(test.fan().trigger(), Some(TriggerReason::NoReading));
+        }
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+    }
+
+    #[test]
+    fn fans_decide_at_their_own_intervals() {
+        let mut test = TestController::new(
+            "fan-intervals",
+            r#"
+            [[fans]]
+            name = "cpu"
+            max_fan_on_temp = 50.0
+
+            [[fans]]
+            name = "case"
+            max_fan_on_temp = 50.0
+            decision_interval_secs = 3
+            "#,
+        );
+        let states = |test: &TestController| -> Vec<Option<bool>> {
+            test.controller
+                .fans()
+                .iter()
+                .map(FanControl::fan_on)
+                .collect()
+        };
+
+        test.tick(40.0, 0.0);
+        assert_eq!(states(&test), [Some(false), Some(false)]);
+        test.tick(70.0, 0.0);
+        assert_eq!(states(&test), [Some(true), Some(false)]);
+        test.tick(70.0, 0.0);
+        assert_eq!(states(&test), [Some(true), Some(false)]);
+        test.tick(70.0, 0.0);
+        assert_eq!(states(&test), [Some(true), Some(true)]);
+    }
+
+    #[test]
+    fn samples_from_before_a_suspend_are_dropped() {
+        let mut test = TestController::new("resume", "temp_intervals = [4]");
+        for _ in 0..3 {
+            test.tick(80.0, 0.0);
+        }
+        assert_eq!(test.fan().usage().temperature[0].samples().len(), 3);
+
+        test.clock.advance(Duration::from_secs(600));
+        test.tick(30.0, 0.0);
+        let window = &test.fan().usage().temperature[0];
+        assert_eq!(window.samples(), &[30.0]);
+        assert_eq!(window.measurement(), 30.0);
+    }
+
+    #[test]
+    fn scheduled_duty_applies_within_its_window() {
+        let mut test = TestController::new(
+            "schedule",
+            "resume_gap_secs = 0\nschedule = [{ from = \"00:02\", to = \"00:04\", duty = 40.0 }]",
+        );
+        let mut duty_at = |secs: u64| {
+            let now = test.clock.since_boot();
+            test.clock.advance(Duration::from_secs(secs) - now);
+            test.tick(40.0, 0.0);
+            let pwm = fs::read_to_string(test.dir.join("hwmon0").join("pwm1")).unwrap();
+            (pwm, test.fan().trigger())
+        };
+
+        let scheduled = Some(TriggerReason::Schedule);
+        assert_eq!(duty_at(119), ("0".to_string(), None));
+        assert_eq!(duty_at(120), ("102".to_string(), scheduled));
+        assert_eq!(duty_at(239), ("102".to_string(), scheduled));
+        assert_eq!(duty_at(240), ("0".to_string(), None));
+    }
+
+    #[test]
+    fn precool_starts_its_lead_time_before_the_entry() {
+        let mut test = TestController::new(
+            "precool",
+            "resume_gap_secs = 0\n\
+             schedule = [{ from = \"00:30\", to = \"00:40\", duty = 40.0, precool_mins = 10 }]",
+        );
+        let pwm = test.dir.join("hwmon0").join("pwm1");
+        let precool = Some(TriggerReason::Precool);
+        let scheduled = Some(TriggerReason::Schedule);
+        // Either side of 00:20, when the precool starts, and of 00:30
+        for (secs, duty, trigger) in [
+            (1199, "0", None),
+            (1200, "255", precool),
+            (1799, "255", precool),
+            (1800, "102", scheduled),
+        ] {
+            test.clock
+                .advance(Duration::from_secs(secs) - test.clock.since_boot());
+            test.tick(40.0, 0.0);
+            assert_eq!(
+                fs::read_to_string(&pwm).unwrap().trim(),
+                duty,
+                "at {}s",
+                secs
+            );
+            assert_eq!(test.fan().trigger(), trigger, "at {}s", secs);
+        }
+    }
+
+    #[test]
+    fn daily_extremes_roll_over_at_midnight() {
+        let mut test = TestController::new("daily", "daily_stats = true\nresume_gap_secs = 0");
+        test.tick(40.0, 0.0);
+        test.tick(60.0, 0.0);
+        let until_midnight = Duration::from_secs(24 * 60 * 60) - test.clock.since_boot();
+        test.clock.advance(until_midnight);
+        test.tick(35.0, 0.0);
+
+        let daily = test.fan().daily().unwrap();
+        let yesterday = daily.yesterday.unwrap();
+        assert_eq!(yesterday.day, 0);
+        assert_eq!(
+            (yesterday.min_temp, yesterday.max_temp),
+            (Some(40.0), Some(60.0))
+        );
+        assert_eq!(yesterday.peak_duty, 100.0);
+        let today = daily.today.unwrap();
+        assert_eq!(today.day, 1);
+        assert_eq!((today.min_temp, today.max_temp), (Some(35.0), Some(35.0)));
+
+        // Saved on the roll, though the last save was moments before
+        let state = State::load(test.dir.join("state.toml")).unwrap();
+        assert_eq!(state.daily.get(test.fan().name()), Some(daily));
+    }
+
+    #[test]
+    fn missed_heartbeat_forces_the_fans_on() {
+        let socket = testing::temp_dir("heartbeat-socket").join("control.sock");
+        let mut test = TestController::new(
+            "heartbeat",
+            &format!(
+                "control_socket = \"{}\"\nheartbeat_secs = 5",
+                socket.display()
+            ),
+        );
+        for _ in 0..6 {
+            test.tick(40.0, 0.0);
+            assert_eq!(test.fan().fan_on(), Some(false));
+        }
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        assert_eq!(test.fan().trigger(), Some(TriggerReason::Heartbeat));
+
+        let mut supervisor = UnixStream::connect(&socket).unwrap();
+        writeln!(supervisor, "heartbeat").unwrap();
+        test.tick(40.0, 0.0);
+        let mut response = String::new();
+        supervisor.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "ok\n");
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+    }
+
+    #[test]
+    fn injected_source_runs_on_injected_readings_alone() {
+        let socket = testing::temp_dir("injected-socket").join("control.sock");
+        let mut test = TestController::new(
+            "injected",
+            &format!(
+                "sensor_source = \"injected\"\ncontrol_socket = \"{}\"",
+                socket.display()
+            ),
+        );
+        let local_reads = testing::refreshes();
+        let inject = |test: &mut TestController, command: &str| {
+            let mut client = UnixStream::connect(&socket).unwrap();
+            writeln!(client, "{}", command).unwrap();
+            test.tick(40.0, 0.0);
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().usage().last_temperature(), None);
+
+        assert_eq!(inject(&mut test, "inject coretemp-pkg 70.5"), "ok\n");
+        assert_eq!(inject(&mut test, "inject-cpu 12"), "ok\n");
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().usage().last_temperature(), Some(70.5));
+        assert_eq!(test.fan().usage().last_cpu(), Some(12.0));
+        assert_eq!(test.fan().fan_on(), Some(true));
+        let pwm = test.dir.join("hwmon0").join("pwm1");
+        assert_eq!(fs::read_to_string(pwm).unwrap().trim(), "255");
+
+        assert_eq!(
+            inject(&mut test, "inject coretemp-pkg nan"),
+            "invalid temperature\n"
+        );
+        // The local sensors were never read
+        assert_eq!(testing::refreshes(), local_reads);
+    }
+
+    #[test]
+    fn override_and_reload_are_audited() {
+        let scratch = testing::temp_dir("audit-scratch");
+        let (socket, audit) = (scratch.join("control.sock"), scratch.join("audit.jsonl"));
+        let mut test = TestController::new(
+            "audit",
+            &format!(
+                "control_socket = \"{}\"\naudit_log = \"{}\"",
+                socket.display(),
+                audit.display()
+            ),
+        );
+        test.tick(40.0, 0.0);
+        let name = test.fan().name().to_string();
+
+        let mut client = UnixStream::connect(&socket).unwrap();
+        writeln!(client, "set {} 40", name).unwrap();
+        test.tick(40.0, 0.0);
+        client.read_to_string(&mut String::new()).unwrap();
+        // As on SIGHUP
+        test.controller.reload();
+
+        let entries: Vec<serde_json::Value> = fs::read_to_string(&audit)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        fn fields(entry: &serde_json::Value) -> [Option<&str>; 4] {
+            assert!(entry["timestamp"].as_u64().unwrap() > 0);
+            ["source", "action", "old", "new"].map(|field| entry[field].as_str())
+        }
+        let set = format!("set {}", name);
+        let client = format!("control socket uid {}", unsafe { libc::geteuid() });
+        assert_eq!(
+            fields(&entries[0]),
+            [Some(&*client), Some(&*set), Some("auto"), Some("40")]
+        );
+        assert_eq!(
+            fields(&entries[1]),
+            [Some("SIGHUP"), Some("reload"), None, Some("full")]
+        );
+    }
+
+    #[test]
+    fn maintenance_sentinel_runs_the_fans_at_full() {
+        let sentinel = testing::temp_dir("maintenance-sentinel").join("backup-running");
+        let mut test = TestController::new(
+            "maintenance",
+            &format!("maintenance_file = \"{}\"", sentinel.display()),
+        );
+        let pwm = test.dir.join("hwmon0").join("pwm1");
+        test.tick(40.0, 0.0);
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+
+        fs::write(&sentinel, "").unwrap();
+        for _ in 0..3 {
+            test.tick(40.0, 0.0);
+            assert_eq!(test.fan().fan_on(), Some(true));
+            assert_eq!(test.fan().trigger(), Some(TriggerReason::Maintenance));
+            assert_eq!(fs::read_to_string(&pwm).unwrap().trim(), "255");
+        }
+
+        fs::remove_file(&sentinel).unwrap();
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+    }
+
+    #[test]
+    fn one_critical_fan_forces_every_fan_on() {
+        let mut test = TestController::new(
+            "shared-critical",
+            "critical_all_fans = true\n\
+             [[fans]]\nname = \"cpu\"\nmax_fan_on_temp = 60.0\ncritical_temp = 80.0\n\
+             [[fans]]\nname = \"case\"\nmax_fan_on_temp = 90.0",
+        );
+        let states = |test: &TestController| -> Vec<(Option<bool>, Option<TriggerReason>)> {
+            test.controller
+                .fans()
+                .iter()
+                .map(|f| (f.fan_on(), f.trigger()))
+                .collect()
+        };
+        test.tick(40.0, 0.0);
+        test.tick(40.0, 0.0);
+        assert_eq!(states(&test), [(Some(false), None), (Some(false), None)]);
+
+        test.tick(85.0, 0.0);
+        assert_eq!(
+            states(&test),
+            [
+                (Some(true), Some(TriggerReason::Critical)),
+                (Some(true), Some(TriggerReason::SharedCritical)),
+            ]
+        );
+
+        test.tick(40.0, 0.0);
+        assert_eq!(states(&test), [(Some(false), None), (Some(false), None)]);
+    }
+
+    #[test]
+    fn subscriber_sees_the_fan_turn_on() {
+        let mut test = TestController::new("events", "");
+        let events = test.controller.subscribe();
+        let state_changes = |events: &Receiver<Event>| -> Vec<Event> {
+            events
+                .try_iter()
+                .filter(|e| matches!(e, Event::FanStateChanged { .. }))
+                .collect()
+        };
+
+        test.tick(40.0, 0.0);
+        test.tick(40.0, 0.0);
+        state_changes(&events);
+        test.tick(40.0, 0.0);
+        assert!(state_changes(&events).is_empty());
+
+        test.tick(70.0, 0.0);
+        assert_eq!(
+            state_changes(&events),
+            [Event::FanStateChanged {
+                fan: "fan0".to_string(),
+                on: true,
+                duty: None,
+                trigger: Some(TriggerReason::Temperature { window: 0 }),
+            }]
+        );
+    }
+
+    #[test]
+    fn shared_source_refreshes_once_per_tick() {
+        let fans = "[[fans]]\nname = \"cpu\"\n[[fans]]\nname = \"case\"\n\
+                    [[fans]]\nname = \"drives\"\n";
+        let refreshes_per_tick = |refresh: &str| {
+            let mut test = TestController::new(
+                "shared-refresh",
+                &format!("sensor_refresh = \"{}\"\n{}", refresh, fans),
+            );
+            let before = testing::refreshes();
+            for _ in 0..4 {
+                test.tick(40.0, 0.0);
+            }
+            (testing::refreshes() - before) / 4
+        };
+
+        assert_eq!(refreshes_per_tick("shared"), 1);
+        assert_eq!(refreshes_per_tick("per-fan"), 3);
+    }
+
+    #[test]
+    fn bad_reload_keeps_config_and_fails_hot() {
+        let mut test = TestController::new("fail-hot", "fail_hot = true");
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+
+        // Out of range for the threshold of 50, so the config doesn't load
+        test.rewrite("fail_hot = true\ncooldown_target = 80.0");
+        test.controller.reload();
+        assert!(test.controller.reload_failed());
+        assert_eq!(test.controller.config().fans()[0].cooldown_target, None);
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        assert_eq!(test.fan().trigger(), Some(TriggerReason::FailHot));
+
+        test.rewrite("fail_hot = true\ncooldown_target = 45.0");
+        test.controller.reload();
+        assert!(!test.controller.reload_failed());
+        assert_eq!(
+            test.controller.config().fans()[0].cooldown_target,
+            Some(45.0)
+        );
+        test.tick(40.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+    }
+
+    #[test]
+    fn reload_leaves_fans_on_when_neither_config_starts() {
+        let config = |dir: &std::path::Path, fail_hot: bool| {
+            format!(
+                "fail_hot = {}\n[sample_log]\npath = \"{}\"",
+                fail_hot,
+                dir.join("logs/samples.csv").display()
+            )
+        };
+        let dir = testing::temp_dir("fans-left-on-logs");
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        let mut test = TestController::new("fans-left-on", &config(&dir, false));
+        let pwm = test.dir.join("hwmon0/pwm1");
+        test.tick(40.0, 0.0);
+        assert_eq!(fs::read_to_string(&pwm).unwrap(), "0");
+
+        // Neither config can open its sample log any more
+        fs::remove_dir_all(dir.join("logs")).unwrap();
+        test.rewrite(&config(&dir, true));
+        test.controller.reload();
+        assert!(test.controller.reload_failed());
+        assert!(test.controller.fans().is_empty());
+        assert_eq!(fs::read_to_string(&pwm).unwrap(), "255");
+        assert_eq!(
+            fs::read_to_string(test.dir.join("hwmon0/pwm1_enable")).unwrap(),
+            "1"
+        );
+
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        test.controller.reload();
+        assert!(!test.controller.reload_failed());
+        assert_eq!(test.controller.fans().len(), 1);
+    }
+
+    #[test]
+    fn status_line_is_rate_limited_but_transitions_are_not() {
+        let mut test = TestController::new("verbose", "verbose_interval_secs = 5");
+        let events = test.controller.subscribe();
+        let tick = |test: &mut TestController, temperature: f32| {
+            test.tick(temperature, 0.0);
+            test.controller.last_verbose
+        };
+
+        let start = tick(&mut test, 40.0);
+        assert!(start.is_some());
+        assert_eq!(tick(&mut test, 40.0), start);
+        assert_eq!(tick(&mut test, 60.0), start);
+        assert_eq!(tick(&mut test, 60.0), start);
+        assert_eq!(tick(&mut test, 60.0), start);
+        assert_ne!(tick(&mut test, 60.0), start);
+
+        // The transition to on is still reported between status lines
+        let transitions: Vec<bool> = events
+            .try_iter()
+            .filter_map(|e| match e {
+                Event::FanStateChanged { on, .. } => Some(on),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(transitions, vec![false, true]);
+    }
+
+    #[test]
+    fn on_ticks_wait_for_consecutive_decisions_but_critical_does_not() {
+        let mut test = TestController::new(
+            "on-ticks",
+            "on_ticks = 3\ndecision_interval_secs = 2\ncritical_temp = 90.0",
+        );
+        test.tick(40.0, 0.0);
+        test.tick(40.0, 0.0);
+
+        // A single decision over the threshold isn't enough
+        for temperature in [60.0, 60.0, 40.0, 40.0] {
+            test.tick(temperature, 0.0);
+            assert_eq!(test.fan().fan_on(), Some(false));
+        }
+        for _ in 0..4 {
+            test.tick(60.0, 0.0);
+            assert_eq!(test.fan().fan_on(), Some(false));
+        }
+        // The third decision over in a row
+        test.tick(60.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+
+        // Critical ignores on_ticks and the decision interval
+        let mut test = TestController::new(
+            "on-ticks-critical",
+            "on_ticks = 3\ndecision_interval_secs = 2\ncritical_temp = 90.0",
+        );
+        test.tick(40.0, 0.0);
+        test.tick(95.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        assert_eq!(test.fan().trigger(), Some(TriggerReason::Critical));
+    }
+
+    #[test]
+    fn samples_between_decisions_feed_the_windows() {
+        let mut test = TestController::new(
+            "decision-interval",
+            "decision_interval_secs = 3\ntemp_intervals = [3]\ncritical_temp = 90.0",
+        );
+        test.tick(40.0, 0.0);
+        test.tick(70.0, 0.0);
+        test.tick(70.0, 0.0);
+        assert_eq!(
+            test.fan().usage().temperature[0].samples(),
+            &[40.0, 70.0, 70.0]
+        );
+        assert_eq!(test.fan().fan_on(), Some(false));
+
+        // The next decision sees every sample taken since
+        test.tick(70.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        test.tick(30.0, 0.0);
+        test.tick(30.0, 0.0);
+        test.tick(30.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+
+        // A critical reading is acted on between decisions
+        test.tick(95.0, 0.0);
+        assert_eq!(test.fan().trigger(), Some(TriggerReason::Critical));
+    }
+
+    #[test]
+    fn purge_runs_for_its_duration_then_hands_over() {
+        let mut test = TestController::new("purge", "startup_purge = { secs = 3, duty = 60.0 }");
+        let mut purging = Vec::new();
+        for _ in 0..5 {
+            let started = test.clock.since_boot().as_secs();
+            test.tick(40.0, 0.0);
+            purging.push((started, test.fan().trigger() == Some(TriggerReason::Purge)));
+        }
+        assert_eq!(
+            purging,
+            [(0, true), (1, true), (2, true), (3, false), (4, false)]
+        );
+        assert_eq!(test.fan().fan_on(), Some(false));
+    }
+
+    #[test]
+    fn switched_profile_applies_on_the_next_tick() {
+        let mut test = TestController::new(
+            "profiles",
+            r#"
+            profile = "quiet"
+
+            [[profiles]]
+            name = "quiet"
+            max_fan_on_temp = 60.0
+
+            [[profiles]]
+            name = "performance"
+            max_fan_on_temp = 45.0
+            "#,
+        );
+        test.tick(52.0, 0.0);
+        test.tick(52.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+        assert_eq!(test.fan().profile(), Some("quiet"));
+
+        test.controller.set_profile(Some("performance")).unwrap();
+        test.tick(52.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        assert_eq!(test.fan().profile(), Some("performance"));
+
+        test.controller.set_profile(None).unwrap();
+        assert_eq!(test.fan().profile(), None);
+        assert!(test.controller.set_profile(Some("turbo")).is_err());
+    }
+
+    #[test]
+    fn reloading_thresholds_keeps_the_windows_and_curve() {
+        let toml = |extra: &str, curve: &str| {
+            format!(
+                "fail_hot = true\ntemp_intervals = [3]\n{}\ncurve = [{}]",
+                extra, curve
+            )
+        };
+        let old_curve = "{ temp = 30.0, duty = 20.0 }, { temp = 80.0, duty = 70.0 }";
+        let new_curve = "{ temp = 30.0, duty = 50.0 }, { temp = 60.0, duty = 100.0 }";
+        let mut test = TestController::new("reload-thresholds", &toml("", old_curve));
+        for temperature in [40.0, 42.0, 44.0] {
+            test.tick(temperature, 0.0);
+        }
+        assert!(!test.fan().temp_alert());
+
+        test.rewrite(&toml("alert_temp = 40.0", new_curve));
+        test.controller.reload_section(ReloadSection::Thresholds);
+        assert!(!test.controller.reload_failed());
+        assert_eq!(
+            test.fan().usage().temperature[0].samples(),
+            &[40.0, 42.0, 44.0]
+        );
+        test.tick(44.0, 0.0);
+        assert!(test.fan().temp_alert());
+        let (low, high) = test.fan().curve_segment().unwrap();
+        assert_eq!((low.duty, high.duty), (20.0, 70.0));
+
+        // A file listing other fans is passed over without failing hot
+        test.rewrite(&format!(
+            "{}\n[[fans]]\nname = \"a\"\n[[fans]]\nname = \"b\"",
+            toml("", old_curve)
+        ));
+        test.controller.reload_section(ReloadSection::Thresholds);
+        assert!(!test.controller.reload_failed());
+        test.tick(44.0, 0.0);
+        assert_ne!(test.fan().trigger(), Some(TriggerReason::FailHot));
+        assert!(test.fan().temp_alert());
+    }
+
+    #[test]
+    fn runaway_rise_is_acted_on_between_decisions() {
+        let mut test = TestController::new(
+            "runaway-between",
+            "decision_interval_secs = 3\nrunaway_rate = 3.0",
+        );
+        test.tick(40.0, 0.0);
+        test.tick(41.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(false));
+
+        // 7°C/s on a sample between decisions, still under the threshold of 50
+        test.tick(48.0, 0.0);
+        assert_eq!(test.fan().fan_on(), Some(true));
+        assert_eq!(test.fan().trigger(), Some(TriggerReason::Runaway));
+        let pwm = test.dir.join("hwmon0").join("pwm1");
+        assert_eq!(fs::read_to_string(pwm).unwrap().trim(), "255");
+    }
+
+    #[test]
+    fn reload_check_rejects_an_insane_curve() {
+        let curve = |points: &str| format!("reload_check = true\ncurve = [{}]", points);
+        let rising = "{ temp = 40.0, duty = 20.0 }, { temp = 80.0, duty = 100.0 }";
+        let mut test = TestController::new("reload-check", &curve(rising));
+        test.tick(60.0, 0.0);
+        test.tick(60.0, 0.0);
+        assert_eq!(test.fan().duty(), Some(60.0));
+        let points = test.controller.config().fans()[0].curve.clone();
+
+        let falling = "{ temp = 40.0, duty = 60.0 }, { temp = 80.0, duty = 30.0 }";
+        test.rewrite(&curve(falling));
+        test.controller.reload_section(ReloadSection::Curve);
+        assert!(test.controller.reload_failed());
+        let unnumbered = "{ temp = 40.0, duty = 20.0 }, { temp = nan, duty = 100.0 }";
+        test.rewrite(&curve(unnumbered));
+        test.controller.reload();
+        assert!(test.controller.reload_failed());
+
+        // Still running on the curve from before
+        assert_eq!(test.controller.config().fans()[0].curve, points);
+        test.tick(60.0, 0.0);
+        assert_eq!(test.fan().duty(), Some(60.0));
+    }
+}
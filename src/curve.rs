@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// A point on a fan curve, running the fan at `duty` percent at `temp` °C
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct CurvePoint {
+    pub temp: f32,
+    pub duty: f32,
+}
+
+/// Fan duty as a function of temperature, interpolated linearly between
+/// points ordered by temperature. Below the first point and above the last
+/// the duty of that end point is held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanCurve {
+    points: Vec<CurvePoint>,
+}
+
+impl FanCurve {
+    /// Build a curve from `points`, which must be non-empty, ordered by
+    /// strictly increasing temperature, with duties between 0 and 100
+    pub fn new(points: &[CurvePoint]) -> Result<Self, String> {
+        validate(points)?;
+
+        Ok(Self {
+            points: points.to_vec(),
+        })
+    }
+
+    pub fn points(&self) -> &[CurvePoint] {
+        &self.points
+    }
+
+    /// Duty in percent to run the fan at for `temp`
+    pub fn duty(&self, temp: f32) -> f32 {
+        let (low, high) = self.segment(temp);
+        if high.temp <= low.temp {
+            return low.duty;
+        }
+
+        let t = ((temp - low.temp) / (high.temp - low.temp)).clamp(0f32, 1f32);
+        low.duty + (high.duty - low.duty) * t
+    }
+
+    /// Points either side of `temp`, both the end point when outside the curve
+    pub fn segment(&self, temp: f32) -> (CurvePoint, CurvePoint) {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if temp <= first.temp {
+            return (first, first);
+        }
+
+        self.points
+            .windows(2)
+            .find(|pair| temp <= pair[1].temp)
+            .map(|pair| (pair[0], pair[1]))
+            .unwrap_or((last, last))
+    }
+}
+
+pub fn validate(points: &[CurvePoint]) -> Result<(), String> {
+    if points.is_empty() {
+        return Err("curve requires at least one point".to_string());
+    }
+    if let Some(point) = points.iter().find(|p| !(0f32..=100f32).contains(&p.duty)) {
+        return Err(format!(
+            "curve duty {} at {}°C must be between 0 and 100",
+            point.duty, point.temp
+        ));
+    }
+    if let Some(pair) = points.windows(2).find(|pair| pair[1].temp <= pair[0].temp) {
+        return Err(format!(
+            "curve temperatures must increase, {}°C follows {}°C",
+            pair[1].temp, pair[0].temp
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Detach from the controlling terminal by forking twice, leaving the
+/// grandchild running in a new session from `/`. Standard input is read from
+/// `/dev/null`, output is appended to `log_file`, and the daemon's PID is
+/// written to `pid_file`. Call before any GPIO lines are requested or signal
+/// handlers registered so they belong to the daemon.
+///
+/// Fails while `pid_file` names another running process, before forking so
+/// the error is still seen. Returns the absolute path of the PID file, to be
+/// removed with `remove_pid_file` on exit.
+pub fn daemonize(pid_file: &Path, log_file: &Path) -> io::Result<PathBuf> {
+    // Resolve everything relative to the starting directory before leaving it
+    let pid_file = std::path::absolute(pid_file)?;
+    check_pid_file(&pid_file)?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+    let null = File::open("/dev/null")?;
+
+    fork_parent_exit()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The session leader could acquire a terminal again, its child can't
+    fork_parent_exit()?;
+
+    std::env::set_current_dir("/")?;
+    unsafe { libc::umask(0o022) };
+
+    redirect(&null, libc::STDIN_FILENO)?;
+    redirect(&log, libc::STDOUT_FILENO)?;
+    redirect(&log, libc::STDERR_FILENO)?;
+
+    write_pid_file(&pid_file)?;
+
+    Ok(pid_file)
+}
+
+/// Write this process's PID to `pid_file`
+pub fn write_pid_file(pid_file: &Path) -> io::Result<()> {
+    fs::write(pid_file, format!("{}\n", std::process::id()))
+}
+
+/// Fail if `pid_file` holds the PID of another process still running. A file
+/// left behind by one that has gone, e.g. after a crash, is stale and fine to
+/// replace.
+pub fn check_pid_file(pid_file: &Path) -> io::Result<()> {
+    let pid = match fs::read_to_string(pid_file) {
+        Ok(contents) => contents.trim().parse::<libc::pid_t>().ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let Some(pid) = pid.filter(|&pid| pid > 0 && pid as u32 != std::process::id()) else {
+        return Ok(());
+    };
+    // Signal 0 only checks the process exists, EPERM meaning it does but
+    // belongs to someone else
+    let running = unsafe { libc::kill(pid, 0) } == 0
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if running {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is already running as PID {}", pid_file.display(), pid),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Remove `pid_file` if it still holds this process's PID, leaving one
+/// written since by another instance
+pub fn remove_pid_file(pid_file: &Path) -> io::Result<()> {
+    let ours = fs::read_to_string(pid_file)?.trim() == std::process::id().to_string();
+    if ours {
+        fs::remove_file(pid_file)?;
+    }
+
+    Ok(())
+}
+
+fn fork_parent_exit() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+fn redirect(file: &File, fd: i32) -> io::Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::process::Command;
+
+    #[test]
+    fn pid_file_of_running_process_is_refused() {
+        let pid_file = testing::temp_dir("pid-running").join("gpio-fan.pid");
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        fs::write(&pid_file, format!("{}\n", child.id())).unwrap();
+
+        let refused = check_pid_file(&pid_file);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(refused.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+
+        // Gone now, so the file is stale
+        check_pid_file(&pid_file).unwrap();
+    }
+
+    #[test]
+    fn pid_file_is_written_and_removed() {
+        let pid_file = testing::temp_dir("pid-file").join("gpio-fan.pid");
+        check_pid_file(&pid_file).unwrap();
+        write_pid_file(&pid_file).unwrap();
+        assert_eq!(
+            fs::read_to_string(&pid_file).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+
+        // Holding our own PID, it isn't taken as another instance
+        check_pid_file(&pid_file).unwrap();
+        remove_pid_file(&pid_file).unwrap();
+        assert!(!pid_file.exists());
+
+        // Another instance's file is left alone
+        fs::write(&pid_file, "1\n").unwrap();
+        remove_pid_file(&pid_file).unwrap();
+        assert!(pid_file.exists());
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Lowest and highest temperature, and highest duty, over one local day
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct DayExtremes {
+    /// Days since the Unix epoch, in local time
+    pub day: i64,
+    pub min_temp: Option<f32>,
+    pub max_temp: Option<f32>,
+    /// Highest duty in percent the fan ran at, 100 while an on/off fan is on
+    pub peak_duty: f32,
+}
+
+impl DayExtremes {
+    fn new(day: i64) -> Self {
+        Self {
+            day,
+            min_temp: None,
+            max_temp: None,
+            peak_duty: 0f32,
+        }
+    }
+}
+
+/// The extremes of the current day and of the last day before it that was
+/// recorded, rolling over at local midnight
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DailyExtremes {
+    pub today: Option<DayExtremes>,
+    pub yesterday: Option<DayExtremes>,
+}
+
+impl DailyExtremes {
+    /// Count a sample taken on `day`, first rolling today over to yesterday
+    /// when `day` has moved on. Returns whether anything changed.
+    pub fn record(&mut self, day: i64, temperature: Option<f32>, duty: f32) -> bool {
+        let before = self.clone();
+        match self.today {
+            Some(today) if today.day == day => {}
+            // A clock set back leaves the day being recorded as it is
+            Some(today) if today.day > day => return false,
+            today => {
+                self.yesterday = today.or(self.yesterday);
+                self.today = Some(DayExtremes::new(day));
+            }
+        }
+
+        if let Some(today) = self.today.as_mut() {
+            if let Some(temp) = temperature {
+                today.min_temp = Some(today.min_temp.map_or(temp, |min| min.min(temp)));
+                today.max_temp = Some(today.max_temp.map_or(temp, |max| max.max(temp)));
+            }
+            today.peak_duty = today.peak_duty.max(duty);
+        }
+        *self != before
+    }
+}
@@ -0,0 +1,57 @@
+use std::io;
+use std::path::PathBuf;
+
+/// Errors returned by the crate's public API
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Requesting or driving a GPIO line failed
+    #[error(transparent)]
+    Gpio(#[from] gpio_cdev::Error),
+    /// Reading or writing a file or device failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The config couldn't be parsed or is invalid
+    #[error("{0}")]
+    Config(String),
+    /// A file included by the config couldn't be loaded
+    #[error("included {}: {source}", path.display())]
+    Include { path: PathBuf, source: Box<Error> },
+    /// A sensor gave a reading that couldn't be understood
+    #[error("{0}")]
+    Sensor(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_variant_displays_its_message() {
+        let io = || io::Error::new(io::ErrorKind::NotFound, "no such chip");
+        let errors = [
+            (Error::from(gpio_cdev::Error::from(io())), "no such chip"),
+            (Error::from(io()), "no such chip"),
+            (
+                Error::Config("unknown zone gpu".to_string()),
+                "unknown zone gpu",
+            ),
+            (
+                Error::Include {
+                    path: PathBuf::from("/etc/gpio-fan/fans.toml"),
+                    source: Box::new(Error::Config("expected a table".to_string())),
+                },
+                "included /etc/gpio-fan/fans.toml: expected a table",
+            ),
+            (
+                Error::Sensor("unexpected output \"\"".to_string()),
+                "unexpected output \"\"",
+            ),
+        ];
+
+        for (error, message) in errors {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
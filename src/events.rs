@@ -0,0 +1,51 @@
+use crate::fan_control::TriggerReason;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What happened during a tick, for consumers outside the control loop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A fan's sensors were sampled. Values are `None` when nothing was read.
+    SampleTaken {
+        fan: String,
+        temperature: Option<f32>,
+        cpu: Option<f32>,
+    },
+    /// A fan turned on or off, changed trigger or changed duty
+    FanStateChanged {
+        fan: String,
+        on: bool,
+        duty: Option<f32>,
+        trigger: Option<TriggerReason>,
+    },
+    /// A fan's sample read no temperature
+    SensorError { fan: String },
+    /// A fan was forced on by a critical temperature, its own or one a sensor
+    /// reports
+    CriticalTriggered {
+        fan: String,
+        temperature: Option<f32>,
+    },
+}
+
+/// Fans events out to every subscriber, dropping those that have gone away
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+use crate::fan_control::FanControl;
+use crate::measurement::Measurement;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Write every sample held in each fan's windows as CSV, one row per sample
+/// with the fan, window and the Unix time it was taken at, oldest first.
+/// Samples are one sample interval apart, the newest taken at `now`.
+pub fn write_csv<W: Write>(fans: &[FanControl], now: SystemTime, out: &mut W) -> io::Result<()> {
+    writeln!(out, "fan,window,interval,timestamp,sample")?;
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    for fan in fans {
+        let usage = fan.usage();
+        let interval = usage.sample_interval();
+        let windows = usage
+            .cpu
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (format!("cpu[{}]", i), m))
+            .chain(
+                usage
+                    .temperature
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| (format!("temperature[{}]", i), m)),
+            );
+        for (window, measurement) in windows {
+            write_window(out, fan.name(), &window, measurement, now, interval)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_window<W: Write>(
+    out: &mut W,
+    fan: &str,
+    window: &str,
+    measurement: &Measurement,
+    now: Duration,
+    interval: Duration,
+) -> io::Result<()> {
+    let samples = measurement.samples();
+    for (i, sample) in samples.iter().enumerate() {
+        let age = interval * (samples.len() - 1 - i) as u32;
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            fan,
+            window,
+            measurement.interval(),
+            now.saturating_sub(age).as_secs(),
+            sample
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestFan};
+
+    #[test]
+    fn one_row_per_sample() {
+        let config = testing::config("temp_intervals = [2]\ncpu_intervals = [1]");
+        let mut fan = TestFan::new(&config);
+        for (temperature, cpu) in [(40.0, 5.0), (41.5, 7.0), (43.0, 9.0)] {
+            fan.update(temperature, cpu);
+        }
+
+        let mut out = Vec::new();
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        write_csv(&[fan.control], now, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "fan,window,interval,timestamp,sample\n\
+             fan0,cpu[0],1,1700000000,9\n\
+             fan0,temperature[0],2,1699999999,41.5\n\
+             fan0,temperature[0],2,1700000000,43\n"
+        );
+    }
+}
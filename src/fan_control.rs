@@ -0,0 +1,3322 @@
+use crate::calibrate;
+use crate::config::{
+    AntiStallConfig, AutoTrigger, BlendConfig, Boundary, ColdStart, Config, CurveSumConfig,
+    FanConfig, HealthCheckConfig, HookFailure, IdleConfig, KernelCoolingConfig, KernelCoolingMode,
+    LowUsageOffConfig, OutputConfig, PulseStep, ScheduleConfig, SensorFloorAction, SpinDownConfig,
+    SwitchLimitAction, SwitchLimitConfig, WarmupState,
+};
+use crate::curve::{CurvePoint, FanCurve};
+use crate::daily::DailyExtremes;
+use crate::hook;
+use crate::output::{self, FanOutput, LastStart};
+#[cfg(feature = "rgb")]
+use crate::rgb;
+use crate::sensors;
+use crate::usage::{Usage, WindowRole};
+use crate::Error;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Why the fan was last driven on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriggerReason {
+    /// Temperature window at `window` averaged above the temperature threshold
+    Temperature { window: usize },
+    /// Zone at `zone` of the fan's zones above that zone's threshold
+    Zone { zone: usize },
+    /// CPU usage window at `window` averaged above the usage threshold
+    CpuUsage { window: usize },
+    /// Sustained CPU frequency above the frequency trigger
+    CpuFrequency,
+    /// Temperature in the shortest window rising faster than the configured rate
+    RiseRate,
+    /// Fan kept running while the latest temperature average is still rising
+    Trend,
+    /// Fan kept running until the temperature falls to the cooldown target
+    Cooldown,
+    /// Fan turned on by the cold start setting on the first update
+    ColdStart,
+    /// Fan forced on because the config failed to reload
+    FailHot,
+    /// Fan running at the duty given by its curve
+    Curve,
+    /// Fan running at full speed above the curve's full speed temperature
+    FullSpeed,
+    /// Blended temperature and CPU usage demand calling for the fan
+    Demand,
+    /// Latest reading at or above the critical temperature
+    Critical,
+    /// No temperature read for longer than the staleness limit
+    Stale,
+    /// A component reading above its own critical temperature
+    SensorCritical,
+    /// Fan mirroring the fan it follows
+    Follow,
+    /// Fan forced on because a hook command failed
+    HookFailure,
+    /// Fan run until a temperature is first read
+    NoReading,
+    /// Fan held on while the windows fill on start
+    Warmup,
+    /// Every sensor stuck at the floor reading of sensors not reporting
+    SensorFloor,
+    /// Fan run at the duty scheduled for the time of day
+    Schedule,
+    /// Fan forced on because the supervisor's heartbeat lapsed
+    Heartbeat,
+    /// Fan run to check its tach
+    HealthCheck,
+    /// Fan run at the purge duty on start
+    Purge,
+    /// Fan run because it has been switched too often
+    SwitchLimit,
+    /// Fan run at the duty set through the control socket
+    Manual,
+    /// Fan run at the state the kernel's thermal governor requested
+    Kernel,
+    /// Fan forced on for the duration of a maintenance task
+    Maintenance,
+    /// Fan forced on because another fan's readings are critical
+    SharedCritical,
+    /// Fan run ahead of a schedule entry to cool the box before it starts
+    Precool,
+    /// Temperature rising between samples faster than the runaway rate
+    Runaway,
+}
+
+impl fmt::Display for TriggerReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerReason::Temperature { window } => write!(f, "temperature[{}]", window),
+            TriggerReason::Zone { zone } => write!(f, "zone[{}]", zone),
+            TriggerReason::CpuUsage { window } => write!(f, "cpu[{}]", window),
+            TriggerReason::CpuFrequency => write!(f, "cpu frequency"),
+            TriggerReason::RiseRate => write!(f, "rise rate"),
+            TriggerReason::Trend => write!(f, "trend"),
+            TriggerReason::Cooldown => write!(f, "cooldown"),
+            TriggerReason::ColdStart => write!(f, "cold start"),
+            TriggerReason::FailHot => write!(f, "fail hot"),
+            TriggerReason::Curve => write!(f, "curve"),
+            TriggerReason::FullSpeed => write!(f, "full speed"),
+            TriggerReason::Demand => write!(f, "demand"),
+            TriggerReason::Critical => write!(f, "critical"),
+            TriggerReason::Stale => write!(f, "stale"),
+            TriggerReason::SensorCritical => write!(f, "sensor critical"),
+            TriggerReason::Follow => write!(f, "follow"),
+            TriggerReason::HookFailure => write!(f, "hook failure"),
+            TriggerReason::NoReading => write!(f, "no reading"),
+            TriggerReason::Warmup => write!(f, "warmup"),
+            TriggerReason::SensorFloor => write!(f, "sensor floor"),
+            TriggerReason::Schedule => write!(f, "schedule"),
+            TriggerReason::Heartbeat => write!(f, "heartbeat"),
+            TriggerReason::HealthCheck => write!(f, "health check"),
+            TriggerReason::Purge => write!(f, "purge"),
+            TriggerReason::SwitchLimit => write!(f, "switch limit"),
+            TriggerReason::Manual => write!(f, "manual"),
+            TriggerReason::Kernel => write!(f, "kernel"),
+            TriggerReason::Maintenance => write!(f, "maintenance"),
+            TriggerReason::SharedCritical => write!(f, "shared critical"),
+            TriggerReason::Precool => write!(f, "precool"),
+            TriggerReason::Runaway => write!(f, "runaway"),
+        }
+    }
+}
+
+/// Where a duty set by hand came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManualSource {
+    /// Interface the setting arrived on, as the daemon saw it, e.g.
+    /// `control socket uid 1000`
+    pub interface: String,
+    /// Name the client gave itself, taken as sent and not checked
+    pub label: Option<String>,
+}
+
+impl ManualSource {
+    pub fn new(interface: String, label: Option<String>) -> Self {
+        Self { interface, label }
+    }
+}
+
+impl fmt::Display for ManualSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{}, labelled {}", self.interface, label),
+            None => write!(f, "{}", self.interface),
+        }
+    }
+}
+
+/// Most decisions to wait between attempts to re-open a failed output
+const MAX_REOPEN_BACKOFF: u32 = 64;
+
+pub struct FanControl {
+    name: String,
+    usage: Usage,
+    /// `None` after a write failed, until it is re-opened
+    output: Option<Box<dyn FanOutput>>,
+    output_config: OutputConfig,
+    brake_pulse: Vec<PulseStep>,
+    start_stagger: Duration,
+    /// Shared with the other fans to stagger starts against
+    last_start: Option<LastStart>,
+    #[cfg(feature = "rgb")]
+    rgb: Option<rgb::Ws2812<std::fs::File>>,
+    reopen_in: u32,
+    reopen_backoff: u32,
+    fan_on: Option<bool>,
+    duty: Option<f32>,
+    trigger: Option<TriggerReason>,
+    trigger_counts: HashMap<TriggerReason, usize>,
+    over_ticks: u32,
+    enabled: bool,
+    delta_alert: bool,
+    alert_temp: Option<f32>,
+    /// Whether a temperature window was above `alert_temp` on the last sample
+    temp_alert: bool,
+    max_fan_on_temp: f32,
+    max_fan_on_cpu: f32,
+    /// Every automatic trigger, in the order they are checked
+    trigger_order: Vec<AutoTrigger>,
+    /// Duty set through the control socket, replacing automatic control
+    manual: Option<f32>,
+    /// Where `manual` was set from
+    manual_source: Option<ManualSource>,
+    threshold_boundary: Boundary,
+    usage_trigger_min_temp: Option<f32>,
+    cpu_freq_trigger: Option<f32>,
+    cooldown_target: Option<f32>,
+    max_fan_on_critical_percent: Option<f32>,
+    rise_rate_trigger: Option<f32>,
+    runaway_rate: Option<f32>,
+    cold_start: ColdStart,
+    /// Decisions left to hold the fan in `warmup_state` for
+    warmup_left: u32,
+    warmup_state: WarmupState,
+    /// Samples left of the startup purge and the duty it runs at
+    purge: Option<(u64, f32)>,
+    temp_delta_alert: Option<f32>,
+    temp_delta_period: Option<Duration>,
+    sensor_critical_force_on: bool,
+    /// Whether the sensors were at the floor on the last decision
+    at_floor: bool,
+    /// Whether CPU usage was stuck at 0% on the last decision
+    usage_at_zero: bool,
+    critical_temp: Option<f32>,
+    panic_temp: Option<f32>,
+    /// `None` when the panic command is only logged
+    panic_command: Option<String>,
+    /// Set once the panic command has been run, so it is run only once
+    panicked: bool,
+    on_ticks: u32,
+    off_confirm_samples: u64,
+    debounce_decisions: u32,
+    /// Consecutive decisions calling for the fan to switch
+    switch_pending: u32,
+    /// Samples the threshold stays raised for after turning off, and by how
+    /// much
+    heat_soak: Option<(u64, f32)>,
+    /// Samples left of the heat soak since the fan last turned off
+    soak_left: u64,
+    /// Band around the threshold within which the tie breaker decides
+    tie_band: f32,
+    /// Samples since the thresholds first called for the fan to turn off,
+    /// while that is waiting to be confirmed
+    off_pending: Option<u64>,
+    curve: Option<FanCurve>,
+    full_speed_temp: Option<f32>,
+    full_speed_hysteresis: f32,
+    curve_min_delta: Option<f32>,
+    curve_max_step: Option<f32>,
+    curve_sum: Option<CurveSumConfig>,
+    /// Temperature and duty the curve last changed the duty at
+    curve_held: Option<(f32, f32)>,
+    blend: Option<BlendConfig>,
+    variable_speed: bool,
+    follow: Option<Follow>,
+    idle: Option<IdleConfig>,
+    off_below_temp: Option<f32>,
+    /// Whether the last decision kept the fan off for being below
+    /// `off_below_temp`
+    below_off_floor: bool,
+    schedule: Vec<ScheduleConfig>,
+    /// Local time, minutes from midnight, as last given by the controller
+    time_of_day: Option<u32>,
+    /// Consecutive samples the system has been idle for
+    idle_samples: u64,
+    low_usage_off: Option<LowUsageOffConfig>,
+    /// Consecutive samples the CPU usage has been below `low_usage_off`'s
+    low_usage_samples: u64,
+    anti_stall: Option<AntiStallConfig>,
+    min_duty: Option<f32>,
+    tach: Option<PathBuf>,
+    health_check: Option<HealthCheckConfig>,
+    /// Samples since the last health check, or left in the running one
+    health_samples: u64,
+    checking_health: bool,
+    /// Whether the last health check found the RPM out of bounds
+    degraded: bool,
+    spin_down: Option<SpinDownConfig>,
+    /// Consecutive samples the fan has been on with its tach below
+    /// `spin_down.min_rpm`
+    slow_samples: u64,
+    spinning_down: bool,
+    /// Whether the last spin down check failed to read the tach
+    tach_failing: bool,
+    kernel_cooling: Option<KernelCoolingConfig>,
+    /// Duty the kernel requested this decision, which the fan isn't run
+    /// below, with `kernel_cooling` in max mode
+    kernel_floor: Option<f32>,
+    /// Whether the kernel's cooling device failed to read last decision
+    kernel_failing: bool,
+    /// Time spent writing the output since last taken
+    write_time: Duration,
+    /// Profile the fan's settings were taken from, if any
+    profile: Option<String>,
+    /// Whether turning on and off is printed, rather than left to the
+    /// journal
+    log_transitions: bool,
+    /// The day's temperature extremes and peak duty, when tracked
+    daily: Option<DailyExtremes>,
+    switch_limit: Option<SwitchLimitConfig>,
+    /// Samples taken since starting, the clock switches are timed by
+    samples: u64,
+    /// Sample each recent switch happened at, oldest first
+    switches: VecDeque<u64>,
+    /// Whether the fan is being held by its switch limit
+    switch_limited: bool,
+    /// Consecutive samples the duty has been below the stall threshold for
+    stall_samples: u64,
+    /// Whether the output is currently pulsed to the anti-stall duty
+    nudging: bool,
+    on_command: Option<String>,
+    off_command: Option<String>,
+    hook_timeout: Duration,
+    hook_failure: HookFailure,
+    /// State whose hook failed under `force-on`, keeping the fan on until
+    /// the hook is retried successfully
+    hook_forced: Option<bool>,
+    alarm: Option<Box<dyn FanOutput>>,
+    alarm_on: bool,
+}
+
+/// State of a fan following another
+struct Follow {
+    leader: String,
+    duty_offset: f32,
+    off_delay_ticks: u64,
+    off_in: u64,
+}
+
+impl FanControl {
+    pub fn new(name: String, config: &FanConfig, usage: Usage) -> Result<Self, Error> {
+        let output = output::from_config(&config.output)?;
+        let alarm = config
+            .alarm_output
+            .as_ref()
+            .map(output::from_config)
+            .transpose()?;
+        #[cfg(feature = "rgb")]
+        let rgb = config.rgb.as_ref().map(rgb::Ws2812::open).transpose()?;
+        let mut fan_control = Self::with_output(name, config, usage, output)?;
+        #[cfg(feature = "rgb")]
+        {
+            fan_control.rgb = rgb;
+        }
+        if let Some(mut alarm) = alarm {
+            alarm.set(false)?;
+            fan_control.alarm = Some(alarm);
+        }
+
+        Ok(fan_control)
+    }
+
+    /// As `new`, driving `output` instead of the one configured
+    pub fn with_output(
+        name: String,
+        config: &FanConfig,
+        usage: Usage,
+        output: Box<dyn FanOutput>,
+    ) -> Result<Self, Error> {
+        let curve = if config.curve.is_empty() {
+            None
+        } else {
+            Some(FanCurve::new(&config.curve).map_err(Error::Config)?)
+        };
+
+        let interval = usage.sample_interval().as_secs().max(1);
+        let follow = config.follow.as_ref().map(|follow| Follow {
+            leader: follow.fan.clone(),
+            duty_offset: follow.duty_offset,
+            off_delay_ticks: follow.off_delay_secs / interval,
+            off_in: 0,
+        });
+
+        Ok(Self {
+            name,
+            usage,
+            output: Some(output::braked(output, &config.brake_pulse)),
+            output_config: config.output.clone(),
+            brake_pulse: config.brake_pulse.clone(),
+            start_stagger: Duration::from_millis(config.start_stagger_ms),
+            last_start: None,
+            #[cfg(feature = "rgb")]
+            rgb: None,
+            reopen_in: 0,
+            reopen_backoff: 1,
+            fan_on: None,
+            duty: None,
+            trigger: None,
+            trigger_counts: HashMap::new(),
+            over_ticks: 0,
+            enabled: true,
+            delta_alert: false,
+            alert_temp: config.alert_temp,
+            temp_alert: false,
+            max_fan_on_temp: config.max_fan_on_temp,
+            max_fan_on_cpu: config.max_fan_on_cpu,
+            trigger_order: AutoTrigger::resolve(&config.trigger_order),
+            manual: None,
+            manual_source: None,
+            threshold_boundary: config.threshold_boundary,
+            usage_trigger_min_temp: config.usage_trigger_min_temp,
+            cpu_freq_trigger: config.cpu_freq_trigger,
+            cooldown_target: config.off_threshold(),
+            max_fan_on_critical_percent: config.max_fan_on_critical_percent,
+            rise_rate_trigger: config.rise_rate_trigger,
+            runaway_rate: config.runaway_rate,
+            cold_start: config.cold_start,
+            warmup_left: config.warmup_ticks,
+            warmup_state: config.warmup_state,
+            purge: config
+                .startup_purge
+                .as_ref()
+                .map(|p| ((p.secs / interval).max(1), p.duty)),
+            temp_delta_alert: config.temp_delta_alert,
+            temp_delta_period: config.temp_delta_secs.map(Duration::from_secs),
+            sensor_critical_force_on: config.sensor_critical_force_on,
+            at_floor: false,
+            usage_at_zero: false,
+            critical_temp: config.critical_temp,
+            panic_temp: config.panic_temp,
+            panic_command: Some(config.panic_command.clone()),
+            panicked: false,
+            on_ticks: config.on_ticks,
+            off_confirm_samples: config.off_confirm_secs / interval,
+            debounce_decisions: config.debounce_decisions,
+            switch_pending: 0,
+            heat_soak: config
+                .heat_soak
+                .as_ref()
+                .map(|h| ((h.secs / interval).max(1), h.margin)),
+            soak_left: 0,
+            off_pending: None,
+            tie_band: config.tie_breaker.as_ref().map_or(0f32, |t| t.band),
+            curve,
+            full_speed_temp: config.full_speed_temp,
+            full_speed_hysteresis: config.full_speed_hysteresis,
+            curve_min_delta: config.curve_min_delta,
+            curve_max_step: config.curve_max_step,
+            curve_sum: config.curve_sum.clone(),
+            curve_held: None,
+            blend: config.blend.clone(),
+            variable_speed: config.output.variable_speed(),
+            follow,
+            idle: config.idle.clone(),
+            off_below_temp: config.off_below_temp,
+            below_off_floor: false,
+            schedule: config.schedule.clone(),
+            time_of_day: None,
+            idle_samples: 0,
+            low_usage_off: config.low_usage_off.clone(),
+            low_usage_samples: 0,
+            anti_stall: config.anti_stall.clone(),
+            min_duty: config.min_duty,
+            tach: config.tach.clone(),
+            health_check: config.health_check.clone(),
+            health_samples: 0,
+            checking_health: false,
+            degraded: false,
+            spin_down: config.spin_down.clone(),
+            slow_samples: 0,
+            spinning_down: false,
+            tach_failing: false,
+            kernel_cooling: config.kernel_cooling.clone(),
+            kernel_floor: None,
+            kernel_failing: false,
+            write_time: Duration::ZERO,
+            profile: None,
+            log_transitions: true,
+            daily: None,
+            switch_limit: config.switch_limit.clone(),
+            samples: 0,
+            switches: VecDeque::new(),
+            switch_limited: false,
+            stall_samples: 0,
+            nudging: false,
+            on_command: config.on_command.clone(),
+            off_command: config.off_command.clone(),
+            hook_timeout: Duration::from_secs(config.hook_timeout_secs),
+            hook_failure: config.hook_failure,
+            hook_forced: None,
+            alarm: None,
+            alarm_on: false,
+        })
+    }
+
+    /// Take up the thresholds `config` now sets, keeping the windows and
+    /// the fan's state
+    pub fn reload_thresholds(&mut self, config: &FanConfig) {
+        self.alert_temp = config.alert_temp;
+        self.max_fan_on_temp = config.max_fan_on_temp;
+        self.max_fan_on_cpu = config.max_fan_on_cpu;
+        self.usage_trigger_min_temp = config.usage_trigger_min_temp;
+        self.cpu_freq_trigger = config.cpu_freq_trigger;
+        self.cooldown_target = config.off_threshold();
+        self.max_fan_on_critical_percent = config.max_fan_on_critical_percent;
+        self.rise_rate_trigger = config.rise_rate_trigger;
+        self.runaway_rate = config.runaway_rate;
+        self.temp_delta_alert = config.temp_delta_alert;
+        self.critical_temp = config.critical_temp;
+        self.panic_temp = config.panic_temp;
+        self.off_below_temp = config.off_below_temp;
+    }
+
+    /// Follow the curve `config` now sets, keeping the windows and the
+    /// fan's state
+    pub fn reload_curve(&mut self, config: &FanConfig) -> Result<(), Error> {
+        self.curve = if config.curve.is_empty() {
+            None
+        } else {
+            Some(FanCurve::new(&config.curve).map_err(Error::Config)?)
+        };
+        self.full_speed_temp = config.full_speed_temp;
+        self.full_speed_hysteresis = config.full_speed_hysteresis;
+        self.curve_min_delta = config.curve_min_delta;
+        self.curve_max_step = config.curve_max_step;
+        self.curve_sum = config.curve_sum.clone();
+        self.curve_held = None;
+
+        Ok(())
+    }
+
+    /// Follow the sensors `config` and `fan` now list, keeping the windows
+    /// and the fan's state
+    pub fn reload_sensors(&mut self, config: &Config, fan: &FanConfig) {
+        self.usage.reload_sensors(config, fan);
+    }
+
+    /// Only log reaching the panic temperature rather than running the
+    /// panic command, for simulations that mustn't power off the host
+    pub fn without_panic_command(mut self) -> Self {
+        self.panic_command = None;
+        self
+    }
+
+    /// Record the profile the fan's settings were taken from, for status
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Leave printing the fan turning on and off to whoever records it
+    /// instead, such as the journal
+    pub fn without_transition_log(mut self) -> Self {
+        self.log_transitions = false;
+        self
+    }
+
+    /// Stagger starting the fan after the other fans sharing `last_start`
+    /// by `start_stagger_ms`
+    pub fn with_start_stagger(mut self, last_start: &LastStart) -> Self {
+        self.output = self
+            .output
+            .take()
+            .map(|output| output::staggered(output, self.start_stagger, last_start));
+        self.last_start = Some(Rc::clone(last_start));
+        self
+    }
+
+    /// Track the day's temperature extremes and peak duty, carrying on
+    /// from `daily` as kept from a previous run
+    pub fn with_daily(mut self, daily: DailyExtremes) -> Self {
+        self.daily = Some(daily);
+        self
+    }
+
+    /// Count the latest sample and state towards `day`'s extremes, when
+    /// tracked, returning whether they changed
+    pub fn record_day(&mut self, day: i64) -> bool {
+        let duty = match (self.duty, self.fan_on) {
+            (Some(duty), _) => duty,
+            (None, Some(true)) => 100f32,
+            (None, _) => 0f32,
+        };
+        let temperature = self.usage.last_temperature();
+        self.daily
+            .as_mut()
+            .is_some_and(|daily| daily.record(day, temperature, duty))
+    }
+
+    pub fn daily(&self) -> Option<&DailyExtremes> {
+        self.daily.as_ref()
+    }
+
+    fn update_fan(&mut self, trigger: Option<TriggerReason>) -> Option<bool> {
+        self.restore_output();
+        let trigger = trigger.or_else(|| {
+            self.kernel_floor
+                .filter(|&floor| floor > 0f32)
+                .map(|_| TriggerReason::Kernel)
+        });
+        let trigger = self.limit_switch(trigger);
+        let state = trigger.is_some();
+        if self.fan_on != Some(state) {
+            match trigger {
+                Some(reason) => {
+                    if self.log_transitions {
+                        println!("{} ON: {}", self.name, reason);
+                    }
+                    *self.trigger_counts.entry(reason).or_default() += 1;
+                }
+                None if self.log_transitions => println!("{} OFF", self.name),
+                None => {}
+            }
+        }
+        // A fan last driven by duty may be running at any speed, so rewrite it
+        if self.fan_on != Some(state) || self.duty.is_some() {
+            self.write_output(|output| output.set(state));
+        }
+        let changed = self.fan_on != Some(state);
+        if changed {
+            self.soak_left = match self.heat_soak {
+                Some((samples, _)) if !state && self.fan_on.is_some() => samples,
+                _ => 0,
+            };
+        }
+        self.fan_on = Some(state);
+        self.duty = None;
+        self.trigger = trigger;
+        if changed {
+            self.run_hook(state);
+        }
+
+        self.fan_on
+    }
+
+    /// Hold the fan once it has been switched `switch_limit` times within
+    /// the window, returning the trigger to drive it with instead of
+    /// `trigger`. Only critical and runaway temperatures can switch a held
+    /// fan on.
+    fn limit_switch(&mut self, trigger: Option<TriggerReason>) -> Option<TriggerReason> {
+        let Some(limit) = &self.switch_limit else {
+            return trigger;
+        };
+        let window = limit.window_secs / self.usage.sample_interval().as_secs().max(1);
+        while self
+            .switches
+            .front()
+            .is_some_and(|&at| self.samples.saturating_sub(at) >= window)
+        {
+            self.switches.pop_front();
+        }
+
+        let Some(current) = self.fan_on else {
+            return trigger;
+        };
+        let limited = self.switches.len() >= limit.max as usize;
+        if limited != self.switch_limited {
+            if limited {
+                eprintln!(
+                    "{} switched {} times in {}s, holding it {}",
+                    self.name,
+                    self.switches.len(),
+                    limit.window_secs,
+                    match (limit.action, current) {
+                        (SwitchLimitAction::FanOn, _) | (_, true) => "on",
+                        _ => "off",
+                    }
+                );
+            } else {
+                println!("{} switch limit cleared", self.name);
+            }
+            self.switch_limited = limited;
+        }
+
+        // Critical or runaway temperatures and a duty set by hand aren't
+        // held back
+        let bypass = self.manual.is_some()
+            || matches!(
+                trigger,
+                Some(
+                    TriggerReason::Critical
+                        | TriggerReason::SensorCritical
+                        | TriggerReason::Runaway
+                )
+            );
+        let trigger = match (limited, limit.action) {
+            (false, _) => trigger,
+            _ if bypass => trigger,
+            (true, SwitchLimitAction::FanOn) => trigger.or(Some(TriggerReason::SwitchLimit)),
+            (true, SwitchLimitAction::Hold) if current => trigger
+                .or(self.trigger)
+                .or(Some(TriggerReason::SwitchLimit)),
+            (true, SwitchLimitAction::Hold) => None,
+        };
+        if trigger.is_some() != current {
+            self.switches.push_back(self.samples);
+        }
+
+        trigger
+    }
+
+    fn update_duty(&mut self, duty: f32, reason: TriggerReason) -> Option<bool> {
+        self.restore_output();
+        // Never below what the kernel's governor asks for
+        let (duty, reason) = match self.kernel_floor {
+            Some(floor) if floor > duty => (floor, TriggerReason::Kernel),
+            _ => (duty, reason),
+        };
+        let state = duty > 0f32;
+        let trigger = state.then_some(reason);
+        if self.fan_on != Some(state) || (state && self.trigger != trigger) {
+            match trigger {
+                Some(reason) => {
+                    if self.log_transitions {
+                        println!("{} ON: {}", self.name, reason);
+                    }
+                    *self.trigger_counts.entry(reason).or_default() += 1;
+                }
+                None if self.log_transitions => println!("{} OFF", self.name),
+                None => {}
+            }
+        }
+        let nudge = state && self.stall_nudge();
+        if self.duty != Some(duty) || self.nudging != nudge {
+            let written = match &self.anti_stall {
+                Some(anti_stall) if nudge => duty.max(anti_stall.duty),
+                _ => duty,
+            };
+            let written = match self.min_duty {
+                Some(min_duty) if state => written.max(min_duty),
+                _ => written,
+            };
+            self.write_output(|output| output.set_duty(written));
+        }
+        self.nudging = nudge;
+        let changed = self.fan_on != Some(state);
+        self.fan_on = Some(state);
+        self.duty = Some(duty);
+        self.trigger = trigger;
+        if changed {
+            self.run_hook(state);
+        }
+
+        self.fan_on
+    }
+
+    /// Run the hook command for the fan having turned on or off, applying
+    /// the failure policy if it doesn't succeed
+    fn run_hook(&mut self, on: bool) {
+        let hook = if on { "on_command" } else { "off_command" };
+        let e = match self.call_hook(on) {
+            None => return,
+            Some(Ok(())) => {
+                if self.hook_forced.take().is_some() {
+                    println!(
+                        "{}: {} succeeded, no longer forcing the fan on",
+                        self.name, hook
+                    );
+                }
+                return;
+            }
+            Some(Err(e)) => e,
+        };
+
+        match self.hook_failure {
+            HookFailure::Ignore => {}
+            HookFailure::Log => eprintln!("{}: {} failed: {}", self.name, hook, e),
+            HookFailure::ForceOn => {
+                eprintln!("{}: {} failed, forcing the fan on: {}", self.name, hook, e);
+                if !on {
+                    self.update_fan(Some(TriggerReason::HookFailure));
+                }
+                // Set after turning on, which runs the on hook in turn
+                self.hook_forced = Some(on);
+            }
+        }
+    }
+
+    /// Run the hook command for `on`, `None` without one
+    fn call_hook(&self, on: bool) -> Option<io::Result<()>> {
+        let command = if on {
+            self.on_command.as_ref()
+        } else {
+            self.off_command.as_ref()
+        }?;
+        let trigger = self.trigger.map(|t| t.to_string()).unwrap_or_default();
+        let env = [
+            ("FAN_NAME", self.name.as_str()),
+            ("FAN_STATE", if on { "on" } else { "off" }),
+            ("FAN_TRIGGER", trigger.as_str()),
+        ];
+
+        Some(hook::run(command, &env, self.hook_timeout))
+    }
+
+    /// Write to the output, dropping it to be re-opened if the write fails,
+    /// e.g. because the chip went away with a driver reload
+    fn write_output<F>(&mut self, write: F)
+    where
+        F: FnOnce(&mut dyn FanOutput) -> Result<(), Error>,
+    {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        let started = Instant::now();
+        let written = write(output.as_mut());
+        self.write_time += started.elapsed();
+        if let Err(e) = written {
+            eprintln!("Unable to set {} output, re-opening it: {}", self.name, e);
+            self.output = None;
+            self.reopen_in = 1;
+        }
+    }
+
+    /// Try to re-open a failed output, backing off exponentially between
+    /// attempts. Once open again it is driven to the current state.
+    fn restore_output(&mut self) {
+        if self.output.is_some() {
+            return;
+        }
+        self.reopen_in = self.reopen_in.saturating_sub(1);
+        if self.reopen_in > 0 {
+            return;
+        }
+
+        match output::from_config(&self.output_config) {
+            Ok(output) => {
+                let output = output::braked(output, &self.brake_pulse);
+                self.output = Some(match &self.last_start {
+                    Some(last_start) => output::staggered(output, self.start_stagger, last_start),
+                    None => output,
+                });
+                let (duty, on) = (self.duty, self.fan_on == Some(true));
+                self.write_output(|output| match duty {
+                    Some(duty) => output.set_duty(duty),
+                    None => output.set(on),
+                });
+                if self.output.is_some() {
+                    println!("{} output re-opened", self.name);
+                    self.reopen_backoff = 1;
+                    return;
+                }
+            }
+            Err(e) => eprintln!("Unable to re-open {} output: {}", self.name, e),
+        }
+        self.reopen_backoff = (self.reopen_backoff * 2).min(MAX_REOPEN_BACKOFF);
+        self.reopen_in = self.reopen_backoff;
+    }
+
+    /// Drive the fan on for `reason` rather than deciding from its
+    /// measurements
+    pub fn force(&mut self, reason: TriggerReason) -> Option<bool> {
+        if !self.enabled {
+            return self.fan_on;
+        }
+        self.update_fan(Some(reason))
+    }
+
+    /// Whether the latest readings are critical, at or above `critical_temp`
+    /// or above a sensor's own critical temperature where that forces the
+    /// fan on
+    pub fn critical(&self) -> bool {
+        let configured = self
+            .critical_temp
+            .zip(self.usage.last_temperature())
+            .is_some_and(|(critical, temp)| temp >= critical);
+        let sensor = self.sensor_critical_force_on && self.usage.over_critical().is_some();
+
+        self.enabled && (configured || sensor)
+    }
+
+    /// Whether the latest sample can't wait for the next decision: a
+    /// critical temperature or a runaway rise
+    pub fn urgent(&self) -> bool {
+        self.critical() || (self.enabled && self.runaway().is_some())
+    }
+
+    /// Rise between the last two samples in °C/sec, when faster than
+    /// `runaway_rate`
+    fn runaway(&self) -> Option<f32> {
+        let limit = self.runaway_rate?;
+        self.usage.sample_rise_rate().filter(|&rate| rate > limit)
+    }
+
+    /// Take new measurements and decide the fan state from them
+    pub fn update(&mut self) -> Option<bool> {
+        self.sample();
+        self.decide()
+    }
+
+    /// Take new measurements without re-evaluating the fan
+    pub fn sample(&mut self) {
+        if self.enabled {
+            self.samples += 1;
+            if let Some(samples) = self.off_pending.as_mut() {
+                *samples += 1;
+            }
+            self.soak_left = self.soak_left.saturating_sub(1);
+            self.purge = self.purge.and_then(|(left, duty)| match left {
+                0 => {
+                    println!("{} purge done", self.name);
+                    None
+                }
+                left => Some((left - 1, duty)),
+            });
+            self.usage.update();
+            self.check_panic();
+            self.check_temp_alert();
+            self.check_delta_alert();
+            self.check_idle();
+            self.check_low_usage();
+            self.check_stall();
+            self.check_health();
+            self.check_spin_down();
+            #[cfg(feature = "rgb")]
+            self.show_rgb();
+        }
+    }
+
+    /// Color the fan's LEDs for the latest temperature, leaving them be
+    /// without one
+    #[cfg(feature = "rgb")]
+    fn show_rgb(&mut self) {
+        let (Some(led), Some(temperature)) = (self.rgb.as_mut(), self.usage.last_temperature())
+        else {
+            return;
+        };
+        if let Err(e) = led.show(temperature) {
+            eprintln!(
+                "Unable to set {} LEDs, no longer driving them: {}",
+                self.name, e
+            );
+            self.rgb = None;
+        }
+    }
+
+    /// Flag the fan as spinning down once its tach has read below the
+    /// minimum for long enough while it is on, clearing it once the fan is
+    /// seen turning properly again
+    fn check_spin_down(&mut self) {
+        let (Some(spin_down), Some(tach)) = (&self.spin_down, &self.tach) else {
+            return;
+        };
+        if self.fan_on != Some(true) {
+            self.slow_samples = 0;
+            return;
+        }
+        let rpm = match calibrate::read_rpm(tach) {
+            Ok(rpm) => rpm,
+            Err(e) => {
+                if !self.tach_failing {
+                    eprintln!("Unable to read {} tach: {}", self.name, e);
+                }
+                self.tach_failing = true;
+                return;
+            }
+        };
+        self.tach_failing = false;
+
+        if rpm >= spin_down.min_rpm {
+            self.slow_samples = 0;
+            if self.spinning_down {
+                println!("{} spinning normally again: {} RPM", self.name, rpm);
+                self.spinning_down = false;
+            }
+            return;
+        }
+        self.slow_samples += 1;
+        let sustained = spin_down.secs / self.usage.sample_interval().as_secs().max(1);
+        if !self.spinning_down && self.slow_samples >= sustained.max(1) {
+            eprintln!(
+                "{} spinning down: {} RPM while on, below {} for {}s, it may be failing",
+                self.name, rpm, spin_down.min_rpm, spin_down.secs
+            );
+            self.spinning_down = true;
+        }
+    }
+
+    /// Start a health check once one is due, and check the tach once it has
+    /// run long enough
+    fn check_health(&mut self) {
+        let (Some(check), Some(tach)) = (&self.health_check, &self.tach) else {
+            return;
+        };
+        let samples = |secs: u64| secs / self.usage.sample_interval().as_secs().max(1);
+        self.health_samples += 1;
+
+        if !self.checking_health {
+            if self.health_samples < samples(check.every_secs) {
+                return;
+            }
+            self.health_samples = 0;
+            if check.skip_if_running && self.fan_on == Some(true) {
+                return;
+            }
+            println!("{} health check", self.name);
+            self.checking_health = true;
+            self.drive_health_check();
+            return;
+        }
+        if self.health_samples < samples(check.run_secs) {
+            return;
+        }
+
+        self.checking_health = false;
+        let (min, max) = (check.min_rpm, check.max_rpm);
+        let degraded = match calibrate::read_rpm(tach) {
+            Ok(rpm) if rpm < min || max.is_some_and(|max| rpm > max) => {
+                let expected = match max {
+                    Some(max) => format!("{}-{}", min, max),
+                    None => format!("at least {}", min),
+                };
+                eprintln!(
+                    "{} degraded: {} RPM at {}%, expected {}",
+                    self.name, rpm, check.duty, expected
+                );
+                true
+            }
+            Ok(rpm) => {
+                println!("{} healthy: {} RPM", self.name, rpm);
+                false
+            }
+            Err(e) => {
+                eprintln!("{} degraded, unable to read tach: {}", self.name, e);
+                true
+            }
+        };
+        self.degraded = degraded;
+        self.health_samples = 0;
+    }
+
+    /// Run the fan for a health check at its duty
+    fn drive_health_check(&mut self) -> Option<bool> {
+        let duty = self.health_check.as_ref().map_or(100f32, |c| c.duty);
+        if self.variable_speed {
+            return self.update_duty(duty, TriggerReason::HealthCheck);
+        }
+        self.update_fan(Some(TriggerReason::HealthCheck))
+    }
+
+    /// Run the panic command the moment a reading reaches the panic
+    /// temperature, without waiting for a decision
+    fn check_panic(&mut self) {
+        let Some(panic) = self.panic_temp else {
+            return;
+        };
+        let Some(temp) = self.usage.last_temperature().filter(|t| *t >= panic) else {
+            return;
+        };
+        if self.panicked {
+            return;
+        }
+        self.panicked = true;
+
+        eprintln!(
+            "{}: temperature {} reached the panic temperature {}",
+            self.name,
+            self.usage.display(temp),
+            self.usage.display(panic)
+        );
+        let Some(command) = &self.panic_command else {
+            return;
+        };
+        eprintln!("{}: running {}", self.name, command);
+        let temperature = format!("{:.1}", temp);
+        let env = [
+            ("FAN_NAME", self.name.as_str()),
+            ("FAN_TEMPERATURE", temperature.as_str()),
+        ];
+        if let Err(e) = hook::run(command, &env, self.hook_timeout) {
+            eprintln!("{}: panic_command failed: {}", self.name, e);
+        }
+    }
+
+    /// Count how long both temperature and CPU usage have been below the
+    /// idle thresholds
+    fn check_idle(&mut self) {
+        let Some(idle) = &self.idle else {
+            return;
+        };
+        let below = self
+            .usage
+            .last_temperature()
+            .is_some_and(|t| t < idle.max_temp)
+            && self.usage.last_cpu().is_some_and(|u| u < idle.max_cpu);
+        self.idle_samples = if below { self.idle_samples + 1 } else { 0 };
+    }
+
+    /// Count how long the CPU usage has stayed low enough to turn off early
+    fn check_low_usage(&mut self) {
+        let Some(low_usage) = &self.low_usage_off else {
+            return;
+        };
+        let low = self.usage.last_cpu().is_some_and(|u| u < low_usage.max_cpu);
+        self.low_usage_samples = if low { self.low_usage_samples + 1 } else { 0 };
+    }
+
+    /// Whether the usage has been low for long enough, and the latest
+    /// temperature is low enough, for the fan to stop cooling down
+    fn low_usage(&self) -> bool {
+        self.low_usage_off.as_ref().is_some_and(|low_usage| {
+            let max_temp = low_usage.max_temp.unwrap_or(self.max_fan_on_temp);
+            self.usage.sample_interval() * self.low_usage_samples as u32
+                >= Duration::from_secs(low_usage.secs)
+                && self.usage.last_temperature().is_some_and(|t| t < max_temp)
+        })
+    }
+
+    /// Count how long the duty has been low enough to risk stalling
+    fn check_stall(&mut self) {
+        let Some(anti_stall) = &self.anti_stall else {
+            return;
+        };
+        let low = self.duty.is_some_and(|d| d > 0f32 && d < anti_stall.below);
+        self.stall_samples = if low { self.stall_samples + 1 } else { 0 };
+    }
+
+    /// Whether the fan is due an anti-stall pulse, once every `every_secs`
+    /// spent at a low duty
+    fn stall_nudge(&self) -> bool {
+        self.anti_stall.as_ref().is_some_and(|anti_stall| {
+            let low = (self.usage.sample_interval() * self.stall_samples as u32).as_secs();
+            low >= anti_stall.every_secs && low % anti_stall.every_secs < anti_stall.for_secs
+        })
+    }
+
+    /// Whether the system has been idle long enough to stop the fan
+    pub fn idle(&self) -> bool {
+        self.idle.as_ref().is_some_and(|idle| {
+            self.usage.sample_interval() * self.idle_samples as u32
+                >= Duration::from_secs(idle.secs)
+        })
+    }
+
+    /// Warn while the temperature is above `alert_temp`, giving notice of it
+    /// warming before the fan is turned on at `max_fan_on_temp`
+    fn check_temp_alert(&mut self) {
+        let Some(alert_temp) = self.alert_temp else {
+            return;
+        };
+        let highest = self
+            .usage
+            .temperature
+            .iter()
+            .filter(|t| !t.samples().is_empty())
+            .map(|t| t.measurement())
+            .reduce(f32::max);
+        let alert = highest.is_some_and(|t| self.threshold_boundary.exceeds(t, alert_temp));
+        if alert && !self.temp_alert {
+            eprintln!(
+                "{}: temperature {} above the {} alert",
+                self.name,
+                self.usage.display(highest.unwrap_or_default()),
+                self.usage.display(alert_temp)
+            );
+        } else if !alert && self.temp_alert {
+            println!("{}: temperature back below alert", self.name);
+        }
+        self.temp_alert = alert;
+    }
+
+    /// Warn when the temperature has risen too quickly, which can point to a
+    /// cooling failure before any threshold is reached
+    fn check_delta_alert(&mut self) {
+        let Some(limit) = self.temp_delta_alert else {
+            return;
+        };
+        let rise = self.usage.temperature_rise(self.temp_delta_period);
+        let alert = rise.is_some_and(|r| r > limit);
+        if alert && !self.delta_alert {
+            eprintln!(
+                "{}: temperature rose {}, more than the {} alert",
+                self.name,
+                self.usage.display_delta(rise.unwrap_or_default()),
+                self.usage.display_delta(limit)
+            );
+        } else if !alert && self.delta_alert {
+            println!("{}: temperature rise back within alert", self.name);
+        }
+        self.delta_alert = alert;
+    }
+
+    /// Decide the fan state from the measurements taken so far
+    pub fn decide(&mut self) -> Option<bool> {
+        let fan_on = self.decide_state();
+        self.update_alarm();
+        fan_on
+    }
+
+    /// Switch the alarm output to match whether a critical temperature is
+    /// driving the fan
+    fn update_alarm(&mut self) {
+        let critical = matches!(
+            self.trigger,
+            Some(TriggerReason::Critical | TriggerReason::SensorCritical)
+        );
+        let Some(alarm) = self.alarm.as_mut() else {
+            return;
+        };
+        if critical == self.alarm_on {
+            return;
+        }
+        match alarm.set(critical) {
+            Ok(()) => {
+                let state = if critical { "on" } else { "off" };
+                println!("{} alarm {}", self.name, state);
+                self.alarm_on = critical;
+            }
+            Err(e) => eprintln!("Unable to set {} alarm output: {}", self.name, e),
+        }
+    }
+
+    pub fn alarm_on(&self) -> bool {
+        self.alarm_on
+    }
+
+    fn decide_state(&mut self) -> Option<bool> {
+        if !self.enabled {
+            return self.fan_on;
+        }
+        self.kernel_floor = None;
+
+        // Held on by a failed hook, retried each decision until it succeeds
+        if let Some(on) = self.hook_forced {
+            if let Some(Err(_)) = self.call_hook(on) {
+                return self.update_fan(Some(TriggerReason::HookFailure));
+            }
+            let hook = if on { "on_command" } else { "off_command" };
+            println!(
+                "{}: {} succeeded, no longer forcing the fan on",
+                self.name, hook
+            );
+            self.hook_forced = None;
+        }
+
+        // The averages can't be trusted without fresh readings to back them
+        if self.usage.stale() {
+            if self.trigger != Some(TriggerReason::Stale) {
+                eprintln!(
+                    "{}: no temperature read for {}s, forcing the fan on",
+                    self.name,
+                    self.usage.staleness().as_secs()
+                );
+            }
+            return self.update_fan(Some(TriggerReason::Stale));
+        }
+
+        // Usage that never leaves 0% isn't being read, only worth a warning
+        // here as the triggers leave it out themselves
+        let usage_at_zero = self.usage.usage_stuck_at_zero();
+        if usage_at_zero != self.usage_at_zero {
+            if usage_at_zero {
+                eprintln!(
+                    "{}: CPU usage has read 0% for too long, the usage trigger appears not to work{}",
+                    self.name,
+                    if self.usage.usage_disabled() {
+                        ", leaving it out of decisions"
+                    } else {
+                        ""
+                    }
+                );
+            } else {
+                println!("{}: CPU usage reading again", self.name);
+            }
+            self.usage_at_zero = usage_at_zero;
+        }
+
+        // Stuck at a floor reading, the sensors aren't reporting rather than
+        // the board being that cold
+        let at_floor = self.usage.at_floor();
+        if at_floor != self.at_floor {
+            if at_floor {
+                eprintln!(
+                    "{}: sensors stuck at their floor reading, not reporting",
+                    self.name
+                );
+            } else {
+                println!("{}: sensors reporting again", self.name);
+            }
+            self.at_floor = at_floor;
+        }
+        if at_floor {
+            return match self.usage.sensor_floor_action() {
+                SensorFloorAction::FanOn => self.update_fan(Some(TriggerReason::SensorFloor)),
+                SensorFloorAction::Hold => self.fan_on,
+            };
+        }
+
+        // Beyond the hardware's own limit, whatever the configured thresholds
+        if let Some(over) = self
+            .usage
+            .over_critical()
+            .filter(|_| self.sensor_critical_force_on)
+        {
+            if self.trigger != Some(TriggerReason::SensorCritical) {
+                eprintln!(
+                    "{}: {} at {} is above its critical {}, forcing the fan on",
+                    self.name,
+                    over.label,
+                    self.usage.display(over.temperature),
+                    self.usage.display(over.critical)
+                );
+            }
+            return self.update_fan(Some(TriggerReason::SensorCritical));
+        }
+
+        // Critical readings turn the fan on straight away, whatever the mode
+        if self.critical() {
+            return self.update_fan(Some(TriggerReason::Critical));
+        }
+
+        // Heating too fast to wait for the thresholds, e.g. a heatsink that
+        // has lost contact
+        if let Some(rate) = self.runaway() {
+            if self.trigger != Some(TriggerReason::Runaway) {
+                eprintln!(
+                    "{}: temperature rising at {}/s, forcing the fan to full",
+                    self.name,
+                    self.usage.display_delta(rate)
+                );
+            }
+            return self.update_fan(Some(TriggerReason::Runaway));
+        }
+
+        // Set by hand, above everything but the safety checks
+        if let Some(duty) = self.manual {
+            if self.variable_speed {
+                return self.update_duty(duty, TriggerReason::Manual);
+            }
+            return self.update_fan((duty > 0f32).then_some(TriggerReason::Manual));
+        }
+
+        // Held at the check's duty while a health check runs
+        if self.checking_health {
+            return self.drive_health_check();
+        }
+
+        // Run at the purge duty on start, before any automatic control
+        if let Some((_, duty)) = self.purge {
+            if self.variable_speed {
+                return self.update_duty(duty, TriggerReason::Purge);
+            }
+            return self.update_fan((duty > 0f32).then_some(TriggerReason::Purge));
+        }
+
+        // A schedule replaces automatic control, but not the overrides above
+        if let Some((duty, reason)) = self.scheduled_duty() {
+            if self.variable_speed {
+                return self.update_duty(duty, reason);
+            }
+            return self.update_fan((duty > 0f32).then_some(reason));
+        }
+
+        // Deferring to the kernel's thermal governor rather than fighting it
+        if let Some(duty) = self.kernel_duty() {
+            if self.kernel_cooling.as_ref().map(|k| k.mode) == Some(KernelCoolingMode::Max) {
+                self.kernel_floor = Some(duty);
+            } else if self.variable_speed {
+                return self.update_duty(duty, TriggerReason::Kernel);
+            } else {
+                return self.update_fan((duty > 0f32).then_some(TriggerReason::Kernel));
+            }
+        }
+
+        // Held while the windows fill, so early averages can't flap the fan
+        if self.warmup_left > 0 {
+            self.warmup_left -= 1;
+            let trigger = (self.warmup_state == WarmupState::On).then_some(TriggerReason::Warmup);
+            return self.update_fan(trigger);
+        }
+
+        // Cool enough to be silent, whatever the usage or other triggers say
+        self.below_off_floor = self
+            .off_below_temp
+            .zip(self.usage.last_temperature())
+            .is_some_and(|(floor, temp)| temp < floor);
+        if self.below_off_floor {
+            if self.variable_speed {
+                return self.update_duty(0f32, TriggerReason::Curve);
+            }
+            return self.update_fan(None);
+        }
+
+        // Fully stopped while idle, even below a curve's lowest duty
+        if self.idle() {
+            if self.fan_on != Some(false) {
+                println!("{} idle", self.name);
+            }
+            if self.variable_speed {
+                return self.update_duty(0f32, TriggerReason::Curve);
+            }
+            return self.update_fan(None);
+        }
+
+        if self.curve.is_some() || self.usage.zones.iter().any(|z| z.curve.is_some()) {
+            return self.decide_curve();
+        }
+        if self.blend.is_some() {
+            return self.decide_blend();
+        }
+
+        let max_temp = self.temp_threshold();
+
+        // First update, the windows hold a single sample so decide directly
+        if self.fan_on.is_none() {
+            let on = match self.cold_start {
+                ColdStart::Off => false,
+                ColdStart::On => true,
+                ColdStart::Temperature => {
+                    let boundary = self.threshold_boundary;
+                    self.usage
+                        .last_temperature()
+                        .is_some_and(|t| boundary.exceeds(t, max_temp))
+                }
+            };
+            return self.update_fan(on.then_some(TriggerReason::ColdStart));
+        }
+
+        let mut trigger = self.threshold_trigger(max_temp);
+
+        // The heat source gone, no need to keep cooling down to the target
+        if matches!(
+            trigger,
+            Some(TriggerReason::Cooldown | TriggerReason::Trend)
+        ) && self.low_usage()
+        {
+            if self.fan_on == Some(true) {
+                println!("{} usage low, turning off before cooling down", self.name);
+            }
+            trigger = None;
+        }
+
+        // Only turn on once the triggers have held for enough decisions
+        self.over_ticks = match trigger {
+            Some(_) => self.over_ticks.saturating_add(1),
+            None => 0,
+        };
+        if self.fan_on != Some(true) && self.over_ticks < self.on_ticks {
+            trigger = None;
+        }
+
+        // Keep running through a momentary dip, turning off only if the
+        // thresholds still call for it once the delay has passed
+        if trigger.is_none()
+            && self.fan_on == Some(true)
+            && *self.off_pending.get_or_insert(0) < self.off_confirm_samples
+        {
+            return self.fan_on;
+        }
+        self.off_pending = None;
+
+        // Only switch once the decision has held, whatever led to it
+        if self.fan_on != Some(trigger.is_some()) {
+            self.switch_pending = self.switch_pending.saturating_add(1);
+            if self.switch_pending < self.debounce_decisions {
+                return self.fan_on;
+            }
+        }
+        self.switch_pending = 0;
+
+        self.update_fan(trigger)
+    }
+
+    /// Maximum temperature to use, scaled to the sensors' own limits when
+    /// they report them, and raised while heat soaking after turning off
+    fn temp_threshold(&self) -> f32 {
+        let max_temp = self
+            .max_fan_on_critical_percent
+            .and_then(|pct| self.usage.critical_threshold(pct))
+            .unwrap_or(self.max_fan_on_temp);
+        let max_temp = match self.usage.cpu_max_temp() {
+            Some(usage_max) if usage_max < max_temp => usage_max,
+            _ => max_temp,
+        };
+        match self.heat_soak {
+            Some((_, margin)) if self.soak_left > 0 => max_temp + margin,
+            _ => max_temp,
+        }
+    }
+
+    /// Whether the tie breaker zone is above its threshold, when `temp` is
+    /// within its band of `max_temp` and the zone has been read
+    fn tie_break(&self, temp: f32, max_temp: f32) -> Option<bool> {
+        let zone = self.usage.tie_breaker.as_ref()?;
+        if (temp - max_temp).abs() > self.tie_band || zone.window.samples().is_empty() {
+            return None;
+        }
+        let threshold = zone.threshold?;
+
+        Some(
+            self.threshold_boundary
+                .exceeds(zone.window.measurement(), threshold),
+        )
+    }
+
+    /// Reason the thresholds call for the fan to be on, if any, the first
+    /// automatic trigger in `trigger_order` that does
+    fn threshold_trigger(&self, max_temp: f32) -> Option<TriggerReason> {
+        if let Some(reason) = self
+            .trigger_order
+            .iter()
+            .find_map(|&trigger| self.auto_trigger(trigger, max_temp))
+        {
+            return Some(reason);
+        }
+
+        let first = self.usage.temperature.first().map(|t| t.measurement());
+
+        // Once triggered, run until the shortest window cools to the target
+        if let (Some(target), Some(true)) = (self.cooldown_target, self.fan_on) {
+            let cooling = first.is_some_and(|f| f > target);
+            return cooling.then_some(TriggerReason::Cooldown);
+        }
+
+        // Use middle measurement
+        let middle = self
+            .usage
+            .temperature
+            .get(self.usage.temperature.len() / 2)
+            .map(|t| t.measurement());
+
+        // Latest rolling average > max / 2 && > next rolling
+        let on =
+            first.is_some_and(|f| f > self.max_fan_on_temp / 2f32 && middle.is_some_and(|m| f > m));
+
+        on.then_some(TriggerReason::Trend)
+    }
+
+    /// Reason `trigger` calls for the fan to be on, if it does
+    fn auto_trigger(&self, trigger: AutoTrigger, max_temp: f32) -> Option<TriggerReason> {
+        // Windows only vote on the decisions their role takes part in
+        let fan_on = self.fan_on == Some(true);
+        let exceeds = |value, threshold| self.threshold_boundary.exceeds(value, threshold);
+        let voting = |roles: &[WindowRole], i: usize| {
+            roles.get(i).copied().unwrap_or_default().votes(fan_on)
+        };
+        let shortest = self.usage.temperature.iter().min_by_key(|t| t.interval());
+
+        match trigger {
+            // Any temperature above maximum? Too close to call, the tie
+            // breaker decides
+            AutoTrigger::Temperature => {
+                let over = |temp: f32| match self.tie_break(temp, max_temp) {
+                    Some(over) => over,
+                    None => exceeds(temp, max_temp),
+                };
+                let window = self
+                    .usage
+                    .temperature
+                    .iter()
+                    .enumerate()
+                    .position(|(i, t)| {
+                        voting(&self.usage.temperature_roles, i) && over(t.measurement())
+                    })?;
+                Some(TriggerReason::Temperature { window })
+            }
+            // Any zone above its own threshold?
+            AutoTrigger::Zone => {
+                let zone = self.usage.zones.iter().position(|z| {
+                    !z.window.samples().is_empty()
+                        && z.threshold
+                            .is_some_and(|threshold| exceeds(z.window.measurement(), threshold))
+                })?;
+                Some(TriggerReason::Zone { zone })
+            }
+            // CPU Usage > max, unless still too cold for it to matter
+            AutoTrigger::Cpu => {
+                let warm = self
+                    .usage_trigger_min_temp
+                    .is_none_or(|floor| shortest.is_some_and(|t| t.measurement() > floor));
+                if !warm || self.usage.usage_disabled() {
+                    return None;
+                }
+                let window = self.usage.cpu.iter().enumerate().position(|(i, u)| {
+                    voting(&self.usage.cpu_roles, i)
+                        && exceeds(u.measurement(), self.max_fan_on_cpu)
+                })?;
+                Some(TriggerReason::CpuUsage { window })
+            }
+            // Frequency held high, a proxy for load where sensors are missing
+            AutoTrigger::CpuFrequency => {
+                let (trigger, window) = (self.cpu_freq_trigger?, self.usage.cpu_freq.as_ref()?);
+                exceeds(window.measurement(), trigger).then_some(TriggerReason::CpuFrequency)
+            }
+            // Rising quickly enough to turn on before the threshold is
+            // reached?
+            AutoTrigger::RiseRate => {
+                let rate = self.rise_rate_trigger?;
+                let per_sec = self.usage.sample_interval().as_secs_f32();
+                shortest
+                    .and_then(|t| t.slope())
+                    .is_some_and(|s| s / per_sec > rate)
+                    .then_some(TriggerReason::RiseRate)
+            }
+        }
+    }
+
+    /// Drive the fan at the highest duty of its own curve, for the shortest
+    /// temperature window, and of its zones' curves, or at their weighted
+    /// sum with `curve_sum`
+    fn decide_curve(&mut self) -> Option<bool> {
+        let (Some((duty, ..)), Some(temp)) = (self.curve_duty(), self.curve_temperature()) else {
+            return self.fan_on;
+        };
+
+        // Held at full speed until cooled by the hysteresis below the limit
+        let full_speed = self.full_speed_temp.is_some_and(|full| {
+            if self.trigger == Some(TriggerReason::FullSpeed) {
+                temp > full - self.full_speed_hysteresis
+            } else {
+                temp >= full
+            }
+        });
+        if full_speed {
+            return self.update_duty(100f32, TriggerReason::FullSpeed);
+        }
+
+        // Keep the duty through small swings so the fan doesn't hunt, as
+        // long as nothing else has driven it since
+        if let (Some(delta), Some((held_temp, held_duty))) = (self.curve_min_delta, self.curve_held)
+        {
+            if self.duty == Some(held_duty) && (temp - held_temp).abs() < delta {
+                return self.update_duty(held_duty, TriggerReason::Curve);
+            }
+        }
+        if self.duty != Some(duty) {
+            self.curve_held = Some((temp, duty));
+        }
+
+        // Ramp towards the curve rather than jumping to it
+        let duty = match (self.curve_max_step, self.duty) {
+            (Some(step), Some(current)) => duty.clamp(current - step, current + step),
+            _ => duty,
+        };
+
+        self.update_duty(duty, TriggerReason::Curve)
+    }
+
+    /// Highest duty called for by the curves, or their capped weighted sum
+    /// with `curve_sum`, along with the points the temperature lies between
+    /// of the curve calling for the most
+    fn curve_duty(&self) -> Option<(f32, CurvePoint, CurvePoint)> {
+        let weight = |zone: Option<&str>| match (&self.curve_sum, zone) {
+            (Some(sum), Some(zone)) => sum.zone_weight(zone),
+            (Some(sum), None) => sum.weight,
+            (None, _) => 1f32,
+        };
+        let own = self
+            .curve
+            .as_ref()
+            .zip(self.curve_temperature())
+            .map(|(curve, temp)| (curve.duty(temp) * weight(None), curve.segment(temp)));
+        let zones = self
+            .usage
+            .zones
+            .iter()
+            .filter(|z| !z.window.samples().is_empty())
+            .filter_map(|z| {
+                let curve = z.curve.as_ref()?;
+                let temp = z.window.measurement();
+                Some((
+                    curve.duty(temp) * weight(Some(&z.name)),
+                    curve.segment(temp),
+                ))
+            });
+        let duties: Vec<(f32, (CurvePoint, CurvePoint))> = own.into_iter().chain(zones).collect();
+
+        let (duty, (low, high)) = duties.iter().copied().max_by(|a, b| a.0.total_cmp(&b.0))?;
+        let duty = match &self.curve_sum {
+            Some(sum) => duties.iter().map(|d| d.0).sum::<f32>().min(sum.cap),
+            None => duty,
+        };
+        Some((duty, low, high))
+    }
+
+    /// Temperature the curve is followed from, the shortest window
+    fn curve_temperature(&self) -> Option<f32> {
+        self.usage
+            .temperature
+            .first()
+            .map(|t| t.measurement())
+            .or(self.usage.last_temperature())
+    }
+
+    /// Drive the fan from the blended demand of the shortest windows, as a
+    /// duty when the output supports it, otherwise on at the threshold
+    fn decide_blend(&mut self) -> Option<bool> {
+        let Some(blend) = &self.blend else {
+            return self.fan_on;
+        };
+        let temp = self.usage.temperature.first().map(|t| t.measurement());
+        let cpu = self.usage.cpu.first().map(|u| u.measurement());
+        let (Some(temp), Some(cpu)) = (temp, cpu) else {
+            return self.fan_on;
+        };
+        // Usage that doesn't work would only drag the demand down
+        let demand = if self.usage.usage_disabled() {
+            let temp_only = BlendConfig {
+                temp_weight: 1f32,
+                cpu_weight: 0f32,
+                ..blend.clone()
+            };
+            temp_only.demand(temp, cpu, self.max_fan_on_temp)
+        } else {
+            blend.demand(temp, cpu, self.max_fan_on_temp)
+        };
+
+        if self.variable_speed {
+            return self.update_duty(demand * 100f32, TriggerReason::Demand);
+        }
+        let on = demand >= blend.threshold;
+        self.update_fan(on.then_some(TriggerReason::Demand))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the fan this one follows, if it is a follower
+    pub fn leader(&self) -> Option<&str> {
+        self.follow.as_ref().map(|f| f.leader.as_str())
+    }
+
+    /// Mirror the leader's state, called every tick in place of deciding. A
+    /// leader stopping is followed once the off delay has passed.
+    pub fn follow(&mut self, leader_on: bool, leader_duty: Option<f32>) -> Option<bool> {
+        let Some(follow) = self.follow.as_mut().filter(|_| self.enabled) else {
+            return self.fan_on;
+        };
+
+        if leader_on {
+            follow.off_in = follow.off_delay_ticks;
+            return match leader_duty {
+                Some(duty) => {
+                    let duty = (duty + follow.duty_offset).clamp(0f32, 100f32);
+                    self.update_duty(duty, TriggerReason::Follow)
+                }
+                None => self.update_fan(Some(TriggerReason::Follow)),
+            };
+        }
+        if follow.off_in > 0 {
+            follow.off_in -= 1;
+            return self.fan_on;
+        }
+
+        self.update_fan(None)
+    }
+
+    /// Set the local time, in minutes from midnight, schedules are followed by
+    pub fn set_time_of_day(&mut self, minute: u32) {
+        self.time_of_day = Some(minute);
+    }
+
+    /// Duty the kernel's cooling device is set to, `None` without one or
+    /// while it can't be read, leaving the fan to decide for itself
+    fn kernel_duty(&mut self) -> Option<f32> {
+        let device = &self.kernel_cooling.as_ref()?.device;
+        match sensors::read_cooling_state(device) {
+            Ok((current, max)) => {
+                if self.kernel_failing {
+                    println!("{}: kernel cooling device read again", self.name);
+                    self.kernel_failing = false;
+                }
+                let duty = match max {
+                    0 => 0f32,
+                    max => current.min(max) as f32 * 100f32 / max as f32,
+                };
+                Some(duty)
+            }
+            Err(e) => {
+                if !self.kernel_failing {
+                    eprintln!(
+                        "{}: unable to read {}, deciding without the kernel: {}",
+                        self.name,
+                        device.display(),
+                        e
+                    );
+                    self.kernel_failing = true;
+                }
+                None
+            }
+        }
+    }
+
+    /// Duty of the first schedule entry covering the time of day, otherwise
+    /// of the first about to start that precools, if any
+    fn scheduled_duty(&self) -> Option<(f32, TriggerReason)> {
+        let minute = self.time_of_day?;
+        let entries = &self.schedule;
+        entries
+            .iter()
+            .find(|entry| entry.contains(minute))
+            .map(|entry| (entry.duty, TriggerReason::Schedule))
+            .or_else(|| {
+                entries
+                    .iter()
+                    .find(|entry| entry.precooling(minute))
+                    .map(|entry| (entry.precool_duty, TriggerReason::Precool))
+            })
+    }
+
+    /// Start again from empty windows after the system resumed from suspend,
+    /// re-opening the output on the next decision in case the device was
+    /// reset while suspended
+    pub fn resumed(&mut self) {
+        self.usage.clear();
+        self.over_ticks = 0;
+        self.off_pending = None;
+        if self.output.take().is_some() {
+            self.reopen_in = 1;
+            self.reopen_backoff = 1;
+        }
+    }
+
+    /// A disabled fan is driven off and left alone, neither sampling nor
+    /// deciding, until enabled again. Re-enabled fans start from empty
+    /// windows.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+        if enabled {
+            println!("{} enabled", self.name);
+            self.usage.clear();
+            self.over_ticks = 0;
+            self.off_pending = None;
+        } else {
+            println!("{} disabled", self.name);
+            self.update_fan(None);
+        }
+        self.enabled = enabled;
+    }
+
+    /// Run the fan at `duty` whatever the automatic control would do, only
+    /// the staleness, sensor floor and critical checks still apply. `None`
+    /// hands it back to automatic control. The latest setting wins, whatever
+    /// `source` it came from.
+    pub fn set_manual(&mut self, duty: Option<f32>, source: ManualSource) {
+        match duty {
+            Some(duty) => println!("{} set to {}% by {}", self.name, duty, source),
+            None if self.manual.is_some() => {
+                println!("{} back to automatic control by {}", self.name, source)
+            }
+            None => {}
+        }
+        self.manual = duty;
+        self.manual_source = duty.map(|_| source);
+    }
+
+    /// Duty set by hand, if any
+    pub fn manual(&self) -> Option<f32> {
+        self.manual
+    }
+
+    /// Where the duty was set by hand from, while it is
+    pub fn manual_source(&self) -> Option<&ManualSource> {
+        self.manual_source.as_ref()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether a temperature window is above `alert_temp`
+    pub fn temp_alert(&self) -> bool {
+        self.temp_alert
+    }
+
+    /// Whether the temperature is rising faster than `temp_delta_alert`
+    pub fn delta_alert(&self) -> bool {
+        self.delta_alert
+    }
+
+    /// Time spent writing the output since this was last called
+    pub fn take_write_time(&mut self) -> Duration {
+        std::mem::take(&mut self.write_time)
+    }
+
+    /// Whether the fan has been slowing down while on, `None` without
+    /// `spin_down`
+    pub fn spinning_down(&self) -> Option<bool> {
+        self.spin_down.as_ref().map(|_| self.spinning_down)
+    }
+
+    /// Whether the last health check found the fan degraded, `None` without
+    /// a health check
+    pub fn degraded(&self) -> Option<bool> {
+        self.health_check.as_ref().map(|_| self.degraded)
+    }
+
+    pub fn fan_on(&self) -> Option<bool> {
+        self.fan_on
+    }
+
+    /// Duty the fan was last driven at, `None` unless following a curve
+    pub fn duty(&self) -> Option<f32> {
+        self.duty
+    }
+
+    /// Points of the winning curve the current temperature lies between
+    pub fn curve_segment(&self) -> Option<(CurvePoint, CurvePoint)> {
+        self.curve_duty().map(|(_, low, high)| (low, high))
+    }
+
+    /// Reason the fan is currently on, `None` while it is off
+    pub fn trigger(&self) -> Option<TriggerReason> {
+        self.trigger
+    }
+
+    /// The condition behind the fan's current state along with the values it
+    /// was decided on, e.g. `temperature[1]=34.2°C > 31.0°C → ON`
+    pub fn explain(&self) -> String {
+        let usage = &self.usage;
+        let over = match self.threshold_boundary {
+            Boundary::Exclusive => ">",
+            Boundary::Inclusive => "≥",
+        };
+        let max_temp = self.temp_threshold();
+        let first = usage.temperature.first().map(|t| t.measurement());
+        let state = match (self.fan_on, self.duty) {
+            (Some(false), _) => "OFF".to_string(),
+            (_, Some(duty)) if self.variable_speed => format!("{:.0}%", duty),
+            (Some(true), _) => "ON".to_string(),
+            (None, _) => return "no decision yet".to_string(),
+        };
+        if !self.enabled {
+            return format!("disabled → {}", state);
+        }
+
+        let condition = match self.trigger {
+            Some(TriggerReason::Temperature { window }) => usage.temperature.get(window).map(|t| {
+                format!(
+                    "temperature[{}]={} {} {}",
+                    window,
+                    usage.display(t.measurement()),
+                    over,
+                    usage.display(max_temp)
+                )
+            }),
+            Some(TriggerReason::Zone { zone }) => usage.zones.get(zone).and_then(|z| {
+                Some(format!(
+                    "zone {}={} {} {}",
+                    z.name,
+                    usage.display(z.window.measurement()),
+                    over,
+                    usage.display(z.threshold?)
+                ))
+            }),
+            Some(TriggerReason::CpuUsage { window }) => usage.cpu.get(window).map(|u| {
+                format!(
+                    "cpu[{}]={:.1}% {} {:.1}%",
+                    window,
+                    u.measurement(),
+                    over,
+                    self.max_fan_on_cpu
+                )
+            }),
+            Some(TriggerReason::CpuFrequency) => {
+                let (window, trigger) = (usage.cpu_freq.as_ref(), self.cpu_freq_trigger);
+                window.zip(trigger).map(|(w, t)| {
+                    format!(
+                        "cpu frequency={:.0} MHz {} {:.0} MHz",
+                        w.measurement(),
+                        over,
+                        t
+                    )
+                })
+            }
+            Some(TriggerReason::RiseRate) => self.rise_rate_trigger.map(|rate| {
+                format!(
+                    "temperature rising faster than {}/s",
+                    usage.display_delta(rate)
+                )
+            }),
+            Some(TriggerReason::Cooldown) => first.zip(self.cooldown_target).map(|(f, target)| {
+                format!(
+                    "temperature[0]={} > cooldown target {}",
+                    usage.display(f),
+                    usage.display(target)
+                )
+            }),
+            Some(TriggerReason::Trend) => first.map(|f| {
+                format!(
+                    "temperature[0]={} > {} and rising",
+                    usage.display(f),
+                    usage.display(self.max_fan_on_temp / 2f32)
+                )
+            }),
+            Some(TriggerReason::Critical) => {
+                usage
+                    .last_temperature()
+                    .zip(self.critical_temp)
+                    .map(|(t, critical)| {
+                        format!(
+                            "latest={} ≥ critical {}",
+                            usage.display(t),
+                            usage.display(critical)
+                        )
+                    })
+            }
+            Some(TriggerReason::Curve | TriggerReason::FullSpeed) => {
+                self.curve_temperature().map(|t| {
+                    format!(
+                        "{} at {}",
+                        self.trigger.unwrap_or(TriggerReason::Curve),
+                        usage.display(t)
+                    )
+                })
+            }
+            Some(trigger) => Some(trigger.to_string()),
+            None if self.below_off_floor => {
+                usage
+                    .last_temperature()
+                    .zip(self.off_below_temp)
+                    .map(|(t, floor)| {
+                        format!(
+                            "latest={} < off floor {}",
+                            usage.display(t),
+                            usage.display(floor)
+                        )
+                    })
+            }
+            None => Some(format!(
+                "all windows at or below {} and {:.1}%",
+                usage.display(max_temp),
+                self.max_fan_on_cpu
+            )),
+        };
+
+        match condition {
+            Some(condition) => format!("{} → {}", condition, state),
+            None => state,
+        }
+    }
+
+    /// Estimated time until the fan turns off, from how fast the longest
+    /// temperature window is falling and how far the windows still are
+    /// above the temperature the fan runs down to. `None` unless the fan is
+    /// on under its on/off triggers with the temperature falling.
+    pub fn time_to_off(&self) -> Option<Duration> {
+        let automatic = self.curve.is_none() && self.blend.is_none() && self.manual.is_none();
+        if self.fan_on != Some(true) || !automatic {
+            return None;
+        }
+        let temperature = &self.usage.temperature;
+        let slope = temperature.iter().max_by_key(|t| t.interval())?.slope()?;
+        if slope >= 0f32 {
+            return None;
+        }
+        let target = self
+            .cooldown_target
+            .unwrap_or_else(|| self.temp_threshold());
+        let highest = temperature
+            .iter()
+            .map(|t| t.measurement())
+            .fold(f32::MIN, f32::max);
+        let samples = ((highest - target) / -slope).max(0f32);
+
+        Some(self.usage.sample_interval().mul_f32(samples))
+    }
+
+    /// Number of times each reason has turned the fan on
+    pub fn trigger_counts(&self) -> &HashMap<TriggerReason, usize> {
+        &self.trigger_counts
+    }
+
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::NullOutput;
+    use crate::sensors::ComponentReading;
+    use crate::testing::{self, TestFan};
+    use std::fs;
+
+    const WINDOWS: &str = "temp_intervals = [1]\ncpu_intervals = [1]\n";
+
+    #[test]
+    fn trigger_attributes_temperature_and_usage() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nmax_fan_on_cpu = 80.0",
+            WINDOWS
+        ));
+
+        let mut hot = TestFan::new(&config);
+        hot.update(40.0, 5.0);
+        assert_eq!(hot.update(60.0, 5.0), Some(true));
+        assert_eq!(
+            hot.control.trigger(),
+            Some(TriggerReason::Temperature { window: 0 })
+        );
+
+        let mut busy = TestFan::new(&config);
+        busy.update(40.0, 5.0);
+        assert_eq!(busy.update(40.0, 95.0), Some(true));
+        assert_eq!(
+            busy.control.trigger(),
+            Some(TriggerReason::CpuUsage { window: 0 })
+        );
+        assert_eq!(
+            busy.control.trigger_counts()[&TriggerReason::CpuUsage { window: 0 }],
+            1
+        );
+    }
+
+    #[test]
+    fn cold_start_decides_by_first_temperature() {
+        let toml = |cold_start: &str| {
+            format!(
+                "{}max_fan_on_temp = 50.0\ncold_start = \"{}\"",
+                WINDOWS, cold_start
+            )
+        };
+
+        let mut fan = TestFan::new(&testing::config(&toml("temperature")));
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::ColdStart));
+
+        let mut fan = TestFan::new(&testing::config(&toml("temperature")));
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+
+        let mut fan = TestFan::new(&testing::config(&toml("off")));
+        assert_eq!(fan.update(60.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn blend_decides_on_combined_demand() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\n\
+             blend = {{ temp_weight = 0.5, cpu_weight = 0.5, idle_temp = 30.0, threshold = 0.5 }}",
+            WINDOWS
+        ));
+        let blend = config.fan.blend.as_ref().unwrap();
+        assert_eq!(blend.demand(40.0, 20.0, 50.0), 0.35);
+        assert_eq!(blend.demand(40.0, 80.0, 50.0), 0.65);
+
+        let mut fan = TestFan::new(&config);
+        assert_eq!(fan.update(40.0, 20.0), Some(false));
+        assert_eq!(fan.update(40.0, 80.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Demand));
+    }
+
+    #[test]
+    fn cooldown_runs_until_average_reaches_target() {
+        let config = testing::config(
+            "temp_intervals = [2]\ncpu_intervals = [1]\nmax_fan_on_temp = 50.0\ncooldown_target = 40.0",
+        );
+        let mut fan = TestFan::new(&config);
+        fan.update(30.0, 0.0);
+        fan.update(60.0, 0.0);
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+
+        // A reading under the target only brings the average down to 48
+        assert_eq!(fan.update(36.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Cooldown));
+        assert_eq!(fan.update(46.0, 0.0), Some(true));
+        assert_eq!(fan.update(34.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn rise_rate_fires_below_threshold() {
+        let config = testing::config(
+            "temp_intervals = [3]\ncpu_intervals = [1]\nmax_fan_on_temp = 70.0\nrise_rate_trigger = 2.0",
+        );
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        assert_eq!(fan.update(41.0, 0.0), Some(false));
+
+        // 2.5 degrees a second over the window, while averaging 42
+        assert_eq!(fan.update(45.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::RiseRate));
+    }
+
+    #[test]
+    fn samples_between_decisions_feed_the_windows() {
+        let mut fan = TestFan::new(&testing::config(
+            "temp_intervals = [3]\ncpu_intervals = [1]\nmax_fan_on_temp = 50.0",
+        ));
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+        fan.sample(70.0, 0.0);
+        fan.sample(70.0, 0.0);
+        assert_eq!(fan.control.usage().temperature[0].measurement(), 60.0);
+        assert_eq!(fan.control.fan_on(), Some(false));
+
+        // The next decision sees every sample taken since
+        assert_eq!(fan.control.decide(), Some(true));
+    }
+
+    #[test]
+    fn full_speed_holds_until_hysteresis() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\n\
+             curve = [{{ temp = 30.0, duty = 20.0 }}, {{ temp = 80.0, duty = 70.0 }}]\n\
+             full_speed_temp = 70.0\nfull_speed_hysteresis = 5.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(30.0));
+
+        fan.update(70.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(100.0));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::FullSpeed));
+        fan.update(66.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(100.0));
+
+        fan.update(64.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(54.0));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Curve));
+    }
+
+    #[test]
+    fn stale_readings_force_the_fan_on() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nstale_after_secs = 3",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+
+        for _ in 0..2 {
+            fan.miss();
+            assert_eq!(fan.control.decide(), Some(false));
+        }
+        fan.miss();
+        assert_eq!(fan.control.decide(), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Stale));
+
+        // Released by the next reading
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn delta_alert_fires_below_threshold() {
+        let config = testing::config(
+            "temp_intervals = [10]\ncpu_intervals = [1]\nmax_fan_on_temp = 80.0\n\
+             temp_delta_alert = 5.0\ntemp_delta_secs = 4",
+        );
+        let mut fan = TestFan::new(&config);
+
+        fan.update(40.0, 0.0);
+        fan.update(42.0, 0.0);
+        assert!(!fan.control.delta_alert());
+
+        for temperature in [44.0, 46.0, 48.0] {
+            assert_eq!(fan.update(temperature, 0.0), Some(false));
+        }
+        assert!(fan.control.delta_alert());
+
+        // Levelling off ages the rise out of the period
+        for _ in 0..4 {
+            fan.update(48.0, 0.0);
+        }
+        assert!(!fan.control.delta_alert());
+    }
+
+    /// An output whose device has gone away
+    struct GoneOutput;
+
+    impl FanOutput for GoneOutput {
+        fn set(&mut self, _on: bool) -> Result<(), Error> {
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+        }
+    }
+
+    #[test]
+    fn failed_output_is_reopened() {
+        let dir = testing::temp_dir("reopen");
+        let hwmon = testing::hwmon(&dir);
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\n\
+             output = {{ type = \"hwmon-pwm\", hwmon = \"{}\", pwm = 1 }}",
+            WINDOWS,
+            hwmon.display()
+        ));
+        let mut fan = TestFan::with_output(&config, 0, Box::new(GoneOutput));
+        fan.update(40.0, 0.0);
+        assert!(fan.control.output.is_none());
+
+        // Re-opened from the config on the next write, which then lands
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+        assert!(fan.control.output.is_some());
+        assert_eq!(fs::read_to_string(hwmon.join("pwm1")).unwrap(), "255");
+    }
+
+    #[test]
+    fn on_ticks_wait_for_consecutive_decisions_but_critical_does_not() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\non_ticks = 3\ncritical_temp = 90.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+
+        // A single decision over the threshold isn't enough
+        for temperature in [60.0, 60.0, 40.0, 60.0, 60.0] {
+            assert_eq!(fan.update(temperature, 0.0), Some(false));
+        }
+        // The third decision over in a row
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+
+        // Critical ignores on_ticks
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        fan.sample(95.0, 0.0);
+        assert!(fan.control.critical());
+        assert_eq!(fan.control.decide(), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Critical));
+    }
+
+    #[test]
+    fn over_sensor_critical_forces_the_fan_on() {
+        let hot = || ComponentReading {
+            critical: Some(60.0),
+            ..testing::reading("coretemp Package id 0", 65.0)
+        };
+        let config = testing::config(&format!("{}max_fan_on_temp = 80.0", WINDOWS));
+        let mut fan = TestFan::new(&config);
+        fan.push(vec![0.0], vec![hot()]);
+        assert_eq!(fan.control.update(), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::SensorCritical));
+
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 80.0\nsensor_critical_force_on = false",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.push(vec![0.0], vec![hot()]);
+        assert_eq!(fan.control.update(), Some(false));
+    }
+
+    #[test]
+    fn cpu_frequency_trigger_reads_scaling_cur_freq() {
+        let root = testing::temp_dir("cpu-freq");
+        let set_khz = |cpu: &str, khz: u64| {
+            let cpufreq = root.join(cpu).join("cpufreq");
+            fs::create_dir_all(&cpufreq).unwrap();
+            fs::write(cpufreq.join("scaling_cur_freq"), format!("{}\n", khz)).unwrap();
+        };
+        // Not a CPU, and never read
+        fs::create_dir_all(root.join("cpufreq")).unwrap();
+        set_khz("cpu0", 1_200_000);
+        set_khz("cpu1", 1_800_000);
+
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 80.0\ncpu_freq_trigger = 3000.0\ncpu_freq_secs = 1\n\
+             cpu_freq_root = \"{}\"",
+            WINDOWS,
+            root.display()
+        ));
+        let mut fan = TestFan::new(&config);
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+        assert_eq!(
+            fan.control.usage.cpu_freq.as_ref().unwrap().measurement(),
+            1800.0
+        );
+
+        // The fastest CPU counts
+        set_khz("cpu1", 3_400_000);
+        assert_eq!(fan.update(40.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::CpuFrequency));
+    }
+
+    #[test]
+    fn follower_mirrors_its_leader() {
+        let config = testing::config(
+            r#"
+            [[fans]]
+            name = "intake"
+
+            [[fans]]
+            name = "exhaust"
+            follow = { fan = "intake", duty_offset = -10.0, off_delay_secs = 2 }
+            "#,
+        );
+        let mut exhaust = TestFan::with_output(&config, 1, Box::new(NullOutput));
+        assert_eq!(exhaust.control.leader(), Some("intake"));
+
+        assert_eq!(exhaust.control.follow(true, Some(60.0)), Some(true));
+        assert_eq!(exhaust.control.duty(), Some(50.0));
+        assert_eq!(exhaust.control.trigger(), Some(TriggerReason::Follow));
+        // Offset down to nothing, which is off
+        assert_eq!(exhaust.control.follow(true, Some(5.0)), Some(false));
+        assert_eq!(exhaust.control.duty(), Some(0.0));
+
+        // Kept running for the off delay once the leader stops
+        exhaust.control.follow(true, None);
+        for _ in 0..2 {
+            assert_eq!(exhaust.control.follow(false, None), Some(true));
+        }
+        assert_eq!(exhaust.control.follow(false, None), Some(false));
+    }
+
+    #[test]
+    fn failed_off_hook_under_each_policy() {
+        let fan = |policy: &str| {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 50.0\noff_command = \"exit 1\"\nhook_failure = \"{}\"",
+                WINDOWS, policy
+            ));
+            let mut fan = TestFan::new(&config);
+            fan.update(60.0, 0.0);
+            fan
+        };
+        for policy in ["ignore", "log"] {
+            assert_eq!(fan(policy).update(40.0, 0.0), Some(false));
+        }
+
+        let mut fan = fan("force-on");
+        assert_eq!(fan.update(40.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::HookFailure));
+        // Held on while the hook keeps failing, rather than turned off and
+        // forced back on each time
+        assert_eq!(fan.update(40.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger_counts[&TriggerReason::HookFailure], 1);
+
+        fan.control.off_command = Some("true".to_string());
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+        assert_eq!(fan.control.hook_forced, None);
+    }
+
+    #[test]
+    fn hottest_zone_curve_sets_the_duty() {
+        let config = testing::config(
+            r#"
+            temp_intervals = [1]
+            cpu_intervals = [1]
+
+            [[zones]]
+            name = "cpu"
+            sensors = ["coretemp"]
+            curve = [{ temp = 40.0, duty = 20.0 }, { temp = 80.0, duty = 100.0 }]
+
+            [[zones]]
+            name = "gpu"
+            sensors = ["amdgpu"]
+            curve = [{ temp = 30.0, duty = 40.0 }, { temp = 60.0, duty = 100.0 }]
+
+            [[fans]]
+            zones = ["cpu", "gpu"]
+            "#,
+        );
+        let mut fan = TestFan::new(&config);
+        let mut update = |cpu: f32, gpu: f32| {
+            let readings = vec![
+                testing::reading("coretemp Package id 0", cpu),
+                testing::reading("amdgpu edge", gpu),
+            ];
+            fan.push(vec![0.0], readings);
+            fan.control.update();
+            fan.control.duty()
+        };
+
+        // The CPU is hotter, but the GPU's curve asks for more: 70% over 68%
+        assert_eq!(update(64.0, 45.0), Some(70.0));
+        // Now the CPU's does, 84% over 52%
+        assert_eq!(update(72.0, 36.0), Some(84.0));
+    }
+
+    #[test]
+    fn summed_zone_curves_add_up_to_the_cap() {
+        let duty = |curve_sum: &str| {
+            let config = testing::config(&format!(
+                r#"
+                {}
+                [[zones]]
+                name = "cpu"
+                sensors = ["coretemp"]
+                curve = [{{ temp = 40.0, duty = 20.0 }}, {{ temp = 80.0, duty = 100.0 }}]
+
+                [[zones]]
+                name = "disks"
+                sensors = ["drivetemp"]
+                curve = [{{ temp = 30.0, duty = 20.0 }}, {{ temp = 50.0, duty = 100.0 }}]
+
+                [[fans]]
+                zones = ["cpu", "disks"]
+                {}
+                "#,
+                WINDOWS, curve_sum
+            ));
+            let mut fan = TestFan::new(&config);
+            // Each zone's curve calls for 40% on its own
+            let readings = vec![
+                testing::reading("coretemp Package id 0", 50.0),
+                testing::reading("drivetemp sda", 35.0),
+            ];
+            for _ in 0..2 {
+                fan.push(vec![0.0], readings.clone());
+                fan.control.update();
+            }
+            fan.control.duty().unwrap()
+        };
+
+        assert_eq!(duty(""), 40.0);
+        let weights = "zone_weights = { cpu = 0.8, disks = 0.8 }";
+        assert_eq!(duty(&format!("curve_sum = {{ {} }}", weights)), 64.0);
+        assert_eq!(
+            duty(&format!("curve_sum = {{ {}, cap = 60.0 }}", weights)),
+            60.0
+        );
+    }
+
+    #[test]
+    fn idle_stops_the_fan_only_once_sustained() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\n\
+             curve = [{{ temp = 30.0, duty = 20.0 }}, {{ temp = 80.0, duty = 70.0 }}]\n\
+             idle = {{ max_temp = 45.0, max_cpu = 10.0, secs = 3 }}",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+
+        for _ in 0..2 {
+            assert_eq!(fan.update(40.0, 2.0), Some(true));
+        }
+        // A burst of usage starts the count again
+        assert_eq!(fan.update(40.0, 50.0), Some(true));
+        for _ in 0..2 {
+            assert_eq!(fan.update(40.0, 2.0), Some(true));
+            assert_eq!(fan.control.duty(), Some(30.0));
+        }
+        assert_eq!(fan.update(40.0, 2.0), Some(false));
+        assert!(fan.control.idle());
+
+        assert_eq!(fan.update(46.0, 2.0), Some(true));
+    }
+
+    #[test]
+    fn window_roles_split_on_and_off() {
+        let config = testing::config(
+            "temp_intervals = [1, 5]\ncpu_intervals = [1]\nmax_fan_on_temp = 50.0\n\
+             on_ticks = 2\n\
+             temp_window_roles = [\"on\", \"off\"]",
+        );
+        let mut fan = TestFan::new(&config);
+        for _ in 0..4 {
+            fan.update(40.0, 0.0);
+        }
+
+        // The long window's 51 would make a second tick over, but it can't
+        // turn the fan on
+        fan.update(95.0, 0.0);
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+
+        fan.update(60.0, 0.0);
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+        assert_eq!(
+            fan.control.trigger(),
+            Some(TriggerReason::Temperature { window: 0 })
+        );
+
+        // Once on only the long window keeps it running, down to its 48
+        assert_eq!(fan.update(60.0, 0.0), Some(true));
+        assert_eq!(
+            fan.control.trigger(),
+            Some(TriggerReason::Temperature { window: 1 })
+        );
+        for _ in 0..2 {
+            assert_eq!(fan.update(40.0, 0.0), Some(true));
+        }
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn panic_command_runs_on_the_sample_reaching_panic_temp() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\npanic_temp = 90.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        let ran = testing::temp_dir("panic").join("ran");
+        fan.control.panic_command = Some(format!(
+            "echo \"$FAN_NAME $FAN_TEMPERATURE\" >> {}",
+            ran.display()
+        ));
+
+        let sample = |fan: &mut TestFan, temperature: f32| {
+            fan.push(
+                vec![0.0],
+                vec![testing::reading("coretemp Package id 0", temperature)],
+            );
+            fan.control.sample();
+        };
+        sample(&mut fan, 89.5);
+        assert!(!ran.exists());
+        // Run by the sample itself, with no decision made
+        sample(&mut fan, 91.0);
+        assert_eq!(fs::read_to_string(&ran).unwrap(), "fan0 91.0\n");
+        // Only once
+        sample(&mut fan, 95.0);
+        assert_eq!(fs::read_to_string(&ran).unwrap(), "fan0 91.0\n");
+    }
+
+    #[test]
+    fn one_pegged_core_under_each_cpu_aggregation() {
+        let decide = |aggregation: &str| {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 50.0\nmax_fan_on_cpu = 80.0\ncpu_aggregation = \"{}\"",
+                WINDOWS, aggregation
+            ));
+            let mut fan = TestFan::new(&config);
+            // Past the cold start, which only looks at the temperature
+            fan.update(40.0, 0.0);
+            let package = vec![testing::reading("coretemp Package id 0", 40.0)];
+            fan.push(vec![100.0, 2.0, 0.0, 2.0], package);
+            (fan.control.update(), fan.control.usage().last_cpu())
+        };
+
+        assert_eq!(decide("max"), (Some(true), Some(100.0)));
+        assert_eq!(decide("mean"), (Some(false), Some(26.0)));
+    }
+
+    #[test]
+    fn warmup_holds_the_fan_then_hands_over() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nwarmup_ticks = 3",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        for _ in 0..3 {
+            assert_eq!(fan.update(70.0, 0.0), Some(false));
+        }
+        assert!(fan.control.trigger_counts.is_empty());
+        assert_eq!(fan.update(70.0, 0.0), Some(true));
+        assert_eq!(
+            fan.control.trigger(),
+            Some(TriggerReason::Temperature { window: 0 })
+        );
+
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nwarmup_ticks = 2\nwarmup_state = \"on\"",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        for _ in 0..2 {
+            assert_eq!(fan.update(40.0, 0.0), Some(true));
+            assert_eq!(fan.control.trigger(), Some(TriggerReason::Warmup));
+        }
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn small_oscillations_hold_the_duty() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 80.0\ncurve_min_delta = 2.0\n\
+             curve = [{{ temp = 30.0, duty = 20.0 }}, {{ temp = 80.0, duty = 70.0 }}]",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(50.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(40.0));
+
+        for temperature in [51.0, 49.5, 51.5, 48.5, 50.5] {
+            fan.update(temperature, 0.0);
+            assert_eq!(fan.control.duty(), Some(40.0));
+        }
+        fan.update(52.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(42.0));
+    }
+
+    #[test]
+    fn readings_stuck_at_the_floor_are_not_cold() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nsensor_floor = 0.0\nsensor_floor_secs = 3",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+
+        for _ in 0..2 {
+            assert_eq!(fan.update(0.0, 0.0), Some(false));
+        }
+        assert_eq!(fan.update(0.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::SensorFloor));
+        assert_eq!(fan.update(0.0, 0.0), Some(true));
+
+        // A real reading is trusted again straight away
+        assert_eq!(fan.update(40.0, 0.0), Some(false));
+    }
+
+    #[test]
+    fn duty_ramps_to_the_curve_within_its_step() {
+        let config = testing::config(&format!(
+            "{}curve = [{{ temp = 40.0, duty = 20.0 }}, {{ temp = 80.0, duty = 100.0 }}]\n\
+             curve_max_step = 15.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        assert_eq!(fan.control.duty(), Some(20.0));
+
+        let duties: Vec<_> = (0..7)
+            .map(|_| {
+                fan.update(80.0, 0.0);
+                fan.control.duty().unwrap()
+            })
+            .collect();
+        assert_eq!(duties, [35.0, 50.0, 65.0, 80.0, 95.0, 100.0, 100.0]);
+    }
+
+    #[test]
+    fn busy_but_cold_leaves_the_fan_off() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\nmax_fan_on_cpu = 50.0\nusage_trigger_min_temp = 35.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(25.0, 0.0);
+        assert_eq!(fan.update(25.0, 95.0), Some(false));
+        assert_eq!(fan.update(30.0, 95.0), Some(false));
+        // Usage counts again once warm enough
+        assert_eq!(fan.update(40.0, 95.0), Some(true));
+        assert_eq!(
+            fan.control.trigger(),
+            Some(TriggerReason::CpuUsage { window: 0 })
+        );
+    }
+
+    #[test]
+    fn reading_exactly_at_the_threshold() {
+        let at_threshold = |boundary: &str| {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 60.0\nthreshold_boundary = \"{}\"",
+                WINDOWS, boundary
+            ));
+            let mut fan = TestFan::new(&config);
+            fan.update(40.0, 0.0);
+            fan.update(60.0, 0.0)
+        };
+        assert_eq!(at_threshold("exclusive"), Some(false));
+        assert_eq!(at_threshold("inclusive"), Some(true));
+    }
+
+    #[test]
+    fn health_check_flags_a_slow_fan() {
+        let tach = testing::temp_dir("health-check").join("fan1_input");
+        fs::write(&tach, "600\n").unwrap();
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\ntach = \"{}\"\n\
+             health_check = {{ every_secs = 5, run_secs = 2, min_rpm = 1000 }}",
+            WINDOWS,
+            tach.display()
+        ));
+        let mut fan = TestFan::new(&config);
+        let mut checks = Vec::new();
+        for _ in 0..8 {
+            fan.update(40.0, 0.0);
+            checks.push((fan.control.trigger(), fan.control.degraded()));
+        }
+        let check = Some(TriggerReason::HealthCheck);
+        assert_eq!(
+            checks,
+            [
+                (None, Some(false)),
+                (None, Some(false)),
+                (None, Some(false)),
+                (None, Some(false)),
+                (check, Some(false)),
+                (check, Some(false)),
+                (None, Some(true)),
+                (None, Some(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn sustained_low_rpm_while_on_is_a_spin_down() {
+        let tach = testing::temp_dir("spin-down").join("fan1_input");
+        fs::write(&tach, "1500\n").unwrap();
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\ntach = \"{}\"\n\
+             spin_down = {{ min_rpm = 800, secs = 3 }}",
+            WINDOWS,
+            tach.display()
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(70.0, 0.0);
+        assert_eq!(fan.update(70.0, 0.0), Some(true));
+        assert_eq!(fan.control.spinning_down(), Some(false));
+
+        fs::write(&tach, "400\n").unwrap();
+        let alerts: Vec<Option<bool>> = (0..4)
+            .map(|_| {
+                fan.update(70.0, 0.0);
+                fan.control.spinning_down()
+            })
+            .collect();
+        assert_eq!(alerts, [Some(false), Some(false), Some(true), Some(true)]);
+
+        fs::write(&tach, "1400\n").unwrap();
+        fan.update(70.0, 0.0);
+        assert_eq!(fan.control.spinning_down(), Some(false));
+    }
+
+    #[test]
+    fn explanation_names_the_governing_condition() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 50.0\nmax_fan_on_cpu = 80.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        assert_eq!(fan.control.explain(), "no decision yet");
+
+        fan.update(45.0, 20.0);
+        assert_eq!(
+            fan.control.explain(),
+            "all windows at or below 50.0°C and 80.0% → OFF"
+        );
+        fan.update(53.7, 20.0);
+        assert_eq!(fan.control.explain(), "temperature[0]=53.7°C > 50.0°C → ON");
+        fan.update(45.0, 92.5);
+        assert_eq!(fan.control.explain(), "cpu[0]=92.5% > 80.0% → ON");
+    }
+
+    #[test]
+    fn fan_follows_the_kernel_cooling_device() {
+        let device = testing::temp_dir("cooling-device");
+        fs::write(device.join("max_state"), "3\n").unwrap();
+        fs::write(device.join("cur_state"), "2\n").unwrap();
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\nkernel_cooling = {{ device = \"{}\" }}",
+            WINDOWS,
+            device.display()
+        ));
+        let mut fan = TestFan::new(&config);
+
+        // On while cold because the kernel asks for it
+        assert_eq!(fan.update(40.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Kernel));
+        // And off while hot once it no longer does
+        fs::write(device.join("cur_state"), "0\n").unwrap();
+        assert_eq!(fan.update(70.0, 0.0), Some(false));
+
+        // Left to decide for itself while the device can't be read
+        fs::remove_file(device.join("cur_state")).unwrap();
+        assert_eq!(fan.update(70.0, 0.0), Some(true));
+        assert!(matches!(
+            fan.control.trigger(),
+            Some(TriggerReason::Temperature { .. })
+        ));
+    }
+
+    #[test]
+    fn busy_but_below_the_off_floor_stays_off() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\nmax_fan_on_cpu = 50.0\noff_below_temp = 30.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(25.0, 95.0);
+        for temperature in [25.0, 29.0] {
+            assert_eq!(fan.update(temperature, 95.0), Some(false));
+        }
+        // Above the floor the usage is heard again
+        assert_eq!(fan.update(35.0, 95.0), Some(true));
+        assert_eq!(
+            fan.control.trigger(),
+            Some(TriggerReason::CpuUsage { window: 0 })
+        );
+        // And turned straight off once back below it
+        assert_eq!(fan.update(29.0, 95.0), Some(false));
+    }
+
+    #[test]
+    fn falling_temperature_estimates_the_time_to_off() {
+        let config = testing::config(
+            "sample_interval_secs = 2\ndecision_interval_secs = 2\n\
+             temp_intervals = [10]\ncpu_intervals = [10]\n\
+             max_fan_on_temp = 60.0\ncooldown_target = 50.0",
+        );
+        let mut fan = TestFan::new(&config);
+        fan.update(71.5, 0.0);
+        assert_eq!(fan.control.time_to_off(), None);
+
+        // Falling 1.5°C a sample, the window's mean of 67 is 17 above the
+        // target: 17 / 1.5 samples of 2s
+        for temperature in [70.0, 68.5, 67.0, 65.5, 64.0] {
+            assert_eq!(fan.update(temperature, 0.0), Some(true));
+        }
+        let estimate = fan.control.time_to_off().unwrap().as_secs_f32();
+        assert!((estimate - 17.0 / 1.5 * 2.0).abs() < 0.01, "{}", estimate);
+
+        // No estimate once the temperature stops falling
+        for _ in 0..5 {
+            fan.update(64.0, 0.0);
+        }
+        assert_eq!(fan.control.time_to_off(), None);
+    }
+
+    #[test]
+    fn fans_aggregate_the_same_sensors_their_own_way() {
+        let config = testing::config(&format!(
+            "{}\
+             [[fans]]\nname = \"max\"\nmax_fan_on_temp = 55.0\ntemp_aggregation = \"max\"\n\
+             [[fans]]\nname = \"mean\"\nmax_fan_on_temp = 55.0\ntemp_aggregation = \"mean\"",
+            WINDOWS
+        ));
+        let decide = |index: usize| {
+            let mut fan = TestFan::with_output(&config, index, Box::new(NullOutput));
+            let readings = vec![
+                testing::reading("coretemp Core 0", 60.0),
+                testing::reading("coretemp Core 1", 40.0),
+            ];
+            fan.push(vec![0.0], readings.clone());
+            fan.control.update();
+            fan.push(vec![0.0], readings);
+            (fan.control.update(), fan.control.usage().last_temperature())
+        };
+        assert_eq!(decide(0), (Some(true), Some(60.0)));
+        assert_eq!(decide(1), (Some(false), Some(50.0)));
+    }
+
+    #[test]
+    fn alert_stage_warns_before_the_fan_acts() {
+        let config = testing::config(&format!(
+            "{}alert_temp = 50.0\nmax_fan_on_temp = 60.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        let stages: Vec<(Option<bool>, bool)> = [45.0, 55.0, 58.0, 65.0, 45.0]
+            .into_iter()
+            .map(|temperature| (fan.update(temperature, 0.0), fan.control.temp_alert()))
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                (Some(false), false),
+                (Some(false), true),
+                (Some(false), true),
+                (Some(true), true),
+                (Some(false), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebound_within_the_heat_soak_stays_off() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\nheat_soak = {{ secs = 3, margin = 3.0 }}",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(65.0, 0.0);
+        assert_eq!(fan.update(65.0, 0.0), Some(true));
+        assert_eq!(fan.update(55.0, 0.0), Some(false));
+
+        // Held off against 63 for the rest of the 3s soak, then back to 60
+        assert_eq!(fan.update(61.0, 0.0), Some(false));
+        assert_eq!(fan.update(62.0, 0.0), Some(false));
+        assert_eq!(fan.update(61.0, 0.0), Some(true));
+
+        // A rise past the margin still turns it on while soaking
+        fan.update(55.0, 0.0);
+        assert_eq!(fan.update(64.0, 0.0), Some(true));
+    }
+
+    #[test]
+    fn debounced_decision_switches_once_it_has_held() {
+        let borderline = [
+            61.0, 59.0, 61.0, 59.0, 61.0, 61.0, 61.0, 59.0, 61.0, 59.0, 59.0, 59.0,
+        ];
+        let states = |debounce: u32| -> String {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 60.0\ndebounce_decisions = {}",
+                WINDOWS, debounce
+            ));
+            let mut fan = TestFan::new(&config);
+            fan.update(55.0, 0.0);
+            borderline
+                .iter()
+                .map(|&temp| match fan.update(temp, 0.0) {
+                    Some(true) => '1',
+                    _ => '0',
+                })
+                .collect()
+        };
+
+        assert_eq!(states(0), "101011101000");
+        // On at the third decision above in a row, off at the third below
+        assert_eq!(states(3), "000000111110");
+    }
+
+    #[test]
+    fn runaway_rise_forces_the_fan_on_below_the_threshold() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 70.0\nrunaway_rate = 3.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        for temp in [40.0, 41.0, 43.0] {
+            assert_eq!(fan.update(temp, 0.0), Some(false));
+        }
+
+        // 4°C/s, still far under 70
+        assert_eq!(fan.update(47.0, 0.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Runaway));
+        assert_eq!(fan.update(47.5, 0.0), Some(false));
+    }
+
+    #[test]
+    fn sustained_low_usage_ends_the_cooldown_early() {
+        let cooling = |low_usage_off: &str, temp: f32| -> Vec<Option<bool>> {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 60.0\nmax_fan_on_cpu = 95.0\ncooldown_target = 45.0\n{}",
+                WINDOWS, low_usage_off
+            ));
+            let mut fan = TestFan::new(&config);
+            fan.update(62.0, 50.0);
+            assert_eq!(fan.update(62.0, 50.0), Some(true));
+            // Idle, but still warmer than the cooldown target
+            (0..4).map(|_| fan.update(temp, 5.0)).collect()
+        };
+
+        assert_eq!(cooling("", 52.0), [Some(true); 4]);
+        let early = "low_usage_off = { max_cpu = 10.0, secs = 3, max_temp = 55.0 }";
+        assert_eq!(
+            cooling(early, 52.0),
+            [Some(true), Some(true), Some(false), Some(false)]
+        );
+        // Too warm to stop, however idle
+        assert_eq!(cooling(early, 57.0), [Some(true); 4]);
+    }
+
+    #[test]
+    fn divergent_sensors_decide_on_the_higher() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\ntemp_aggregation = \"mean\"\n\
+             [[divergence]]\nsensors = [\"coretemp Package id 0\", \"coretemp Package id 1\"]\n\
+             max_delta = 10.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        let mut decide = |first: f32, second: f32| {
+            fan.push(
+                vec![0.0],
+                vec![
+                    testing::reading("coretemp Package id 0", first),
+                    testing::reading("coretemp Package id 1", second),
+                ],
+            );
+            let on = fan.control.update();
+            let usage = fan.control.usage();
+            (on, usage.last_temperature(), usage.sensors_diverged())
+        };
+        assert_eq!(decide(45.0, 50.0), (Some(false), Some(47.5), false));
+        // The mean of 55 would leave the fan off
+        assert_eq!(decide(40.0, 70.0), (Some(true), Some(70.0), true));
+        assert_eq!(decide(50.0, 55.0), (Some(false), Some(52.5), false));
+    }
+
+    #[test]
+    fn each_zone_runs_its_own_fans() {
+        let config = testing::config(
+            r#"
+            temp_intervals = [1]
+            cpu_intervals = [1]
+
+            [[zones]]
+            name = "cpu"
+            sensors = ["coretemp"]
+            threshold = 65.0
+
+            [[zones]]
+            name = "disks"
+            sensors = ["drivetemp"]
+            threshold = 45.0
+
+            [[fans]]
+            name = "cpu"
+            zones = ["cpu"]
+            max_fan_on_temp = 90.0
+
+            [[fans]]
+            name = "exhaust"
+            zones = ["cpu", "disks"]
+            max_fan_on_temp = 90.0
+
+            [[fans]]
+            name = "drives"
+            zones = ["disks"]
+            max_fan_on_temp = 90.0
+            "#,
+        );
+        let states = |cpu: f32, disks: f32| -> Vec<Option<bool>> {
+            (0..3)
+                .map(|i| {
+                    let mut fan = TestFan::with_output(&config, i, Box::new(NullOutput));
+                    let readings = vec![
+                        testing::reading("coretemp Package id 0", cpu),
+                        testing::reading("drivetemp sda", disks),
+                    ];
+                    // Past the cold start, which only looks at the fan's own
+                    // threshold
+                    fan.push(vec![0.0], readings.clone());
+                    fan.control.update();
+                    fan.push(vec![0.0], readings);
+                    fan.control.update()
+                })
+                .collect()
+        };
+
+        assert_eq!(states(70.0, 30.0), [Some(true), Some(true), Some(false)]);
+        assert_eq!(states(40.0, 50.0), [Some(false), Some(true), Some(true)]);
+        assert_eq!(states(40.0, 30.0), [Some(false); 3]);
+    }
+
+    #[test]
+    fn momentary_dip_within_the_confirm_delay_keeps_the_fan_on() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 60.0\ncooldown_target = 55.0\noff_confirm_secs = 3",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        let states: Vec<_> = [70.0, 40.0, 45.0, 70.0, 70.0]
+            .into_iter()
+            .map(|temperature| fan.update(temperature, 0.0))
+            .collect();
+        assert_eq!(states, [Some(true); 5]);
+
+        // A dip lasting past the delay does turn it off
+        let states: Vec<_> = (0..5).map(|_| fan.update(40.0, 0.0)).collect();
+        assert_eq!(
+            states,
+            [Some(true), Some(true), Some(true), Some(false), Some(false)]
+        );
+    }
+
+    #[test]
+    fn tie_breaker_decides_at_the_threshold() {
+        let config = testing::config(
+            r#"
+            temp_intervals = [1]
+            cpu_intervals = [1]
+
+            [[zones]]
+            name = "board"
+            sensors = ["acpitz"]
+
+            [[fans]]
+            max_fan_on_temp = 60.0
+            tie_breaker = { zone = "board", threshold = 40.0 }
+            "#,
+        );
+        let decide = |cpu: f32, board: f32| {
+            let mut fan = TestFan::new(&config);
+            for _ in 0..2 {
+                let readings = vec![
+                    testing::reading("coretemp Package id 0", cpu),
+                    testing::reading("acpitz temp1", board),
+                ];
+                fan.push(vec![0.0], readings);
+                fan.control.update();
+            }
+            fan.control.fan_on()
+        };
+
+        assert_eq!(decide(60.0, 45.0), Some(true));
+        assert_eq!(decide(60.0, 35.0), Some(false));
+        // Outside the band the fan's own sensors decide
+        assert_eq!(decide(62.0, 35.0), Some(true));
+        assert_eq!(decide(58.0, 45.0), Some(false));
+    }
+
+    #[test]
+    fn switch_limit_caps_rapid_toggling() {
+        let toggled = |limit: &str| {
+            let config = testing::config(&format!(
+                "{}max_fan_on_temp = 60.0\ncooldown_target = 50.0\n{}",
+                WINDOWS, limit
+            ));
+            let mut fan = TestFan::new(&config);
+            fan.update(40.0, 0.0);
+            let mut transitions = 0;
+            for i in 0..12 {
+                let was = fan.control.fan_on();
+                let temperature = if i % 2 == 0 { 70.0 } else { 40.0 };
+                if fan.update(temperature, 0.0) != was {
+                    transitions += 1;
+                }
+            }
+            transitions
+        };
+
+        assert_eq!(toggled(""), 12);
+        assert_eq!(toggled("switch_limit = { max = 3, window_secs = 60 }"), 3);
+    }
+
+    #[test]
+    fn constant_zero_usage_warns() {
+        let config = testing::config(&format!("{}zero_usage_secs = 5", WINDOWS));
+        let mut fan = TestFan::new(&config);
+
+        for _ in 0..4 {
+            fan.update(40.0, 0.0);
+        }
+        assert!(!fan.control.usage_at_zero);
+        fan.update(40.0, 0.0);
+        assert!(fan.control.usage_at_zero);
+        // Only warned of, still counted in decisions
+        assert!(!fan.control.usage().usage_disabled());
+
+        fan.update(40.0, 12.0);
+        assert!(!fan.control.usage_at_zero);
+    }
+
+    #[test]
+    fn manual_override_beats_an_automatic_trigger() {
+        let config = testing::config(&format!("{}max_fan_on_temp = 60.0", WINDOWS));
+        let mut fan = TestFan::new(&config);
+        fan.update(70.0, 10.0);
+        assert_eq!(fan.update(70.0, 10.0), Some(true));
+        assert!(matches!(
+            fan.control.trigger(),
+            Some(TriggerReason::Temperature { .. })
+        ));
+
+        let by_hand = || ManualSource::new("test".to_string(), None);
+        // Off by hand while the temperature still calls for the fan
+        fan.control.set_manual(Some(0.0), by_hand());
+        assert_eq!(fan.update(70.0, 10.0), Some(false));
+        // And on by hand once it no longer does
+        fan.control.set_manual(Some(100.0), by_hand());
+        assert_eq!(fan.update(40.0, 10.0), Some(true));
+        assert_eq!(fan.control.trigger(), Some(TriggerReason::Manual));
+
+        fan.control.set_manual(None, by_hand());
+        assert_eq!(fan.update(40.0, 10.0), Some(false));
+    }
+
+    /// An output recording every duty written to it
+    struct DutyOutput(std::rc::Rc<std::cell::RefCell<Vec<f32>>>);
+
+    impl FanOutput for DutyOutput {
+        fn set(&mut self, on: bool) -> Result<(), Error> {
+            self.set_duty(if on { 100.0 } else { 0.0 })
+        }
+
+        fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+            self.0.borrow_mut().push(duty);
+            Ok(())
+        }
+    }
+
+    /// A switched output noting when it was last turned on
+    struct StartTime(Rc<std::cell::Cell<Option<Instant>>>);
+
+    impl FanOutput for StartTime {
+        fn set(&mut self, on: bool) -> Result<(), Error> {
+            if on {
+                self.0.set(Some(Instant::now()));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fans_started_together_are_staggered() {
+        let config = testing::config(&format!(
+            "{w}[[fans]]\nmax_fan_on_temp = 60.0\nstart_stagger_ms = 100\n\
+             [[fans]]\nmax_fan_on_temp = 60.0\nstart_stagger_ms = 100",
+            w = WINDOWS
+        ));
+        let last_start = LastStart::default();
+        let mut started = Vec::new();
+        let mut fans: Vec<TestFan> = (0..2)
+            .map(|i| {
+                let time = Rc::default();
+                started.push(Rc::clone(&time));
+                let mut fan = TestFan::with_output(&config, i, Box::new(StartTime(time)));
+                fan.control = fan.control.with_start_stagger(&last_start);
+                fan
+            })
+            .collect();
+        for fan in fans.iter_mut() {
+            fan.update(40.0, 0.0);
+        }
+
+        // Forced on in the same pass
+        for fan in fans.iter_mut() {
+            assert_eq!(fan.update(70.0, 0.0), Some(true));
+        }
+        let (first, second) = (started[0].get().unwrap(), started[1].get().unwrap());
+        assert!(second.duration_since(first) >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn low_duty_is_nudged_periodically() {
+        let config = testing::config(&format!(
+            "{}curve = [{{ temp = 30.0, duty = 10.0 }}, {{ temp = 80.0, duty = 100.0 }}]\n\
+             anti_stall = {{ below = 20.0, duty = 50.0, every_secs = 4, for_secs = 1 }}",
+            WINDOWS
+        ));
+        let writes = std::rc::Rc::default();
+        let output = DutyOutput(std::rc::Rc::clone(&writes));
+        let mut fan = TestFan::with_output(&config, 0, Box::new(output));
+        let duties: Vec<_> = (0..10)
+            .map(|_| {
+                fan.update(30.0, 0.0);
+                writes.borrow().last().copied().unwrap()
+            })
+            .collect();
+        // Pulsed for a second out of every four, the target never changing
+        assert_eq!(
+            duties,
+            [10.0, 10.0, 10.0, 10.0, 50.0, 10.0, 10.0, 10.0, 50.0, 10.0]
+        );
+        assert_eq!(fan.control.duty(), Some(10.0));
+    }
+
+    /// An output recording every state written to it
+    struct RecordingOutput(std::rc::Rc<std::cell::RefCell<Vec<bool>>>);
+
+    impl FanOutput for RecordingOutput {
+        fn set(&mut self, on: bool) -> Result<(), Error> {
+            self.0.borrow_mut().push(on);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn alarm_follows_critical_and_recovery() {
+        let config = testing::config(&format!(
+            "{}max_fan_on_temp = 80.0\ncritical_temp = 90.0",
+            WINDOWS
+        ));
+        let mut fan = TestFan::new(&config);
+        let writes = std::rc::Rc::default();
+        fan.control.alarm = Some(Box::new(RecordingOutput(std::rc::Rc::clone(&writes))));
+
+        fan.update(95.0, 0.0);
+        assert!(fan.control.alarm_on());
+        fan.update(95.0, 0.0);
+        fan.update(40.0, 0.0);
+        assert!(!fan.control.alarm_on());
+        assert_eq!(*writes.borrow(), vec![true, false]);
+    }
+}
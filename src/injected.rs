@@ -0,0 +1,81 @@
+use crate::sensors::{ComponentReading, SensorSource};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Readings injected since starting, by label, with when they were
+#[derive(Default)]
+struct Injected {
+    temperatures: BTreeMap<String, (f32, Instant)>,
+    cpu_usage: Option<(f32, Instant)>,
+}
+
+static INJECTED: Mutex<Injected> = Mutex::new(Injected {
+    temperatures: BTreeMap::new(),
+    cpu_usage: None,
+});
+
+/// Record `temperature` as the latest reading of the sensor `label`
+pub fn temperature(label: &str, temperature: f32) {
+    let mut injected = INJECTED.lock().unwrap_or_else(|e| e.into_inner());
+    injected
+        .temperatures
+        .insert(label.to_string(), (temperature, Instant::now()));
+}
+
+/// Record `usage` percent as the latest CPU usage
+pub fn cpu_usage(usage: f32) {
+    let mut injected = INJECTED.lock().unwrap_or_else(|e| e.into_inner());
+    injected.cpu_usage = Some((usage, Instant::now()));
+}
+
+/// Readings injected through the control socket's `inject` and
+/// `inject-cpu` commands, for running where nothing can be read locally,
+/// e.g. in a container. A reading not injected again within
+/// `injected_max_age_secs` is dropped, so `stale_after_secs` applies.
+pub struct InjectedSource {
+    max_age: Duration,
+    cpu_usages: Vec<f32>,
+    components: Vec<ComponentReading>,
+}
+
+impl InjectedSource {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            cpu_usages: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+}
+
+impl SensorSource for InjectedSource {
+    fn refresh(&mut self) {
+        let injected = INJECTED.lock().unwrap_or_else(|e| e.into_inner());
+        let fresh = |at: &Instant| at.elapsed() <= self.max_age;
+        self.components = injected
+            .temperatures
+            .iter()
+            .filter(|(_, (_, at))| fresh(at))
+            .map(|(label, (temperature, _))| ComponentReading {
+                label: label.clone(),
+                temperature: *temperature,
+                max: f32::NAN,
+                critical: None,
+            })
+            .collect();
+        self.cpu_usages = injected
+            .cpu_usage
+            .filter(|(_, at)| fresh(at))
+            .map(|(usage, _)| vec![usage])
+            .unwrap_or_default();
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu_usages.clone()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
@@ -0,0 +1,170 @@
+use crate::fan_control::{FanControl, TriggerReason};
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// Where journald listens for entries in its native protocol
+const SOCKET: &str = "/run/systemd/journal/socket";
+const IDENTIFIER: &str = "gpio-fan";
+/// Syslog priorities entries are sent at
+const INFO: u8 = 6;
+const CRITICAL: u8 = 2;
+
+/// Sends each fan turning on or off to the systemd journal as a structured
+/// entry, with `FAN`, `FAN_STATE`, `TRIGGER`, `DUTY`, `TEMPERATURE` and
+/// `CPU_USAGE` fields alongside the usual message, so `journalctl` can
+/// filter on them, e.g. `journalctl FAN=cpu TRIGGER=critical`.
+pub struct Journal {
+    socket: UnixDatagram,
+    /// State and trigger each fan was last recorded in
+    last: HashMap<String, (Option<bool>, Option<TriggerReason>)>,
+}
+
+impl Journal {
+    /// Connect to the journal when running under systemd with output going
+    /// to it, `None` otherwise so plain logging carries on
+    pub fn detect() -> Option<Self> {
+        if env::var_os("JOURNAL_STREAM").is_none() || !Path::new(SOCKET).exists() {
+            return None;
+        }
+        match Self::connect(SOCKET) {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                eprintln!("Unable to connect to the journal, logging plainly: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+
+        Ok(Self {
+            socket,
+            last: HashMap::new(),
+        })
+    }
+
+    /// Send an entry for every fan that has turned on or off, or changed
+    /// what it is on for, since the last record
+    pub fn record(&mut self, fans: &[FanControl]) {
+        for fan in fans {
+            let state = (fan.fan_on(), fan.trigger());
+            let last = self.last.insert(fan.name().to_string(), state);
+            let changed = match last {
+                Some((on, trigger)) => {
+                    on != state.0 || (state.0 == Some(true) && trigger != state.1)
+                }
+                None => true,
+            };
+            if changed && state.0.is_some() {
+                if let Err(e) = self.socket.send(&entry(fan)) {
+                    eprintln!("Unable to write to the journal: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// A journal entry in the native protocol for `fan`'s current state
+pub fn entry(fan: &FanControl) -> Vec<u8> {
+    let on = fan.fan_on() == Some(true);
+    let message = match fan.trigger() {
+        Some(trigger) if on => format!("{} ON: {}", fan.name(), trigger),
+        _ => format!("{} OFF", fan.name()),
+    };
+    let critical = matches!(
+        fan.trigger(),
+        Some(TriggerReason::Critical | TriggerReason::SensorCritical)
+    );
+
+    let mut entry = Vec::new();
+    field(&mut entry, "MESSAGE", &message);
+    let priority = if critical { CRITICAL } else { INFO };
+    field(&mut entry, "PRIORITY", &priority.to_string());
+    field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    field(&mut entry, "FAN", fan.name());
+    field(&mut entry, "FAN_STATE", if on { "on" } else { "off" });
+    if let Some(trigger) = fan.trigger().filter(|_| on) {
+        field(&mut entry, "TRIGGER", &trigger.to_string());
+    }
+    if let Some(duty) = fan.duty() {
+        field(&mut entry, "DUTY", &duty.to_string());
+    }
+    let usage = fan.usage();
+    if let Some(temperature) = usage.last_temperature() {
+        field(&mut entry, "TEMPERATURE", &temperature.to_string());
+    }
+    if let Some(cpu) = usage.last_cpu() {
+        field(&mut entry, "CPU_USAGE", &cpu.to_string());
+    }
+    entry
+}
+
+/// Append `name=value`, in the length prefixed form when the value spans
+/// lines
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend((value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestFan};
+    use std::slice;
+    use std::time::Duration;
+
+    /// Fields of the next entry sent to `sink`
+    fn receive(sink: &UnixDatagram) -> HashMap<String, String> {
+        let mut buffer = [0u8; 4096];
+        let length = sink.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length])
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn transition_sends_structured_fields() {
+        let path = testing::temp_dir("journal").join("socket");
+        let sink = UnixDatagram::bind(&path).unwrap();
+        sink.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut journal = Journal::connect(&path).unwrap();
+
+        let config = testing::config(
+            "temp_intervals = [1]\ncpu_intervals = [1]\n[[fans]]\nname = \"cpu\"\nmax_fan_on_temp = 60.0",
+        );
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 5.0);
+        journal.record(slice::from_ref(&fan.control));
+        assert_eq!(receive(&sink)["FAN_STATE"], "off");
+
+        // Nothing sent while the state holds
+        fan.update(45.0, 5.0);
+        journal.record(slice::from_ref(&fan.control));
+        fan.update(72.5, 7.5);
+        journal.record(slice::from_ref(&fan.control));
+        let fields = receive(&sink);
+        assert_eq!(fields["MESSAGE"], "cpu ON: temperature[0]");
+        assert_eq!(fields["PRIORITY"], "6");
+        assert_eq!(fields["SYSLOG_IDENTIFIER"], "gpio-fan");
+        assert_eq!(fields["FAN"], "cpu");
+        assert_eq!(fields["FAN_STATE"], "on");
+        assert_eq!(fields["TRIGGER"], "temperature[0]");
+        assert_eq!(fields["TEMPERATURE"], "72.5");
+        assert_eq!(fields["CPU_USAGE"], "7.5");
+        assert!(!fields.contains_key("DUTY"));
+    }
+}
@@ -0,0 +1,44 @@
+pub mod audit;
+pub mod benchmark;
+pub mod calibrate;
+pub mod chip;
+pub mod clock;
+pub mod config;
+pub mod container;
+pub mod control;
+pub mod controller;
+pub mod curve;
+pub mod daemon;
+pub mod daily;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod fan_control;
+pub mod hook;
+pub mod injected;
+pub mod journal;
+pub mod list;
+pub mod mcp23017;
+pub mod mcp4725;
+pub mod measurement;
+pub mod metrics;
+pub mod mqtt;
+pub mod notify;
+pub mod output;
+pub mod plot;
+pub mod pwm;
+#[cfg(feature = "rgb")]
+pub mod rgb;
+pub mod sample_log;
+pub mod sensors;
+pub mod simulate;
+pub mod status;
+pub mod status_fifo;
+pub mod stdin_sensors;
+#[cfg(test)]
+mod testing;
+pub mod units;
+pub mod usage;
+pub mod validate;
+
+pub use error::{Error, Result};
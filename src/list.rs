@@ -0,0 +1,255 @@
+use crate::chip;
+use crate::config::{Config, OutputConfig};
+use crate::sensors::SensorSource;
+use crate::Error;
+use gpio_cdev::LineDirection;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use sysinfo::{System, SystemExt};
+
+/// Print every component with its temperature, then each CPU's usage
+pub fn sensors<W: Write>(source: &mut dyn SensorSource, out: &mut W) -> io::Result<()> {
+    // Usage is measured between two refreshes
+    source.refresh();
+    std::thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+    source.refresh();
+
+    for component in source.components() {
+        write!(out, "{}\t{:.1}°C", component.label, component.temperature)?;
+        // Not every source knows a component's maximum
+        if component.max.is_finite() {
+            write!(out, "\tmax {:.1}°C", component.max)?;
+        }
+        writeln!(out)?;
+    }
+    for (i, usage) in source.cpu_usages().iter().enumerate() {
+        writeln!(out, "cpu{}\t{:.1}%", i, usage)?;
+    }
+
+    Ok(())
+}
+
+/// Chips driven by the outputs of `config`, each listed once
+pub fn chips(config: &Config) -> Vec<String> {
+    let mut chips: Vec<String> = Vec::new();
+    let outputs = config
+        .fans()
+        .iter()
+        .flat_map(|f| std::iter::once(&f.output).chain(&f.alarm_output));
+    for output in outputs {
+        let output_chips = match output {
+            OutputConfig::Line { chip, .. } => vec![chip.clone()],
+            OutputConfig::MultiLine { groups, .. } => {
+                groups.iter().map(|g| g.chip.clone()).collect()
+            }
+            _ => Vec::new(),
+        };
+        for chip in output_chips {
+            if !chips.contains(&chip) {
+                chips.push(chip);
+            }
+        }
+    }
+
+    chips
+}
+
+/// Print each line of `chip` with its offset, name, consumer and direction
+pub fn gpio<W: Write>(chip: &str, out: &mut W) -> Result<(), Error> {
+    let chip = chip::open(chip)?;
+    writeln!(
+        out,
+        "{} [{}] {} lines",
+        chip.path().display(),
+        chip.label(),
+        chip.num_lines()
+    )?;
+
+    for line in chip.lines() {
+        let info = line.info()?;
+        let direction = match info.direction() {
+            LineDirection::In => "input",
+            LineDirection::Out => "output",
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            line.offset(),
+            info.name().unwrap_or("-"),
+            info.consumer().unwrap_or("-"),
+            direction
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Print the effective configuration, with every default filled in, as
+/// TOML. Comments after it give what each fan resolved to.
+pub fn effective<W: Write>(
+    config: &Config,
+    source: &mut dyn SensorSource,
+    out: &mut W,
+) -> io::Result<()> {
+    let toml = toml::to_string(config).map_err(io::Error::other)?;
+    writeln!(out, "{}", toml.trim_end())?;
+    writeln!(out)?;
+    for line in resolved(config, source) {
+        writeln!(out, "# {}", line)?;
+    }
+
+    Ok(())
+}
+
+/// Describe the sensors each fan of `config` follows, from a fresh read of
+/// `source`, and the device its output resolved to
+pub fn resolved(config: &Config, source: &mut dyn SensorSource) -> Vec<String> {
+    source.refresh();
+    let components = source.components();
+    let chips: Vec<(PathBuf, String)> = gpio_cdev::chips()
+        .map(|chips| {
+            chips
+                .filter_map(Result::ok)
+                .map(|c| (c.path().to_path_buf(), c.label().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let resolve = |name: &str| {
+        let path = chip::resolve(name, chips.iter().cloned());
+        match chips.iter().find(|(p, _)| *p == path) {
+            Some((_, label)) => format!("{} [{}]", path.display(), label),
+            None => format!("{} (not found)", path.display()),
+        }
+    };
+
+    let describe = |output: &OutputConfig| match output {
+        OutputConfig::Line { chip, line, .. } => format!("line {} of {}", line, resolve(chip)),
+        OutputConfig::MultiLine { groups, .. } => groups
+            .iter()
+            .map(|g| format!("lines {:?} of {}", g.lines, resolve(&g.chip)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        OutputConfig::HwmonPwm { hwmon, pwm, .. } => {
+            format!("{}", hwmon.join(format!("pwm{}", pwm)).display())
+        }
+        OutputConfig::AcpiFan { path, .. } => format!("{}", path.display()),
+        OutputConfig::Pwm { chip, channel, .. } => {
+            format!("{}", chip.join(format!("pwm{}", channel)).display())
+        }
+        OutputConfig::Mcp23017 { bus, address, pin } => {
+            format!("{} 0x{:02x} pin {}", bus.display(), address, pin)
+        }
+        OutputConfig::Mcp4725 { bus, address, .. } => {
+            format!("{} 0x{:02x}", bus.display(), address)
+        }
+    };
+
+    let mut lines = Vec::new();
+    for (i, fan) in config.fans().iter().enumerate() {
+        let name = fan.name(i);
+        let filter = config.sensor_filter(fan);
+        let sensors: Vec<String> = filter
+            .select(components.clone())
+            .iter()
+            .map(|c| {
+                let temp = config.display_units.from_celsius(c.temperature);
+                let temp = config.display_units.format(temp, config.display_decimals);
+                format!("{} {}", c.label, temp)
+            })
+            .collect();
+        if sensors.is_empty() {
+            lines.push(format!("{} sensors: none matched", name));
+        } else {
+            lines.push(format!("{} sensors: {}", name, sensors.join(", ")));
+        }
+
+        lines.push(format!("{} output: {}", name, describe(&fan.output)));
+        if let Some(alarm) = &fan.alarm_output {
+            lines.push(format!("{} alarm output: {}", name, describe(alarm)));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::{ComponentReading, FakeSensorSource};
+    use crate::testing;
+    use std::fs;
+
+    #[test]
+    fn sensors_lists_components_and_cpus() {
+        let mut source = FakeSensorSource::new();
+        let readings = vec![
+            ComponentReading {
+                max: 100.0,
+                ..testing::reading("coretemp Package id 0", 45.25)
+            },
+            testing::reading("acpitz temp1", 30.0),
+        ];
+        source.push(vec![5.0, 12.5], readings);
+
+        let mut out = Vec::new();
+        sensors(&mut source, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "coretemp Package id 0\t45.2°C\tmax 100.0°C\n\
+             acpitz temp1\t30.0°C\n\
+             cpu0\t5.0%\n\
+             cpu1\t12.5%\n"
+        );
+    }
+
+    #[test]
+    fn chips_lists_each_configured_chip_once() {
+        let config = testing::config(
+            r#"
+            [[fans]]
+            output = { type = "line", chip = "pinctrl-bcm2835", line = 17 }
+
+            [[fans]]
+            output = { type = "multi-line", groups = [
+                { chip = "pinctrl-bcm2835", lines = [18], on = [1], off = [0] },
+                { chip = "/dev/gpiochip1", lines = [2], on = [1], off = [0] },
+            ] }
+            "#,
+        );
+        assert_eq!(chips(&config), vec!["pinctrl-bcm2835", "/dev/gpiochip1"]);
+    }
+
+    #[test]
+    fn gpio_reports_a_missing_chip() {
+        let mut out = Vec::new();
+        assert!(gpio("/nonexistent/gpiochip", &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn effective_config_takes_the_override() {
+        let path = testing::temp_dir("effective").join("gpio-fan.toml");
+        fs::write(
+            &path,
+            "verbose_interval_secs = 30\nmax_fan_on_temp = 55.0\n",
+        )
+        .unwrap();
+        let overrides = ["max_fan_on_temp=70".to_string()];
+        let config = Config::load_with(Some(&path), &overrides).unwrap();
+        let mut source = FakeSensorSource::new();
+        source.push(
+            vec![0.0],
+            vec![testing::reading("coretemp Package id 0", 40.0)],
+        );
+
+        let mut out = Vec::new();
+        effective(&config, &mut source, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let dumped: toml::Table = out.parse().unwrap();
+        assert_eq!(dumped["max_fan_on_temp"].as_float(), Some(70.0));
+        assert_eq!(dumped["verbose_interval_secs"].as_integer(), Some(30));
+        // Defaults are filled in
+        assert_eq!(dumped["decision_interval_secs"].as_integer(), Some(1));
+        assert!(out.contains("\n# "));
+    }
+}
@@ -1,249 +1,206 @@
-use gpio_cdev::{Chip, Line};
-use std::iter::Sum;
-use std::{env::args, time::Duration};
-use sysinfo::{Component, ComponentExt, Cpu, CpuExt, System, SystemExt};
+use gpio_fan::config::Config;
+use gpio_fan::controller::Controller;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-#[derive(Debug)]
-struct Measurement {
-    measures: Vec<f32>,
-    avg: f32,
-    max: usize,
-}
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let validate = args.iter().any(|a| a == "--validate");
+    let daemon = args.iter().any(|a| a == "--daemon");
+    let explain = args.iter().any(|a| a == "--explain");
+    let value_of = |flag: &str| {
+        args.iter().position(|a| a == flag).map(|i| {
+            PathBuf::from(
+                args.get(i + 1)
+                    .unwrap_or_else(|| panic!("{} requires a path", flag)),
+            )
+        })
+    };
 
-impl Measurement {
-    pub fn new(max: usize) -> Self {
-        Self {
-            measures: Vec::with_capacity(max),
-            avg: 0f32,
-            max,
+    // Absolute so reloads still find it after daemonizing changes directory
+    let path = value_of("--config").map(|p| std::path::absolute(&p).unwrap_or(p));
+    // Fields set with `--set key=value`, over the file and environment
+    let overrides: Vec<String> = args
+        .windows(2)
+        .filter(|pair| pair[0] == "--set")
+        .map(|pair| pair[1].clone())
+        .collect();
+    let config = match Config::load_with(path.as_deref(), &overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            let source = path
+                .as_ref()
+                .map_or("config".into(), |p| p.display().to_string());
+            if validate {
+                println!("{}: {}", source, e);
+                std::process::exit(1);
+            }
+            panic!("Unable to load {}: {}", source, e);
         }
-    }
+    };
 
-    pub fn update(&mut self, measurement: f32) -> f32 {
-        if self.measures.len() > self.max {
-            self.measures.drain(0..0);
+    // Hidden, for scripted tests and demos of the decision logic
+    if args.iter().any(|a| a == "--stdin-sensors") {
+        let stdin = std::io::stdin().lock();
+        if let Err(e) = gpio_fan::stdin_sensors::run(&config, stdin, &mut std::io::stdout()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-        self.measures.push(measurement);
-
-        self.avg = self.measures.iter().copied().sum();
-        self.avg /= self.measures.len() as f32;
-        self.avg
+        return;
     }
-
-    pub fn interval(&self) -> usize {
-        self.max
+    if let Some(trace) = value_of("--simulate") {
+        let simulated = std::fs::File::open(&trace)
+            .map_err(gpio_fan::Error::from)
+            .and_then(|file| {
+                let trace = std::io::BufReader::new(file);
+                gpio_fan::simulate::run(&config, trace, &mut std::io::stdout())
+            });
+        if let Err(e) = simulated {
+            eprintln!("Unable to simulate {}: {}", trace.display(), e);
+            std::process::exit(1);
+        }
+        return;
     }
-
-    pub fn measurement(&self) -> f32 {
-        self.avg
+    let open_sensors = |config: &Config| {
+        gpio_fan::sensors::from_config(config).unwrap_or_else(|e| {
+            eprintln!("Unable to open sensors: {}", e);
+            std::process::exit(1);
+        })
+    };
+    if args.iter().any(|a| a == "--list-sensors") {
+        let mut source = open_sensors(&config);
+        gpio_fan::list::sensors(source.as_mut(), &mut std::io::stdout()).unwrap();
+        return;
     }
-}
-
-struct Usage {
-    system: System,
-    cpu: Vec<Measurement>,
-    temperature: Vec<Measurement>,
-    max_temp: Option<f32>,
-}
-
-const CPU_COMPONENT_LABEL: &str = "coretemp";
-
-impl Usage {
-    pub fn new(cpu_intervals_sec: Vec<usize>, temp_intervals_sec: Vec<usize>) -> Self {
-        let mut cpu = Vec::new();
-        for interval in cpu_intervals_sec {
-            cpu.push(Measurement::new(interval));
-        }
-
-        let mut temperature = Vec::new();
-        for interval in temp_intervals_sec {
-            temperature.push(Measurement::new(interval));
-        }
-
-        Self {
-            cpu,
-            temperature,
-            system: System::new_all(),
-            max_temp: None,
+    if args.iter().any(|a| a == "--print-config") {
+        let mut source = open_sensors(&config);
+        gpio_fan::list::effective(&config, source.as_mut(), &mut std::io::stdout()).unwrap();
+        return;
+    }
+    if let Some(plot) = value_of("--plot") {
+        let written = std::fs::File::create(&plot)
+            .and_then(|mut file| gpio_fan::plot::svg(&config, &mut file));
+        if let Err(e) = written {
+            eprintln!("Unable to write {}: {}", plot.display(), e);
+            std::process::exit(1);
         }
+        return;
     }
-
-    pub fn update(&mut self) {
-        self.system.refresh_cpu();
-        self.system.refresh_system();
-        self.system.refresh_components();
-
-        let mut max_cpu_usage: Option<f32> = None;
-        for cpu in self.system.cpus() {
-            if let Some(cpu_usage) = max_cpu_usage {
-                if cpu.cpu_usage() > cpu_usage {
-                    max_cpu_usage = Some(cpu.cpu_usage());
-                }
-            } else {
-                max_cpu_usage = Some(cpu.cpu_usage());
+    if args.iter().any(|a| a == "--list-gpio") {
+        let mut failed = false;
+        for chip in gpio_fan::list::chips(&config) {
+            if let Err(e) = gpio_fan::list::gpio(&chip, &mut std::io::stdout()) {
+                eprintln!("{}: {}", chip, e);
+                failed = true;
             }
         }
+        std::process::exit(if failed { 1 } else { 0 });
+    }
 
-        let mut max_cpu_temps: Option<f32> = None;
-        let mut min_cpu_max = self.max_temp;
-        for c in self.system.components() {
-            if c.label().starts_with(CPU_COMPONENT_LABEL) {
-                if max_cpu_temps.is_none() || c.temperature() > max_cpu_temps.unwrap() {
-                    max_cpu_temps = Some(c.temperature());
+    if args.iter().any(|a| a == "--calibrate-fan") {
+        let mut state = gpio_fan::calibrate::State::load(&config.state_file)
+            .unwrap_or_else(|e| panic!("Unable to load state: {}", e));
+        if config.fans().iter().all(|fan| fan.tach.is_none()) {
+            eprintln!("No fan has a tach to calibrate from");
+            std::process::exit(1);
+        }
+        let mut failed = false;
+        for (i, fan) in config.fans().iter().enumerate() {
+            let name = fan.name(i);
+            if fan.tach.is_none() {
+                continue;
+            }
+            println!("Calibrating {}", name);
+            match gpio_fan::calibrate::calibrate(fan) {
+                Ok(Some(duty)) => {
+                    println!("{} spins from {}%", name, duty);
+                    state.min_duty.insert(name, duty);
                 }
-                if min_cpu_max.is_none() || c.max() < min_cpu_max.unwrap() {
-                    min_cpu_max = Some(c.max());
+                Ok(None) => {
+                    eprintln!("{} didn't spin at any duty", name);
+                    failed = true;
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", name, e);
+                    failed = true;
                 }
             }
         }
-
-        if let Some(max_cpu_usage) = max_cpu_usage {
-            for cpu in self.cpu.iter_mut() {
-                cpu.update(max_cpu_usage);
-            }
-        }
-        if let Some(max_cpu_temps) = max_cpu_temps {
-            for temp in self.temperature.iter_mut() {
-                temp.update(max_cpu_temps);
-            }
+        if let Err(e) = state.save(&config.state_file) {
+            eprintln!("Unable to save {}: {}", config.state_file.display(), e);
+            failed = true;
         }
+        std::process::exit(if failed { 1 } else { 0 });
     }
 
-    pub fn cpu_max_temp(&self) -> Option<f32> {
-        self.max_temp
-    }
-}
-
-struct FanControl {
-    usage: Usage,
-    chip: Chip,
-    fan_output: Line,
-    fan_on: Option<bool>,
-    max_fan_on_temp: f32,
-    max_fan_on_cpu: f32,
-}
-
-impl FanControl {
-    pub fn new(
-        chip: String,
-        line: u32,
-        usage: Usage,
-        max_fan_on_temp: f32,
-        max_fan_on_cpu: f32,
-    ) -> Result<Self, gpio_cdev::Error> {
-        let mut chip = Chip::new(chip)?;
-        let fan_output = chip.get_line(line)?;
-
-        Ok(Self {
-            usage,
-            chip,
-            fan_output,
-            fan_on: None,
-            max_fan_on_temp,
-            max_fan_on_cpu,
-        })
+    if validate {
+        let problems = gpio_fan::validate::check(&config);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        std::process::exit(if problems.is_empty() { 0 } else { 1 });
     }
 
-    fn update_fan(&mut self, state: bool) -> Option<bool> {
-        self.fan_on = Some(state);
-
-        self.fan_on
+    // Runs the real loop, so outputs are driven as usual
+    if let Some(i) = args.iter().position(|a| a == "--benchmark") {
+        let ticks = args
+            .get(i + 1)
+            .and_then(|t| t.parse().ok())
+            .filter(|&t: &u64| t > 0)
+            .unwrap_or_else(|| panic!("--benchmark requires a number of ticks above 0"));
+        let mut controller =
+            Controller::new(config, path).unwrap_or_else(|e| panic!("Unable to start: {}", e));
+        controller.set_explain(explain);
+
+        controller.wait_for_sensors();
+        gpio_fan::benchmark::run(&mut controller, ticks, &mut std::io::stdout()).unwrap();
+        return;
     }
 
-    pub fn update(&mut self) -> Option<bool> {
-        self.usage.update();
-
-        // Find maximum temperature to use
-        let mut max_temp = self.max_fan_on_temp;
-        if let Some(usage_max) = self.usage.cpu_max_temp() {
-            if usage_max < max_temp {
-                max_temp = usage_max;
-            }
-        }
-
-        // Any temperature above maximum?
-        if self
-            .usage
-            .temperature
-            .iter()
-            .any(|t| t.measurement() > max_temp)
-        {
-            return self.update_fan(true);
-        }
-
-        // CPU Usage > max
-        if self
-            .usage
-            .cpu
-            .iter()
-            .any(|u| u.measurement() > self.max_fan_on_cpu)
-        {
-            return self.update_fan(true);
-        }
-
-        // Use middle measurement
-        if let Some(fan_on) = self.fan_on {
-            let first = self.usage.temperature.first().map(|t| t.measurement());
-            let middle = self
-                .usage
-                .temperature
-                .get(self.usage.temperature.len() / 2)
-                .map(|t| t.measurement());
+    let pid_file = daemon.then(|| {
+        let pid_file = value_of("--pid-file").unwrap_or_else(|| "/run/gpio-fan.pid".into());
+        let log_file = value_of("--log-file").unwrap_or_else(|| "/var/log/gpio-fan.log".into());
+        gpio_fan::daemon::daemonize(&pid_file, &log_file)
+            .unwrap_or_else(|e| panic!("Unable to daemonize: {}", e))
+    });
 
-            // Latest rolling average > max / 2 && > next rolling
-            let on = first
-                .is_some_and(|f| f > self.max_fan_on_temp / 2f32 && middle.is_some_and(|m| f > m));
-
-            self.update_fan(on)
-        } else {
-            // Fan's not been used yet, turn it off
-            self.update_fan(false)
+    if let Some(runtime) = gpio_fan::container::detect() {
+        for line in gpio_fan::container::guidance(&runtime, &config) {
+            println!("{}", line);
         }
     }
-
-    pub fn fan_on(&self) -> Option<bool> {
-        self.fan_on
+    // Started first for its startup delay, so sensors are resolved as read
+    let mut controller = Controller::new(config.clone(), path)
+        .unwrap_or_else(|e| panic!("Unable to start: {}", e))
+        .with_overrides(overrides);
+    controller.set_explain(explain);
+    let mut source = open_sensors(&config);
+    for line in gpio_fan::list::resolved(&config, source.as_mut()) {
+        println!("{}", line);
     }
+    drop(source);
 
-    pub fn usage(&self) -> &Usage {
-        &self.usage
+    // Stop cleanly on SIGINT/SIGTERM so outputs can restore their state
+    let term = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&term)).unwrap();
     }
-}
-
-fn verbose(fan_control: &FanControl) {
-    let usage = fan_control.usage();
-    let cpu_measurements: Vec<f32> = usage.cpu.iter().map(|c| c.measurement()).collect();
-    let temp_measurements: Vec<f32> = usage.temperature.iter().map(|t| t.measurement()).collect();
-    let fan_verbose = match fan_control.fan_on() {
-        Some(true) => "ON",
-        Some(false) => "OFF",
-        _ => "--",
-    };
-    println!(
-        "[{}] {:?}, {:?}",
-        fan_verbose, cpu_measurements, temp_measurements
-    );
-}
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let max_history = 100;
-
-    let cpu_intervals = vec![3, 10, 60];
-    let temp_intervals = vec![5, 30, 60];
-    let cpu_crit = 60f32;
-    let mut usage = Usage::new(cpu_intervals, temp_intervals);
+    let reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload)).unwrap();
 
-    let chip = "/dev/gpiochip0";
-    let max_fan_on_temp = 31f32;
-    let max_fan_on_cpu = 10f32;
-    let mut fan_control =
-        FanControl::new(chip.to_string(), 1, usage, max_fan_on_temp, max_fan_on_cpu).unwrap();
+    controller.wait_for_sensors();
+    while !term.load(Ordering::Relaxed) {
+        if reload.swap(false, Ordering::Relaxed) {
+            controller.reload();
+        }
+        controller.tick();
 
-    loop {
-        fan_control.update();
-        verbose(&fan_control);
+        std::thread::sleep(controller.sample_interval());
+    }
 
-        std::thread::sleep(Duration::from_secs(1));
+    drop(controller);
+    if let Some(pid_file) = &pid_file {
+        let _ = gpio_fan::daemon::remove_pid_file(pid_file);
     }
 }
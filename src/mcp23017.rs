@@ -0,0 +1,90 @@
+use crate::output::FanOutput;
+use crate::Error;
+use i2cdev::core::I2CDevice;
+use std::io;
+
+// Register addresses with the default IOCON.BANK = 0 layout, port B
+// registers follow their port A counterpart
+const IODIRA: u8 = 0x00;
+const OLATA: u8 = 0x14;
+
+/// Drives a fan from one pin of an MCP23017 I2C GPIO expander, for boards
+/// short on native GPIO. Pins 0-7 are port A, 8-15 port B.
+pub struct Mcp23017Output<D: I2CDevice> {
+    device: D,
+    pin: u8,
+}
+
+impl<D: I2CDevice> Mcp23017Output<D> {
+    /// Configure `pin` as an output, driven low, leaving the other pins as
+    /// they were
+    pub fn new(mut device: D, pin: u8) -> Result<Self, Error> {
+        if pin > 15 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("MCP23017 pin {} out of range 0-15", pin),
+            )
+            .into());
+        }
+
+        let (iodir, bit) = (IODIRA + pin / 8, 1 << (pin % 8));
+        let direction = device.smbus_read_byte_data(iodir).map_err(i2c_err)?;
+        let mut output = Self { device, pin };
+        output.set(false)?;
+        output
+            .device
+            .smbus_write_byte_data(iodir, direction & !bit)
+            .map_err(i2c_err)?;
+
+        Ok(output)
+    }
+}
+
+impl<D: I2CDevice> FanOutput for Mcp23017Output<D> {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        let (olat, bit) = (OLATA + self.pin / 8, 1 << (self.pin % 8));
+        let latch = self.device.smbus_read_byte_data(olat).map_err(i2c_err)?;
+        let latch = if on { latch | bit } else { latch & !bit };
+        self.device
+            .smbus_write_byte_data(olat, latch)
+            .map_err(i2c_err)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn i2c_err<E: std::error::Error>(e: E) -> Error {
+    io::Error::other(format!("I2C: {}", e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use i2cdev::mock::MockI2CDevice;
+
+    fn register(output: &mut Mcp23017Output<MockI2CDevice>, register: u8) -> u8 {
+        output.device.smbus_read_byte_data(register).unwrap()
+    }
+
+    #[test]
+    fn drives_only_its_pin() {
+        let mut device = MockI2CDevice::new();
+        // Every pin an input, two port B pins latched high by someone else
+        device.regmap.write_regs(IODIRA as usize + 1, &[0xff]);
+        device.regmap.write_regs(OLATA as usize + 1, &[0b1000_0001]);
+
+        let mut output = Mcp23017Output::new(device, 10).unwrap();
+        assert_eq!(register(&mut output, IODIRA + 1), 0b1111_1011);
+
+        output.set(true).unwrap();
+        assert_eq!(register(&mut output, OLATA + 1), 0b1000_0101);
+        output.set(false).unwrap();
+        assert_eq!(register(&mut output, OLATA + 1), 0b1000_0001);
+        assert_eq!(register(&mut output, OLATA), 0);
+    }
+
+    #[test]
+    fn rejects_pin_out_of_range() {
+        assert!(Mcp23017Output::new(MockI2CDevice::new(), 16).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::mcp23017::i2c_err;
+use crate::output::FanOutput;
+use crate::Error;
+use i2cdev::core::I2CDevice;
+
+/// Largest code of the 12-bit DAC
+pub const MAX_CODE: u16 = 0x0fff;
+
+/// Drives an analog controlled fan from an MCP4725 I2C DAC, setting its
+/// output voltage in proportion to the duty. `max_code` is the code written
+/// at 100%, limiting the voltage below the DAC's supply if the fan needs it.
+pub struct Mcp4725Output<D: I2CDevice> {
+    device: D,
+    max_code: u16,
+}
+
+impl<D: I2CDevice> Mcp4725Output<D> {
+    /// Start with the output at 0V
+    pub fn new(device: D, max_code: u16) -> Result<Self, Error> {
+        let mut output = Self {
+            device,
+            max_code: max_code.min(MAX_CODE),
+        };
+        output.set(false)?;
+
+        Ok(output)
+    }
+}
+
+/// DAC code for `duty` percent, scaled so 100% is `max_code`
+pub fn code(duty: f32, max_code: u16) -> u16 {
+    (duty.clamp(0f32, 100f32) * max_code.min(MAX_CODE) as f32 / 100f32).round() as u16
+}
+
+impl<D: I2CDevice> FanOutput for Mcp4725Output<D> {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        self.set_duty(if on { 100f32 } else { 0f32 })
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        // Fast mode write, the power down bits left clear for normal output
+        let code = code(duty, self.max_code);
+        self.device
+            .write(&[(code >> 8) as u8 & 0x0f, code as u8])
+            .map_err(i2c_err)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Records the DAC's writes, the only transfer it makes, refusing any other
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl I2CDevice for Writes {
+        type Error = io::Error;
+
+        fn read(&mut self, _data: &mut [u8]) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn write(&mut self, data: &[u8]) -> io::Result<()> {
+            self.0.push(data.to_vec());
+            Ok(())
+        }
+
+        fn smbus_write_quick(&mut self, _bit: bool) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn smbus_read_block_data(&mut self, _register: u8) -> io::Result<Vec<u8>> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> io::Result<Vec<u8>> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn smbus_read_i2c_block_data(&mut self, _register: u8, _len: u8) -> io::Result<Vec<u8>> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        fn smbus_write_i2c_block_data(&mut self, _register: u8, _values: &[u8]) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+
+    #[test]
+    fn writes_fast_mode_codes() {
+        let mut output = Mcp4725Output::new(Writes::default(), 0x0800).unwrap();
+        output.set(true).unwrap();
+        output.set_duty(25.0).unwrap();
+
+        assert_eq!(
+            output.device.0,
+            vec![vec![0x00, 0x00], vec![0x08, 0x00], vec![0x02, 0x00]]
+        );
+    }
+
+    #[test]
+    fn duty_maps_onto_the_code_range() {
+        assert_eq!(code(0.0, MAX_CODE), 0);
+        assert_eq!(code(50.0, MAX_CODE), 2048);
+        assert_eq!(code(100.0, MAX_CODE), MAX_CODE);
+        // Out of range duties and codes are held to the DAC's range
+        assert_eq!(code(150.0, MAX_CODE), MAX_CODE);
+        assert_eq!(code(-10.0, MAX_CODE), 0);
+        assert_eq!(code(100.0, 0xffff), MAX_CODE);
+        assert_eq!(code(33.0, 1000), 330);
+    }
+}
@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do with a sample that jumps too far from the previous one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JumpAction {
+    /// Limit the sample to the maximum jump from the previous one
+    #[default]
+    Clamp,
+    /// Drop the sample, unless the next one confirms the jump
+    Discard,
+}
+
+/// How a window combines its samples into one value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowMode {
+    /// Every sample in the window weighted equally
+    #[default]
+    Mean,
+    /// Exponentially weighted, with the smoothing of a window as long
+    Ewma,
+    /// Middle sample, ignoring outliers on either side
+    Median,
+    /// Linearly weighted, the newest sample counting the most and the
+    /// oldest the least
+    Wma,
+}
+
+/// EWMA smoothing factor for samples `interval` seconds apart that follows
+/// a change with a time constant of `time_constant` seconds, reaching 63% of
+/// the way to a new level after that long
+pub fn ewma_alpha(time_constant: f32, interval: f32) -> f32 {
+    1f32 - (-interval / time_constant).exp()
+}
+
+/// Most samples a window keeps unless given another cap, a day of samples a
+/// second apart
+pub const DEFAULT_MAX_SAMPLES: usize = 86_400;
+
+#[derive(Debug, Clone, Copy)]
+pub struct JumpLimit {
+    pub max_jump: f32,
+    pub action: JumpAction,
+}
+
+#[derive(Debug)]
+pub struct Measurement {
+    measures: Vec<f32>,
+    avg: f32,
+    max: usize,
+    /// Most samples actually kept, whatever the window's length
+    cap: usize,
+    mode: WindowMode,
+    /// EWMA smoothing factor, derived from the window length when not given
+    alpha: Option<f32>,
+    jump_limit: Option<JumpLimit>,
+    last_accepted: Option<f32>,
+    rejected: Option<f32>,
+}
+
+impl Measurement {
+    pub fn new(max: usize) -> Self {
+        Self {
+            measures: Vec::with_capacity(max.min(DEFAULT_MAX_SAMPLES)),
+            avg: 0f32,
+            max,
+            cap: DEFAULT_MAX_SAMPLES,
+            mode: WindowMode::default(),
+            alpha: None,
+            jump_limit: None,
+            last_accepted: None,
+            rejected: None,
+        }
+    }
+
+    /// Keep at most `cap` samples, however long the window. The average is
+    /// taken over the samples kept; the length still sets EWMA smoothing.
+    pub fn with_cap(mut self, cap: usize) -> Self {
+        self.cap = cap.max(1);
+        self.measures = Vec::with_capacity(self.kept());
+        self
+    }
+
+    /// Number of samples the window holds once full
+    fn kept(&self) -> usize {
+        self.max.min(self.cap)
+    }
+
+    /// Combine the samples with `mode` rather than a plain mean
+    pub fn with_mode(mut self, mode: WindowMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Smooth EWMA windows by `alpha` rather than by their length
+    pub fn with_ewma_alpha(mut self, alpha: Option<f32>) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Treat samples jumping further than the limit from the previously
+    /// accepted one as glitches
+    pub fn with_jump_limit(mut self, jump_limit: Option<JumpLimit>) -> Self {
+        self.jump_limit = jump_limit;
+        self
+    }
+
+    pub fn update(&mut self, measurement: f32) -> f32 {
+        let Some(measurement) = self.limit_jump(measurement) else {
+            return self.avg;
+        };
+        self.last_accepted = Some(measurement);
+
+        self.measures.push(measurement);
+        if self.measures.len() > self.kept() {
+            let excess = self.measures.len() - self.kept();
+            self.measures.drain(0..excess);
+        }
+
+        self.avg = match self.mode {
+            WindowMode::Mean => self.mean(),
+            // Starts from the first sample, then moves by a fraction set by
+            // the window length, 2 / (n + 1), unless given
+            WindowMode::Ewma if self.measures.len() == 1 => measurement,
+            WindowMode::Ewma => {
+                let alpha = self
+                    .alpha
+                    .unwrap_or_else(|| 2f32 / (self.max as f32 + 1f32));
+                self.avg + (measurement - self.avg) * alpha
+            }
+            WindowMode::Median => {
+                let mut sorted = self.measures.clone();
+                sorted.sort_by(f32::total_cmp);
+                let middle = sorted.len() / 2;
+                if sorted.len().is_multiple_of(2) {
+                    (sorted[middle - 1] + sorted[middle]) / 2f32
+                } else {
+                    sorted[middle]
+                }
+            }
+            // Weights 1 for the oldest up to n for the newest
+            WindowMode::Wma => {
+                let weighted: f32 = (1..)
+                    .zip(&self.measures)
+                    .map(|(weight, m)| weight as f32 * m)
+                    .sum();
+                let n = self.measures.len() as f32;
+                weighted / (n * (n + 1f32) / 2f32)
+            }
+        };
+        self.avg
+    }
+
+    fn mean(&self) -> f32 {
+        self.measures.iter().copied().sum::<f32>() / self.measures.len() as f32
+    }
+
+    /// Apply the jump limit to `measurement`, `None` if it is discarded
+    fn limit_jump(&mut self, measurement: f32) -> Option<f32> {
+        let (Some(limit), Some(previous)) = (self.jump_limit, self.last_accepted) else {
+            return Some(measurement);
+        };
+        if (measurement - previous).abs() <= limit.max_jump {
+            self.rejected = None;
+            return Some(measurement);
+        }
+
+        match limit.action {
+            JumpAction::Clamp => {
+                Some(measurement.clamp(previous - limit.max_jump, previous + limit.max_jump))
+            }
+            JumpAction::Discard => {
+                // A second sample close to the rejected one is a real change
+                let confirmed = self
+                    .rejected
+                    .is_some_and(|r| (measurement - r).abs() <= limit.max_jump);
+                self.rejected = (!confirmed).then_some(measurement);
+                confirmed.then_some(measurement)
+            }
+        }
+    }
+
+    /// Fill the window with `measurement`, replacing any samples
+    pub fn seed(&mut self, measurement: f32) {
+        self.measures.clear();
+        self.measures.resize(self.kept(), measurement);
+        self.avg = measurement;
+        self.last_accepted = Some(measurement);
+        self.rejected = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.measures.clear();
+        self.avg = 0f32;
+        self.last_accepted = None;
+        self.rejected = None;
+    }
+
+    pub fn interval(&self) -> usize {
+        self.max
+    }
+
+    pub fn measurement(&self) -> f32 {
+        self.avg
+    }
+
+    /// Samples currently in the window, oldest first
+    pub fn samples(&self) -> &[f32] {
+        &self.measures
+    }
+
+    /// Least squares slope of the samples in the window, in units per sample.
+    /// `None` until at least two samples have been taken.
+    pub fn slope(&self) -> Option<f32> {
+        let n = self.measures.len();
+        if n < 2 {
+            return None;
+        }
+
+        let mean_x = (n - 1) as f32 / 2f32;
+        let mean_y = self.mean();
+        let mut covariance = 0f32;
+        let mut variance = 0f32;
+        for (x, y) in self.measures.iter().enumerate() {
+            let dx = x as f32 - mean_x;
+            covariance += dx * (y - mean_y);
+            variance += dx * dx;
+        }
+
+        Some(covariance / variance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(action: JumpAction) -> Measurement {
+        Measurement::new(5).with_jump_limit(Some(JumpLimit {
+            max_jump: 5f32,
+            action,
+        }))
+    }
+
+    #[test]
+    fn jump_is_clamped() {
+        let mut window = limited(JumpAction::Clamp);
+        window.update(40.0);
+        window.update(80.0);
+        assert_eq!(window.samples(), &[40.0, 45.0]);
+    }
+
+    #[test]
+    fn jump_is_discarded_unless_confirmed() {
+        let mut window = limited(JumpAction::Discard);
+        window.update(40.0);
+        window.update(80.0);
+        assert_eq!(window.samples(), &[40.0]);
+        window.update(41.0);
+        assert_eq!(window.samples(), &[40.0, 41.0]);
+
+        // Two in a row close together are a real change
+        window.update(80.0);
+        window.update(81.0);
+        assert_eq!(window.samples(), &[40.0, 41.0, 81.0]);
+    }
+
+    #[test]
+    fn window_never_holds_more_than_its_cap() {
+        let mut window = Measurement::new(1_000_000).with_cap(4);
+        for sample in 0..10 {
+            window.update(sample as f32);
+        }
+        assert_eq!(window.samples(), &[6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(window.measurement(), 7.5);
+        assert_eq!(window.interval(), 1_000_000);
+
+        window.seed(40.0);
+        assert_eq!(window.samples().len(), 4);
+    }
+
+    #[test]
+    fn wma_weights_recent_samples_linearly() {
+        let mut wma = Measurement::new(4).with_mode(WindowMode::Wma);
+        let mut mean = Measurement::new(4);
+        for sample in [40.0, 40.0, 40.0, 60.0] {
+            wma.update(sample);
+            mean.update(sample);
+        }
+        // (1 * 40 + 2 * 40 + 3 * 40 + 4 * 60) / 10
+        assert_eq!(wma.measurement(), 48.0);
+        assert_eq!(mean.measurement(), 45.0);
+
+        // (1 * 40 + 2 * 40 + 3 * 60 + 4 * 60) / 10
+        wma.update(60.0);
+        mean.update(60.0);
+        assert_eq!(wma.measurement(), 54.0);
+        assert_eq!(mean.measurement(), 50.0);
+    }
+
+    #[test]
+    fn alpha_from_time_constant() {
+        // 1 - e^(-1 / 10) and 1 - e^(-5 / 5)
+        assert!((ewma_alpha(10.0, 1.0) - 0.095_162_6).abs() < 1e-6);
+        assert!((ewma_alpha(5.0, 5.0) - 0.632_120_6).abs() < 1e-6);
+
+        let mut window = Measurement::new(30)
+            .with_mode(WindowMode::Ewma)
+            .with_ewma_alpha(Some(ewma_alpha(5.0, 5.0)));
+        window.update(0.0);
+        assert!((window.update(100.0) - 63.212_06).abs() < 1e-3);
+    }
+}
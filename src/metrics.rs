@@ -0,0 +1,173 @@
+use crate::config::{MetricsConfig, MetricsProtocol};
+use crate::fan_control::FanControl;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+/// Pushes every fan's latest temperature, CPU usage, state and duty to a
+/// StatsD or InfluxDB endpoint over UDP, at most once per interval. Each fan
+/// is sent as one datagram.
+///
+/// InfluxDB: `<prefix>,fan=<name> temperature=47.8,cpu=3.1,on=1i,duty=40`
+/// StatsD: `<prefix>.<name>.temperature:47.8|g`, one line per value
+pub struct MetricsPush {
+    socket: UdpSocket,
+    protocol: MetricsProtocol,
+    prefix: String,
+    interval: Duration,
+    last_push: Option<Instant>,
+}
+
+impl MetricsPush {
+    pub fn new(config: &MetricsConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&config.address)?;
+
+        Ok(Self {
+            socket,
+            protocol: config.protocol,
+            prefix: config.prefix.clone(),
+            interval: Duration::from_secs(config.interval_secs),
+            last_push: None,
+        })
+    }
+
+    /// Send the fans' metrics if the interval has passed since the last push
+    pub fn push(&mut self, now: Instant, fans: &[FanControl]) {
+        if self
+            .last_push
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return;
+        }
+        self.last_push = Some(now);
+
+        for fan in fans {
+            let payload = match self.protocol {
+                MetricsProtocol::Influx => influx(&self.prefix, fan),
+                MetricsProtocol::StatsD => statsd(&self.prefix, fan),
+            };
+            // Push metrics are best effort, an absent sink refuses datagrams
+            if let Err(e) = self.socket.send(payload.as_bytes()) {
+                if e.kind() != io::ErrorKind::ConnectionRefused {
+                    eprintln!("Unable to push metrics: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Values reported for each fan, skipping those not known yet
+fn values(fan: &FanControl) -> Vec<(&'static str, f32)> {
+    let usage = fan.usage();
+    [
+        ("temperature", usage.last_temperature()),
+        ("cpu", usage.last_cpu()),
+        ("duty", fan.duty()),
+        ("degraded", fan.degraded().map(|d| d as u8 as f32)),
+        ("spinning_down", fan.spinning_down().map(|s| s as u8 as f32)),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|v| (name, v)))
+    .collect()
+}
+
+pub fn influx(measurement: &str, fan: &FanControl) -> String {
+    let mut fields: Vec<String> = values(fan)
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if let Some(on) = fan.fan_on() {
+        fields.push(format!("on={}i", on as u8));
+    }
+
+    // Tag values escape spaces, commas and equals signs
+    let mut tag = String::new();
+    for c in fan.name().chars() {
+        if matches!(c, ' ' | ',' | '=') {
+            tag.push('\\');
+        }
+        tag.push(c);
+    }
+    format!("{},fan={} {}", measurement, tag, fields.join(","))
+}
+
+pub fn statsd(prefix: &str, fan: &FanControl) -> String {
+    let name: String = fan
+        .name()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut lines: Vec<String> = values(fan)
+        .into_iter()
+        .map(|(metric, value)| format!("{}.{}.{}:{}|g", prefix, name, metric, value))
+        .collect();
+    if let Some(on) = fan.fan_on() {
+        lines.push(format!("{}.{}.on:{}|g", prefix, name, on as u8));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestFan};
+
+    fn received(sink: &UdpSocket) -> String {
+        let mut buf = [0u8; 1024];
+        let len = sink.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn pushed_lines_name_each_value() {
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sink.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = testing::config(
+            r#"
+            temp_intervals = [1]
+            cpu_intervals = [1]
+
+            [[fans]]
+            name = "cpu fan"
+            max_fan_on_temp = 50.0
+            "#,
+        );
+        let mut fan = TestFan::new(&config);
+        fan.update(47.5, 3.0);
+        let fans = [fan.control];
+
+        let push = |protocol| {
+            let mut push = MetricsPush::new(&MetricsConfig {
+                address: sink.local_addr().unwrap().to_string(),
+                protocol,
+                interval_secs: 10,
+                prefix: "gpio_fan".to_string(),
+            })
+            .unwrap();
+            let now = Instant::now();
+            push.push(now, &fans);
+            // Within the interval, so nothing more is sent
+            push.push(now + Duration::from_secs(5), &fans);
+            received(&sink)
+        };
+
+        assert_eq!(
+            push(MetricsProtocol::Influx),
+            "gpio_fan,fan=cpu\\ fan temperature=47.5,cpu=3,on=0i"
+        );
+        assert_eq!(
+            push(MetricsProtocol::StatsD),
+            "gpio_fan.cpu_fan.temperature:47.5|g\ngpio_fan.cpu_fan.cpu:3|g\ngpio_fan.cpu_fan.on:0|g"
+        );
+        sink.set_nonblocking(true).unwrap();
+        assert!(sink.recv(&mut [0u8; 16]).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use crate::config::{MqttConfig, MqttTopic};
+use crate::sensors::{ComponentReading, SensorSource, SysinfoSource};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+/// Longest wait between reconnection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Latest value published on each topic, cleared while disconnected
+#[derive(Default)]
+struct Latest {
+    connected: bool,
+    values: HashMap<String, (f32, Instant)>,
+}
+
+/// Temperatures published by remote sensors to an MQTT broker, one topic per
+/// sensor, labelled as configured. A background thread stays subscribed,
+/// reconnecting with a growing delay when the connection drops. While it is
+/// down, and for topics not published to within `max_age_secs`, nothing is
+/// reported, so `stale_after_secs` applies. CPU usage still comes from
+/// `sysinfo`.
+pub struct MqttSource {
+    cpu: SysinfoSource,
+    topics: Vec<MqttTopic>,
+    max_age: Duration,
+    latest: Arc<Mutex<Latest>>,
+    stop: Arc<AtomicBool>,
+    components: Vec<ComponentReading>,
+}
+
+impl MqttSource {
+    pub fn new(config: &MqttConfig) -> Self {
+        let latest = Arc::new(Mutex::new(Latest::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let subscriber = Subscriber {
+            config: config.clone(),
+            latest: Arc::clone(&latest),
+            stop: Arc::clone(&stop),
+        };
+        thread::spawn(move || subscriber.run());
+
+        Self {
+            cpu: SysinfoSource::cpu_only(),
+            topics: config.topics.clone(),
+            max_age: Duration::from_secs(config.max_age_secs),
+            latest,
+            stop,
+            components: Vec::new(),
+        }
+    }
+}
+
+impl Drop for MqttSource {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl SensorSource for MqttSource {
+    fn refresh(&mut self) {
+        self.cpu.refresh();
+        let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        self.components = self
+            .topics
+            .iter()
+            .filter(|_| latest.connected)
+            .filter_map(|topic| {
+                let (temperature, at) = latest.values.get(&topic.topic)?;
+                (at.elapsed() <= self.max_age).then(|| ComponentReading {
+                    label: topic.label().to_string(),
+                    temperature: *temperature,
+                    max: f32::NAN,
+                    critical: None,
+                })
+            })
+            .collect();
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu.cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
+
+/// Keeps a subscription to the configured topics, recording what is
+/// published to them
+struct Subscriber {
+    config: MqttConfig,
+    latest: Arc<Mutex<Latest>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Subscriber {
+    fn run(self) {
+        let mut delay = Duration::from_secs(1);
+        while !self.stop.load(Ordering::Relaxed) {
+            let result = self.subscribe();
+            let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+            // Back to the shortest delay once a connection got through
+            if latest.connected {
+                delay = Duration::from_secs(1);
+            }
+            latest.connected = false;
+            latest.values.clear();
+            drop(latest);
+            match result {
+                Ok(()) => return,
+                Err(e) => eprintln!(
+                    "MQTT connection to {} lost, retrying in {}s: {}",
+                    self.config.address,
+                    delay.as_secs(),
+                    e
+                ),
+            }
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Connect and subscribe, then record publishes until told to stop or
+    /// the connection fails
+    fn subscribe(&self) -> io::Result<()> {
+        let keep_alive = Duration::from_secs(self.config.keep_alive_secs);
+        let mut stream = TcpStream::connect(&self.config.address)?;
+        // Woken at least twice per keep alive to ping and check for stop
+        stream.set_read_timeout(Some(keep_alive / 2))?;
+
+        let mut packets = PacketReader::default();
+        write_packet(&mut stream, CONNECT, &self.connect_packet())?;
+        let (kind, body) = packets.read(&mut stream)?;
+        if kind & 0xf0 != CONNACK || body.get(1) != Some(&0) {
+            return Err(io::Error::other(format!(
+                "connection refused, return code {:?}",
+                body.get(1)
+            )));
+        }
+
+        let mut subscribe = 1u16.to_be_bytes().to_vec();
+        for topic in &self.config.topics {
+            push_string(&mut subscribe, &topic.topic);
+            subscribe.push(0);
+        }
+        write_packet(&mut stream, SUBSCRIBE, &subscribe)?;
+        println!("MQTT connected to {}", self.config.address);
+        self.latest
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .connected = true;
+
+        let mut last_ping = Instant::now();
+        while !self.stop.load(Ordering::Relaxed) {
+            if last_ping.elapsed() >= keep_alive / 2 {
+                write_packet(&mut stream, PINGREQ, &[])?;
+                last_ping = Instant::now();
+            }
+            let (kind, body) = match packets.read(&mut stream) {
+                Ok(packet) => packet,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            if kind & 0xf0 == PUBLISH {
+                self.record(kind, &body);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn connect_packet(&self) -> Vec<u8> {
+        let mut flags = 0x02;
+        if self.config.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.config.password.is_some() {
+            flags |= 0x40;
+        }
+
+        let mut packet = Vec::new();
+        push_string(&mut packet, "MQTT");
+        packet.push(4);
+        packet.push(flags);
+        packet.extend((self.config.keep_alive_secs as u16).to_be_bytes());
+        push_string(&mut packet, &self.config.client_id);
+        for field in [&self.config.username, &self.config.password]
+            .into_iter()
+            .flatten()
+        {
+            push_string(&mut packet, field);
+        }
+        packet
+    }
+
+    /// Record the temperature in a PUBLISH packet, if it is on one of the
+    /// topics and reads as one
+    fn record(&self, kind: u8, body: &[u8]) {
+        let Some((topic, payload)) = parse_publish(kind, body) else {
+            return;
+        };
+        let Some(config) = self.config.topics.iter().find(|t| t.topic == topic) else {
+            return;
+        };
+        match parse_payload(payload, config.json_key.as_deref()) {
+            Some(temperature) => {
+                let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+                latest.values.insert(topic, (temperature, Instant::now()));
+            }
+            None => eprintln!(
+                "Ignoring MQTT message on {}, not a temperature: {:?}",
+                topic,
+                String::from_utf8_lossy(payload)
+            ),
+        }
+    }
+}
+
+/// Topic and payload of a PUBLISH packet's body
+fn parse_publish(kind: u8, body: &[u8]) -> Option<(String, &[u8])> {
+    let length = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + length)?).ok()?;
+    // QoS 1 and 2 messages carry a packet identifier before the payload
+    let qos = (kind >> 1) & 0x03;
+    let start = 2 + length + if qos > 0 { 2 } else { 0 };
+
+    Some((topic.to_string(), body.get(start..)?))
+}
+
+/// A temperature from a payload holding just the number, or a JSON object
+/// with it under `json_key`
+pub fn parse_payload(payload: &[u8], json_key: Option<&str>) -> Option<f32> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    match json_key {
+        None => text.parse().ok(),
+        Some(key) => {
+            let value: serde_json::Value = serde_json::from_str(text).ok()?;
+            value.get(key)?.as_f64().map(|v| v as f32)
+        }
+    }
+}
+
+fn push_string(packet: &mut Vec<u8>, value: &str) {
+    packet.extend((value.len() as u16).to_be_bytes());
+    packet.extend(value.as_bytes());
+}
+
+fn write_packet<W: Write>(out: &mut W, kind: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![kind];
+    // Remaining length, 7 bits per byte with the top bit marking more
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    out.write_all(&packet)
+}
+
+/// Reads packets off a stream, keeping whatever part of one has arrived
+/// when a read times out so the next read carries on from it
+#[derive(Default)]
+struct PacketReader {
+    buffer: Vec<u8>,
+}
+
+impl PacketReader {
+    fn read<R: Read>(&mut self, input: &mut R) -> io::Result<(u8, Vec<u8>)> {
+        loop {
+            if let Some((kind, body, used)) = parse_packet(&self.buffer)? {
+                self.buffer.drain(..used);
+                return Ok((kind, body));
+            }
+            let mut chunk = [0u8; 1024];
+            match input.read(&mut chunk)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                read => self.buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+/// The packet at the start of `data` and the bytes it takes up, or `None`
+/// until all of it is there
+fn parse_packet(data: &[u8]) -> io::Result<Option<(u8, Vec<u8>, usize)>> {
+    let Some(&kind) = data.first() else {
+        return Ok(None);
+    };
+
+    let mut length = 0usize;
+    for (i, shift) in (0..28).step_by(7).enumerate() {
+        let Some(&byte) = data.get(1 + i) else {
+            return Ok(None);
+        };
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            let start = 2 + i;
+            let packet = data
+                .get(start..start + length)
+                .map(|body| (kind, body.to_vec(), start + length));
+            return Ok(packet);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed remaining length",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::usage::Usage;
+    use std::collections::VecDeque;
+    use std::net::TcpListener;
+
+    /// A broker accepting one client and publishing `payload` on `topic`
+    /// once it has subscribed, then holding the connection open
+    fn broker(topic: &'static str, payload: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut packets = PacketReader::default();
+            let (kind, _) = packets.read(&mut stream).unwrap();
+            assert_eq!(kind, CONNECT);
+            write_packet(&mut stream, CONNACK, &[0, 0]).unwrap();
+            let (kind, _) = packets.read(&mut stream).unwrap();
+            assert_eq!(kind, SUBSCRIBE);
+
+            let mut publish = Vec::new();
+            push_string(&mut publish, topic);
+            publish.extend(payload);
+            write_packet(&mut stream, PUBLISH, &publish).unwrap();
+            while packets.read(&mut stream).is_ok() {}
+        });
+        address
+    }
+
+    #[test]
+    fn published_value_reaches_the_window() {
+        let address = broker("rack/inlet", b"41.5");
+        let config = testing::config(&format!(
+            "temp_intervals = [1]\ncpu_intervals = [1]\n\
+             [mqtt]\naddress = \"{}\"\n\
+             topics = [{{ topic = \"rack/inlet\", label = \"coretemp inlet\" }}]",
+            address
+        ));
+        let source = MqttSource::new(config.mqtt.as_ref().unwrap());
+        let mut usage = Usage::with_source(&config, &config.fans()[0], Box::new(source));
+
+        // Nothing until the broker's message has been received
+        let received = Instant::now();
+        while usage.last_temperature().is_none() {
+            assert!(received.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+            usage.update();
+        }
+        assert_eq!(usage.last_temperature(), Some(41.5));
+        assert_eq!(usage.temperature[0].measurement(), 41.5);
+    }
+
+    /// Hands out each read in turn, as a stream with a read timeout would
+    struct Reads(VecDeque<io::Result<Vec<u8>>>);
+
+    impl Read for Reads {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let data = self.0.pop_front().unwrap_or(Ok(Vec::new()))?;
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn packet_split_by_a_timeout_is_read_whole() {
+        let mut first = Vec::new();
+        push_string(&mut first, "garage/ambient");
+        first.extend(b"18.25");
+        let mut second = Vec::new();
+        push_string(&mut second, "garage/ambient");
+        second.extend(b"19");
+        let mut stream = Vec::new();
+        write_packet(&mut stream, PUBLISH, &first).unwrap();
+        write_packet(&mut stream, PUBLISH, &second).unwrap();
+
+        // The first packet's length and topic, a timeout, then the rest of
+        // both packets at once
+        let mut input = Reads(VecDeque::from([
+            Ok(stream[..6].to_vec()),
+            Err(io::ErrorKind::WouldBlock.into()),
+            Ok(stream[6..].to_vec()),
+        ]));
+        let mut packets = PacketReader::default();
+        let timeout = packets.read(&mut input).unwrap_err();
+        assert_eq!(timeout.kind(), io::ErrorKind::WouldBlock);
+
+        for (payload, temperature) in [(&first, 18.25), (&second, 19.0)] {
+            let (kind, body) = packets.read(&mut input).unwrap();
+            assert_eq!((kind, &body), (PUBLISH, payload));
+            let (topic, payload) = parse_publish(kind, &body).unwrap();
+            assert_eq!(topic, "garage/ambient");
+            assert_eq!(parse_payload(payload, None), Some(temperature));
+        }
+        let eof = packets.read(&mut input).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::config::NotificationsConfig;
+use crate::fan_control::{FanControl, TriggerReason};
+use std::collections::HashMap;
+use std::io;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const APP_NAME: &str = "gpio-fan";
+
+/// A desktop notification, urgent ones shown at the critical urgency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub urgent: bool,
+}
+
+/// Shows notifications on the desktop
+pub trait Notifier {
+    fn send(&mut self, notification: &Notification) -> io::Result<()>;
+}
+
+/// Shows notifications through a `notify-send` compatible command, which
+/// hands them to the freedesktop notification service. The command runs in
+/// the background so a slow notification daemon doesn't hold up control.
+pub struct NotifySend {
+    command: String,
+}
+
+impl NotifySend {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Notifier for NotifySend {
+    fn send(&mut self, notification: &Notification) -> io::Result<()> {
+        let urgency = if notification.urgent {
+            "critical"
+        } else {
+            "normal"
+        };
+        let mut child = Command::new(&self.command)
+            .arg(format!("--app-name={}", APP_NAME))
+            .arg(format!("--urgency={}", urgency))
+            .arg(&notification.summary)
+            .arg(&notification.body)
+            .stdin(Stdio::null())
+            .spawn()?;
+        let command = self.command.clone();
+        thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                eprintln!("Notification command {} exited with {}", command, status)
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Unable to wait for notification command {}: {}", command, e),
+        });
+
+        Ok(())
+    }
+}
+
+/// What was last notified for a fan
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Notified {
+    on: bool,
+    critical: bool,
+    failing: bool,
+}
+
+/// Notifies each fan turning on or off, reaching a critical temperature and
+/// appearing to fail. Turning on and off is rate limited to one notification
+/// per `min_interval_secs` across all fans, changes in between being skipped;
+/// critical temperatures and failures are always shown.
+pub struct Notifications {
+    notifier: Box<dyn Notifier>,
+    min_interval: Duration,
+    fan_state: bool,
+    last: HashMap<String, Notified>,
+    last_sent: Option<Instant>,
+}
+
+impl Notifications {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        Self::with_notifier(config, Box::new(NotifySend::new(&config.command)))
+    }
+
+    pub fn with_notifier(config: &NotificationsConfig, notifier: Box<dyn Notifier>) -> Self {
+        Self {
+            notifier,
+            min_interval: Duration::from_secs(config.min_interval_secs),
+            fan_state: config.fan_state,
+            last: HashMap::new(),
+            last_sent: None,
+        }
+    }
+
+    /// Send a notification for everything that happened to each fan since
+    /// the last record
+    pub fn record(&mut self, now: Instant, fans: &[FanControl]) {
+        for fan in fans {
+            let Some(on) = fan.fan_on() else {
+                continue;
+            };
+            let state = Notified {
+                on,
+                critical: matches!(
+                    fan.trigger(),
+                    Some(TriggerReason::Critical | TriggerReason::SensorCritical)
+                ) && on,
+                failing: fan.spinning_down() == Some(true) || fan.degraded() == Some(true),
+            };
+            let last = self
+                .last
+                .insert(fan.name().to_string(), state)
+                .unwrap_or_default();
+
+            if state.critical && !last.critical {
+                let temperature = fan
+                    .usage()
+                    .last_temperature()
+                    .map(|t| format!(" at {}", fan.usage().display(t)))
+                    .unwrap_or_default();
+                self.send(
+                    now,
+                    Notification {
+                        summary: format!("{} critical temperature", fan.name()),
+                        body: format!("Temperature critical{}, fan forced on", temperature),
+                        urgent: true,
+                    },
+                );
+            } else if self.fan_state && state.on != last.on && !self.limited(now) {
+                let body = match fan.trigger().filter(|_| on) {
+                    Some(trigger) => format!("Fan on: {}", trigger),
+                    None => "Fan off".to_string(),
+                };
+                self.send(
+                    now,
+                    Notification {
+                        summary: fan.name().to_string(),
+                        body,
+                        urgent: false,
+                    },
+                );
+            }
+            if state.failing && !last.failing {
+                self.send(
+                    now,
+                    Notification {
+                        summary: format!("{} may be failing", fan.name()),
+                        body: "Its tach shows it slowing down or outside its expected speed"
+                            .to_string(),
+                        urgent: true,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Whether a notification was sent within the last `min_interval_secs`
+    fn limited(&self, now: Instant) -> bool {
+        self.last_sent
+            .is_some_and(|last| now.duration_since(last) < self.min_interval)
+    }
+
+    fn send(&mut self, now: Instant, notification: Notification) {
+        self.last_sent = Some(now);
+        if let Err(e) = self.notifier.send(&notification) {
+            eprintln!("Unable to send notification: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestFan};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::slice;
+
+    /// Keeps every notification sent to it
+    struct Shown(Rc<RefCell<Vec<Notification>>>);
+
+    impl Notifier for Shown {
+        fn send(&mut self, notification: &Notification) -> io::Result<()> {
+            self.0.borrow_mut().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn critical_temperature_is_notified_urgently() {
+        let shown = Rc::new(RefCell::new(Vec::new()));
+        let config: NotificationsConfig = toml::from_str("").unwrap();
+        let mut notifications =
+            Notifications::with_notifier(&config, Box::new(Shown(Rc::clone(&shown))));
+        let fan_config = testing::config(
+            "temp_intervals = [1]\ncpu_intervals = [1]\n\
+             [[fans]]\nname = \"cpu\"\nmax_fan_on_temp = 60.0\ncritical_temp = 80.0",
+        );
+        let mut fan = TestFan::new(&fan_config);
+        let now = Instant::now();
+
+        for temperature in [40.0, 70.0, 85.0] {
+            fan.update(temperature, 0.0);
+            notifications.record(now, slice::from_ref(&fan.control));
+        }
+        // The critical one shown even within the rate limit of the fan
+        // turning on just before
+        assert_eq!(
+            *shown.borrow(),
+            [
+                Notification {
+                    summary: "cpu".to_string(),
+                    body: "Fan on: temperature[0]".to_string(),
+                    urgent: false,
+                },
+                Notification {
+                    summary: "cpu critical temperature".to_string(),
+                    body: "Temperature critical at 85.0°C, fan forced on".to_string(),
+                    urgent: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,949 @@
+use crate::chip;
+use crate::config::{Bias, EmptyChip, LineGroupConfig, OutputConfig, PulseStep};
+use crate::mcp23017::Mcp23017Output;
+use crate::mcp4725::Mcp4725Output;
+use crate::pwm::{Pwm, SysfsPwm};
+use crate::Error;
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags, MultiLineHandle};
+use i2cdev::linux::LinuxI2CDevice;
+use nix::errno::Errno;
+use std::cell::Cell;
+use std::error::Error as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONSUMER: &str = "gpio-fan";
+
+pub trait FanOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error>;
+
+    /// Drive the fan at `duty` percent. Outputs that can only switch the fan
+    /// turn it on for any duty above 0.
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        self.set(duty > 0f32)
+    }
+}
+
+pub fn from_config(config: &OutputConfig) -> Result<Box<dyn FanOutput>, Error> {
+    if let Some(chip) = empty_chip(config, |chip| Ok(chip::open(chip)?.num_lines()))? {
+        eprintln!("{} has no lines, running its output dry", chip);
+        return Ok(Box::new(NullOutput));
+    }
+
+    Ok(match config {
+        OutputConfig::Line {
+            chip,
+            line,
+            bias,
+            line_name,
+            write_retries,
+            ..
+        } => Box::new(
+            LineOutput::new(chip, *line, *bias, line_name.as_deref())?.with_retries(*write_retries),
+        ),
+        OutputConfig::MultiLine {
+            groups,
+            write_retries,
+            ..
+        } => Box::new(MultiLineOutput::new(groups)?.with_retries(*write_retries)),
+        OutputConfig::HwmonPwm {
+            hwmon,
+            pwm,
+            on_value,
+            off_value,
+        } => Box::new(HwmonPwmOutput::new(hwmon, *pwm, *on_value, *off_value)?),
+        OutputConfig::AcpiFan { path, on_level } => Box::new(AcpiFanOutput::new(path, *on_level)?),
+        OutputConfig::Pwm {
+            chip,
+            channel,
+            frequency_hz,
+            on_duty,
+            off_duty,
+        } => Box::new(PwmOutput::new(
+            SysfsPwm::open(chip, *channel)?,
+            *frequency_hz,
+            *on_duty,
+            *off_duty,
+        )?),
+        OutputConfig::Mcp23017 { bus, address, pin } => {
+            let device = LinuxI2CDevice::new(bus, *address).map_err(std::io::Error::from)?;
+            Box::new(Mcp23017Output::new(device, *pin)?)
+        }
+        OutputConfig::Mcp4725 {
+            bus,
+            address,
+            max_code,
+        } => {
+            let device = LinuxI2CDevice::new(bus, *address).map_err(std::io::Error::from)?;
+            Box::new(Mcp4725Output::new(device, *max_code)?)
+        }
+    })
+}
+
+/// The first chip of a line output allowed to run dry that has no lines,
+/// as counted by `num_lines`
+fn empty_chip<F>(config: &OutputConfig, num_lines: F) -> Result<Option<&str>, Error>
+where
+    F: Fn(&str) -> Result<u32, Error>,
+{
+    let chips: Vec<&str> = match config {
+        OutputConfig::Line {
+            chip,
+            empty_chip: EmptyChip::DryRun,
+            ..
+        } => vec![chip],
+        OutputConfig::MultiLine {
+            groups,
+            empty_chip: EmptyChip::DryRun,
+            ..
+        } => groups.iter().map(|g| g.chip.as_str()).collect(),
+        _ => return Ok(None),
+    };
+    for chip in chips {
+        if num_lines(chip)? == 0 {
+            return Ok(Some(chip));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Discards every write, for running the control logic without hardware
+pub struct NullOutput;
+
+impl FanOutput for NullOutput {
+    fn set(&mut self, _on: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_duty(&mut self, _duty: f32) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Another output written a pulse sequence each time it turns off, before
+/// being left off. The sequence runs in the write, so it is kept short.
+pub struct BrakeOutput {
+    output: Box<dyn FanOutput>,
+    steps: Vec<PulseStep>,
+    on: Option<bool>,
+}
+
+impl BrakeOutput {
+    pub fn new(output: Box<dyn FanOutput>, steps: Vec<PulseStep>) -> Self {
+        Self {
+            output,
+            steps,
+            on: None,
+        }
+    }
+}
+
+/// `output` braked with `steps` as it turns off, unchanged without any
+pub fn braked(output: Box<dyn FanOutput>, steps: &[PulseStep]) -> Box<dyn FanOutput> {
+    if steps.is_empty() {
+        return output;
+    }
+    Box::new(BrakeOutput::new(output, steps.to_vec()))
+}
+
+impl FanOutput for BrakeOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        if !on && self.on == Some(true) {
+            for step in &self.steps {
+                self.output.set(step.on)?;
+                thread::sleep(Duration::from_millis(step.ms));
+            }
+        }
+        self.output.set(on)?;
+        self.on = Some(on);
+
+        Ok(())
+    }
+}
+
+/// When any of the fans sharing it last started
+pub type LastStart = Rc<Cell<Option<Instant>>>;
+
+/// Another output that, when starting the fan, first waits until `delay`
+/// after any fan sharing `last_start` started, so fans switched on together
+/// engage one after another rather than all drawing their inrush current
+/// at once.
+pub struct StaggerOutput {
+    output: Box<dyn FanOutput>,
+    delay: Duration,
+    last_start: LastStart,
+    on: Option<bool>,
+}
+
+impl StaggerOutput {
+    pub fn new(output: Box<dyn FanOutput>, delay: Duration, last_start: LastStart) -> Self {
+        Self {
+            output,
+            delay,
+            last_start,
+            on: None,
+        }
+    }
+
+    /// Wait out the stagger if the write starts the fan, returning whether
+    /// it does
+    fn wait(&self, on: bool) -> bool {
+        let starting = on && self.on != Some(true);
+        if starting {
+            if let Some(last) = self.last_start.get() {
+                thread::sleep(self.delay.saturating_sub(last.elapsed()));
+            }
+        }
+        starting
+    }
+
+    fn written(&mut self, on: bool, starting: bool) {
+        if starting {
+            self.last_start.set(Some(Instant::now()));
+        }
+        self.on = Some(on);
+    }
+}
+
+/// `output` waiting `delay` after other fans start, unchanged without one
+pub fn staggered(
+    output: Box<dyn FanOutput>,
+    delay: Duration,
+    last_start: &LastStart,
+) -> Box<dyn FanOutput> {
+    if delay.is_zero() {
+        return output;
+    }
+    Box::new(StaggerOutput::new(output, delay, Rc::clone(last_start)))
+}
+
+impl FanOutput for StaggerOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        let starting = self.wait(on);
+        self.output.set(on)?;
+        self.written(on, starting);
+
+        Ok(())
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        let on = duty > 0f32;
+        let starting = self.wait(on);
+        self.output.set_duty(duty)?;
+        self.written(on, starting);
+
+        Ok(())
+    }
+}
+
+/// `GPIOHANDLE_REQUEST_BIAS_*` flags, added in Linux 5.5 and not yet known
+/// to gpio-cdev
+const BIAS_PULL_UP: u32 = 1 << 5;
+const BIAS_PULL_DOWN: u32 = 1 << 6;
+const BIAS_DISABLE: u32 = 1 << 7;
+
+/// Flags to request output lines with, including `bias`
+pub fn output_flags(bias: Bias) -> LineRequestFlags {
+    let bias = match bias {
+        Bias::AsIs => 0,
+        Bias::PullUp => BIAS_PULL_UP,
+        Bias::PullDown => BIAS_PULL_DOWN,
+        Bias::Disable => BIAS_DISABLE,
+    };
+    LineRequestFlags::OUTPUT | LineRequestFlags::from_bits_retain(bias)
+}
+
+/// Pause before retrying a line write the kernel asked to be retried
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Whether a failed line write is a transient `EAGAIN` or `EINTR`, from
+/// either the ioctl or the file underneath
+fn recoverable(e: &gpio_cdev::Error) -> bool {
+    let errno = e.source().and_then(|cause| {
+        cause.downcast_ref::<Errno>().copied().or_else(|| {
+            let io = cause.downcast_ref::<std::io::Error>()?;
+            io.raw_os_error().map(Errno::from_i32)
+        })
+    });
+    matches!(errno, Some(Errno::EAGAIN | Errno::EINTR))
+}
+
+/// Run `write`, trying again up to `retries` times while it fails with a
+/// recoverable error
+pub fn retry_write<F>(retries: u32, mut write: F) -> Result<(), gpio_cdev::Error>
+where
+    F: FnMut() -> Result<(), gpio_cdev::Error>,
+{
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Err(e) if attempt < retries && recoverable(&e) => {
+                attempt += 1;
+                thread::sleep(WRITE_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Check every offset in `lines` exists on `chip`, as `get_line` only
+/// reports an invalid offset without saying what would be valid
+fn check_offsets(chip: &Chip, lines: &[u32]) -> Result<(), Error> {
+    check_line_count(chip.label(), chip.path(), chip.num_lines(), lines)
+}
+
+/// Check every offset in `lines` is below `count`, the number of lines of
+/// the chip `label` at `path`
+fn check_line_count(label: &str, path: &Path, count: u32, lines: &[u32]) -> Result<(), Error> {
+    if count == 0 {
+        return Err(Error::Config(format!(
+            "{} ({}) has no lines, it may be a virtual or placeholder chip; \
+             set empty_chip = \"dry-run\" to run without it",
+            label,
+            path.display()
+        )));
+    }
+    match lines.iter().find(|&&line| line >= count) {
+        Some(line) => Err(Error::Config(format!(
+            "line {} is out of range for {} ({}), which has {} lines (0-{})",
+            line,
+            label,
+            path.display(),
+            count,
+            count - 1
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Check `line` of `chip`, named `name` by the kernel, is the one
+/// configured as `expected`, so a renumbered chip can't switch something else
+fn check_line_name(chip: &str, line: u32, name: Option<&str>, expected: &str) -> Result<(), Error> {
+    if name == Some(expected) {
+        return Ok(());
+    }
+
+    Err(Error::Config(format!(
+        "line {} of {} is named {}, expected {}",
+        line,
+        chip,
+        name.unwrap_or("nothing"),
+        expected
+    )))
+}
+
+pub struct LineOutput {
+    handle: LineHandle,
+    retries: u32,
+}
+
+impl LineOutput {
+    /// Request `line` of `chip`, checking it is named `expected_name` when
+    /// given
+    pub fn new(
+        chip: &str,
+        line: u32,
+        bias: Bias,
+        expected_name: Option<&str>,
+    ) -> Result<Self, Error> {
+        let mut opened = chip::open(chip)?;
+        check_offsets(&opened, &[line])?;
+        let line_handle = opened.get_line(line)?;
+        if let Some(expected) = expected_name {
+            check_line_name(chip, line, line_handle.info()?.name(), expected)?;
+        }
+        let handle = line_handle.request(output_flags(bias), 0, CONSUMER)?;
+
+        Ok(Self { handle, retries: 0 })
+    }
+
+    /// Retry writes failing with `EAGAIN` or `EINTR` up to `retries` times
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+impl FanOutput for LineOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        Ok(retry_write(self.retries, || {
+            self.handle.set_value(on as u8)
+        })?)
+    }
+}
+
+/// Lines requested together, whose values are all set in one ioctl
+pub trait LineValues {
+    fn set_values(&self, values: &[u8]) -> Result<(), gpio_cdev::Error>;
+}
+
+impl LineValues for MultiLineHandle {
+    fn set_values(&self, values: &[u8]) -> Result<(), gpio_cdev::Error> {
+        MultiLineHandle::set_values(self, values)
+    }
+}
+
+/// Lines of one chip and their values for each state
+pub struct LineGroup {
+    pub handle: Box<dyn LineValues>,
+    pub on: Vec<u8>,
+    pub off: Vec<u8>,
+}
+
+/// Drives several lines per state. Each group of lines shares a chip and is
+/// written in one ioctl, so all of its lines change together. Groups on
+/// different chips are written one after another, in configured order.
+pub struct MultiLineOutput {
+    groups: Vec<LineGroup>,
+    retries: u32,
+}
+
+impl MultiLineOutput {
+    pub fn new(groups: &[LineGroupConfig]) -> Result<Self, Error> {
+        let mut handles = Vec::with_capacity(groups.len());
+        for group in groups {
+            let mut chip = chip::open(&group.chip)?;
+            check_offsets(&chip, &group.lines)?;
+            let handle = chip.get_lines(&group.lines)?.request(
+                output_flags(group.bias),
+                &group.off,
+                CONSUMER,
+            )?;
+            handles.push(LineGroup {
+                handle: Box::new(handle),
+                on: group.on.clone(),
+                off: group.off.clone(),
+            });
+        }
+
+        Ok(Self::with_groups(handles))
+    }
+
+    /// Drive groups of lines already requested
+    pub fn with_groups(groups: Vec<LineGroup>) -> Self {
+        Self { groups, retries: 0 }
+    }
+
+    /// Retry writes failing with `EAGAIN` or `EINTR` up to `retries` times
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+impl FanOutput for MultiLineOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        for group in &self.groups {
+            let values = if on { &group.on } else { &group.off };
+            retry_write(self.retries, || group.handle.set_values(values))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `pwmY_enable` value selecting manual (userspace) control
+const PWM_ENABLE_MANUAL: &str = "1";
+
+/// Drives a fan already exposed by the kernel as `hwmonX/pwmY`, writing a
+/// 0-255 duty to it. Manual control is enabled on start and the previous
+/// `pwmY_enable` mode is restored when dropped.
+pub struct HwmonPwmOutput {
+    pwm: PathBuf,
+    enable: PathBuf,
+    restore_enable: String,
+    on_value: u8,
+    off_value: u8,
+}
+
+impl HwmonPwmOutput {
+    pub fn new<P: AsRef<Path>>(
+        hwmon: P,
+        pwm: u32,
+        on_value: u8,
+        off_value: u8,
+    ) -> Result<Self, Error> {
+        let hwmon = hwmon.as_ref();
+        let pwm_path = hwmon.join(format!("pwm{}", pwm));
+        let enable = hwmon.join(format!("pwm{}_enable", pwm));
+
+        let restore_enable = fs::read_to_string(&enable)?.trim().to_string();
+        fs::write(&enable, PWM_ENABLE_MANUAL)?;
+
+        Ok(Self {
+            pwm: pwm_path,
+            enable,
+            restore_enable,
+            on_value,
+            off_value,
+        })
+    }
+}
+
+impl FanOutput for HwmonPwmOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        let value = if on { self.on_value } else { self.off_value };
+        fs::write(&self.pwm, value.to_string())?;
+
+        Ok(())
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        let value = (duty.clamp(0f32, 100f32) * 255f32 / 100f32).round() as u8;
+        fs::write(&self.pwm, value.to_string())?;
+
+        Ok(())
+    }
+}
+
+impl Drop for HwmonPwmOutput {
+    fn drop(&mut self) {
+        if let Err(e) = fs::write(&self.enable, &self.restore_enable) {
+            eprintln!(
+                "Unable to restore {} to {}: {}",
+                self.enable.display(),
+                self.restore_enable,
+                e
+            );
+        }
+    }
+}
+
+/// Drives an ACPI fan through its thermal cooling device, writing a level
+/// between 0 (off) and `max_state` to `cur_state`.
+pub struct AcpiFanOutput {
+    cur_state: PathBuf,
+    on_level: u32,
+    max_state: u32,
+}
+
+impl AcpiFanOutput {
+    /// `path` is the fan's cooling device directory, e.g.
+    /// `/sys/bus/acpi/devices/PNP0C0B:00/physical_node/thermal_cooling`.
+    /// The fan is driven to `on_level` when on, or `max_state` if not given.
+    pub fn new<P: AsRef<Path>>(path: P, on_level: Option<u32>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let max_state: u32 = fs::read_to_string(path.join("max_state"))?
+            .trim()
+            .parse()
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid max_state in {}: {}", path.display(), e),
+                )
+            })?;
+
+        Ok(Self {
+            cur_state: path.join("cur_state"),
+            on_level: on_level.unwrap_or(max_state).min(max_state),
+            max_state,
+        })
+    }
+}
+
+impl FanOutput for AcpiFanOutput {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        let level = if on { self.on_level } else { 0 };
+        fs::write(&self.cur_state, level.to_string())?;
+
+        Ok(())
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        let level = (duty.clamp(0f32, 100f32) * self.max_state as f32 / 100f32).round() as u32;
+        fs::write(&self.cur_state, level.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// Frequencies fans are generally driven at, 4-pin fans expect 25kHz
+const FAN_PWM_FREQUENCY_HZ: std::ops::RangeInclusive<u32> = 20..=40_000;
+
+/// Drives a fan from a hardware PWM channel at a fixed frequency, with duty
+/// cycles as percentages for on and off.
+pub struct PwmOutput<P: Pwm> {
+    pwm: P,
+    period_ns: u64,
+    on_duty: f32,
+    off_duty: f32,
+}
+
+impl<P: Pwm> PwmOutput<P> {
+    pub fn new(mut pwm: P, frequency_hz: u32, on_duty: f32, off_duty: f32) -> Result<Self, Error> {
+        if let Some(warning) = frequency_warning(frequency_hz) {
+            eprintln!("{}", warning);
+        }
+
+        // The duty cycle can't exceed the period, clear it before changing
+        pwm.set_duty_cycle_ns(0)?;
+        let period_ns = 1_000_000_000 / frequency_hz.max(1) as u64;
+        let period_ns = match pwm.set_period_ns(period_ns) {
+            Ok(()) => period_ns,
+            Err(e) => {
+                let current = pwm.period_ns()?;
+                eprintln!(
+                    "PWM frequency {}Hz not supported ({}), keeping a period of {}ns",
+                    frequency_hz, e, current
+                );
+                current
+            }
+        };
+        pwm.set_enabled(true)?;
+
+        let mut output = Self {
+            pwm,
+            period_ns,
+            on_duty,
+            off_duty,
+        };
+        output.set(false)?;
+
+        Ok(output)
+    }
+}
+
+/// Warning for a frequency fans aren't usually driven at
+fn frequency_warning(frequency_hz: u32) -> Option<String> {
+    (!FAN_PWM_FREQUENCY_HZ.contains(&frequency_hz)).then(|| {
+        format!(
+            "PWM frequency {}Hz is outside the usual fan range of {}-{}Hz",
+            frequency_hz,
+            FAN_PWM_FREQUENCY_HZ.start(),
+            FAN_PWM_FREQUENCY_HZ.end()
+        )
+    })
+}
+
+impl<P: Pwm> FanOutput for PwmOutput<P> {
+    fn set(&mut self, on: bool) -> Result<(), Error> {
+        let duty = if on { self.on_duty } else { self.off_duty };
+        self.set_duty(duty)
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        let duty_ns = (self.period_ns as f64 * duty.clamp(0f32, 100f32) as f64 / 100f64) as u64;
+        self.pwm.set_duty_cycle_ns(duty_ns)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::cell::RefCell;
+    use std::io;
+
+    /// Lines recording each set of values written to them, shared with the
+    /// test
+    struct RecordedLines(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl LineValues for RecordedLines {
+        fn set_values(&self, values: &[u8]) -> Result<(), gpio_cdev::Error> {
+            self.0.borrow_mut().push(values.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn multi_line_sets_each_group_in_one_write() {
+        let relays = Rc::new(RefCell::new(Vec::new()));
+        let indicator = Rc::new(RefCell::new(Vec::new()));
+        let mut output = MultiLineOutput::with_groups(vec![
+            LineGroup {
+                handle: Box::new(RecordedLines(Rc::clone(&relays))),
+                on: vec![1, 0, 1],
+                off: vec![0, 1, 0],
+            },
+            LineGroup {
+                handle: Box::new(RecordedLines(Rc::clone(&indicator))),
+                on: vec![1],
+                off: vec![0],
+            },
+        ]);
+
+        output.set(true).unwrap();
+        output.set(false).unwrap();
+
+        assert_eq!(*relays.borrow(), vec![vec![1, 0, 1], vec![0, 1, 0]]);
+        assert_eq!(*indicator.borrow(), vec![vec![1], vec![0]]);
+    }
+
+    /// Lines whose first `failures` writes fail with `EAGAIN`
+    struct BusyLines {
+        failures: Cell<u32>,
+        writes: Rc<RefCell<Vec<Vec<u8>>>>,
+    }
+
+    impl LineValues for BusyLines {
+        fn set_values(&self, values: &[u8]) -> Result<(), gpio_cdev::Error> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::from_raw_os_error(libc::EAGAIN).into());
+            }
+            self.writes.borrow_mut().push(values.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn busy_line_write_is_retried() {
+        let output = |failures: u32, writes: &Rc<RefCell<Vec<Vec<u8>>>>| {
+            MultiLineOutput::with_groups(vec![LineGroup {
+                handle: Box::new(BusyLines {
+                    failures: Cell::new(failures),
+                    writes: Rc::clone(writes),
+                }),
+                on: vec![1],
+                off: vec![0],
+            }])
+            .with_retries(2)
+        };
+
+        let writes = Rc::default();
+        output(2, &writes).set(true).unwrap();
+        assert_eq!(*writes.borrow(), vec![vec![1]]);
+
+        // One failure more than the retries allow
+        let writes = Rc::default();
+        assert!(output(3, &writes).set(true).is_err());
+        assert!(writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn out_of_range_offset_names_the_valid_range() {
+        let path = Path::new("/dev/gpiochip0");
+        assert!(check_line_count("pinctrl-bcm2711", path, 58, &[4, 57]).is_ok());
+
+        let error = check_line_count("pinctrl-bcm2711", path, 58, &[4, 60]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 60 is out of range for pinctrl-bcm2711 (/dev/gpiochip0), \
+             which has 58 lines (0-57)"
+        );
+    }
+
+    #[test]
+    fn chip_without_lines_errors_or_runs_dry() {
+        let path = Path::new("/dev/gpiochip3");
+        let error = check_line_count("gpio-sim", path, 0, &[0]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "gpio-sim (/dev/gpiochip3) has no lines, it may be a virtual or placeholder chip; \
+             set empty_chip = \"dry-run\" to run without it"
+        );
+
+        let lines = |chip: &str| Ok(if chip == "/dev/gpiochip3" { 0 } else { 54 });
+        let line = |chip: &str, empty_chip| OutputConfig::Line {
+            chip: chip.to_string(),
+            line: 0,
+            bias: Bias::default(),
+            line_name: None,
+            write_retries: 0,
+            empty_chip,
+        };
+        let dry = line("/dev/gpiochip3", EmptyChip::DryRun);
+        assert_eq!(empty_chip(&dry, lines).unwrap(), Some("/dev/gpiochip3"));
+        // Only run dry when asked to, and only for a chip without lines
+        let error = line("/dev/gpiochip3", EmptyChip::Error);
+        assert_eq!(empty_chip(&error, lines).unwrap(), None);
+        let populated = line("/dev/gpiochip0", EmptyChip::DryRun);
+        assert_eq!(empty_chip(&populated, lines).unwrap(), None);
+    }
+
+    /// An output logging each state set and when, relative to its creation
+    struct TimedSwitch {
+        start: Instant,
+        sets: Rc<RefCell<Vec<(bool, Duration)>>>,
+    }
+
+    impl FanOutput for TimedSwitch {
+        fn set(&mut self, on: bool) -> Result<(), Error> {
+            self.sets.borrow_mut().push((on, self.start.elapsed()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn turning_off_writes_the_brake_pulse() {
+        let sets = Rc::new(RefCell::new(Vec::new()));
+        let steps = [
+            PulseStep { on: false, ms: 50 },
+            PulseStep { on: true, ms: 20 },
+        ];
+        let switch = TimedSwitch {
+            start: Instant::now(),
+            sets: Rc::clone(&sets),
+        };
+        let mut output = braked(Box::new(switch), &steps);
+
+        output.set(true).unwrap();
+        output.set(false).unwrap();
+        // Already off, so no pulse
+        output.set(false).unwrap();
+
+        let sets = sets.borrow();
+        let states: Vec<bool> = sets.iter().map(|&(on, _)| on).collect();
+        assert_eq!(states, [true, false, true, false, false]);
+        // Each step held for at least its time before the next write
+        let held = |i: usize| sets[i + 1].1 - sets[i].1;
+        assert!(held(1) >= Duration::from_millis(50));
+        assert!(held(2) >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn mismatched_line_name_is_an_error() {
+        assert!(check_line_name("gpiochip0", 17, Some("GPIO17"), "GPIO17").is_ok());
+
+        let error = check_line_name("gpiochip0", 17, Some("GPIO27"), "GPIO17").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 17 of gpiochip0 is named GPIO27, expected GPIO17"
+        );
+        let unnamed = check_line_name("gpiochip0", 17, None, "GPIO17").unwrap_err();
+        assert!(unnamed.to_string().contains("is named nothing"));
+    }
+
+    /// A PWM channel recording what it is set to, rejecting any period but
+    /// `supported` when given
+    #[derive(Default)]
+    struct MockPwm {
+        period: u64,
+        supported: Option<u64>,
+        duty_cycles: Vec<u64>,
+        enabled: bool,
+    }
+
+    impl Pwm for MockPwm {
+        fn period_ns(&self) -> io::Result<u64> {
+            Ok(self.period)
+        }
+
+        fn set_period_ns(&mut self, period: u64) -> io::Result<()> {
+            if self.supported.is_some_and(|s| s != period) {
+                return Err(io::Error::from(io::ErrorKind::InvalidInput));
+            }
+            self.period = period;
+            Ok(())
+        }
+
+        fn set_duty_cycle_ns(&mut self, duty_cycle: u64) -> io::Result<()> {
+            self.duty_cycles.push(duty_cycle);
+            Ok(())
+        }
+
+        fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+            self.enabled = enabled;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pwm_applies_frequency_as_period() {
+        let mut output = PwmOutput::new(MockPwm::default(), 25_000, 80.0, 0.0).unwrap();
+        assert_eq!(output.pwm.period, 40_000);
+        assert!(output.pwm.enabled);
+
+        output.set(true).unwrap();
+        assert_eq!(output.pwm.duty_cycles, vec![0, 0, 32_000]);
+    }
+
+    #[test]
+    fn pwm_keeps_period_when_frequency_rejected() {
+        let pwm = MockPwm {
+            period: 50_000,
+            supported: Some(50_000),
+            ..MockPwm::default()
+        };
+        let mut output = PwmOutput::new(pwm, 25_000, 100.0, 0.0).unwrap();
+        assert_eq!(output.pwm.period, 50_000);
+        output.set_duty(50.0).unwrap();
+        assert_eq!(output.pwm.duty_cycles.last(), Some(&25_000));
+    }
+
+    #[test]
+    fn pwm_warns_outside_fan_range() {
+        assert_eq!(frequency_warning(25_000), None);
+        assert_eq!(
+            frequency_warning(10),
+            Some("PWM frequency 10Hz is outside the usual fan range of 20-40000Hz".to_string())
+        );
+        assert!(frequency_warning(100_000).is_some());
+    }
+
+    #[test]
+    fn hwmon_pwm_writes_duty_and_restores_enable() {
+        let hwmon = testing::temp_dir("hwmon-pwm");
+        fs::write(hwmon.join("pwm2"), "0\n").unwrap();
+        fs::write(hwmon.join("pwm2_enable"), "2\n").unwrap();
+        let read = |file: &str| fs::read_to_string(hwmon.join(file)).unwrap();
+
+        let mut output = HwmonPwmOutput::new(&hwmon, 2, 200, 10).unwrap();
+        assert_eq!(read("pwm2_enable"), PWM_ENABLE_MANUAL);
+
+        output.set(true).unwrap();
+        assert_eq!(read("pwm2"), "200");
+        output.set(false).unwrap();
+        assert_eq!(read("pwm2"), "10");
+        output.set_duty(50.0).unwrap();
+        assert_eq!(read("pwm2"), "128");
+
+        drop(output);
+        assert_eq!(read("pwm2_enable"), "2");
+    }
+
+    #[test]
+    fn acpi_fan_writes_levels() {
+        let device = testing::temp_dir("acpi-fan");
+        fs::write(device.join("max_state"), "4\n").unwrap();
+        fs::write(device.join("cur_state"), "0\n").unwrap();
+        let read = || fs::read_to_string(device.join("cur_state")).unwrap();
+
+        let mut output = AcpiFanOutput::new(&device, Some(3)).unwrap();
+        output.set(true).unwrap();
+        assert_eq!(read(), "3");
+        output.set(false).unwrap();
+        assert_eq!(read(), "0");
+        output.set_duty(50.0).unwrap();
+        assert_eq!(read(), "2");
+
+        // Levels beyond the device's are capped to its maximum
+        let mut output = AcpiFanOutput::new(&device, Some(9)).unwrap();
+        output.set(true).unwrap();
+        assert_eq!(read(), "4");
+    }
+
+    #[test]
+    fn line_requests_carry_the_configured_bias() {
+        let requested = |bias: &str| {
+            let config = testing::config(&format!(
+                "output = {{ type = \"line\", chip = \"gpiochip0\", line = 17{} }}",
+                bias
+            ));
+            let OutputConfig::Line { bias, .. } = config.fans()[0].output else {
+                panic!("not a line output");
+            };
+            output_flags(bias).bits()
+        };
+
+        assert_eq!(requested(""), LineRequestFlags::OUTPUT.bits());
+        assert_eq!(
+            requested(", bias = \"pull-up\""),
+            LineRequestFlags::OUTPUT.bits() | 1 << 5
+        );
+        assert_eq!(
+            requested(", bias = \"pull-down\""),
+            LineRequestFlags::OUTPUT.bits() | 1 << 6
+        );
+        assert_eq!(
+            requested(", bias = \"disable\""),
+            LineRequestFlags::OUTPUT.bits() | 1 << 7
+        );
+    }
+}
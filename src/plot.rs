@@ -0,0 +1,211 @@
+use crate::config::{Config, FanConfig};
+use crate::units::Units;
+use std::io::{self, Write};
+
+/// Size of each fan's plot, and the margin around its axes
+const WIDTH: f32 = 640f32;
+const HEIGHT: f32 = 320f32;
+const MARGIN: f32 = 48f32;
+/// Temperature shown either side of the outermost point or threshold
+const PADDING: f32 = 10f32;
+
+/// Render each fan's curve, or its on/off hysteresis loop when it has no
+/// curve, with its thresholds marked, as an SVG with one plot per fan
+/// stacked top to bottom. Temperatures are in `logic_units`.
+pub fn svg<W: Write>(config: &Config, out: &mut W) -> io::Result<()> {
+    let fans = config.fans();
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="11">"#,
+        WIDTH,
+        HEIGHT * fans.len() as f32
+    )?;
+    for (i, fan) in fans.iter().enumerate() {
+        writeln!(out, r#"<g transform="translate(0,{})">"#, HEIGHT * i as f32)?;
+        write_fan(out, &fan.name(i), fan, config.logic_units)?;
+        writeln!(out, "</g>")?;
+    }
+    writeln!(out, "</svg>")
+}
+
+/// Thresholds marked on a fan's plot, with their labels
+fn thresholds(fan: &FanConfig) -> Vec<(&'static str, f32)> {
+    let curve = !fan.curve.is_empty();
+    [
+        (!curve).then_some(("on", fan.max_fan_on_temp)),
+        fan.off_threshold()
+            .filter(|_| !curve)
+            .map(|temp| ("off", temp)),
+        fan.full_speed_temp.map(|temp| ("full speed", temp)),
+        fan.alert_temp.map(|temp| ("alert", temp)),
+        fan.critical_temp.map(|temp| ("critical", temp)),
+        fan.panic_temp.map(|temp| ("panic", temp)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn write_fan<W: Write>(out: &mut W, name: &str, fan: &FanConfig, units: Units) -> io::Result<()> {
+    let thresholds = thresholds(fan);
+    // Duty against temperature: the curve, or the fan off up to the on
+    // threshold and on down to the off threshold
+    let mut points: Vec<(f32, f32)> = if fan.curve.is_empty() {
+        let off = fan.off_threshold().unwrap_or(fan.max_fan_on_temp);
+        vec![
+            (off, 0f32),
+            (fan.max_fan_on_temp, 0f32),
+            (fan.max_fan_on_temp, 100f32),
+            (off, 100f32),
+        ]
+    } else {
+        fan.curve.iter().map(|p| (p.temp, p.duty)).collect()
+    };
+    let temps = points
+        .iter()
+        .map(|p| p.0)
+        .chain(thresholds.iter().map(|t| t.1));
+    let (low, high) = temps.fold((f32::MAX, f32::MIN), |(low, high), t| {
+        (low.min(t), high.max(t))
+    });
+    let (low, high) = (low - PADDING, high + PADDING);
+    let x = |temp: f32| MARGIN + (temp - low) / (high - low) * (WIDTH - 2f32 * MARGIN);
+    let y = |duty: f32| HEIGHT - MARGIN - duty / 100f32 * (HEIGHT - 2f32 * MARGIN);
+
+    writeln!(
+        out,
+        r#"<text x="{}" y="{}" font-size="14">{}</text>"#,
+        MARGIN,
+        MARGIN / 2f32,
+        escape(name)
+    )?;
+    // Axes, with the duty and temperature at their ends
+    writeln!(
+        out,
+        r#"<path d="M{x0},{y1} L{x0},{y0} L{x1},{y0}" fill="none" stroke="black"/>"#,
+        x0 = x(low),
+        x1 = x(high),
+        y0 = y(0f32),
+        y1 = y(100f32)
+    )?;
+    for duty in [0f32, 100f32] {
+        writeln!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="end">{}%</text>"#,
+            x(low) - 4f32,
+            y(duty) + 4f32,
+            duty
+        )?;
+    }
+    for temp in [low, high] {
+        writeln!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            x(temp),
+            y(0f32) + 16f32,
+            units.format(temp, 0)
+        )?;
+    }
+
+    for (label, temp) in &thresholds {
+        writeln!(
+            out,
+            r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="gray" stroke-dasharray="4,3"/>"#,
+            y(0f32),
+            y(100f32),
+            x = x(*temp)
+        )?;
+        writeln!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="middle" fill="gray">{} {}</text>"#,
+            x(*temp),
+            y(100f32) - 6f32,
+            label,
+            units.format(*temp, 1)
+        )?;
+    }
+
+    // The end points' duties are held beyond them
+    if let (Some(first), Some(last)) = (fan.curve.first(), fan.curve.last()) {
+        points.insert(0, (low, first.duty));
+        points.push((high, last.duty));
+    }
+    let path: Vec<String> = points
+        .iter()
+        .map(|&(temp, duty)| format!("{},{}", x(temp), y(duty)))
+        .collect();
+    writeln!(
+        out,
+        r#"<polyline points="{}" fill="none" stroke="steelblue" stroke-width="2"/>"#,
+        path.join(" ")
+    )?;
+    for curve_point in &fan.curve {
+        let (cx, cy) = (x(curve_point.temp), y(curve_point.duty));
+        writeln!(
+            out,
+            r#"<circle cx="{}" cy="{}" r="3" fill="steelblue"/>"#,
+            cx, cy
+        )?;
+        writeln!(
+            out,
+            r#"<text x="{}" y="{}">{}, {}%</text>"#,
+            cx + 5f32,
+            cy - 5f32,
+            units.format(curve_point.temp, 1),
+            curve_point.duty
+        )?;
+    }
+
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn render(toml: &str) -> String {
+        let mut out = Vec::new();
+        svg(&testing::config(toml), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn svg_plots_each_curve_and_threshold() {
+        let svg = render(
+            r#"
+            [[fans]]
+            name = "cpu <main>"
+            curve = [{ temp = 40.0, duty = 20.0 }, { temp = 70.0, duty = 100.0 }]
+            critical_temp = 88.0
+
+            [[fans]]
+            name = "case"
+            max_fan_on_temp = 60.0
+            "#,
+        );
+
+        assert!(svg.starts_with("<svg ") && svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(r#"height="640""#));
+        assert!(svg.contains("cpu &lt;main&gt;</text>"));
+        // 30 to 98°C across the axes, 8px a degree
+        assert!(svg.contains(r#"<circle cx="128" cy="227.2" r="3""#));
+        assert!(svg.contains(">40.0°C, 20%</text>"));
+        assert!(svg.contains(r#"<circle cx="368" cy="48" r="3""#));
+        assert!(svg.contains(">70.0°C, 100%</text>"));
+        assert!(svg.contains(r#"points="48,227.2 128,227.2 368,48 592,48""#));
+        assert!(svg.contains(r#"<line x1="512""#));
+        assert!(svg.contains(">critical 88.0°C</text>"));
+
+        // The second fan, plotted below, without a curve
+        let case = &svg[svg.find(r#"<g transform="translate(0,320)">"#).unwrap()..];
+        assert!(case.contains(">on 60.0°C</text>"));
+        assert!(!case.contains("<circle"));
+    }
+}
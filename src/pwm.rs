@@ -0,0 +1,63 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// A hardware PWM channel, timed in nanoseconds.
+pub trait Pwm {
+    fn period_ns(&self) -> io::Result<u64>;
+    fn set_period_ns(&mut self, period: u64) -> io::Result<()>;
+    fn set_duty_cycle_ns(&mut self, duty_cycle: u64) -> io::Result<()>;
+    fn set_enabled(&mut self, enabled: bool) -> io::Result<()>;
+}
+
+/// A channel of a `/sys/class/pwm/pwmchipN` device.
+pub struct SysfsPwm {
+    path: PathBuf,
+}
+
+impl SysfsPwm {
+    /// Open `channel` of the chip at `chip`, exporting it first if needed
+    pub fn open<P: AsRef<Path>>(chip: P, channel: u32) -> io::Result<Self> {
+        let chip = chip.as_ref();
+        let path = chip.join(format!("pwm{}", channel));
+        if !path.exists() {
+            fs::write(chip.join("export"), channel.to_string())?;
+            // udev may take a moment to make the new attributes writable
+            for _ in 0..10 {
+                if path.join("period").exists() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        Ok(Self { path })
+    }
+
+    fn write(&self, attribute: &str, value: impl ToString) -> io::Result<()> {
+        fs::write(self.path.join(attribute), value.to_string())
+    }
+}
+
+impl Pwm for SysfsPwm {
+    fn period_ns(&self) -> io::Result<u64> {
+        fs::read_to_string(self.path.join("period"))?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn set_period_ns(&mut self, period: u64) -> io::Result<()> {
+        self.write("period", period)
+    }
+
+    fn set_duty_cycle_ns(&mut self, duty_cycle: u64) -> io::Result<()> {
+        self.write("duty_cycle", duty_cycle)
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.write("enable", enabled as u8)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::config::RgbConfig;
+use crate::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+
+/// SPI clock making each WS2812 bit three SPI bits of ~417ns
+const SPI_HZ: u32 = 2_400_000;
+/// `SPI_IOC_WR_MAX_SPEED_HZ`, `_IOW('k', 4, u32)`
+const SPI_IOC_WR_MAX_SPEED_HZ: libc::c_ulong = 0x4004_6b04;
+/// Low bytes latching the colors, over the 50µs reset time
+const RESET_BYTES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Color for `temperature` on a gradient from blue at `cold` to red at
+/// `hot`, scaled by `brightness` (0-1)
+pub fn color(temperature: f32, cold: f32, hot: f32, brightness: f32) -> Rgb {
+    let heat = ((temperature - cold) / (hot - cold)).clamp(0f32, 1f32);
+    let level = |v: f32| (v * brightness.clamp(0f32, 1f32) * 255f32).round() as u8;
+
+    Rgb {
+        r: level(heat),
+        g: 0,
+        b: level(1f32 - heat),
+    }
+}
+
+/// SPI bytes showing `colors` on a WS2812 chain, in the chain's GRB order,
+/// each data bit sent as `110` for a 1 and `100` for a 0
+pub fn encode(colors: &[Rgb]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(colors.len() * 9 + RESET_BYTES);
+    for color in colors {
+        for byte in [color.g, color.r, color.b] {
+            let bits = (0..8).rev().fold(0u32, |bits, i| {
+                bits << 3 | if byte >> i & 1 == 1 { 0b110 } else { 0b100 }
+            });
+            bytes.extend(&bits.to_be_bytes()[1..]);
+        }
+    }
+    bytes.resize(bytes.len() + RESET_BYTES, 0);
+    bytes
+}
+
+/// A fan's WS2812 LEDs driven over SPI, all lit in the color for the fan's
+/// temperature, independently of its speed
+pub struct Ws2812<W: Write> {
+    device: W,
+    config: RgbConfig,
+    shown: Option<Rgb>,
+}
+
+impl Ws2812<File> {
+    /// Open the `spidev` device, setting the clock WS2812 timing needs
+    pub fn open(config: &RgbConfig) -> Result<Self, Error> {
+        let device = OpenOptions::new().write(true).open(&config.spidev)?;
+        let speed = SPI_HZ;
+        if unsafe { libc::ioctl(device.as_raw_fd(), SPI_IOC_WR_MAX_SPEED_HZ, &speed) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self::new(device, config))
+    }
+}
+
+impl<W: Write> Ws2812<W> {
+    pub fn new(device: W, config: &RgbConfig) -> Self {
+        Self {
+            device,
+            config: config.clone(),
+            shown: None,
+        }
+    }
+
+    /// Show the color for `temperature`, writing only when it changes
+    pub fn show(&mut self, temperature: f32) -> Result<(), Error> {
+        let config = &self.config;
+        let color = color(
+            temperature,
+            config.cold_temp,
+            config.hot_temp,
+            config.brightness,
+        );
+        if self.shown == Some(color) {
+            return Ok(());
+        }
+        self.device
+            .write_all(&encode(&vec![color; config.leds as usize]))?;
+        self.shown = Some(color);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLUE: Rgb = Rgb { r: 0, g: 0, b: 255 };
+    const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+
+    #[test]
+    fn temperature_maps_onto_the_gradient() {
+        assert_eq!(color(30.0, 40.0, 80.0, 1.0), BLUE);
+        assert_eq!(color(40.0, 40.0, 80.0, 1.0), BLUE);
+        assert_eq!(
+            color(50.0, 40.0, 80.0, 1.0),
+            Rgb {
+                r: 64,
+                g: 0,
+                b: 191
+            }
+        );
+        assert_eq!(
+            color(60.0, 40.0, 80.0, 1.0),
+            Rgb {
+                r: 128,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(color(80.0, 40.0, 80.0, 1.0), RED);
+        assert_eq!(color(95.0, 40.0, 80.0, 1.0), RED);
+        assert_eq!(color(80.0, 40.0, 80.0, 0.5), Rgb { r: 128, g: 0, b: 0 });
+    }
+
+    #[test]
+    fn color_is_only_written_when_it_changes() {
+        let config: RgbConfig = toml::from_str("spidev = \"/dev/null\"\nleds = 2").unwrap();
+        let mut leds = Ws2812::new(Vec::new(), &config);
+
+        leds.show(80.0).unwrap();
+        assert_eq!(leds.device, encode(&[RED, RED]));
+        leds.show(90.0).unwrap();
+        assert_eq!(leds.device.len(), 2 * 9 + RESET_BYTES);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::fan_control::FanControl;
+use crate::measurement::Measurement;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Rolling log of every tick's raw samples and decision, for working out
+/// after the fact why the fan did what it did.
+///
+/// Each line is space separated:
+/// `<unix secs> <fan name> <temp> <cpu> <fan> <trigger> <cpu averages> <temp averages>`
+/// where missing values are written as `-` and averages are comma separated.
+///
+/// Once the file would grow past `max_bytes` it is moved to `<path>.1`,
+/// replacing any previous one, and a fresh file is started. At most the
+/// latest `2 * max_bytes` of history is kept on disk.
+pub struct SampleLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    written: u64,
+}
+
+impl SampleLog {
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_bytes,
+            file,
+            written,
+        })
+    }
+
+    pub fn record(&mut self, fan_control: &FanControl) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let usage = fan_control.usage();
+        let fan = match fan_control.fan_on() {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "-",
+        };
+        let trigger = fan_control
+            .trigger()
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let line = format!(
+            "{} {} {} {} {} {} {} {}\n",
+            timestamp,
+            fan_control.name(),
+            optional(usage.last_temperature()),
+            optional(usage.last_cpu()),
+            fan,
+            trigger,
+            averages(&usage.cpu),
+            averages(&usage.temperature),
+        );
+
+        self.write(line.as_bytes())
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+fn optional(value: Option<f32>) -> String {
+    value
+        .map(|v| format!("{:.1}", v))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn averages(measurements: &[Measurement]) -> String {
+    measurements
+        .iter()
+        .map(|m| format!("{:.1}", m.measurement()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn rotates_past_cap_keeping_latest_lines() {
+        let path = testing::temp_dir("sample-log").join("samples.log");
+        let mut log = SampleLog::open(&path, 20).unwrap();
+        for line in ["one 0123456\n", "two 0123456\n", "three 01234\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three 01234\n");
+        assert_eq!(
+            fs::read_to_string(path.with_extension("log.1")).unwrap(),
+            "two 0123456\n"
+        );
+
+        // Reopening carries on from the size already written
+        let mut log = SampleLog::open(&path, 20).unwrap();
+        log.write(b"four\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three 01234\nfour\n");
+    }
+}
@@ -0,0 +1,1169 @@
+use crate::config::{Config, IpmiConfig};
+use crate::injected::InjectedSource;
+use crate::mqtt::MqttSource;
+use crate::Error;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use sysinfo::{ComponentExt, CpuExt, System, SystemExt};
+
+/// A temperature reading from one component
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentReading {
+    pub label: String,
+    pub temperature: f32,
+    /// Temperature limit the component reports, NaN when it reports none
+    pub max: f32,
+    /// Hardware critical temperature, when the component reports one
+    pub critical: Option<f32>,
+}
+
+/// Where `Usage` gets its raw readings from.
+pub trait SensorSource {
+    /// Take a fresh set of readings, returned by the other methods until the
+    /// next refresh
+    fn refresh(&mut self);
+    /// Usage of each CPU, 0-100
+    fn cpu_usages(&self) -> Vec<f32>;
+    fn components(&self) -> Vec<ComponentReading>;
+    /// Whether the last refresh failed, the readings being left from an
+    /// earlier one. Counted as a missed read, like reading no temperature.
+    fn failed(&self) -> bool {
+        false
+    }
+}
+
+/// The source of readings selected by `config` from the built in sources,
+/// timed out after `sensor_timeout_ms` when set
+pub fn from_config(config: &Config) -> Result<Box<dyn SensorSource>, Error> {
+    SourceRegistry::builtin().open(config)
+}
+
+/// Opens a sensor source of one type from the config
+pub type OpenSource = fn(&Config) -> Result<Box<dyn SensorSource + Send>, Error>;
+
+/// Sensor sources by the type `sensor_source` names them with. Programs
+/// embedding the controller can register their own alongside the built in
+/// `sysinfo`, `thermal-zones`, `vcgencmd`, `ipmi`, `mqtt` and `injected`.
+#[derive(Clone)]
+pub struct SourceRegistry {
+    sources: BTreeMap<String, OpenSource>,
+}
+
+impl SourceRegistry {
+    pub fn builtin() -> Self {
+        let mut registry = Self {
+            sources: BTreeMap::new(),
+        };
+        registry.register("sysinfo", |config| {
+            Ok(Box::new(
+                SysinfoSource::new().with_schedule(ReadSchedule::new(config)),
+            ))
+        });
+        registry.register("thermal-zones", |config| {
+            let zones = config.thermal_zones.clone().unwrap_or_default();
+            Ok(Box::new(
+                ThermalZoneSource::new(&zones.root, zones.types)
+                    .with_concurrency(zones.concurrency)
+                    .with_schedule(ReadSchedule::new(config)),
+            ))
+        });
+        registry.register("vcgencmd", |config| {
+            let vcgencmd = config.vcgencmd.clone().unwrap_or_default();
+            Ok(Box::new(VcgencmdSource::new(&vcgencmd.command)))
+        });
+        registry.register("ipmi", |config| {
+            let ipmi = config.ipmi.clone().unwrap_or_default();
+            Ok(Box::new(IpmiSource::new(&ipmi)))
+        });
+        registry.register("mqtt", |config| {
+            let mqtt = config
+                .mqtt
+                .as_ref()
+                .ok_or_else(|| Error::Config("sensor_source mqtt requires [mqtt]".to_string()))?;
+            Ok(Box::new(MqttSource::new(mqtt)))
+        });
+        registry.register("injected", |config| {
+            Ok(Box::new(InjectedSource::new(Duration::from_secs(
+                config.injected_max_age_secs,
+            ))))
+        });
+        registry
+    }
+
+    /// Make `open` the source for `kind`, replacing any already registered
+    pub fn register(&mut self, kind: &str, open: OpenSource) {
+        self.sources.insert(kind.to_string(), open);
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.sources.keys().map(String::as_str)
+    }
+
+    /// Open the source `config` selects, timed out after
+    /// `sensor_timeout_ms` when set
+    pub fn open(&self, config: &Config) -> Result<Box<dyn SensorSource>, Error> {
+        let kind = config.source_kind();
+        let open = self.sources.get(kind).ok_or_else(|| {
+            let kinds: Vec<&str> = self.kinds().collect();
+            Error::Config(format!(
+                "unknown sensor_source {}, expected one of {}",
+                kind,
+                kinds.join(", ")
+            ))
+        })?;
+        let source = open(config)?;
+
+        Ok(match config.sensor_timeout_ms {
+            Some(timeout) => Box::new(TimeoutSource::new(source, Duration::from_millis(timeout))),
+            None => source,
+        })
+    }
+}
+
+/// Which sensors are due to be read on each refresh, from
+/// `sensor_intervals`. Sensors without an interval are read every time.
+#[derive(Debug, Clone, Default)]
+pub struct ReadSchedule {
+    /// Label prefixes and the refreshes between their reads
+    every: Vec<(Vec<String>, u64)>,
+    refreshes: u64,
+}
+
+impl ReadSchedule {
+    pub fn new(config: &Config) -> Self {
+        let secs = config.sample_interval().as_secs().max(1);
+        Self {
+            every: config
+                .sensor_intervals
+                .iter()
+                .map(|i| (i.sensors.clone(), (i.secs / secs).max(1)))
+                .collect(),
+            refreshes: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.every.is_empty()
+    }
+
+    /// Whether the sensor labelled `label` is read on this refresh
+    pub fn due(&self, label: &str) -> bool {
+        self.every
+            .iter()
+            .find(|(prefixes, _)| prefixes.iter().any(|p| label.starts_with(p.as_str())))
+            .is_none_or(|(_, every)| self.refreshes.is_multiple_of(*every))
+    }
+
+    /// Move on to the next refresh
+    pub fn advance(&mut self) {
+        self.refreshes += 1;
+    }
+}
+
+/// Readings from the running system via `sysinfo`
+pub struct SysinfoSource {
+    system: System,
+    components: bool,
+    schedule: ReadSchedule,
+}
+
+impl SysinfoSource {
+    pub fn new() -> Self {
+        Self {
+            system: System::new_all(),
+            components: true,
+            schedule: ReadSchedule::default(),
+        }
+    }
+
+    /// Read components only as often as `schedule` has them due
+    pub fn with_schedule(mut self, schedule: ReadSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Only read CPU usage, reporting no components
+    pub fn cpu_only() -> Self {
+        Self {
+            system: System::new(),
+            components: false,
+            schedule: ReadSchedule::default(),
+        }
+    }
+}
+
+impl Default for SysinfoSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SensorSource for SysinfoSource {
+    fn refresh(&mut self) {
+        self.system.refresh_cpu();
+        if self.components {
+            self.system.refresh_system();
+            if self.schedule.is_empty() {
+                self.system.refresh_components();
+            } else {
+                let schedule = &self.schedule;
+                for component in self.system.components_mut() {
+                    if schedule.due(component.label()) {
+                        component.refresh();
+                    }
+                }
+                self.schedule.advance();
+            }
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.system.cpus().iter().map(|c| c.cpu_usage()).collect()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.system
+            .components()
+            .iter()
+            // `max()` is the highest temperature read so far rather than a
+            // limit, so the critical temperature stands in for one
+            .map(|c| ComponentReading {
+                label: c.label().to_string(),
+                temperature: c.temperature(),
+                max: c.critical().unwrap_or(f32::NAN),
+                critical: c.critical(),
+            })
+            .collect()
+    }
+}
+
+/// Consecutive failed reads of a thermal zone between repeated warnings
+const ZONE_FAILURE_LOG_EVERY: u64 = 60;
+
+/// Temperatures read straight from `/sys/class/thermal/thermal_zone*`,
+/// labelled by each zone's `type` (e.g. `cpu-thermal`). The most portable
+/// source on Linux SBCs. CPU usage still comes from `sysinfo`.
+///
+/// A zone that fails to read while others still succeed keeps reporting its
+/// last reading, so its windows hold rather than the aggregate jumping.
+pub struct ThermalZoneSource {
+    cpu: SysinfoSource,
+    root: PathBuf,
+    types: Vec<String>,
+    concurrency: usize,
+    components: Vec<ComponentReading>,
+    /// Last successful reading of each zone
+    last: HashMap<PathBuf, ComponentReading>,
+    /// Consecutive failed reads of each failing zone
+    failures: HashMap<PathBuf, u64>,
+    schedule: ReadSchedule,
+}
+
+impl ThermalZoneSource {
+    /// Read the zones under `root`, only those whose type is listed in
+    /// `types` unless it is empty
+    pub fn new<P: AsRef<Path>>(root: P, types: Vec<String>) -> Self {
+        Self {
+            cpu: SysinfoSource::cpu_only(),
+            root: root.as_ref().to_path_buf(),
+            types,
+            concurrency: 1,
+            components: Vec::new(),
+            last: HashMap::new(),
+            failures: HashMap::new(),
+            schedule: ReadSchedule::default(),
+        }
+    }
+
+    /// Read zones only as often as `schedule` has their types due, a zone
+    /// not due reporting its last reading
+    pub fn with_schedule(mut self, schedule: ReadSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Read the zones split between `threads` threads, for boards with slow
+    /// sysfs reads. Readings keep the zones' order either way.
+    pub fn with_concurrency(mut self, threads: usize) -> Self {
+        self.concurrency = threads.max(1);
+        self
+    }
+
+    fn read_zones(&mut self) -> io::Result<Vec<ComponentReading>> {
+        let mut zones: Vec<PathBuf> = fs::read_dir(&self.root)?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with("thermal_zone"))
+            })
+            .collect();
+        zones.sort();
+
+        // Zones not due this time report their last reading without a read
+        let (zones, resting): (Vec<PathBuf>, Vec<PathBuf>) = zones.into_iter().partition(|z| {
+            self.failures.contains_key(z)
+                || self.last.get(z).is_none_or(|r| self.schedule.due(&r.label))
+        });
+        self.schedule.advance();
+
+        let mut results: Vec<(PathBuf, io::Result<ComponentReading>)> = if self.concurrency > 1 {
+            let chunk = zones.len().div_ceil(self.concurrency).max(1);
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = zones
+                    .chunks(chunk)
+                    .map(|chunk| scope.spawn(move || read_each(chunk)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|h| h.join().unwrap_or_default())
+                    .collect()
+            })
+        } else {
+            read_each(&zones)
+        };
+        if !resting.is_empty() {
+            results.extend(resting.into_iter().filter_map(|z| {
+                let last = self.last.get(&z)?.clone();
+                Some((z, Ok(last)))
+            }));
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        // With every zone failing the source is down, report nothing so
+        // staleness can be noticed
+        let any_read = results.iter().any(|(_, r)| r.is_ok());
+        let mut readings = Vec::new();
+        for (zone, result) in results {
+            let reading = match result {
+                Ok(reading) => {
+                    if let Some(failures) = self.failures.remove(&zone) {
+                        println!("{} read again after {} failures", zone.display(), failures);
+                    }
+                    self.last.insert(zone, reading.clone());
+                    Some(reading)
+                }
+                Err(e) => {
+                    let failures = self.failures.entry(zone.clone()).or_default();
+                    if failures.is_multiple_of(ZONE_FAILURE_LOG_EVERY) {
+                        eprintln!("Unable to read {}: {}", zone.display(), e);
+                    }
+                    *failures += 1;
+                    any_read.then(|| self.last.get(&zone).cloned()).flatten()
+                }
+            };
+            if let Some(reading) = reading {
+                if self.types.is_empty() || self.types.contains(&reading.label) {
+                    readings.push(reading);
+                }
+            }
+        }
+
+        Ok(readings)
+    }
+}
+
+fn read_each(zones: &[PathBuf]) -> Vec<(PathBuf, io::Result<ComponentReading>)> {
+    zones.iter().map(|z| (z.clone(), read_zone(z))).collect()
+}
+
+/// Read a thermal zone's `type` and `temp`, the latter in millidegrees, and
+/// the temperature of its `critical` trip point if it has one
+pub fn read_zone(zone: &Path) -> io::Result<ComponentReading> {
+    let label = fs::read_to_string(zone.join("type"))?.trim().to_string();
+    let temperature = read_millidegrees(&zone.join("temp"))?;
+
+    let mut critical = None;
+    for trip in 0.. {
+        let Ok(kind) = fs::read_to_string(zone.join(format!("trip_point_{}_type", trip))) else {
+            break;
+        };
+        if kind.trim() == "critical" {
+            critical = read_millidegrees(&zone.join(format!("trip_point_{}_temp", trip))).ok();
+            break;
+        }
+    }
+
+    Ok(ComponentReading {
+        label,
+        temperature,
+        max: f32::NAN,
+        critical,
+    })
+}
+
+/// Current and maximum state of a kernel cooling device such as
+/// `/sys/class/thermal/cooling_device0`
+pub fn read_cooling_state(device: &Path) -> io::Result<(u32, u32)> {
+    let read = |name: &str| -> io::Result<u32> {
+        fs::read_to_string(device.join(name))?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    Ok((read("cur_state")?, read("max_state")?))
+}
+
+fn read_millidegrees(path: &Path) -> io::Result<f32> {
+    let millidegrees: i64 = fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(millidegrees as f32 / 1000f32)
+}
+
+/// Current frequency of each CPU in MHz, read from the `scaling_cur_freq`
+/// (in kHz) of every `cpuN/cpufreq` under `root`
+pub fn read_cpu_frequencies(root: &Path) -> io::Result<Vec<f32>> {
+    let mut frequencies = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        let is_cpu = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix("cpu"))
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        if !is_cpu {
+            continue;
+        }
+        let Ok(khz) = fs::read_to_string(path.join("cpufreq/scaling_cur_freq")) else {
+            continue;
+        };
+        let khz: u64 = khz
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        frequencies.push(khz as f32 / 1000f32);
+    }
+
+    Ok(frequencies)
+}
+
+impl SensorSource for ThermalZoneSource {
+    fn refresh(&mut self) {
+        self.cpu.refresh();
+        match self.read_zones() {
+            Ok(components) => self.components = components,
+            Err(e) => {
+                eprintln!("Unable to read {}: {}", self.root.display(), e);
+                self.components.clear();
+            }
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu.cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
+
+/// Label of the reading taken from `vcgencmd`
+pub const VCGENCMD_LABEL: &str = "vcgencmd";
+
+/// The SoC temperature reported by the Raspberry Pi firmware through
+/// `vcgencmd measure_temp`, for Pi OS images where `sysinfo` doesn't find
+/// it. CPU usage still comes from `sysinfo`.
+pub struct VcgencmdSource {
+    cpu: SysinfoSource,
+    command: PathBuf,
+    components: Vec<ComponentReading>,
+}
+
+impl VcgencmdSource {
+    pub fn new<P: AsRef<Path>>(command: P) -> Self {
+        Self {
+            cpu: SysinfoSource::cpu_only(),
+            command: command.as_ref().to_path_buf(),
+            components: Vec::new(),
+        }
+    }
+
+    fn measure_temp(&self) -> Result<f32, Error> {
+        let output = Command::new(&self.command)
+            .arg("measure_temp")
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Sensor(format!("exited with {}", output.status)));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        parse_measure_temp(&stdout)
+            .ok_or_else(|| Error::Sensor(format!("unexpected output {:?}", stdout.trim())))
+    }
+}
+
+impl SensorSource for VcgencmdSource {
+    fn refresh(&mut self) {
+        self.cpu.refresh();
+        self.components.clear();
+        match self.measure_temp() {
+            Ok(temperature) => self.components.push(ComponentReading {
+                label: VCGENCMD_LABEL.to_string(),
+                temperature,
+                max: f32::NAN,
+                critical: None,
+            }),
+            Err(e) => eprintln!("Unable to run {}: {}", self.command.display(), e),
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu.cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
+
+/// Parse the temperature out of `vcgencmd measure_temp` output, of the form
+/// `temp=47.8'C`
+pub fn parse_measure_temp(output: &str) -> Option<f32> {
+    output
+        .trim()
+        .strip_prefix("temp=")?
+        .strip_suffix("'C")?
+        .parse()
+        .ok()
+}
+
+/// Temperatures the BMC reports through `ipmitool sdr type temperature`, such
+/// as inlet, exhaust and CPU sensors `sysinfo` can't see, labelled by their
+/// sensor names. CPU usage still comes from `sysinfo`.
+pub struct IpmiSource {
+    cpu: SysinfoSource,
+    command: PathBuf,
+    args: Vec<String>,
+    sensors: Vec<String>,
+    components: Vec<ComponentReading>,
+}
+
+impl IpmiSource {
+    pub fn new(config: &IpmiConfig) -> Self {
+        Self {
+            cpu: SysinfoSource::cpu_only(),
+            command: config.command.clone(),
+            args: config.args.clone(),
+            sensors: config.sensors.clone(),
+            components: Vec::new(),
+        }
+    }
+
+    fn read_sdr(&self) -> Result<Vec<(String, f32)>, Error> {
+        let output = Command::new(&self.command)
+            .args(&self.args)
+            .args(["sdr", "type", "temperature"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Sensor(format!("exited with {}", output.status)));
+        }
+
+        Ok(parse_sdr(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+impl SensorSource for IpmiSource {
+    fn refresh(&mut self) {
+        self.cpu.refresh();
+        self.components.clear();
+        match self.read_sdr() {
+            Ok(readings) => {
+                self.components = readings
+                    .into_iter()
+                    .filter(|(name, _)| self.sensors.is_empty() || self.sensors.contains(name))
+                    .map(|(label, temperature)| ComponentReading {
+                        label,
+                        temperature,
+                        max: f32::NAN,
+                        critical: None,
+                    })
+                    .collect();
+            }
+            Err(e) => eprintln!("Unable to run {}: {}", self.command.display(), e),
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu.cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
+
+/// Parse the temperatures out of `ipmitool sdr` output, one sensor per line
+/// with `|` separated fields: the name first, and a reading such as
+/// `23 degrees C` in whichever field holds it. Sensors without a reading,
+/// and those reporting anything but a temperature, are skipped. Readings in
+/// Fahrenheit are converted to Celsius.
+pub fn parse_sdr(output: &str) -> Vec<(String, f32)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('|').map(str::trim);
+            let name = fields.next().filter(|name| !name.is_empty())?;
+            let temperature = fields.find_map(|field| {
+                let (value, unit) = field.split_once(" degrees ")?;
+                let value: f32 = value.trim().parse().ok()?;
+                match unit.trim() {
+                    "C" => Some(value),
+                    "F" => Some((value - 32f32) * 5f32 / 9f32),
+                    _ => None,
+                }
+            })?;
+            Some((name.to_string(), temperature))
+        })
+        .collect()
+}
+
+/// Scripted readings, each refresh moving on to the next pushed set. Once
+/// the script runs out the last readings are kept.
+#[derive(Default)]
+pub struct FakeSensorSource {
+    script: VecDeque<(Vec<f32>, Vec<ComponentReading>)>,
+    cpu_usages: Vec<f32>,
+    components: Vec<ComponentReading>,
+}
+
+impl FakeSensorSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the readings returned after a later refresh
+    pub fn push(&mut self, cpu_usages: Vec<f32>, components: Vec<ComponentReading>) {
+        self.script.push_back((cpu_usages, components));
+    }
+}
+
+impl SensorSource for FakeSensorSource {
+    fn refresh(&mut self) {
+        if let Some((cpu_usages, components)) = self.script.pop_front() {
+            self.cpu_usages = cpu_usages;
+            self.components = components;
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu_usages.clone()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+}
+
+/// Timed out refreshes logged once every this many in a row
+const TIMEOUT_LOG_EVERY: u64 = 60;
+
+/// Another source refreshed on a worker thread, so a hung read, e.g. of a
+/// flaky w1 device, can't stall the tick. A refresh that doesn't finish
+/// within the timeout keeps the last readings; the worker carries on with
+/// it and the results are taken by whichever later refresh they are ready
+/// for.
+pub struct TimeoutSource {
+    timeout: Duration,
+    requests: mpsc::Sender<()>,
+    readings: mpsc::Receiver<(Vec<f32>, Vec<ComponentReading>)>,
+    /// Whether the worker is still busy with a refresh
+    pending: bool,
+    cpu_usages: Vec<f32>,
+    components: Vec<ComponentReading>,
+    /// Consecutive refreshes that timed out
+    timeouts: u64,
+    /// Whether the last refresh timed out or the worker is gone
+    failed: bool,
+}
+
+impl TimeoutSource {
+    pub fn new(mut source: Box<dyn SensorSource + Send>, timeout: Duration) -> Self {
+        let (requests, requested) = mpsc::channel();
+        let (read, readings) = mpsc::channel();
+        thread::spawn(move || {
+            for () in requested {
+                source.refresh();
+                if read
+                    .send((source.cpu_usages(), source.components()))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            timeout,
+            requests,
+            readings,
+            pending: false,
+            cpu_usages: Vec::new(),
+            components: Vec::new(),
+            timeouts: 0,
+            failed: false,
+        }
+    }
+}
+
+impl SensorSource for TimeoutSource {
+    fn refresh(&mut self) {
+        if !self.pending {
+            self.pending = self.requests.send(()).is_ok();
+        }
+        match self.readings.recv_timeout(self.timeout) {
+            Ok((cpu_usages, components)) => {
+                if self.timeouts > 0 {
+                    println!("Sensors read again after {} timeouts", self.timeouts);
+                }
+                self.pending = false;
+                self.timeouts = 0;
+                self.failed = false;
+                self.cpu_usages = cpu_usages;
+                self.components = components;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if self.timeouts.is_multiple_of(TIMEOUT_LOG_EVERY) {
+                    eprintln!(
+                        "Sensor read took longer than {}ms, keeping the last readings",
+                        self.timeout.as_millis()
+                    );
+                }
+                self.timeouts += 1;
+                self.failed = true;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                if self.pending {
+                    eprintln!("Sensor read failed, keeping the last readings");
+                }
+                self.pending = false;
+                self.failed = true;
+            }
+        }
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.cpu_usages.clone()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.components.clone()
+    }
+
+    fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+/// One source read by several fans. Only the owner refreshes it, each fan's
+/// `view` reads whatever it was last refreshed with, so the sensors are read
+/// once however many fans follow them.
+#[derive(Clone)]
+pub struct SharedSource(Rc<RefCell<Box<dyn SensorSource>>>);
+
+impl SharedSource {
+    pub fn new(source: Box<dyn SensorSource>) -> Self {
+        Self(Rc::new(RefCell::new(source)))
+    }
+
+    pub fn refresh(&self) {
+        self.0.borrow_mut().refresh();
+    }
+
+    /// A handle for one fan's `Usage`, whose refreshes are left to the owner
+    pub fn view(&self) -> Box<dyn SensorSource> {
+        Box::new(SharedView(Rc::clone(&self.0)))
+    }
+}
+
+struct SharedView(Rc<RefCell<Box<dyn SensorSource>>>);
+
+impl SensorSource for SharedView {
+    fn refresh(&mut self) {}
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.0.borrow().cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.0.borrow().components()
+    }
+
+    fn failed(&self) -> bool {
+        self.0.borrow().failed()
+    }
+}
+
+/// A source shared with whoever feeds it, e.g. a `FakeSensorSource` scripted
+/// while `Usage` reads from it
+impl<S: SensorSource> SensorSource for Rc<RefCell<S>> {
+    fn refresh(&mut self) {
+        self.borrow_mut().refresh();
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        self.borrow().cpu_usages()
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        self.borrow().components()
+    }
+
+    fn failed(&self) -> bool {
+        self.borrow().failed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::usage::Usage;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn thermal_zones_read_type_and_millidegrees() {
+        let root = testing::temp_dir("thermal-zones");
+        testing::thermal_zone(&root, 0, "cpu-thermal", 47.5);
+        testing::thermal_zone(&root, 1, "gpu-thermal", 52.25);
+        let zone = root.join("thermal_zone0");
+        fs::write(zone.join("trip_point_0_type"), "passive\n").unwrap();
+        fs::write(zone.join("trip_point_1_type"), "critical\n").unwrap();
+        fs::write(zone.join("trip_point_1_temp"), "90000\n").unwrap();
+
+        let reading = read_zone(&zone).unwrap();
+        assert_eq!(reading.label, "cpu-thermal");
+        assert_eq!(reading.temperature, 47.5);
+        assert_eq!(reading.critical, Some(90.0));
+
+        let mut source = ThermalZoneSource::new(&root, vec!["gpu-thermal".to_string()]);
+        source.refresh();
+        let components = source.components();
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].label, "gpu-thermal");
+        assert_eq!(components[0].temperature, 52.25);
+    }
+
+    #[test]
+    fn failing_zone_holds_while_the_others_update() {
+        let root = testing::temp_dir("partial-zones");
+        for (zone, kind) in ["cpu-thermal", "gpu-thermal", "ddr-thermal"]
+            .iter()
+            .enumerate()
+        {
+            testing::thermal_zone(&root, zone as u32, kind, 40.0);
+        }
+        let mut source = ThermalZoneSource::new(&root, Vec::new());
+        source.refresh();
+
+        testing::thermal_zone(&root, 0, "cpu-thermal", 50.0);
+        fs::write(root.join("thermal_zone1").join("temp"), "\n").unwrap();
+        testing::thermal_zone(&root, 2, "ddr-thermal", 45.0);
+        source.refresh();
+        let temperatures: Vec<f32> = source.components().iter().map(|c| c.temperature).collect();
+        assert_eq!(temperatures, [50.0, 40.0, 45.0]);
+
+        // Nothing left to hold once every zone fails
+        for zone in 0..3 {
+            fs::write(
+                root.join(format!("thermal_zone{}", zone)).join("temp"),
+                "\n",
+            )
+            .unwrap();
+        }
+        source.refresh();
+        assert!(source.components().is_empty());
+    }
+
+    #[test]
+    fn slow_sensor_is_read_on_its_own_interval() {
+        let root = testing::temp_dir("sensor-intervals");
+        let config = testing::config("sensor_intervals = [{ sensors = [\"nvme\"], secs = 30 }]");
+        let mut source =
+            ThermalZoneSource::new(&root, Vec::new()).with_schedule(ReadSchedule::new(&config));
+
+        let mut reads = (0, 0);
+        let mut last = (None, None);
+        for tick in 0..60 {
+            testing::thermal_zone(&root, 0, "cpu-thermal", 40.0 + tick as f32);
+            testing::thermal_zone(&root, 1, "nvme-thermal", 40.0 + tick as f32);
+            source.refresh();
+            let read = |label: &str| {
+                source
+                    .components()
+                    .iter()
+                    .find(|c| c.label == label)
+                    .map(|c| c.temperature)
+            };
+            let now = (read("cpu-thermal"), read("nvme-thermal"));
+            reads.0 += (now.0 != last.0) as u32;
+            reads.1 += (now.1 != last.1) as u32;
+            last = now;
+        }
+        assert_eq!(reads, (60, 2));
+        assert_eq!(last, (Some(99.0), Some(70.0)));
+    }
+
+    /// Times `PolledSource` was opened
+    static OPENED: AtomicUsize = AtomicUsize::new(0);
+
+    /// A backend reading higher on every refresh
+    struct PolledSource {
+        refreshes: u32,
+    }
+
+    impl SensorSource for PolledSource {
+        fn refresh(&mut self) {
+            self.refreshes += 1;
+        }
+
+        fn cpu_usages(&self) -> Vec<f32> {
+            vec![5.0]
+        }
+
+        fn components(&self) -> Vec<ComponentReading> {
+            vec![testing::reading("polled", 40.0 + self.refreshes as f32)]
+        }
+    }
+
+    #[test]
+    fn registered_backend_is_opened_from_config() {
+        let mut registry = SourceRegistry::builtin();
+        registry.register("polled", |_| {
+            OPENED.fetch_add(1, Ordering::Relaxed);
+            Ok(Box::new(PolledSource { refreshes: 0 }))
+        });
+        assert!(registry.kinds().any(|kind| kind == "polled"));
+
+        let config = testing::config("sensor_source = \"polled\"");
+        let mut source = registry.open(&config).unwrap();
+        assert_eq!(OPENED.load(Ordering::Relaxed), 1);
+        source.refresh();
+        source.refresh();
+        assert_eq!(source.components()[0].temperature, 42.0);
+
+        // Unknown to the built in registry
+        let error = SourceRegistry::builtin().open(&config).err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("unknown sensor_source polled"));
+    }
+
+    /// A source taking `delay` to refresh, reading higher each time
+    struct SlowSource {
+        delay: Arc<Mutex<Duration>>,
+        refreshes: u32,
+    }
+
+    impl SensorSource for SlowSource {
+        fn refresh(&mut self) {
+            let delay = *self.delay.lock().unwrap();
+            thread::sleep(delay);
+            self.refreshes += 1;
+        }
+
+        fn cpu_usages(&self) -> Vec<f32> {
+            vec![10.0]
+        }
+
+        fn components(&self) -> Vec<ComponentReading> {
+            vec![testing::reading("slow", 40.0 + self.refreshes as f32)]
+        }
+    }
+
+    #[test]
+    fn slow_refresh_times_out_with_the_last_readings() {
+        let delay = Arc::new(Mutex::new(Duration::ZERO));
+        let slow = SlowSource {
+            delay: Arc::clone(&delay),
+            refreshes: 0,
+        };
+        let timeout = Duration::from_millis(50);
+        let mut source = TimeoutSource::new(Box::new(slow), timeout);
+        source.refresh();
+        assert_eq!(source.components()[0].temperature, 41.0);
+
+        *delay.lock().unwrap() = Duration::from_millis(400);
+        let started = Instant::now();
+        source.refresh();
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(source.timeouts, 1);
+        assert!(source.failed());
+        assert_eq!(source.components()[0].temperature, 41.0);
+
+        // Picked up by a later refresh once the worker is done
+        thread::sleep(Duration::from_millis(400));
+        source.refresh();
+        assert_eq!(source.timeouts, 0);
+        assert!(!source.failed());
+        assert_eq!(source.components()[0].temperature, 42.0);
+    }
+
+    /// A source whose refreshes after the first never return, until
+    /// `release` is dropped
+    struct HungSource {
+        release: mpsc::Receiver<()>,
+        read: bool,
+    }
+
+    impl SensorSource for HungSource {
+        fn refresh(&mut self) {
+            if self.read {
+                let _ = self.release.recv();
+            }
+            self.read = true;
+        }
+
+        fn cpu_usages(&self) -> Vec<f32> {
+            vec![0.0]
+        }
+
+        fn components(&self) -> Vec<ComponentReading> {
+            vec![testing::reading("coretemp Package id 0", 45.0)]
+        }
+    }
+
+    #[test]
+    fn hung_source_goes_stale() {
+        let config = testing::config("stale_after_secs = 3");
+        let (_release, hung) = mpsc::channel();
+        let hung = HungSource {
+            release: hung,
+            read: false,
+        };
+        let source = TimeoutSource::new(Box::new(hung), Duration::from_millis(20));
+        let mut usage = Usage::with_source(&config, &config.fans()[0], Box::new(source));
+        usage.update();
+        assert_eq!(usage.last_temperature(), Some(45.0));
+
+        for _ in 0..2 {
+            usage.update();
+            assert!(!usage.stale());
+        }
+        usage.update();
+        assert!(usage.stale());
+        // Still showing the reading from before it hung
+        assert_eq!(usage.last_temperature(), Some(45.0));
+    }
+
+    #[test]
+    fn ipmitool_sdr_output_parses() {
+        let output = "\
+Inlet Temp       | 04h | ok  |  7.1 | 23 degrees C
+Exhaust Temp     | 01h | ok  |  7.1 | 31 degrees C
+Temp             | 0Eh | ok  |  3.1 | 45 degrees C
+Temp             | 0Fh | ns  |  3.2 | No Reading
+PSU1 Temp        | 60h | ok  | 10.1 | 104 degrees F
+Fan1 RPM         | 30h | ok  |  7.1 | 5880 RPM
+";
+        assert_eq!(
+            parse_sdr(output),
+            [
+                ("Inlet Temp".to_string(), 23.0),
+                ("Exhaust Temp".to_string(), 31.0),
+                ("Temp".to_string(), 45.0),
+                ("PSU1 Temp".to_string(), 40.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn vcgencmd_output_parses() {
+        assert_eq!(parse_measure_temp("temp=47.8'C\n"), Some(47.8));
+        assert_eq!(parse_measure_temp("temp=-3.0'C"), Some(-3.0));
+        assert_eq!(parse_measure_temp("temp=47.8'F"), None);
+        assert_eq!(parse_measure_temp("VCHI initialization failed"), None);
+    }
+
+    #[test]
+    fn concurrent_zone_reads_overlap() {
+        const ZONES: u32 = 4;
+        const READ_TIME: Duration = Duration::from_millis(150);
+
+        // Each zone's temp is a FIFO its own writer only answers once it has
+        // been opened and the read time has passed
+        let root = testing::temp_dir("slow-zones");
+        let writers: Vec<_> = (0..ZONES)
+            .map(|n| {
+                testing::thermal_zone(&root, n, &format!("zone{}", n), 0.0);
+                let temp = root.join(format!("thermal_zone{}", n)).join("temp");
+                fs::remove_file(&temp).unwrap();
+                let path = std::ffi::CString::new(temp.to_str().unwrap()).unwrap();
+                assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+                thread::spawn(move || {
+                    let mut fifo = fs::OpenOptions::new().write(true).open(&temp).unwrap();
+                    thread::sleep(READ_TIME);
+                    writeln!(fifo, "{}", 40_000 + n * 1000).unwrap();
+                })
+            })
+            .collect();
+
+        let mut source = ThermalZoneSource::new(&root, Vec::new()).with_concurrency(ZONES as usize);
+        let started = Instant::now();
+        let readings = source.read_zones().unwrap();
+        let elapsed = started.elapsed();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let read: Vec<(&str, f32)> = readings
+            .iter()
+            .map(|r| (r.label.as_str(), r.temperature))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                ("zone0", 40.0),
+                ("zone1", 41.0),
+                ("zone2", 42.0),
+                ("zone3", 43.0)
+            ]
+        );
+        assert!(elapsed < READ_TIME * ZONES, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn shared_source_refreshes_once_for_every_view() {
+        let fake = Rc::new(RefCell::new(FakeSensorSource::new()));
+        for temperature in [40.0, 55.0] {
+            fake.borrow_mut().push(
+                vec![0.0],
+                vec![testing::reading("cpu-thermal", temperature)],
+            );
+        }
+        let shared = SharedSource::new(Box::new(Rc::clone(&fake)));
+        let mut views: Vec<_> = (0..3).map(|_| shared.view()).collect();
+
+        shared.refresh();
+        // Each fan refreshing its view leaves the next reading queued
+        for view in views.iter_mut() {
+            view.refresh();
+            assert_eq!(view.components()[0].temperature, 40.0);
+        }
+        shared.refresh();
+        for view in &views {
+            assert_eq!(view.components()[0].temperature, 55.0);
+        }
+    }
+}
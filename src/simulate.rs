@@ -0,0 +1,160 @@
+use crate::config::Config;
+use crate::stdin_sensors::ReplayFan;
+use crate::Error;
+use std::io::{BufRead, Write};
+
+/// How one fan ran over a simulated trace
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Summary {
+    pub samples: u64,
+    /// Samples the fan was on for
+    pub on_samples: u64,
+    /// Times the fan turned on
+    pub cycles: u64,
+    /// Highest duty the fan ran at, 100 while an on/off fan is on
+    pub peak_duty: f32,
+}
+
+/// Temperature and CPU usage of one line of a trace, `None` where a reading
+/// is missing
+fn parse(line: &str, fan: &mut Option<String>) -> Result<Option<(Option<f32>, f32)>, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let value = |field: Option<&&str>| match field {
+        None | Some(&"-") => Ok(None),
+        Some(value) => value.parse::<f32>().map(Some).map_err(|e| e.to_string()),
+    };
+    if fields.is_empty() || fields[0].starts_with('#') {
+        return Ok(None);
+    }
+
+    // A sample log line, of which only the first fan's are followed
+    if fields.len() >= 4 && fields[1].parse::<f32>().is_err() {
+        if fan.get_or_insert_with(|| fields[1].to_string()) != fields[1] {
+            return Ok(None);
+        }
+        let cpu = value(fields.get(3))?.unwrap_or_default();
+        return Ok(Some((value(fields.get(2))?, cpu)));
+    }
+    let cpu = value(fields.get(1))?.unwrap_or_default();
+
+    Ok(Some((value(fields.first())?, cpu)))
+}
+
+/// Run every fan of `config` through the temperatures and CPU usages of
+/// `trace`, one sample interval per line, with everything done as on real
+/// sensors but no hardware touched. Each sample's decision is written to
+/// `out` as `<secs> <fan> <ON|OFF>[ <duty>%][ (<trigger>)]`, followed by a
+/// summary of each fan, which is also returned.
+///
+/// Lines hold a temperature and optionally a CPU usage, as for
+/// `--stdin-sensors`, or are lines of a sample log, of which the first fan's
+/// are followed. `-` stands for a reading that failed, and blank lines and
+/// those starting with `#` are skipped.
+pub fn run<R: BufRead, W: Write>(
+    config: &Config,
+    trace: R,
+    out: &mut W,
+) -> Result<Vec<Summary>, Error> {
+    let interval = config.sample_interval().as_secs();
+    let mut fans = ReplayFan::all(config, false)?;
+    let mut summaries = vec![Summary::default(); fans.len()];
+    let mut trace_fan = None;
+
+    let mut secs = 0;
+    for (number, line) in trace.lines().enumerate() {
+        let line = line?;
+        let (temperature, cpu) = match parse(&line, &mut trace_fan) {
+            Ok(Some(sample)) => sample,
+            Ok(None) => continue,
+            Err(e) => return Err(Error::Sensor(format!("line {}: {}", number + 1, e))),
+        };
+
+        for (fan, summary) in fans.iter_mut().zip(summaries.iter_mut()) {
+            let was_on = fan.fan_control.fan_on() == Some(true);
+            fan.update(temperature, cpu);
+            let fan_control = &fan.fan_control;
+            let on = fan_control.fan_on() == Some(true);
+
+            summary.samples += 1;
+            if on {
+                summary.on_samples += 1;
+                if !was_on {
+                    summary.cycles += 1;
+                }
+            }
+            let duty = fan_control.duty().unwrap_or(if on { 100f32 } else { 0f32 });
+            summary.peak_duty = summary.peak_duty.max(duty);
+
+            let state = if on { "ON" } else { "OFF" };
+            let duty = fan_control
+                .duty()
+                .map(|d| format!(" {:.0}%", d))
+                .unwrap_or_default();
+            let trigger = fan_control
+                .trigger()
+                .map(|t| format!(" ({})", t))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{} {} {}{}{}",
+                secs,
+                fan_control.name(),
+                state,
+                duty,
+                trigger
+            )?;
+        }
+        secs += interval;
+    }
+
+    writeln!(out)?;
+    for (fan, summary) in fans.iter().zip(&summaries) {
+        let share = summary.on_samples as f32 / summary.samples.max(1) as f32 * 100f32;
+        writeln!(
+            out,
+            "{}: on {}s of {}s ({:.1}%), {} cycles, peak duty {:.0}%",
+            fan.fan_control.name(),
+            summary.on_samples * interval,
+            summary.samples * interval,
+            share,
+            summary.cycles,
+            summary.peak_duty
+        )?;
+    }
+
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn known_trace_summarizes_as_worked_out() {
+        let config = testing::config(
+            "sample_interval_secs = 2\ndecision_interval_secs = 2\n\
+             temp_intervals = [1]\ncpu_intervals = [1]\nmax_fan_on_temp = 60.0",
+        );
+        // Over 60 at the 3rd and 4th, 7th and 9th of the 9 samples
+        let trace = "# soak test\n40\n45 5\n65\n70\n\n50\n50\n66 3\n40\n62\n";
+        let mut out = Vec::new();
+        let summaries = run(&config, trace.as_bytes(), &mut out).unwrap();
+
+        let expected = Summary {
+            samples: 9,
+            on_samples: 4,
+            cycles: 3,
+            peak_duty: 100.0,
+        };
+        assert_eq!(summaries, [expected]);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[2], "4 fan0 ON (temperature[0])");
+        assert_eq!(lines[8], "16 fan0 ON (temperature[0])");
+        assert_eq!(
+            lines.last(),
+            Some(&"fan0: on 8s of 18s (44.4%), 3 cycles, peak duty 100%")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::curve::CurvePoint;
+use crate::daily::DailyExtremes;
+use crate::fan_control::FanControl;
+use crate::measurement::Measurement;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct FanStatus {
+    pub name: String,
+    pub fan_on: Option<bool>,
+    pub enabled: bool,
+    /// Profile the fan's settings come from, when one is active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub temp_alert: bool,
+    pub delta_alert: bool,
+    /// Whether sensors set to agree by `divergence` are too far apart
+    pub sensors_diverged: bool,
+    /// Whether the last health check failed, when health checks are on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degraded: Option<bool>,
+    /// Whether the fan is slowing down while on, when `spin_down` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spinning_down: Option<bool>,
+    /// Trigger that won the last decision
+    pub trigger: Option<String>,
+    /// Duty set by hand through the control socket, when one is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual: Option<f32>,
+    /// Interface `manual` was set on, with the client's user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual_source: Option<String>,
+    /// Name the client setting `manual` gave itself, as sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual_label: Option<String>,
+    /// Duty in percent, when driven by duty rather than on/off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duty: Option<f32>,
+    /// Curve points the temperature lies between, when following a curve
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curve_segment: Option<[CurvePoint; 2]>,
+    /// Estimated seconds until the fan turns off, while the temperature is
+    /// falling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_to_off_secs: Option<f32>,
+    /// Today's and yesterday's temperature extremes and peak duty, with
+    /// `daily_stats` set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<DailyExtremes>,
+    pub cpu: Vec<WindowStatus>,
+    pub temperature: Vec<WindowStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WindowStatus {
+    pub interval: usize,
+    pub average: f32,
+    /// Raw samples in the window, oldest first, only included on request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<f32>>,
+}
+
+impl FanStatus {
+    pub fn new(fan_control: &FanControl, history: bool) -> Self {
+        let usage = fan_control.usage();
+        Self {
+            name: fan_control.name().to_string(),
+            fan_on: fan_control.fan_on(),
+            enabled: fan_control.enabled(),
+            profile: fan_control.profile().map(str::to_string),
+            temp_alert: fan_control.temp_alert(),
+            delta_alert: fan_control.delta_alert(),
+            sensors_diverged: usage.sensors_diverged(),
+            degraded: fan_control.degraded(),
+            spinning_down: fan_control.spinning_down(),
+            trigger: fan_control.trigger().map(|t| t.to_string()),
+            manual: fan_control.manual(),
+            manual_source: fan_control.manual_source().map(|s| s.interface.clone()),
+            manual_label: fan_control.manual_source().and_then(|s| s.label.clone()),
+            duty: fan_control.duty(),
+            curve_segment: fan_control.curve_segment().map(|(low, high)| [low, high]),
+            time_to_off_secs: fan_control.time_to_off().map(|t| t.as_secs_f32()),
+            daily: fan_control.daily().cloned(),
+            cpu: windows(&usage.cpu, history),
+            temperature: windows(&usage.temperature, history),
+        }
+    }
+}
+
+fn windows(measurements: &[Measurement], history: bool) -> Vec<WindowStatus> {
+    measurements
+        .iter()
+        .map(|m| WindowStatus {
+            interval: m.interval(),
+            average: m.measurement(),
+            samples: history.then(|| m.samples().to_vec()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::FanCurve;
+    use crate::testing::{self, TestFan};
+
+    #[test]
+    fn history_holds_each_window_of_samples() {
+        let config = testing::config("temp_intervals = [2, 3]\ncpu_intervals = [2]");
+        let mut fan = TestFan::new(&config);
+        for (temperature, cpu) in [(40.0, 1.0), (41.0, 2.0), (42.0, 3.0), (43.0, 4.0)] {
+            fan.update(temperature, cpu);
+        }
+
+        let status = FanStatus::new(&fan.control, true);
+        let samples: Vec<_> = status
+            .temperature
+            .iter()
+            .map(|w| w.samples.clone())
+            .collect();
+        assert_eq!(
+            samples,
+            vec![Some(vec![42.0, 43.0]), Some(vec![41.0, 42.0, 43.0])]
+        );
+        assert_eq!(status.cpu[0].samples, Some(vec![3.0, 4.0]));
+
+        let status = FanStatus::new(&fan.control, false);
+        assert!(status.temperature.iter().all(|w| w.samples.is_none()));
+    }
+
+    #[test]
+    fn duty_is_the_curve_output() {
+        let config = testing::config(
+            "temp_intervals = [1]\ncpu_intervals = [1]\n\
+             curve = [{ temp = 40.0, duty = 30.0 }, { temp = 60.0, duty = 90.0 }]",
+        );
+        let curve = FanCurve::new(&config.fan.curve).unwrap();
+        let mut fan = TestFan::new(&config);
+        fan.update(47.0, 0.0);
+
+        let status = FanStatus::new(&fan.control, false);
+        assert_eq!(status.duty, Some(curve.duty(47.0)));
+        assert_eq!(status.duty, Some(51.0));
+        assert_eq!(
+            status.curve_segment,
+            Some([config.fan.curve[0], config.fan.curve[1]])
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::config::{StatusFifoConfig, StatusFormat};
+use crate::fan_control::FanControl;
+use crate::status::FanStatus;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::PathBuf;
+
+/// Writes the status of every fan to a named pipe each tick, for scripts to
+/// `cat` without talking to the control socket. The pipe is opened for each
+/// write, so a reader gets the status of one tick and then end of file.
+/// Ticks without a reader attached are skipped rather than blocking.
+pub struct StatusFifo {
+    path: PathBuf,
+    format: StatusFormat,
+    /// Whether the FIFO was made here, and so is removed when dropped
+    created: bool,
+}
+
+impl StatusFifo {
+    /// Use the FIFO at `config.path`, creating it if it doesn't exist
+    pub fn create(config: &StatusFifoConfig) -> io::Result<Self> {
+        let created = match fs::metadata(&config.path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => false,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a FIFO", config.path.display()),
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let path = CString::new(config.path.as_os_str().as_bytes())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                if unsafe { libc::mkfifo(path.as_ptr(), 0o644) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                true
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path: config.path.clone(),
+            format: config.format,
+            created,
+        })
+    }
+
+    /// Write the fans' status if a reader is attached
+    pub fn write(&self, fans: &[FanControl]) {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path);
+        let mut file = match file {
+            Ok(file) => file,
+            // Opening only succeeds once something has it open to read
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return,
+            Err(e) => {
+                eprintln!("Unable to open {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+
+        let status = match self.format {
+            StatusFormat::Json => json(fans),
+            StatusFormat::KeyValue => key_value(fans),
+        };
+        match file.write_all(status.as_bytes()) {
+            // A reader that isn't keeping up just misses this tick
+            Err(e)
+                if e.kind() != io::ErrorKind::WouldBlock
+                    && e.kind() != io::ErrorKind::BrokenPipe =>
+            {
+                eprintln!("Unable to write {}: {}", self.path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for StatusFifo {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The same JSON as the control socket's `status` reply, on one line
+pub fn json(fans: &[FanControl]) -> String {
+    let status: Vec<FanStatus> = fans.iter().map(|f| FanStatus::new(f, false)).collect();
+    match serde_json::to_string(&status) {
+        Ok(status) => format!("{}\n", status),
+        Err(e) => format!("unable to encode status: {}\n", e),
+    }
+}
+
+/// One line per fan, e.g.
+/// `name=fan0 on=1 duty=40.0 trigger=curve temperature=47.8 cpu=3.1`.
+/// Values that aren't known yet are left out.
+pub fn key_value(fans: &[FanControl]) -> String {
+    let mut out = String::new();
+    for fan in fans {
+        let usage = fan.usage();
+        out.push_str(&format!(
+            "name={} on={}",
+            fan.name(),
+            (fan.fan_on() == Some(true)) as u8
+        ));
+        if let Some(duty) = fan.duty() {
+            out.push_str(&format!(" duty={:.1}", duty));
+        }
+        if let Some(trigger) = fan.trigger() {
+            // Triggers can contain spaces, e.g. "cold start"
+            out.push_str(&format!(
+                " trigger={}",
+                trigger.to_string().replace(' ', "-")
+            ));
+        }
+        if let Some(temperature) = usage.last_temperature() {
+            let decimals = usage.display_decimals() as usize;
+            out.push_str(&format!(" temperature={:.*}", decimals, temperature));
+        }
+        if let Some(cpu) = usage.last_cpu() {
+            out.push_str(&format!(" cpu={:.1}", cpu));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestFan};
+    use std::io::Read;
+
+    #[test]
+    fn reader_gets_the_latest_status() {
+        let path = testing::temp_dir("status-fifo").join("status");
+        let fifo = StatusFifo::create(&StatusFifoConfig {
+            path: path.clone(),
+            format: StatusFormat::Json,
+        })
+        .unwrap();
+        let config = testing::config("temp_intervals = [1]\ncpu_intervals = [1]");
+        let mut fan = TestFan::new(&config);
+        fan.update(40.0, 0.0);
+        // Skipped without blocking while nothing reads
+        fifo.write(std::slice::from_ref(&fan.control));
+        fan.update(47.5, 3.0);
+
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        fifo.write(std::slice::from_ref(&fan.control));
+        let mut status = String::new();
+        reader.read_to_string(&mut status).unwrap();
+
+        let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+        assert_eq!(status[0]["name"], "fan0");
+        assert_eq!(status[0]["temperature"][0]["average"], 47.5);
+
+        drop(fifo);
+        assert!(!path.exists());
+    }
+}
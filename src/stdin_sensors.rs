@@ -0,0 +1,143 @@
+use crate::config::Config;
+use crate::fan_control::FanControl;
+use crate::output::NullOutput;
+use crate::sensors::{ComponentReading, FakeSensorSource};
+use crate::usage::Usage;
+use crate::Error;
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// Run every fan of `config` from readings on `input` rather than sensors,
+/// without touching any hardware. Each line holds a temperature and
+/// optionally a CPU usage, and is one sample and decision for every fan;
+/// the decision is written to `out` as
+/// `<line> <fan> <ON|OFF>[ <duty>%][ (<trigger>)]`.
+pub fn run<R: BufRead, W: Write>(config: &Config, input: R, out: &mut W) -> Result<(), Error> {
+    let mut fans = ReplayFan::all(config, true)?;
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let mut values = line.split_whitespace().map(str::parse::<f32>);
+        let temperature = match values.next() {
+            None => continue,
+            Some(Ok(temperature)) => temperature,
+            Some(Err(e)) => return Err(Error::Sensor(format!("line {}: {}", number + 1, e))),
+        };
+        let cpu = values.next().and_then(Result::ok).unwrap_or_default();
+
+        for fan in fans.iter_mut() {
+            fan.update(Some(temperature), cpu);
+            let fan_control = &fan.fan_control;
+
+            let state = if fan_control.fan_on() == Some(true) {
+                "ON"
+            } else {
+                "OFF"
+            };
+            let duty = fan_control
+                .duty()
+                .map(|d| format!(" {:.0}%", d))
+                .unwrap_or_default();
+            let trigger = fan_control
+                .trigger()
+                .map(|t| format!(" ({})", t))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{} {} {}{}{}",
+                number + 1,
+                fan_control.name(),
+                state,
+                duty,
+                trigger
+            )?;
+            if config.explain {
+                writeln!(out, "  {}", fan_control.explain())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A fan decided from given readings rather than sensors, driving no output
+pub struct ReplayFan {
+    pub fan_control: FanControl,
+    source: Rc<RefCell<FakeSensorSource>>,
+    /// Label readings are given, to pass the fan's own sensor filter
+    label: String,
+}
+
+impl ReplayFan {
+    /// Every fan of `config`, logging its transitions when `transition_log`
+    /// is set
+    pub fn all(config: &Config, transition_log: bool) -> Result<Vec<Self>, Error> {
+        let mut fans = Vec::new();
+        for (i, fan) in config.fans().iter().enumerate() {
+            let source = Rc::new(RefCell::new(FakeSensorSource::new()));
+            let usage = Usage::with_source(config, fan, Box::new(Rc::clone(&source)));
+            let filter = config.sensor_filter(fan);
+            let label = filter
+                .include
+                .first()
+                .or(filter.prefixes.first())
+                .cloned()
+                .unwrap_or_default();
+            let mut fan_control =
+                FanControl::with_output(fan.name(i), fan, usage, Box::new(NullOutput))?
+                    .without_panic_command();
+            if !transition_log {
+                fan_control = fan_control.without_transition_log();
+            }
+            fans.push(Self {
+                fan_control,
+                source,
+                label,
+            });
+        }
+
+        Ok(fans)
+    }
+
+    /// Take one sample of `temperature`, `None` for a failed read, and `cpu`
+    /// usage, and decide the fan from it
+    pub fn update(&mut self, temperature: Option<f32>, cpu: f32) {
+        let readings = temperature
+            .map(|temperature| ComponentReading {
+                label: self.label.clone(),
+                temperature,
+                max: f32::NAN,
+                critical: None,
+            })
+            .into_iter()
+            .collect();
+        self.source.borrow_mut().push(vec![cpu], readings);
+        self.fan_control.update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn each_line_prints_the_decision() {
+        let config = testing::config(
+            "temp_intervals = [1]\ncpu_intervals = [1]\n\
+             max_fan_on_temp = 50.0\nmax_fan_on_cpu = 80.0",
+        );
+        let mut out = Vec::new();
+        run(&config, "40\n\n60 10\n45 90\n40\n".as_bytes(), &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 fan0 OFF\n\
+             3 fan0 ON (temperature[0])\n\
+             4 fan0 ON (cpu[0])\n\
+             5 fan0 OFF\n"
+        );
+        assert!(run(&config, "warm\n".as_bytes(), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! Fixtures shared by the unit tests
+
+use crate::clock::ManualClock;
+use crate::config::Config;
+use crate::controller::Controller;
+use crate::fan_control::FanControl;
+use crate::output::{FanOutput, NullOutput};
+use crate::sensors::{ComponentReading, FakeSensorSource, SensorSource, SourceRegistry};
+use crate::usage::Usage;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fresh, empty directory for `test` to write into
+pub fn temp_dir(test: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "gpio-fan-{}-{}-{}",
+        test,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A hwmon directory with a `pwm1` fan under `dir`, for outputs of type
+/// `hwmon-pwm`
+pub fn hwmon(dir: &Path) -> PathBuf {
+    let hwmon = dir.join("hwmon0");
+    fs::create_dir_all(&hwmon).unwrap();
+    fs::write(hwmon.join("pwm1"), "0\n").unwrap();
+    fs::write(hwmon.join("pwm1_enable"), "2\n").unwrap();
+    hwmon
+}
+
+/// A thermal zone of `kind` at `temperature` degrees under `root`
+pub fn thermal_zone(root: &Path, zone: u32, kind: &str, temperature: f32) {
+    let zone = root.join(format!("thermal_zone{}", zone));
+    fs::create_dir_all(&zone).unwrap();
+    fs::write(zone.join("type"), format!("{}\n", kind)).unwrap();
+    fs::write(
+        zone.join("temp"),
+        format!("{}\n", (temperature * 1000f32) as i64),
+    )
+    .unwrap();
+}
+
+/// Parse and validate `toml` as a config
+pub fn config(toml: &str) -> Config {
+    let config: Config = toml::from_str(toml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+pub fn reading(label: &str, temperature: f32) -> ComponentReading {
+    ComponentReading {
+        label: label.to_string(),
+        temperature,
+        max: f32::NAN,
+        critical: None,
+    }
+}
+
+/// A fan of `config` reading from a scripted source
+pub struct TestFan {
+    pub control: FanControl,
+    pub source: Rc<RefCell<FakeSensorSource>>,
+}
+
+impl TestFan {
+    /// The first fan of `config`, writing to nothing
+    pub fn new(config: &Config) -> Self {
+        Self::with_output(config, 0, Box::new(NullOutput))
+    }
+
+    /// Fan `index` of `config`, writing to `output`
+    pub fn with_output(config: &Config, index: usize, output: Box<dyn FanOutput>) -> Self {
+        let fan = &config.fans()[index];
+        let source = Rc::new(RefCell::new(FakeSensorSource::new()));
+        let usage = Usage::with_source(config, fan, Box::new(Rc::clone(&source)));
+        let control = FanControl::with_output(fan.name(index), fan, usage, output)
+            .unwrap()
+            .without_panic_command();
+
+        Self { control, source }
+    }
+
+    /// Sample a `coretemp` reading of `temperature` at `cpu` percent usage
+    /// and decide, returning the fan state
+    pub fn update(&mut self, temperature: f32, cpu: f32) -> Option<bool> {
+        self.sample(temperature, cpu);
+        self.control.decide()
+    }
+
+    /// As `update`, without deciding
+    pub fn sample(&mut self, temperature: f32, cpu: f32) {
+        self.push(
+            vec![cpu],
+            vec![reading("coretemp Package id 0", temperature)],
+        );
+        self.control.sample();
+    }
+
+    /// Sample without reading a temperature
+    pub fn miss(&mut self) {
+        self.push(vec![0.0], Vec::new());
+        self.control.sample();
+    }
+
+    pub fn push(&mut self, cpu_usages: Vec<f32>, components: Vec<ComponentReading>) {
+        self.source.borrow_mut().push(cpu_usages, components);
+    }
+}
+
+thread_local! {
+    /// Temperature and CPU usage the `test` source reads on this thread
+    static READING: Cell<(f32, f32)> = const { Cell::new((40f32, 0f32)) };
+    /// Refreshes still to read no temperature at all
+    static FAILING_READS: Cell<u32> = const { Cell::new(0) };
+    /// Refreshes of every `test` source so far
+    static REFRESHES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Sensor source `test`, reading whatever the test thread last set
+struct ThreadSource {
+    reading: (f32, f32),
+    failed: bool,
+}
+
+impl SensorSource for ThreadSource {
+    fn refresh(&mut self) {
+        REFRESHES.with(|r| r.set(r.get() + 1));
+        self.reading = READING.with(Cell::get);
+        self.failed = FAILING_READS.with(|f| f.replace(f.get().saturating_sub(1)) > 0);
+    }
+
+    fn cpu_usages(&self) -> Vec<f32> {
+        vec![self.reading.1]
+    }
+
+    fn components(&self) -> Vec<ComponentReading> {
+        if self.failed {
+            return Vec::new();
+        }
+        vec![reading("coretemp Package id 0", self.reading.0)]
+    }
+}
+
+/// Have every `test` source on this thread read `temperature` and `cpu`
+/// from its next refresh on
+pub fn set_reading(temperature: f32, cpu: f32) {
+    READING.with(|r| r.set((temperature, cpu)));
+}
+
+/// Refreshes of every `test` source on this thread so far
+pub fn refreshes() -> u64 {
+    REFRESHES.with(Cell::get)
+}
+
+/// Have the next `reads` refreshes of `test` sources on this thread find no
+/// temperature
+pub fn fail_reads(reads: u32) {
+    FAILING_READS.with(|f| f.set(reads));
+}
+
+/// A controller over a config file in its own directory, driving a fixture
+/// hwmon fan from the `test` sensor source
+pub struct TestController {
+    pub controller: Controller,
+    pub clock: Rc<ManualClock>,
+    pub dir: PathBuf,
+}
+
+impl TestController {
+    /// `toml` is laid over a config with one-sample windows, a threshold of
+    /// 50 degrees and everything written kept under the directory
+    pub fn new(test: &str, toml: &str) -> Self {
+        let dir = temp_dir(test);
+        let path = Self::write(&dir, toml);
+        let config = Config::load_with(Some(&path), &[]).unwrap();
+        let mut registry = SourceRegistry::builtin();
+        registry.register("test", |_| {
+            Ok(Box::new(ThreadSource {
+                reading: READING.with(Cell::get),
+                failed: false,
+            }))
+        });
+        let clock = Rc::new(ManualClock::new());
+        let controller =
+            Controller::with_sources(config, Some(path), clock.clone(), registry).unwrap();
+
+        Self {
+            controller,
+            clock,
+            dir,
+        }
+    }
+
+    /// Replace the config file with one made from `toml`, as `new` does
+    pub fn rewrite(&self, toml: &str) {
+        Self::write(&self.dir, toml);
+    }
+
+    fn write(dir: &Path, toml: &str) -> PathBuf {
+        let hwmon = hwmon(dir);
+        let path = dir.join("config.toml");
+        let mut config: toml::Table = format!(
+            "sensor_source = \"test\"\n\
+             state_file = \"{}\"\n\
+             temp_intervals = [1]\n\
+             cpu_intervals = [1]\n\
+             max_fan_on_temp = 50.0\n\
+             panic_command = \"true\"\n\
+             output = {{ type = \"hwmon-pwm\", hwmon = \"{}\", pwm = 1 }}\n",
+            dir.join("state.toml").display(),
+            hwmon.display(),
+        )
+        .parse()
+        .unwrap();
+        config.extend(toml.parse::<toml::Table>().unwrap());
+
+        // Listed fans without an output of their own each get a pwm
+        let fans = config.get_mut("fans").and_then(|f| f.as_array_mut());
+        for (i, fan) in fans.into_iter().flatten().enumerate() {
+            let Some(fan) = fan.as_table_mut().filter(|f| !f.contains_key("output")) else {
+                continue;
+            };
+            let pwm = i + 1;
+            fs::write(hwmon.join(format!("pwm{}", pwm)), "0\n").unwrap();
+            fs::write(hwmon.join(format!("pwm{}_enable", pwm)), "2\n").unwrap();
+            let output = format!(
+                "type = \"hwmon-pwm\"\nhwmon = \"{}\"\npwm = {}",
+                hwmon.display(),
+                pwm
+            );
+            fan.insert(
+                "output".to_string(),
+                toml::Value::Table(output.parse().unwrap()),
+            );
+        }
+        fs::write(&path, config.to_string()).unwrap();
+        path
+    }
+
+    /// Tick once with the sensors reading `temperature` and `cpu`, then move
+    /// the clock on to the next sample
+    pub fn tick(&mut self, temperature: f32, cpu: f32) {
+        set_reading(temperature, cpu);
+        self.controller.tick();
+        self.clock.advance(self.controller.sample_interval());
+    }
+
+    pub fn fan(&self) -> &FanControl {
+        &self.controller.fans()[0]
+    }
+}
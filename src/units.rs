@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+/// `value` rounded to `decimals` places
+pub fn round(value: f32, decimals: u8) -> f32 {
+    let scale = 10f32.powi(decimals as i32);
+    (value * scale).round() / scale
+}
+
+/// Temperature units, for configuring thresholds and for display. Sensors
+/// always report Celsius.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl Units {
+    pub fn from_celsius(self, celsius: f32) -> f32 {
+        match self {
+            Units::Celsius => celsius,
+            Units::Fahrenheit => celsius * Units::Fahrenheit.degree() + 32f32,
+        }
+    }
+
+    pub fn to_celsius(self, temp: f32) -> f32 {
+        match self {
+            Units::Celsius => temp,
+            Units::Fahrenheit => (temp - 32f32) / Units::Fahrenheit.degree(),
+        }
+    }
+
+    /// `temp` in these units converted to `to`
+    pub fn convert(self, temp: f32, to: Units) -> f32 {
+        to.from_celsius(self.to_celsius(temp))
+    }
+
+    /// A temperature difference in these units converted to `to`
+    pub fn convert_delta(self, delta: f32, to: Units) -> f32 {
+        delta / self.degree() * to.degree()
+    }
+
+    /// Size of a degree relative to a degree Celsius
+    fn degree(self) -> f32 {
+        match self {
+            Units::Celsius => 1f32,
+            Units::Fahrenheit => 1.8,
+        }
+    }
+
+    /// `temp` formatted with `decimals` places and the unit symbol
+    pub fn format(self, temp: f32, decimals: u8) -> String {
+        format!("{:.*}{}", decimals as usize, temp, self.symbol())
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Units::Celsius => "°C",
+            Units::Fahrenheit => "°F",
+        }
+    }
+}
@@ -0,0 +1,1234 @@
+use crate::config::{
+    Config, DivergenceCheck, FanConfig, SensorCalibration, SensorFloorAction, SensorMax,
+    ZeroUsageAction,
+};
+use crate::curve::FanCurve;
+use crate::measurement::{self, JumpLimit, Measurement};
+use crate::sensors::{self, ComponentReading, SensorSource};
+use crate::units::{self, Units};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Selects which components feed the temperature windows: a component is
+/// used if its label starts with one of `prefixes`, then, when `include` is
+/// not empty, only if its label is listed there, and never if its label is
+/// listed in `exclude`. When `preference` is given only the components
+/// matching its first label prefix that matches any are used.
+#[derive(Debug, Clone, Default)]
+pub struct SensorFilter {
+    pub prefixes: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub preference: Vec<String>,
+}
+
+impl SensorFilter {
+    pub fn matches(&self, label: &str) -> bool {
+        self.prefixes.iter().any(|p| label.starts_with(p.as_str()))
+            && (self.include.is_empty() || self.include.iter().any(|l| l == label))
+            && !self.exclude.iter().any(|l| l == label)
+    }
+
+    /// The components of `components` to use, in their original order
+    pub fn select(&self, components: Vec<ComponentReading>) -> Vec<ComponentReading> {
+        let matched: Vec<ComponentReading> = components
+            .into_iter()
+            .filter(|c| self.matches(&c.label))
+            .collect();
+        for preferred in &self.preference {
+            let chosen: Vec<ComponentReading> = matched
+                .iter()
+                .filter(|c| c.label.starts_with(preferred.as_str()))
+                .cloned()
+                .collect();
+            if !chosen.is_empty() {
+                return chosen;
+            }
+        }
+
+        matched
+    }
+}
+
+/// How readings from several components are combined into one sample.
+/// Given as `"max"`, `"mean"` or `"pN"` for the Nth percentile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Aggregation {
+    #[default]
+    Max,
+    Mean,
+    /// Nearest-rank percentile, between 0 and 100
+    Percentile(u8),
+}
+
+impl Aggregation {
+    pub fn aggregate(self, values: &[f32]) -> Option<f32> {
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(match self {
+            Aggregation::Max => values.iter().copied().fold(f32::MIN, f32::max),
+            Aggregation::Mean => values.iter().sum::<f32>() / values.len() as f32,
+            Aggregation::Percentile(percentile) => {
+                let mut sorted = values.to_vec();
+                sorted.sort_by(f32::total_cmp);
+                let rank = (percentile as usize * sorted.len()).div_ceil(100);
+                sorted[rank.clamp(1, sorted.len()) - 1]
+            }
+        })
+    }
+}
+
+impl From<Aggregation> for String {
+    fn from(aggregation: Aggregation) -> Self {
+        match aggregation {
+            Aggregation::Max => "max".to_string(),
+            Aggregation::Mean => "mean".to_string(),
+            Aggregation::Percentile(percentile) => format!("p{}", percentile),
+        }
+    }
+}
+
+impl TryFrom<String> for Aggregation {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "max" => Ok(Aggregation::Max),
+            "mean" => Ok(Aggregation::Mean),
+            _ => value
+                .strip_prefix('p')
+                .and_then(|p| p.parse::<u8>().ok())
+                .filter(|p| *p <= 100)
+                .map(Aggregation::Percentile)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown aggregation \"{}\", expected \"max\", \"mean\" or \"p0\" to \"p100\"",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+/// A component reading above the critical temperature it reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverCritical {
+    pub label: String,
+    pub temperature: f32,
+    pub critical: f32,
+}
+
+/// Temperature window of one zone a fan follows, kept when the zone has a
+/// curve or threshold of its own
+pub struct ZoneWindow {
+    pub name: String,
+    prefixes: Vec<String>,
+    pub curve: Option<FanCurve>,
+    pub threshold: Option<f32>,
+    pub window: Measurement,
+}
+
+/// Which threshold decisions a window takes part in: turning the fan on,
+/// keeping it running (so it only turns off once the window has dropped),
+/// both, or neither.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowRole {
+    #[default]
+    Both,
+    On,
+    Off,
+    None,
+}
+
+impl WindowRole {
+    /// Whether the window votes while the fan is in `fan_on` state
+    pub fn votes(self, fan_on: bool) -> bool {
+        match self {
+            WindowRole::Both => true,
+            WindowRole::On => !fan_on,
+            WindowRole::Off => fan_on,
+            WindowRole::None => false,
+        }
+    }
+}
+
+pub struct Usage {
+    source: Box<dyn SensorSource>,
+    pub cpu: Vec<Measurement>,
+    pub temperature: Vec<Measurement>,
+    /// Role of each CPU and temperature window, by position
+    pub cpu_roles: Vec<WindowRole>,
+    pub temperature_roles: Vec<WindowRole>,
+    /// Fastest CPU's frequency in MHz, only read when a fan triggers on it
+    pub cpu_freq: Option<Measurement>,
+    /// Zones with curves or thresholds, windowed like the shortest
+    /// temperature window
+    pub zones: Vec<ZoneWindow>,
+    /// Zone breaking ties around the fan's threshold, its `threshold` the
+    /// tie breaker's, read from every component rather than the fan's own
+    pub tie_breaker: Option<ZoneWindow>,
+    cpu_freq_root: PathBuf,
+    sensors: SensorFilter,
+    calibration: Vec<SensorCalibration>,
+    sample_interval: Duration,
+    seed_windows: bool,
+    /// Short means each raw sample passes through before the windows
+    cpu_prefilter: Option<Measurement>,
+    temp_prefilter: Option<Measurement>,
+    temp_aggregation: Aggregation,
+    /// Sensor prefixes of each zone in the order the fan prefers them, when
+    /// it only follows the first zone read
+    zone_priority: Vec<Vec<String>>,
+    cpu_aggregation: Aggregation,
+    sensor_max: SensorMax,
+    /// Lowest plausible maximum the followed sensors reported, as
+    /// `sensor_max` keeps it
+    max_temp: Option<f32>,
+    /// Sensors whose last reported maximum was implausible
+    implausible_max: HashSet<String>,
+    /// Lowest critical temperature a followed sensor reported on the last
+    /// update, in °C
+    lowest_critical: Option<f32>,
+    last_cpu: Option<f32>,
+    last_temperature: Option<f32>,
+    /// °C/sec between the last two samples, when both read a temperature
+    sample_rise_rate: Option<f32>,
+    over_critical: Option<OverCritical>,
+    /// Whether a temperature has been read since starting or clearing
+    read_temperature: bool,
+    /// Consecutive updates that read no temperature
+    missed_samples: u64,
+    stale_after: Option<Duration>,
+    /// Staleness allowed before the first temperature is read
+    stale_grace: Option<Duration>,
+    sensor_floor: Option<f32>,
+    sensor_floor_after: Duration,
+    sensor_floor_action: SensorFloorAction,
+    /// Consecutive samples every followed sensor has read `sensor_floor`
+    floor_samples: u64,
+    zero_usage_after: Option<Duration>,
+    zero_usage_action: ZeroUsageAction,
+    /// Consecutive samples CPU usage has read exactly 0%
+    zero_usage_samples: u64,
+    /// Samples a newly appeared sensor is left out of decisions for
+    new_sensor_grace: u64,
+    /// Samples each present sensor has been seen for, capped at the grace
+    seen: HashMap<String, u64>,
+    /// Sensors whose last reading was clamped to their plausible range
+    clamped: HashSet<String>,
+    divergence: Vec<DivergenceCheck>,
+    /// Percentage points and samples of a step in load
+    load_step: Option<(f32, u64)>,
+    /// Consecutive samples CPU usage has been stepped away from its long
+    /// average
+    step_samples: u64,
+    /// Whether each divergence check's sensors were too far apart on the
+    /// last update
+    diverged: Vec<bool>,
+    logic_units: Units,
+    display_units: Units,
+    display_decimals: u8,
+}
+
+impl Usage {
+    /// Measurements for `fan`, following the components selected by its
+    /// sensor filter, combined into one sample by the temperature aggregation.
+    /// Updates are expected every sample interval, which sets how many
+    /// samples each window holds.
+    pub fn new(config: &Config, fan: &FanConfig) -> Result<Self, Error> {
+        Ok(Self::with_source(
+            config,
+            fan,
+            sensors::from_config(config)?,
+        ))
+    }
+
+    /// As `new`, reading from `source` rather than the running system
+    pub fn with_source(config: &Config, fan: &FanConfig, source: Box<dyn SensorSource>) -> Self {
+        let sample_interval = config.sample_interval();
+        let samples = |secs: &usize| (secs / sample_interval.as_secs().max(1) as usize).max(1);
+
+        let cap = config.max_window_samples;
+        let alpha = config
+            .ewma_time_constant_secs
+            .map(|t| measurement::ewma_alpha(t, sample_interval.as_secs_f32()));
+        let mut cpu = Vec::new();
+        for interval in &config.cpu_intervals {
+            cpu.push(
+                Measurement::new(samples(interval))
+                    .with_cap(cap)
+                    .with_mode(config.cpu_window_mode)
+                    .with_ewma_alpha(alpha),
+            );
+        }
+
+        let jump_limit = config.sample_jump_limit.map(|max_jump| JumpLimit {
+            max_jump,
+            action: config.sample_jump_action,
+        });
+        let mut temperature = Vec::new();
+        for interval in &config.temp_intervals {
+            temperature.push(
+                Measurement::new(samples(interval))
+                    .with_cap(cap)
+                    .with_mode(config.temp_window_mode)
+                    .with_ewma_alpha(alpha)
+                    .with_jump_limit(jump_limit),
+            );
+        }
+
+        let shortest = config.temp_intervals.iter().min().unwrap_or(&1);
+        let zones = config
+            .zones
+            .iter()
+            .filter(|z| fan.zones.contains(&z.name))
+            .filter_map(|z| {
+                let curve = FanCurve::new(&z.curve).ok();
+                if curve.is_none() && z.threshold.is_none() {
+                    return None;
+                }
+                Some(ZoneWindow {
+                    name: z.name.clone(),
+                    prefixes: z.sensors.clone(),
+                    curve,
+                    threshold: z.threshold,
+                    window: Measurement::new(samples(shortest))
+                        .with_cap(cap)
+                        .with_mode(config.temp_window_mode)
+                        .with_ewma_alpha(alpha)
+                        .with_jump_limit(jump_limit),
+                })
+            })
+            .collect();
+        let tie_breaker = fan.tie_breaker.as_ref().and_then(|t| {
+            let zone = config.zones.iter().find(|z| z.name == t.zone)?;
+            Some(ZoneWindow {
+                name: zone.name.clone(),
+                prefixes: zone.sensors.clone(),
+                curve: None,
+                threshold: Some(t.threshold),
+                window: Measurement::new(samples(shortest))
+                    .with_cap(cap)
+                    .with_mode(config.temp_window_mode)
+                    .with_ewma_alpha(alpha)
+                    .with_jump_limit(jump_limit),
+            })
+        });
+        let cpu_freq = fan
+            .cpu_freq_trigger
+            .map(|_| Measurement::new(samples(&config.cpu_freq_secs)).with_cap(cap));
+
+        let roles = |roles: &[WindowRole], count: usize| {
+            (0..count)
+                .map(|i| roles.get(i).copied().unwrap_or_default())
+                .collect()
+        };
+
+        Self {
+            cpu_roles: roles(&config.cpu_window_roles, cpu.len()),
+            temperature_roles: roles(&config.temp_window_roles, temperature.len()),
+            cpu,
+            temperature,
+            cpu_freq,
+            zones,
+            tie_breaker,
+            cpu_freq_root: config.cpu_freq_root.clone(),
+            sensors: config.sensor_filter(fan),
+            calibration: config.calibration.clone(),
+            sample_interval,
+            seed_windows: config.seed_windows,
+            cpu_prefilter: config.prefilter_samples.map(Measurement::new),
+            temp_prefilter: config.prefilter_samples.map(Measurement::new),
+            temp_aggregation: fan.temp_aggregation.unwrap_or(config.temp_aggregation),
+            zone_priority: zone_priority(config, fan),
+            cpu_aggregation: config.cpu_aggregation,
+            source,
+            sensor_max: config.sensor_max,
+            max_temp: None,
+            implausible_max: HashSet::new(),
+            lowest_critical: None,
+            last_cpu: None,
+            last_temperature: None,
+            sample_rise_rate: None,
+            over_critical: None,
+            read_temperature: false,
+            missed_samples: 0,
+            stale_after: config.stale_after_secs.map(Duration::from_secs),
+            stale_grace: config
+                .stale_grace_secs
+                .or(config.stale_after_secs)
+                .map(Duration::from_secs),
+            sensor_floor: config.sensor_floor,
+            sensor_floor_after: Duration::from_secs(config.sensor_floor_secs),
+            sensor_floor_action: config.sensor_floor_action,
+            floor_samples: 0,
+            zero_usage_after: (config.zero_usage_secs != 0)
+                .then(|| Duration::from_secs(config.zero_usage_secs)),
+            zero_usage_action: config.zero_usage_action,
+            zero_usage_samples: 0,
+            new_sensor_grace: config.new_sensor_grace_secs / sample_interval.as_secs().max(1),
+            seen: HashMap::new(),
+            clamped: HashSet::new(),
+            divergence: config.divergence.clone(),
+            load_step: config.load_step.as_ref().map(|step| {
+                let secs = sample_interval.as_secs().max(1);
+                (step.delta, (step.secs / secs).max(1))
+            }),
+            step_samples: 0,
+            diverged: vec![false; config.divergence.len()],
+            logic_units: config.logic_units,
+            display_units: config.display_units,
+            display_decimals: config.display_decimals,
+        }
+    }
+
+    /// Follow the sensors `config` and `fan` now list, combined and
+    /// calibrated as they now say, keeping every window's samples
+    pub fn reload_sensors(&mut self, config: &Config, fan: &FanConfig) {
+        self.sensors = config.sensor_filter(fan);
+        self.calibration = config.calibration.clone();
+        self.divergence = config.divergence.clone();
+        self.diverged = vec![false; config.divergence.len()];
+        self.temp_aggregation = fan.temp_aggregation.unwrap_or(config.temp_aggregation);
+        self.zone_priority = zone_priority(config, fan);
+        self.cpu_aggregation = config.cpu_aggregation;
+        self.sensor_max = config.sensor_max;
+        self.max_temp = None;
+    }
+
+    /// Check each set of sensors that should agree, returning the highest
+    /// reading of those that don't so it can be trusted over the rest
+    fn check_divergence(&mut self, labels: &[String], temps: &[f32]) -> Option<f32> {
+        let mut highest: Option<f32> = None;
+        for i in 0..self.divergence.len() {
+            let check = &self.divergence[i];
+            let readings: Vec<f32> = labels
+                .iter()
+                .zip(temps)
+                .filter(|(label, _)| check.sensors.iter().any(|s| label.starts_with(s.as_str())))
+                .map(|(_, &t)| t)
+                .collect();
+            let (Some(low), Some(high)) = (
+                readings.iter().copied().reduce(f32::min),
+                readings.iter().copied().reduce(f32::max),
+            ) else {
+                continue;
+            };
+            let divergent = high - low > check.max_delta;
+            if divergent && !self.diverged[i] {
+                eprintln!(
+                    "{} read {} apart, more than {}, one may be faulty, using the highest",
+                    check.sensors.join(", "),
+                    self.display_delta(high - low),
+                    self.display_delta(check.max_delta)
+                );
+            } else if !divergent && self.diverged[i] {
+                println!("{} agree again", check.sensors.join(", "));
+            }
+            self.diverged[i] = divergent;
+            if divergent {
+                highest = Some(highest.map_or(high, |h| h.max(high)));
+            }
+        }
+
+        highest
+    }
+
+    /// Restart the longer temperature windows from the shortest once CPU
+    /// usage has held a step away from its long average, so they follow the
+    /// new load rather than averaging in the old one, then fill again as
+    /// usual. Restarted once per step.
+    fn check_load_step(&mut self) {
+        let Some((delta, samples)) = self.load_step else {
+            return;
+        };
+        let shortest = self.cpu.iter().min_by_key(|c| c.interval());
+        let longest = self.cpu.iter().max_by_key(|c| c.interval());
+        let (Some(shortest), Some(longest)) = (shortest, longest) else {
+            return;
+        };
+        let (recent, average) = (shortest.measurement(), longest.measurement());
+        let stepped = !shortest.samples().is_empty() && (recent - average).abs() >= delta;
+        self.step_samples = if stepped { self.step_samples + 1 } else { 0 };
+        if self.step_samples != samples {
+            return;
+        }
+
+        let Some(shortest) = self.temperature.iter().min_by_key(|t| t.interval()) else {
+            return;
+        };
+        let (interval, latest) = (shortest.interval(), shortest.samples().to_vec());
+        println!(
+            "CPU usage stepped from {:.1}% to {:.1}%, restarting the longer temperature windows",
+            average, recent
+        );
+        for window in self
+            .temperature
+            .iter_mut()
+            .filter(|t| t.interval() > interval)
+        {
+            window.clear();
+            for &sample in &latest {
+                window.update(sample);
+            }
+        }
+    }
+
+    /// Whether sensors that should agree were too far apart on the last
+    /// update
+    pub fn sensors_diverged(&self) -> bool {
+        self.diverged.iter().any(|&d| d)
+    }
+
+    pub fn update(&mut self) {
+        self.source.refresh();
+
+        let cpu_usage = self.cpu_aggregation.aggregate(&self.source.cpu_usages());
+
+        // Logical cores can report the same physical sensor more than once,
+        // keep one reading per label so a mean isn't biased towards it
+        let mut labels: Vec<String> = Vec::new();
+        let mut temps = Vec::new();
+        let mut min_cpu_max = match self.sensor_max {
+            SensorMax::Lowest => self.max_temp,
+            SensorMax::Ignore | SensorMax::Latest => None,
+        };
+        let mut over_critical = None;
+        let mut at_floor = true;
+        let mut lowest_critical: Option<f32> = None;
+        let readings = self.sensors.select(self.source.components());
+        self.track_new_sensors(&readings);
+        for mut c in readings {
+            if labels.contains(&c.label) || self.in_grace(&c.label) {
+                continue;
+            }
+            if let Some(critical) = c.critical {
+                lowest_critical = Some(lowest_critical.map_or(critical, |l| l.min(critical)));
+            }
+            // Sources without a maximum report NaN, leave those out quietly
+            let implausible_max = !c.max.is_nan() && (c.max <= 0f32 || c.max.is_infinite());
+            if self.sensor_max != SensorMax::Ignore
+                && !c.max.is_nan()
+                && implausible_max != self.implausible_max.contains(&c.label)
+            {
+                if implausible_max {
+                    eprintln!(
+                        "{} reported an implausible maximum of {}°C, ignoring it",
+                        c.label, c.max
+                    );
+                    self.implausible_max.insert(c.label.clone());
+                } else {
+                    println!("{} reporting a plausible maximum again", c.label);
+                    self.implausible_max.remove(&c.label);
+                }
+            }
+            // Sensors report Celsius, everything after is in logic units
+            c.temperature = self.logic_units.from_celsius(c.temperature);
+            c.max = self.logic_units.from_celsius(c.max);
+            c.critical = c.critical.map(|t| self.logic_units.from_celsius(t));
+            // Compared in the sensor's own terms, before any calibration
+            at_floor &= self.sensor_floor == Some(c.temperature);
+            if let Some(critical) = c.critical.filter(|&critical| c.temperature > critical) {
+                over_critical.get_or_insert(OverCritical {
+                    label: c.label.clone(),
+                    temperature: c.temperature,
+                    critical,
+                });
+            }
+            let calibration = self.calibration.iter().find(|s| s.label == c.label);
+            let calibrate = |reading: f32| match calibration {
+                Some(calibration) => calibration.apply(reading),
+                None => reading,
+            };
+            let max = calibrate(c.max);
+            let temperature = calibrate(c.temperature);
+            let clamped = calibration.map_or(temperature, |cal| cal.clamp(temperature));
+            if (clamped != temperature) != self.clamped.contains(&c.label) {
+                if clamped != temperature {
+                    eprintln!(
+                        "{} read {}, out of its plausible range, clamping to {}",
+                        c.label,
+                        self.display(temperature),
+                        self.display(clamped)
+                    );
+                    self.clamped.insert(c.label.clone());
+                } else {
+                    println!("{} back within its plausible range", c.label);
+                    self.clamped.remove(&c.label);
+                }
+            }
+            temps.push(clamped);
+            if !implausible_max && !max.is_nan() {
+                min_cpu_max = Some(min_cpu_max.map_or(max, |lowest| lowest.min(max)));
+            }
+            labels.push(c.label);
+        }
+        // Only the zone first in priority with a reading counts, if any
+        let followed = self.zone_priority.iter().find_map(|prefixes| {
+            let zone_temps: Vec<f32> = labels
+                .iter()
+                .zip(&temps)
+                .filter(|(label, _)| prefixes.iter().any(|p| label.starts_with(p.as_str())))
+                .map(|(_, &t)| t)
+                .collect();
+            (!zone_temps.is_empty()).then_some(zone_temps)
+        });
+        let max_cpu_temps = self
+            .temp_aggregation
+            .aggregate(followed.as_deref().unwrap_or(&temps));
+        let max_cpu_temps = match self.check_divergence(&labels, &temps) {
+            Some(highest) => max_cpu_temps.map(|t| t.max(highest)),
+            None => max_cpu_temps,
+        };
+
+        self.max_temp = match self.sensor_max {
+            SensorMax::Ignore => None,
+            SensorMax::Lowest | SensorMax::Latest => min_cpu_max,
+        };
+        self.last_cpu = cpu_usage;
+        self.sample_rise_rate = max_cpu_temps
+            .zip(self.last_temperature)
+            .map(|(t, last)| (t - last) / self.sample_interval.as_secs_f32());
+        self.last_temperature = max_cpu_temps;
+        self.lowest_critical = lowest_critical;
+        self.over_critical = over_critical;
+        self.read_temperature |= max_cpu_temps.is_some();
+        // A failed refresh still reads, but only what it read before
+        self.missed_samples = match max_cpu_temps {
+            Some(_) if !self.source.failed() => 0,
+            _ => self.missed_samples + 1,
+        };
+        self.floor_samples = if at_floor && !labels.is_empty() {
+            self.floor_samples + 1
+        } else {
+            0
+        };
+        self.zero_usage_samples = match cpu_usage {
+            Some(0f32) => self.zero_usage_samples + 1,
+            Some(_) => 0,
+            None => self.zero_usage_samples,
+        };
+
+        if let Some(window) = self.cpu_freq.as_mut() {
+            match sensors::read_cpu_frequencies(&self.cpu_freq_root) {
+                Ok(frequencies) => {
+                    if let Some(fastest) = Aggregation::Max.aggregate(&frequencies) {
+                        Self::push(window, fastest, self.seed_windows);
+                    }
+                }
+                Err(e) => eprintln!(
+                    "Unable to read CPU frequencies from {}: {}",
+                    self.cpu_freq_root.display(),
+                    e
+                ),
+            }
+        }
+        if let Some(mut cpu_usage) = cpu_usage {
+            if let Some(prefilter) = self.cpu_prefilter.as_mut() {
+                cpu_usage = prefilter.update(cpu_usage);
+            }
+            for cpu in self.cpu.iter_mut() {
+                Self::push(cpu, cpu_usage, self.seed_windows);
+            }
+        }
+        if let Some(mut max_cpu_temps) = max_cpu_temps {
+            if let Some(prefilter) = self.temp_prefilter.as_mut() {
+                max_cpu_temps = prefilter.update(max_cpu_temps);
+            }
+            for temp in self.temperature.iter_mut() {
+                Self::push(temp, max_cpu_temps, self.seed_windows);
+            }
+        }
+        self.check_load_step();
+        for zone in self.zones.iter_mut() {
+            let zone_temps: Vec<f32> = labels
+                .iter()
+                .zip(&temps)
+                .filter(|(label, _)| zone.prefixes.iter().any(|p| label.starts_with(p.as_str())))
+                .map(|(_, &t)| t)
+                .collect();
+            if let Some(temp) = self.temp_aggregation.aggregate(&zone_temps) {
+                Self::push(&mut zone.window, temp, self.seed_windows);
+            }
+        }
+        if let Some(tie_breaker) = self.tie_breaker.as_mut() {
+            let temps: Vec<f32> = self
+                .source
+                .components()
+                .iter()
+                .filter(|c| {
+                    let prefixes = &tie_breaker.prefixes;
+                    prefixes.iter().any(|p| c.label.starts_with(p.as_str()))
+                })
+                .map(|c| {
+                    let reading = self.logic_units.from_celsius(c.temperature);
+                    match self.calibration.iter().find(|s| s.label == c.label) {
+                        Some(calibration) => calibration.apply(reading),
+                        None => reading,
+                    }
+                })
+                .collect();
+            if let Some(temp) = self.temp_aggregation.aggregate(&temps) {
+                Self::push(&mut tie_breaker.window, temp, self.seed_windows);
+            }
+        }
+    }
+
+    /// Count how long each sensor in `readings` has been present. Sensors
+    /// read before the first temperature are there from the start, those
+    /// appearing later wait out the grace period.
+    fn track_new_sensors(&mut self, readings: &[ComponentReading]) {
+        let grace = self.new_sensor_grace;
+        let started = self.read_temperature;
+        // Gone sensors count as new again if they come back
+        self.seen
+            .retain(|label, _| readings.iter().any(|r| &r.label == label));
+        for reading in readings {
+            match self.seen.get_mut(&reading.label) {
+                Some(samples) if *samples < grace => {
+                    *samples += 1;
+                    if *samples == grace {
+                        println!("Following new sensor {}", reading.label);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    if started && grace > 0 {
+                        println!(
+                            "New sensor {}, ignored for its first {} samples",
+                            reading.label, grace
+                        );
+                    }
+                    let samples = if started { 0 } else { grace };
+                    self.seen.insert(reading.label.clone(), samples);
+                }
+            }
+        }
+    }
+
+    /// Whether the sensor `label` appeared too recently to take part
+    fn in_grace(&self, label: &str) -> bool {
+        self.seen
+            .get(label)
+            .is_some_and(|&s| s < self.new_sensor_grace)
+    }
+
+    /// Add a sample to `window`, filling an empty window with it when seeding
+    /// so its average is meaningful straight away
+    fn push(window: &mut Measurement, sample: f32, seed: bool) {
+        if seed && window.samples().is_empty() {
+            window.seed(sample);
+        } else {
+            window.update(sample);
+        }
+    }
+
+    /// Empty every window, as if no samples had been taken
+    pub fn clear(&mut self) {
+        for window in self
+            .cpu
+            .iter_mut()
+            .chain(self.temperature.iter_mut())
+            .chain(self.cpu_freq.iter_mut())
+            .chain(self.zones.iter_mut().map(|z| &mut z.window))
+            .chain(self.tie_breaker.iter_mut().map(|z| &mut z.window))
+            .chain(self.cpu_prefilter.iter_mut())
+            .chain(self.temp_prefilter.iter_mut())
+        {
+            window.clear();
+        }
+        self.last_cpu = None;
+        self.last_temperature = None;
+        self.sample_rise_rate = None;
+        self.over_critical = None;
+        self.lowest_critical = None;
+        self.read_temperature = false;
+        self.missed_samples = 0;
+        self.floor_samples = 0;
+        self.zero_usage_samples = 0;
+        self.seen.clear();
+    }
+
+    /// Raw CPU usage read on the last update, before averaging
+    pub fn last_cpu(&self) -> Option<f32> {
+        self.last_cpu
+    }
+
+    /// Raw temperature read on the last update, before averaging
+    pub fn last_temperature(&self) -> Option<f32> {
+        self.last_temperature
+    }
+
+    /// How fast the temperature rose between the last two samples, in
+    /// °C/sec, `None` unless both read one
+    pub fn sample_rise_rate(&self) -> Option<f32> {
+        self.sample_rise_rate
+    }
+
+    /// A component read on the last update above its own critical
+    /// temperature, if any
+    pub fn over_critical(&self) -> Option<&OverCritical> {
+        self.over_critical.as_ref()
+    }
+
+    /// How far the latest temperature sample is above the lowest sample
+    /// within `period`, taken from the longest temperature window
+    pub fn temperature_rise(&self, period: Option<Duration>) -> Option<f32> {
+        let longest = self.temperature.iter().max_by_key(|t| t.interval())?;
+        let samples = longest.samples();
+        let count = match period {
+            Some(period) => {
+                let secs = self.sample_interval.as_secs().max(1);
+                (period.as_secs() / secs + 1) as usize
+            }
+            None => samples.len(),
+        };
+        let recent = &samples[samples.len().saturating_sub(count)..];
+        let latest = *recent.last()?;
+        let lowest = recent.iter().copied().fold(f32::MAX, f32::min);
+
+        Some(latest - lowest)
+    }
+
+    /// Whether any temperature has been read yet
+    pub fn read_temperature(&self) -> bool {
+        self.read_temperature
+    }
+
+    /// How long since a temperature was last read, as counted in samples
+    pub fn staleness(&self) -> Duration {
+        self.sample_interval * self.missed_samples as u32
+    }
+
+    /// Whether no temperature has been read for longer than allowed, the
+    /// grace applying until the first one is
+    pub fn stale(&self) -> bool {
+        let limit = if self.read_temperature {
+            self.stale_after
+        } else {
+            self.stale_grace
+        };
+        limit.is_some_and(|limit| self.staleness() >= limit)
+    }
+
+    /// Whether every followed sensor has been stuck at the configured floor
+    /// for long enough to count as not reporting
+    pub fn at_floor(&self) -> bool {
+        self.floor_samples > 0
+            && self.sample_interval * self.floor_samples as u32 >= self.sensor_floor_after
+    }
+
+    /// Whether CPU usage has read exactly 0% for long enough to count as
+    /// not working
+    pub fn usage_stuck_at_zero(&self) -> bool {
+        self.zero_usage_after.is_some_and(|after| {
+            self.zero_usage_samples > 0
+                && self.sample_interval * self.zero_usage_samples as u32 >= after
+        })
+    }
+
+    /// Whether CPU usage is left out of decisions, stuck at 0% with
+    /// `zero_usage_action = "disable"`
+    pub fn usage_disabled(&self) -> bool {
+        self.zero_usage_action == ZeroUsageAction::Disable && self.usage_stuck_at_zero()
+    }
+
+    /// `temp`, in logic units, converted to the display units
+    pub fn display_value(&self, temp: f32) -> f32 {
+        units::round(
+            self.logic_units.convert(temp, self.display_units),
+            self.display_decimals,
+        )
+    }
+
+    /// `temp`, in logic units, formatted in the display units, e.g. `47.8°C`
+    pub fn display(&self, temp: f32) -> String {
+        self.display_units.format(
+            self.logic_units.convert(temp, self.display_units),
+            self.display_decimals,
+        )
+    }
+
+    /// A temperature difference in logic units formatted in display units
+    pub fn display_delta(&self, delta: f32) -> String {
+        self.display_units.format(
+            self.logic_units.convert_delta(delta, self.display_units),
+            self.display_decimals,
+        )
+    }
+
+    /// Decimal places temperatures are displayed with
+    pub fn display_decimals(&self) -> u8 {
+        self.display_decimals
+    }
+
+    pub fn sensor_floor_action(&self) -> SensorFloorAction {
+        self.sensor_floor_action
+    }
+
+    pub fn sample_interval(&self) -> Duration {
+        self.sample_interval
+    }
+
+    /// `percent` of the lowest critical temperature the followed sensors
+    /// reported on the last update, in logic units. The percentage is taken
+    /// of the temperature in °C, so it means the same whatever the units.
+    pub fn critical_threshold(&self, percent: f32) -> Option<f32> {
+        let critical = self.lowest_critical?;
+        Some(self.logic_units.from_celsius(critical * percent / 100f32))
+    }
+
+    pub fn cpu_max_temp(&self) -> Option<f32> {
+        self.max_temp
+    }
+}
+
+/// Sensor prefixes of each of `fan`'s zones in the order it prefers them,
+/// when it only follows the first zone read
+fn zone_priority(config: &Config, fan: &FanConfig) -> Vec<Vec<String>> {
+    if !fan.zone_priority {
+        return Vec::new();
+    }
+    fan.zones
+        .iter()
+        .filter_map(|name| config.zones.iter().find(|z| &z.name == name))
+        .map(|z| z.sensors.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::FakeSensorSource;
+    use crate::testing::{self, reading};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Usage of the first fan of `toml`, reading from the returned script
+    fn usage(toml: &str) -> (Usage, Rc<RefCell<FakeSensorSource>>) {
+        let config = testing::config(toml);
+        let source = Rc::new(RefCell::new(FakeSensorSource::new()));
+        let usage = Usage::with_source(&config, &config.fans()[0], Box::new(Rc::clone(&source)));
+        (usage, source)
+    }
+
+    fn package(temperature: f32) -> Vec<ComponentReading> {
+        vec![reading("coretemp Package id 0", temperature)]
+    }
+
+    #[test]
+    fn scripted_readings_fill_windows() {
+        let (mut usage, source) =
+            usage("temp_intervals = [2, 3]\ncpu_intervals = [2]\nsensor_max = \"lowest\"");
+        let with_max = |label: &str, temperature: f32, max: f32| ComponentReading {
+            max,
+            ..reading(label, temperature)
+        };
+        let script = [
+            (vec![10.0, 30.0], 40.0, 90.0),
+            (vec![50.0, 70.0], 46.0, 85.0),
+            (vec![80.0, 100.0], 52.0, 95.0),
+        ];
+        for (cpu_usages, temperature, max) in script {
+            source.borrow_mut().push(
+                cpu_usages,
+                vec![with_max("coretemp Package id 0", temperature, max)],
+            );
+            usage.update();
+        }
+
+        // The busiest CPU of each sample
+        assert_eq!(usage.cpu[0].measurement(), 85.0);
+        assert_eq!(usage.temperature[0].measurement(), 49.0);
+        assert_eq!(usage.temperature[1].measurement(), 46.0);
+        // Lowest of every maximum reported, not only the latest
+        assert_eq!(usage.cpu_max_temp(), Some(85.0));
+    }
+
+    #[test]
+    fn threshold_scales_with_each_sensors_critical() {
+        let threshold = |critical: f32| {
+            let (mut usage, source) = usage("temp_intervals = [1]\ncpu_intervals = [1]");
+            let sensor = ComponentReading {
+                critical: Some(critical),
+                ..reading("coretemp Package id 0", 40.0)
+            };
+            source.borrow_mut().push(vec![10.0], vec![sensor]);
+            usage.update();
+            usage.critical_threshold(75.0)
+        };
+        assert_eq!(threshold(100.0), Some(75.0));
+        assert_eq!(threshold(80.0), Some(60.0));
+
+        // Lowest of several, and none until one reports a critical
+        let (mut usage, source) = usage("temp_intervals = [1]\ncpu_intervals = [1]");
+        assert_eq!(usage.critical_threshold(75.0), None);
+        let sensors = [(100.0, "coretemp Core 0"), (80.0, "coretemp Core 1")].map(|(c, label)| {
+            ComponentReading {
+                critical: Some(c),
+                ..reading(label, 40.0)
+            }
+        });
+        source.borrow_mut().push(vec![10.0], sensors.to_vec());
+        usage.update();
+        assert_eq!(usage.critical_threshold(75.0), Some(60.0));
+    }
+
+    #[test]
+    fn most_preferred_available_source_is_selected() {
+        let filter = SensorFilter {
+            prefixes: vec!["".to_string()],
+            preference: ["nvme", "k10temp", "acpitz"].map(String::from).to_vec(),
+            ..SensorFilter::default()
+        };
+        let components = vec![
+            reading("acpitz temp1", 40.0),
+            reading("k10temp Tctl", 62.0),
+            reading("k10temp Tccd1", 58.0),
+            reading("amdgpu edge", 70.0),
+        ];
+        let labels: Vec<String> = filter
+            .select(components.clone())
+            .into_iter()
+            .map(|c| c.label)
+            .collect();
+        assert_eq!(labels, ["k10temp Tctl", "k10temp Tccd1"]);
+
+        // Everything matched when none preferred is there
+        let unpreferred = SensorFilter {
+            preference: vec!["nvme".to_string()],
+            ..filter
+        };
+        assert_eq!(unpreferred.select(components).len(), 4);
+    }
+
+    #[test]
+    fn celsius_threshold_displays_in_fahrenheit() {
+        let (shown, _) = usage("display_units = \"fahrenheit\"\nmax_fan_on_temp = 60.0");
+        assert_eq!(shown.display(60.0), "140.0°F");
+        assert_eq!(shown.display_delta(5.0), "9.0°F");
+
+        let (configured, _) = usage("logic_units = \"fahrenheit\"");
+        assert_eq!(configured.display(140.0), "60.0°C");
+    }
+
+    #[test]
+    fn cpu_ewma_beside_temperature_mean() {
+        let (mut usage, source) =
+            usage("cpu_intervals = [3]\ntemp_intervals = [3]\ncpu_window_mode = \"ewma\"");
+        for value in [0.0, 30.0, 90.0] {
+            source.borrow_mut().push(vec![value], package(value));
+            usage.update();
+        }
+        // Moving half way to each sample, 2 / (3 + 1)
+        assert_eq!(usage.cpu[0].measurement(), 52.5);
+        assert_eq!(usage.temperature[0].measurement(), 40.0);
+    }
+
+    #[test]
+    fn hotplugged_sensor_waits_out_its_grace() {
+        let (mut usage, source) = usage("new_sensor_grace_secs = 3");
+        source.borrow_mut().push(vec![0.0], package(40.0));
+        usage.update();
+
+        let temperatures: Vec<Option<f32>> = (0..5)
+            .map(|_| {
+                let mut readings = package(40.0);
+                readings.push(reading("coretemp Core 7", 90.0));
+                source.borrow_mut().push(vec![0.0], readings);
+                usage.update();
+                usage.last_temperature()
+            })
+            .collect();
+        assert_eq!(
+            temperatures,
+            [Some(40.0), Some(40.0), Some(40.0), Some(90.0), Some(90.0)]
+        );
+    }
+
+    #[test]
+    fn displayed_temperature_is_rounded_but_not_stored() {
+        let (mut usage, source) = usage("display_decimals = 0\ntemp_intervals = [2]");
+        source.borrow_mut().push(vec![0.0], package(47.25));
+        source.borrow_mut().push(vec![0.0], package(48.5));
+        usage.update();
+        usage.update();
+
+        let average = usage.temperature[0].measurement();
+        assert_eq!(average, 47.875);
+        assert_eq!(usage.display(average), "48°C");
+    }
+
+    #[test]
+    fn calibration_offset_shifts_the_reading() {
+        let (mut usage, source) =
+            usage("[[calibration]]\nlabel = \"coretemp Package id 0\"\noffset = -7.5");
+        source.borrow_mut().push(vec![0.0], package(50.0));
+        usage.update();
+        assert_eq!(usage.last_temperature(), Some(42.5));
+        assert_eq!(usage.temperature[0].measurement(), 42.5);
+    }
+
+    #[test]
+    fn extreme_reading_is_clamped_to_its_bound() {
+        let (mut usage, source) = usage(
+            "temp_intervals = [3]\n\
+             [[calibration]]\nlabel = \"coretemp Package id 0\"\nmin = 0.0\nmax = 110.0",
+        );
+        for temperature in [40.0, 40.0, 3000.0] {
+            source.borrow_mut().push(vec![0.0], package(temperature));
+            usage.update();
+        }
+        assert_eq!(usage.last_temperature(), Some(110.0));
+        assert_eq!(usage.temperature[0].measurement(), 190.0 / 3.0);
+
+        source.borrow_mut().push(vec![0.0], package(-273.0));
+        usage.update();
+        assert_eq!(usage.last_temperature(), Some(0.0));
+    }
+
+    #[test]
+    fn windows_receive_prefiltered_samples() {
+        let (mut usage, source) =
+            usage("temp_intervals = [5]\ncpu_intervals = [5]\nprefilter_samples = 3");
+        for (temperature, cpu) in [(40.0, 10.0), (43.0, 40.0), (40.0, 10.0), (43.0, 40.0)] {
+            source.borrow_mut().push(vec![cpu], package(temperature));
+            usage.update();
+        }
+        assert_eq!(usage.temperature[0].samples(), &[40.0, 41.5, 41.0, 42.0]);
+        assert_eq!(usage.cpu[0].samples(), &[10.0, 25.0, 20.0, 30.0]);
+        // The latest reading is still the raw one
+        assert_eq!(usage.last_temperature(), Some(43.0));
+    }
+
+    #[test]
+    fn zero_max_leaves_the_threshold_alone() {
+        let with_max = |max: f32| {
+            vec![ComponentReading {
+                max,
+                ..reading("coretemp Package id 0", 45.0)
+            }]
+        };
+        for mode in ["lowest", "latest"] {
+            let (mut usage, source) = usage(&format!("sensor_max = \"{}\"", mode));
+            source.borrow_mut().push(vec![0.0], with_max(0.0));
+            usage.update();
+            assert_eq!(usage.cpu_max_temp(), None, "{}", mode);
+
+            source.borrow_mut().push(vec![0.0], with_max(85.0));
+            usage.update();
+            source.borrow_mut().push(vec![0.0], with_max(0.0));
+            usage.update();
+            let kept = (mode == "lowest").then_some(85.0);
+            assert_eq!(usage.cpu_max_temp(), kept, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn load_step_restarts_the_long_window() {
+        let long_window_after_step = |load_step: &str| {
+            let (mut usage, source) = usage(&format!(
+                "temp_intervals = [2, 30]\ncpu_intervals = [2, 30]\n{}",
+                load_step
+            ));
+            let script = [(70.0, 90.0); 30].into_iter().chain([(40.0, 5.0); 4]);
+            for (temperature, cpu) in script {
+                source.borrow_mut().push(vec![cpu], package(temperature));
+                usage.update();
+            }
+            usage.temperature[1].measurement()
+        };
+
+        // Still averaging in 26 samples of the old load
+        let lagging = long_window_after_step("");
+        assert_eq!(lagging, (26.0 * 70.0 + 4.0 * 40.0) / 30.0);
+        // Restarted from the short window once the step held for 3 samples
+        let restarted = long_window_after_step("load_step = { delta = 40.0, secs = 3 }");
+        assert_eq!(restarted, 40.0);
+    }
+
+    #[test]
+    fn staleness_waits_out_the_grace_before_the_first_reading() {
+        let stale_after_updates = |toml: &str, first: Option<f32>| {
+            let (mut usage, source) = usage(toml);
+            if let Some(temperature) = first {
+                source.borrow_mut().push(vec![0.0], package(temperature));
+                usage.update();
+                source.borrow_mut().push(vec![0.0], Vec::new());
+            }
+            (1..=20)
+                .find(|_| {
+                    usage.update();
+                    usage.stale()
+                })
+                .unwrap()
+        };
+
+        assert_eq!(stale_after_updates("stale_after_secs = 3", None), 3);
+        let grace = "stale_after_secs = 3\nstale_grace_secs = 8";
+        assert_eq!(stale_after_updates(grace, None), 8);
+        // Once read, only stale_after_secs applies
+        assert_eq!(stale_after_updates(grace, Some(45.0)), 3);
+    }
+
+    #[test]
+    fn excluded_component_is_ignored() {
+        let config = testing::config("sensor_exclude = [\"coretemp Core 1\"]");
+        let filter = config.sensor_filter(&config.fans()[0]);
+        assert!(filter.matches("coretemp Core 0"));
+        assert!(!filter.matches("coretemp Core 1"));
+        assert!(!filter.matches("acpitz temp1"));
+    }
+
+    #[test]
+    fn include_narrows_the_prefix_match() {
+        let config = testing::config("sensor_include = [\"coretemp Package id 0\"]");
+        let filter = config.sensor_filter(&config.fans()[0]);
+        assert!(filter.matches("coretemp Package id 0"));
+        assert!(!filter.matches("coretemp Core 0"));
+    }
+
+    #[test]
+    fn duplicate_labels_count_once_in_mean() {
+        let (mut usage, source) = usage("temp_aggregation = \"mean\"");
+        source.borrow_mut().push(
+            vec![0.0],
+            vec![
+                reading("coretemp Core 0", 40.0),
+                reading("coretemp Core 0", 40.0),
+                reading("coretemp Core 1", 70.0),
+            ],
+        );
+        usage.update();
+        assert_eq!(usage.last_temperature(), Some(55.0));
+    }
+
+    #[test]
+    fn seeded_window_starts_at_first_sample() {
+        let (mut seeded, source) = usage("seed_windows = true\ntemp_intervals = [4]");
+        source.borrow_mut().push(vec![0.0], package(40.0));
+        source.borrow_mut().push(vec![0.0], package(80.0));
+        seeded.update();
+        assert_eq!(seeded.temperature[0].measurement(), 40.0);
+        assert_eq!(seeded.temperature[0].samples(), &[40.0; 4]);
+
+        // A second sample moves the average as it would in a full window,
+        // not halfway as it would in an empty one
+        seeded.update();
+        assert_eq!(seeded.temperature[0].measurement(), 50.0);
+
+        let (mut unseeded, source) = usage("temp_intervals = [4]");
+        source.borrow_mut().push(vec![0.0], package(40.0));
+        source.borrow_mut().push(vec![0.0], package(80.0));
+        unseeded.update();
+        unseeded.update();
+        assert_eq!(unseeded.temperature[0].measurement(), 60.0);
+    }
+}
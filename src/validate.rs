@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::curve::{CurvePoint, FanCurve};
+use crate::output;
+use crate::sensors;
+use crate::usage::SensorFilter;
+
+/// Temperature each step of a curve sweep moves on by
+const SWEEP_STEP: f32 = 0.5f32;
+/// Fall in duty from one step to the next put down to rounding
+const DUTY_TOLERANCE: f32 = 0.01f32;
+
+/// Check that the hardware `config` refers to is present: every fan's output
+/// can be opened (and is released again straight away) and every sensor
+/// prefix matches at least one component. Returns a list of problems found,
+/// empty if everything is usable.
+pub fn check(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Err(e) = config.validate() {
+        problems.push(e);
+    }
+
+    // Which sensors match can't be checked without a source to read them
+    let labels: Option<Vec<String>> = match sensors::from_config(config) {
+        Ok(mut source) => {
+            source.refresh();
+            Some(source.components().into_iter().map(|c| c.label).collect())
+        }
+        Err(e) => {
+            problems.push(format!("unable to open sensors: {}", e));
+            None
+        }
+    };
+
+    for (i, fan) in config.fans().iter().enumerate() {
+        let name = fan.name(i);
+        if let Err(e) = output::from_config(&fan.output) {
+            problems.push(format!("{}: unable to open output: {}", name, e));
+        }
+        let Some(labels) = &labels else {
+            continue;
+        };
+        let filter = config.sensor_filter(fan);
+        for prefix in &filter.prefixes {
+            let single = SensorFilter {
+                prefixes: vec![prefix.clone()],
+                ..filter.clone()
+            };
+            if !labels.iter().any(|l| single.matches(l)) {
+                problems.push(format!("{}: no sensor matches {}", name, prefix));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Sweep every fan's curve, and those of the zones it follows, across their
+/// temperatures, checking each duty is a number between 0 and 100 that
+/// doesn't fall as the temperature rises, and check the fan's thresholds are
+/// numbers. Returns a list of problems found, empty if the duties are sane.
+pub fn sweep(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (i, fan) in config.fans().iter().enumerate() {
+        let name = fan.name(i);
+        let thresholds = [
+            ("max_fan_on_temp", Some(fan.max_fan_on_temp)),
+            ("off threshold", fan.off_threshold()),
+            ("full_speed_temp", fan.full_speed_temp),
+            ("alert_temp", fan.alert_temp),
+            ("critical_temp", fan.critical_temp),
+            ("panic_temp", fan.panic_temp),
+        ];
+        for (threshold, temp) in thresholds {
+            if temp.is_some_and(|t| !t.is_finite()) {
+                problems.push(format!("{}: {} isn't a number", name, threshold));
+            }
+        }
+
+        let zones = config
+            .zones
+            .iter()
+            .filter(|z| fan.zones.contains(&z.name))
+            .map(|z| (format!("zone {} curve", z.name), &z.curve));
+        for (curve, points) in std::iter::once(("curve".to_string(), &fan.curve)).chain(zones) {
+            if points.is_empty() {
+                continue;
+            }
+            if let Err(e) = sweep_curve(points) {
+                problems.push(format!("{}: {} {}", name, curve, e));
+            }
+        }
+    }
+
+    problems
+}
+
+fn sweep_curve(points: &[CurvePoint]) -> Result<(), String> {
+    if let Some(point) = points.iter().find(|p| !p.temp.is_finite()) {
+        return Err(format!("temperature {} isn't a number", point.temp));
+    }
+    let curve = FanCurve::new(points)?;
+    let (first, last) = (points[0].temp, points[points.len() - 1].temp);
+    let steps = ((last - first) / SWEEP_STEP).ceil() as u32;
+
+    let mut previous: Option<(f32, f32)> = None;
+    for step in 0..=steps {
+        let temp = (first + step as f32 * SWEEP_STEP).min(last);
+        let duty = curve.duty(temp);
+        if !(0f32..=100f32).contains(&duty) {
+            return Err(format!("duty {} at {}°C is out of range", duty, temp));
+        }
+        if let Some((previous_temp, previous_duty)) = previous {
+            if duty < previous_duty - DUTY_TOLERANCE {
+                return Err(format!(
+                    "duty falls from {}% at {}°C to {}% at {}°C",
+                    previous_duty, previous_temp, duty, temp
+                ));
+            }
+        }
+        previous = Some((temp, duty));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::path::Path;
+
+    fn config(dir: &Path, zone_sensor: &str, hwmon: &Path) -> Config {
+        testing::config(&format!(
+            r#"
+            thermal_zones = {{ root = "{}" }}
+            zones = [{{ name = "cpu", sensors = ["{}"] }}]
+
+            [[fans]]
+            zones = ["cpu"]
+            output = {{ type = "hwmon-pwm", hwmon = "{}", pwm = 1 }}
+            "#,
+            dir.display(),
+            zone_sensor,
+            hwmon.display()
+        ))
+    }
+
+    #[test]
+    fn check_passes_present_hardware() {
+        let dir = testing::temp_dir("validate-ok");
+        testing::thermal_zone(&dir, 0, "cpu-thermal", 45.0);
+        let hwmon = testing::hwmon(&dir);
+
+        assert_eq!(check(&config(&dir, "cpu", &hwmon)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn check_reports_missing_sensor_and_output() {
+        let dir = testing::temp_dir("validate-missing");
+        testing::thermal_zone(&dir, 0, "cpu-thermal", 45.0);
+
+        let problems = check(&config(&dir, "gpu", &dir.join("hwmon9")));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("fan0: unable to open output"));
+        assert_eq!(problems[1], "fan0: no sensor matches gpu");
+    }
+}